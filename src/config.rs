@@ -18,6 +18,13 @@ use serde::Deserialize;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use thiserror::Error;
+use uuid::Uuid;
+
+use crate::models::{canonical_mime, OutputFormat, VariantQuery, VariantSpec};
+use crate::services::assets::DEFAULT_ASSET;
+
+/// Largest width or height libwebp can encode
+const WEBP_MAX_DIMENSION: u32 = 16383;
 
 /// Configuration loading errors
 #[derive(Debug, Error)]
@@ -45,10 +52,20 @@ pub struct Config {
     pub auth: AuthConfig,
     #[serde(default)]
     pub rexpump: RexPumpConfig,
+    /// Built-in assets served at `/m/{name}` (key is the name)
+    #[serde(default)]
+    pub assets: HashMap<String, AssetConfig>,
+}
+
+/// A built-in asset loaded from disk at startup
+#[derive(Debug, Clone, Deserialize)]
+pub struct AssetConfig {
+    /// Image file to serve
+    pub path: PathBuf,
 }
 
 /// Authentication configuration
-#[derive(Debug, Clone, Default, Deserialize)]
+#[derive(Debug, Clone, Deserialize)]
 pub struct AuthConfig {
     /// Whether authentication is enabled for uploads
     #[serde(default)]
@@ -65,6 +82,43 @@ pub struct AuthConfig {
     /// Paths that are always public (bypass auth)
     #[serde(default)]
     pub public_paths: Vec<String>,
+
+    /// Keys for signing URLs of private media (HMAC-SHA256)
+    ///
+    /// The first key signs, all of them verify, so a key is rotated by
+    /// putting the new one first. Private uploads need at least one key.
+    #[serde(default)]
+    pub url_signing_keys: Vec<String>,
+
+    /// Lifetime of the signed URLs returned by uploads (seconds)
+    #[serde(default = "default_signed_url_ttl")]
+    pub signed_url_ttl_seconds: u64,
+
+    /// Longest lifetime the admin API signs URLs for (seconds)
+    #[serde(default = "default_max_signed_url_ttl")]
+    pub max_signed_url_ttl_seconds: u64,
+}
+
+fn default_signed_url_ttl() -> u64 {
+    3600 // 1 hour
+}
+
+fn default_max_signed_url_ttl() -> u64 {
+    7 * 24 * 3600 // 1 week
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            api_keys: Vec::new(),
+            protected_paths: Vec::new(),
+            public_paths: Vec::new(),
+            url_signing_keys: Vec::new(),
+            signed_url_ttl_seconds: default_signed_url_ttl(),
+            max_signed_url_ttl_seconds: default_max_signed_url_ttl(),
+        }
+    }
 }
 
 /// Server configuration
@@ -88,6 +142,20 @@ pub struct ServerConfig {
     pub cache_max_age: u64,
     /// Cleanup interval for expired sessions in seconds
     pub cleanup_interval_seconds: u64,
+    /// Memory for popular media files served from RAM, in MB (0 disables)
+    #[serde(default = "default_media_cache_mb")]
+    pub media_cache_mb: u64,
+    /// How often view counts collected in memory are written to the database
+    #[serde(default = "default_access_flush_interval_seconds")]
+    pub access_flush_interval_seconds: u64,
+}
+
+fn default_media_cache_mb() -> u64 {
+    64
+}
+
+fn default_access_flush_interval_seconds() -> u64 {
+    30
 }
 
 /// Storage configuration
@@ -101,6 +169,9 @@ pub struct StorageConfig {
     pub optimized_dir: String,
     /// Directory for temporary uploads (relative to data_dir)
     pub temp_dir: String,
+    /// Directory for cached image variants (relative to data_dir)
+    #[serde(default = "default_variants_dir")]
+    pub variants_dir: String,
     /// Number of directory nesting levels for file storage (0-4)
     /// Each level uses 2 hex characters from UUID.
     /// - 0: files stored flat (originals/{uuid}.ext)
@@ -119,6 +190,10 @@ fn default_directory_levels() -> u8 {
     2
 }
 
+fn default_variants_dir() -> String {
+    "variants".to_string()
+}
+
 impl StorageConfig {
     /// Get the full path to the originals directory
     pub fn originals_path(&self) -> PathBuf {
@@ -135,6 +210,10 @@ impl StorageConfig {
         self.data_dir.join(&self.temp_dir)
     }
 
+    /// Get the full path to the variants cache directory
+    pub fn variants_path(&self) -> PathBuf {
+        self.data_dir.join(&self.variants_dir)
+    }
 }
 
 /// Upload configuration
@@ -147,17 +226,35 @@ pub struct UploadConfig {
     /// Chunk size for chunked uploads (bytes)
     pub chunk_size: u64,
     /// Allowed MIME types for images
+    ///
+    /// Formats whose decoder is not compiled in are rejected regardless.
     pub allowed_image_types: Vec<String>,
     /// Allowed MIME types for videos
     pub allowed_video_types: Vec<String>,
     /// Upload session timeout in seconds
     pub upload_session_timeout: u64,
+    /// Process completed chunked uploads in the background
+    ///
+    /// `complete` then returns `202 Accepted` right away and the result is
+    /// polled from the session status.
+    #[serde(default)]
+    pub async_processing: bool,
+    /// Number of background workers draining the processing queue
+    #[serde(default = "default_processing_workers")]
+    pub processing_workers: usize,
+}
+
+fn default_processing_workers() -> usize {
+    2
 }
 
 impl UploadConfig {
     /// Check if a MIME type is allowed for images
     pub fn is_allowed_image_type(&self, mime_type: &str) -> bool {
-        self.allowed_image_types.iter().any(|t| t == mime_type)
+        let mime_type = canonical_mime(mime_type);
+        self.allowed_image_types
+            .iter()
+            .any(|t| canonical_mime(t) == mime_type)
     }
 
     /// Check if a MIME type is allowed for videos
@@ -174,37 +271,226 @@ impl UploadConfig {
 /// Image/video processing configuration
 #[derive(Debug, Clone, Deserialize)]
 pub struct ProcessingConfig {
-    /// Output format for optimized images (webp, jpeg, png)
+    /// Output format for optimized images (webp, jpeg, png, avif)
+    ///
+    /// This is the default rendition, served unless the client's `Accept`
+    /// header asks for one of `output_formats`.
     pub output_format: String,
+    /// Additional formats stored for every image, in order of preference
+    ///
+    /// Served from the same `/m/{id}` URL to clients that list them in
+    /// their `Accept` header.
+    #[serde(default)]
+    pub output_formats: Vec<String>,
     /// Quality for lossy formats (0-100)
     pub output_quality: u8,
+    /// Encode WebP losslessly instead of with `output_quality`
+    ///
+    /// Applies to the optimized file, renditions and variants without an
+    /// explicit `q`.
+    #[serde(default)]
+    pub webp_lossless: bool,
     /// Maximum image dimension (width or height)
     pub max_image_dimension: u32,
+    /// Largest upload width accepted for decoding, before any resizing
+    #[serde(default = "default_max_decode_dimension")]
+    pub max_decode_width: u32,
+    /// Largest upload height accepted for decoding, before any resizing
+    #[serde(default = "default_max_decode_dimension")]
+    pub max_decode_height: u32,
+    /// Maximum memory in bytes a single decode may allocate
+    ///
+    /// Bounds decompression bombs: a few KB of PNG can declare gigabytes of
+    /// pixels. For animations this covers all frames together.
+    #[serde(default = "default_max_decode_alloc")]
+    pub max_decode_alloc: u64,
     /// Whether to keep original files
     pub keep_originals: bool,
-    /// Whether to strip EXIF data
+    /// Whether to strip EXIF data (used when `metadata_policy` is not set)
     pub strip_exif: bool,
+    /// Which metadata survives in stored originals
+    ///
+    /// Defaults to `strip_all` or `keep_all` following `strip_exif`.
+    #[serde(default)]
+    pub metadata_policy: Option<MetadataPolicy>,
+    /// Keep animated GIF/WebP uploads animated
+    ///
+    /// When disabled, animations are stored as a still of their first frame.
+    #[serde(default = "default_preserve_animation")]
+    pub preserve_animation: bool,
+    /// Named variant presets, pre-generated at upload time
+    /// (`[processing.variants.<name>]`)
+    #[serde(default)]
+    pub variants: HashMap<String, VariantPreset>,
+    /// Maximum number of images decoded at the same time (bounds memory use)
+    #[serde(default = "default_max_concurrent_decodes")]
+    pub max_concurrent_decodes: usize,
+    /// Maximum number of images waiting for a decode slot
+    ///
+    /// Further uploads and variant renders are refused with 503 until the
+    /// queue drains.
+    #[serde(default = "default_max_queued_decodes")]
+    pub max_queued_decodes: usize,
+    /// ffmpeg binary used to extract a poster frame from uploaded videos
+    ///
+    /// Videos are stored without a poster when unset.
+    #[serde(default)]
+    pub ffmpeg_path: Option<String>,
+}
+
+fn default_max_decode_dimension() -> u32 {
+    16384
+}
+
+fn default_max_decode_alloc() -> u64 {
+    512 * 1024 * 1024
+}
+
+fn default_preserve_animation() -> bool {
+    true
+}
+
+fn default_max_queued_decodes() -> usize {
+    32
+}
+
+fn default_max_concurrent_decodes() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+}
+
+/// Metadata kept when an uploaded image is stored
+///
+/// Levels are cumulative: each one keeps everything the previous one does.
+/// EXIF orientation is always applied to the pixels first, so no level
+/// needs the Orientation tag except `keep_all`, which stores the upload
+/// byte for byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataPolicy {
+    /// Re-encode without any metadata
+    StripAll,
+    /// Keep the embedded ICC color profile
+    KeepColorProfile,
+    /// Also keep the EXIF Artist and Copyright tags
+    KeepCopyright,
+    /// Store the upload untouched, including GPS and camera data
+    KeepAll,
+}
+
+/// Named variant preset
+///
+/// Fields mirror the `/m/{id}` variant query parameters and are normalized
+/// the same way, so a preset and the equivalent query share one cached file.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct VariantPreset {
+    /// Target width in pixels
+    #[serde(default)]
+    pub width: Option<u32>,
+    /// Target height in pixels
+    #[serde(default)]
+    pub height: Option<u32>,
+    /// Fit mode: contain (default), cover, fill
+    #[serde(default)]
+    pub fit: Option<String>,
+    /// Crop focus for cover: center (default), auto, or `x,y` fractions
+    #[serde(default)]
+    pub focus: Option<String>,
+    /// Output format (defaults to output_format)
+    #[serde(default)]
+    pub format: Option<String>,
+    /// Quality for lossy formats (defaults to output_quality)
+    #[serde(default)]
+    pub quality: Option<u8>,
+}
+
+impl VariantPreset {
+    /// Convert to the equivalent variant query
+    pub fn to_query(&self) -> VariantQuery {
+        VariantQuery {
+            w: self.width,
+            h: self.height,
+            fit: self.fit.clone(),
+            focus: self.focus.clone(),
+            fmt: self.format.clone(),
+            q: self.quality,
+        }
+    }
 }
 
 impl ProcessingConfig {
     /// Get the output MIME type based on format
     pub fn output_mime_type(&self) -> &'static str {
-        match self.output_format.as_str() {
-            "webp" => "image/webp",
-            "jpeg" | "jpg" => "image/jpeg",
-            "png" => "image/png",
-            _ => "image/webp",
-        }
+        self.default_output_format().mime_type()
     }
 
     /// Get the file extension for output format
     pub fn output_extension(&self) -> &'static str {
-        match self.output_format.as_str() {
-            "webp" => "webp",
-            "jpeg" | "jpg" => "jpg",
-            "png" => "png",
-            _ => "webp",
+        self.default_output_format().extension()
+    }
+
+    /// Get the effective metadata policy
+    pub fn metadata_policy(&self) -> MetadataPolicy {
+        self.metadata_policy.unwrap_or(if self.strip_exif {
+            MetadataPolicy::StripAll
+        } else {
+            MetadataPolicy::KeepAll
+        })
+    }
+
+    /// Get the default output format for optimized images and variants
+    pub fn default_output_format(&self) -> OutputFormat {
+        OutputFormat::parse(&self.output_format).unwrap_or(OutputFormat::WebP)
+    }
+
+    /// Get the additional rendition formats, without duplicates or the
+    /// default format
+    pub fn alternate_formats(&self) -> Vec<OutputFormat> {
+        let default = self.default_output_format();
+        let mut formats: Vec<OutputFormat> = Vec::new();
+
+        for format in self
+            .output_formats
+            .iter()
+            .filter_map(|f| OutputFormat::parse(f))
+        {
+            if format != default && !formats.contains(&format) {
+                formats.push(format);
+            }
+        }
+
+        formats
+    }
+
+    /// Get the encoder quality for a format, `None` for lossless output
+    pub fn quality_for(&self, format: OutputFormat) -> Option<u8> {
+        format.encoder_quality(self.output_quality, self.webp_lossless)
+    }
+
+    /// Normalize variant query parameters using configured defaults and limits
+    ///
+    /// An explicit `q` always wins; without one, the quality follows
+    /// [`Self::quality_for`] (so WebP stays lossless if configured).
+    pub fn variant_spec(&self, query: &VariantQuery) -> crate::error::Result<VariantSpec> {
+        let mut spec = query.normalize(
+            self.default_output_format(),
+            self.output_quality,
+            self.max_image_dimension,
+        )?;
+        if query.q.is_none() {
+            spec.quality = self.quality_for(spec.format);
         }
+        Ok(spec)
+    }
+
+    /// Resolve a named preset into a normalized variant spec
+    ///
+    /// Returns `None` if no preset with that name is configured.
+    pub fn preset_spec(&self, name: &str) -> Option<crate::error::Result<VariantSpec>> {
+        self.variants
+            .get(name)
+            .map(|preset| self.variant_spec(&preset.to_query()))
     }
 }
 
@@ -244,6 +530,23 @@ pub struct EvmNetworkConfig {
     /// Fallback RPC URL (optional)
     #[serde(default)]
     pub fallback_rpc_url: Option<String>,
+    /// Asset shown for tokens locked with defaults, light theme
+    /// (default: the bundled `default`)
+    #[serde(default)]
+    pub default_image_light: Option<String>,
+    /// Asset shown for tokens locked with defaults, dark theme
+    /// (default: the light theme asset)
+    #[serde(default)]
+    pub default_image_dark: Option<String>,
+}
+
+impl EvmNetworkConfig {
+    /// Asset names of the light and dark default token images
+    pub fn default_images(&self) -> (&str, &str) {
+        let light = self.default_image_light.as_deref().unwrap_or(DEFAULT_ASSET);
+        let dark = self.default_image_dark.as_deref().unwrap_or(light);
+        (light, dark)
+    }
 }
 
 /// RexPump metadata feature configuration
@@ -261,6 +564,25 @@ pub struct RexPumpConfig {
     /// Networks configuration (key is network name)
     #[serde(default)]
     pub networks: HashMap<String, EvmNetworkConfig>,
+    /// What to do when an uploaded token image resembles a locked token's image
+    #[serde(default)]
+    pub near_duplicate_policy: NearDuplicatePolicy,
+    /// Largest perceptual hash distance (bits out of 64) counted as a near-duplicate
+    #[serde(default = "default_near_duplicate_max_distance")]
+    pub near_duplicate_max_distance: u32,
+}
+
+/// Handling of token images that resemble a locked token's image
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NearDuplicatePolicy {
+    /// No check
+    #[default]
+    Off,
+    /// Accept the image but record the match on the token's metadata
+    Flag,
+    /// Refuse the update with 409
+    Reject,
 }
 
 fn default_update_cooldown() -> u64 {
@@ -271,6 +593,10 @@ fn default_signature_max_age() -> u64 {
     300 // 5 minutes
 }
 
+fn default_near_duplicate_max_distance() -> u32 {
+    10
+}
+
 impl Default for RexPumpConfig {
     fn default() -> Self {
         Self {
@@ -278,6 +604,8 @@ impl Default for RexPumpConfig {
             update_cooldown_seconds: default_update_cooldown(),
             signature_max_age_seconds: default_signature_max_age(),
             networks: HashMap::new(),
+            near_duplicate_policy: NearDuplicatePolicy::default(),
+            near_duplicate_max_distance: default_near_duplicate_max_distance(),
         }
     }
 }
@@ -292,6 +620,12 @@ impl RexPumpConfig {
     pub fn is_chain_supported(&self, chain_id: u64) -> bool {
         self.get_network_by_chain_id(chain_id).is_some()
     }
+
+    /// Asset names of the light and dark default token images for a chain
+    pub fn default_images(&self, chain_id: u64) -> (&str, &str) {
+        self.get_network_by_chain_id(chain_id)
+            .map_or((DEFAULT_ASSET, DEFAULT_ASSET), |n| n.default_images())
+    }
 }
 
 impl Config {
@@ -342,14 +676,81 @@ impl Config {
             ));
         }
 
-        // Validate output format
-        let valid_formats = ["webp", "jpeg", "jpg", "png"];
+        if self.processing.max_decode_width == 0
+            || self.processing.max_decode_height == 0
+            || self.processing.max_decode_alloc == 0
+        {
+            return Err(ConfigError::ValidationError(
+                "max_decode_width, max_decode_height and max_decode_alloc must be greater than 0"
+                    .to_string(),
+            ));
+        }
+
+        if self.processing.max_concurrent_decodes == 0 {
+            return Err(ConfigError::ValidationError(
+                "max_concurrent_decodes must be at least 1".to_string(),
+            ));
+        }
+
+        // Validate output formats
+        let valid_formats = ["webp", "jpeg", "jpg", "png", "avif"];
         if !valid_formats.contains(&self.processing.output_format.as_str()) {
             return Err(ConfigError::ValidationError(format!(
                 "output_format must be one of: {:?}",
                 valid_formats
             )));
         }
+        for format in &self.processing.output_formats {
+            if !valid_formats.contains(&format.as_str()) {
+                return Err(ConfigError::ValidationError(format!(
+                    "output_formats entries must be one of: {:?}",
+                    valid_formats
+                )));
+            }
+        }
+
+        // Validate variant presets
+        for (name, preset) in &self.processing.variants {
+            let valid_name = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid_name {
+                return Err(ConfigError::ValidationError(format!(
+                    "variant preset name '{}' must contain only [A-Za-z0-9_-]",
+                    name
+                )));
+            }
+
+            if preset.width.is_none() && preset.height.is_none() {
+                return Err(ConfigError::ValidationError(format!(
+                    "variant preset '{}' must set width and/or height",
+                    name
+                )));
+            }
+
+            self.processing
+                .variant_spec(&preset.to_query())
+                .map_err(|e| {
+                    ConfigError::ValidationError(format!("variant preset '{}': {}", name, e))
+                })?;
+        }
+
+        // Stored images are at most max_image_dimension a side, so every
+        // WebP file written must fit libwebp's limit
+        let writes_webp = self.processing.default_output_format() == OutputFormat::WebP
+            || self.processing.alternate_formats().contains(&OutputFormat::WebP)
+            || self.processing.variants.values().any(|preset| {
+                self.processing
+                    .variant_spec(&preset.to_query())
+                    .is_ok_and(|spec| spec.format == OutputFormat::WebP)
+            });
+        if writes_webp && self.processing.max_image_dimension > WEBP_MAX_DIMENSION {
+            return Err(ConfigError::ValidationError(format!(
+                "max_image_dimension must be at most {} when WebP is an output format",
+                WEBP_MAX_DIMENSION
+            )));
+        }
 
         // Validate chunk size
         if self.upload.chunk_size < 1024 {
@@ -358,6 +759,24 @@ impl Config {
             ));
         }
 
+        if self.rexpump.near_duplicate_max_distance > 64 {
+            return Err(ConfigError::ValidationError(
+                "near_duplicate_max_distance must be at most 64".to_string(),
+            ));
+        }
+
+        if self.upload.processing_workers == 0 {
+            return Err(ConfigError::ValidationError(
+                "processing_workers must be at least 1".to_string(),
+            ));
+        }
+
+        if self.server.access_flush_interval_seconds == 0 {
+            return Err(ConfigError::ValidationError(
+                "access_flush_interval_seconds must be at least 1".to_string(),
+            ));
+        }
+
         // Validate that max_chunked_upload_size >= max_simple_upload_size
         if self.upload.max_chunked_upload_size < self.upload.max_simple_upload_size {
             return Err(ConfigError::ValidationError(
@@ -372,6 +791,47 @@ impl Config {
             ));
         }
 
+        // Validate URL signing
+        if self.auth.url_signing_keys.iter().any(|k| k.len() < 32) {
+            return Err(ConfigError::ValidationError(
+                "url_signing_keys must be at least 32 characters long".to_string(),
+            ));
+        }
+        if self.auth.signed_url_ttl_seconds == 0
+            || self.auth.signed_url_ttl_seconds > self.auth.max_signed_url_ttl_seconds
+        {
+            return Err(ConfigError::ValidationError(
+                "signed_url_ttl_seconds must be between 1 and max_signed_url_ttl_seconds"
+                    .to_string(),
+            ));
+        }
+
+        // Validate built-in asset names and references to them
+        for name in self.assets.keys() {
+            let valid_name = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+                && Uuid::parse_str(name).is_err();
+            if !valid_name {
+                return Err(ConfigError::ValidationError(format!(
+                    "asset name '{}' must contain only [A-Za-z0-9_-] and not be a UUID",
+                    name
+                )));
+            }
+        }
+        for (network, config) in &self.rexpump.networks {
+            let (light, dark) = config.default_images();
+            for name in [light, dark] {
+                if name != DEFAULT_ASSET && !self.assets.contains_key(name) {
+                    return Err(ConfigError::ValidationError(format!(
+                        "network '{}' refers to unknown asset '{}'",
+                        network, name
+                    )));
+                }
+            }
+        }
+
         // Validate directory_levels (0-4)
         if self.storage.directory_levels > 4 {
             return Err(ConfigError::ValidationError(
@@ -394,6 +854,7 @@ mod tests {
             originals_dir: "originals".to_string(),
             optimized_dir: "optimized".to_string(),
             temp_dir: "temp".to_string(),
+            variants_dir: "variants".to_string(),
             directory_levels: 2,
             database_file: String::new(),
         };
@@ -401,6 +862,7 @@ mod tests {
         assert_eq!(storage.originals_path(), PathBuf::from("/data/originals"));
         assert_eq!(storage.optimized_path(), PathBuf::from("/data/optimized"));
         assert_eq!(storage.temp_path(), PathBuf::from("/data/temp"));
+        assert_eq!(storage.variants_path(), PathBuf::from("/data/variants"));
     }
 
     #[test]
@@ -412,14 +874,167 @@ mod tests {
             allowed_image_types: vec!["image/jpeg".to_string(), "image/png".to_string()],
             allowed_video_types: vec!["video/mp4".to_string()],
             upload_session_timeout: 3600,
+            async_processing: false,
+            processing_workers: 2,
         };
 
         assert!(upload.is_allowed_image_type("image/jpeg"));
         assert!(!upload.is_allowed_image_type("image/gif"));
+        assert!(upload.is_allowed_image_type("image/jpg"));
         assert!(upload.is_allowed_video_type("video/mp4"));
         assert!(upload.is_allowed_type("image/jpeg"));
         assert!(upload.is_allowed_type("video/mp4"));
         assert!(!upload.is_allowed_type("text/plain"));
     }
-}
 
+    #[test]
+    fn test_preset_spec() {
+        let mut processing = ProcessingConfig {
+            output_format: "webp".to_string(),
+            output_formats: Vec::new(),
+            output_quality: 80,
+            webp_lossless: false,
+            max_image_dimension: 1024,
+            max_decode_width: 16384,
+            max_decode_height: 16384,
+            max_decode_alloc: 512 * 1024 * 1024,
+            keep_originals: true,
+            strip_exif: true,
+            metadata_policy: None,
+            preserve_animation: true,
+            variants: HashMap::new(),
+            max_concurrent_decodes: 2,
+            max_queued_decodes: 16,
+            ffmpeg_path: None,
+        };
+        processing.variants.insert(
+            "card".to_string(),
+            VariantPreset {
+                width: Some(600),
+                height: Some(315),
+                fit: Some("cover".to_string()),
+                focus: Some("auto".to_string()),
+                format: Some("jpg".to_string()),
+                quality: None,
+            },
+        );
+        processing.variants.insert(
+            "huge".to_string(),
+            VariantPreset {
+                width: Some(4096),
+                ..Default::default()
+            },
+        );
+
+        let spec = processing.preset_spec("card").unwrap().unwrap();
+        assert_eq!(spec.cache_key(), "600x315-cover-auto-q80");
+        assert_eq!(spec.format, OutputFormat::Jpeg);
+
+        assert!(processing.preset_spec("huge").unwrap().is_err());
+        assert!(processing.preset_spec("missing").is_none());
+    }
+
+    #[test]
+    fn test_webp_lossless_variant_spec() {
+        let mut processing = ProcessingConfig {
+            output_format: "webp".to_string(),
+            output_formats: Vec::new(),
+            output_quality: 80,
+            webp_lossless: false,
+            max_image_dimension: 1024,
+            max_decode_width: 16384,
+            max_decode_height: 16384,
+            max_decode_alloc: 512 * 1024 * 1024,
+            keep_originals: true,
+            strip_exif: true,
+            metadata_policy: None,
+            preserve_animation: true,
+            variants: HashMap::new(),
+            max_concurrent_decodes: 2,
+            max_queued_decodes: 16,
+            ffmpeg_path: None,
+        };
+        let query = VariantQuery {
+            w: Some(64),
+            ..Default::default()
+        };
+
+        assert_eq!(processing.quality_for(OutputFormat::WebP), Some(80));
+        assert_eq!(processing.quality_for(OutputFormat::Png), None);
+        assert_eq!(
+            processing.variant_spec(&query).unwrap().cache_key(),
+            "64xauto-contain-q80"
+        );
+
+        processing.webp_lossless = true;
+        assert_eq!(processing.quality_for(OutputFormat::WebP), None);
+        assert_eq!(processing.quality_for(OutputFormat::Jpeg), Some(80));
+        assert_eq!(
+            processing.variant_spec(&query).unwrap().cache_key(),
+            "64xauto-contain"
+        );
+
+        // An explicit q overrides lossless mode
+        let query = VariantQuery {
+            q: Some(60),
+            ..query
+        };
+        assert_eq!(processing.variant_spec(&query).unwrap().quality, Some(60));
+    }
+
+    #[test]
+    fn test_alternate_formats() {
+        let processing = ProcessingConfig {
+            output_format: "jpeg".to_string(),
+            output_formats: vec![
+                "avif".to_string(),
+                "jpg".to_string(),
+                "webp".to_string(),
+                "avif".to_string(),
+            ],
+            output_quality: 80,
+            webp_lossless: false,
+            max_image_dimension: 1024,
+            max_decode_width: 16384,
+            max_decode_height: 16384,
+            max_decode_alloc: 512 * 1024 * 1024,
+            keep_originals: true,
+            strip_exif: true,
+            metadata_policy: None,
+            preserve_animation: true,
+            variants: HashMap::new(),
+            max_concurrent_decodes: 2,
+            max_queued_decodes: 16,
+            ffmpeg_path: None,
+        };
+
+        assert_eq!(processing.output_mime_type(), "image/jpeg");
+        assert_eq!(
+            processing.alternate_formats(),
+            vec![OutputFormat::Avif, OutputFormat::WebP]
+        );
+    }
+
+    #[test]
+    fn test_default_images() {
+        let network = |light: Option<&str>, dark: Option<&str>| EvmNetworkConfig {
+            name: "test".to_string(),
+            chain_id: 1,
+            rpc_url: "http://localhost:8545".to_string(),
+            fallback_rpc_url: None,
+            default_image_light: light.map(String::from),
+            default_image_dark: dark.map(String::from),
+        };
+
+        assert_eq!(network(None, None).default_images(), ("default", "default"));
+        assert_eq!(network(Some("brand"), None).default_images(), ("brand", "brand"));
+        assert_eq!(network(None, Some("night")).default_images(), ("default", "night"));
+
+        let rexpump = RexPumpConfig {
+            networks: HashMap::from([("test".to_string(), network(Some("a"), Some("b")))]),
+            ..Default::default()
+        };
+        assert_eq!(rexpump.default_images(1), ("a", "b"));
+        assert_eq!(rexpump.default_images(2), ("default", "default"));
+    }
+}
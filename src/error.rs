@@ -6,7 +6,8 @@
 //! # Error Categories
 //!
 //! - **Client errors (4xx)**: Invalid input, validation failures, not found
-//! - **Server errors (5xx)**: Internal failures, I/O errors, processing errors
+//! - **Server errors (5xx)**: Internal failures, I/O errors, processing errors,
+//!   overload (503 with `Retry-After`)
 //!
 //! # Example
 //!
@@ -21,7 +22,7 @@
 //! }
 //! ```
 
-use axum::http::StatusCode;
+use axum::http::{header, StatusCode};
 use axum::response::{IntoResponse, Response};
 use axum::Json;
 use serde::Serialize;
@@ -55,6 +56,10 @@ pub enum AppError {
     #[error("Payload too large: {0}")]
     PayloadTooLarge(String),
 
+    /// Image dimensions or decoded size exceed the processing limits
+    #[error("Image too large: {0}")]
+    ImageTooLarge(String),
+
     /// Rate limit exceeded
     #[error("Rate limit exceeded: {0}")]
     RateLimitExceeded(String),
@@ -79,10 +84,23 @@ pub enum AppError {
     #[error("Invalid signature: {0}")]
     InvalidSignature(String),
 
-    /// Not authorized to perform action (RexPump)
+    /// Not authorized to perform action (RexPump) or to fetch private media
     #[error("Not authorized: {0}")]
     NotAuthorized(String),
 
+    /// Image resembles a locked token's image (RexPump)
+    #[error("Near-duplicate image: {0}")]
+    NearDuplicate(String),
+
+    /// A conditional request header (`If-Match`, `If-Unmodified-Since`) did
+    /// not hold
+    #[error("Precondition failed: {0}")]
+    PreconditionFailed(String),
+
+    /// Request conflicts with work already in progress
+    #[error("Conflict: {0}")]
+    Conflict(String),
+
     // -------------------------------------------------------------------------
     // Server Errors (5xx)
     // -------------------------------------------------------------------------
@@ -105,6 +123,14 @@ pub enum AppError {
     /// Configuration error
     #[error("Configuration error: {0}")]
     Config(String),
+
+    /// Server temporarily overloaded; the client should retry later
+    #[error("Service unavailable: {message}")]
+    ServiceUnavailable {
+        message: String,
+        /// Seconds to wait before retrying (sent as `Retry-After`)
+        retry_after_secs: u64,
+    },
 }
 
 impl AppError {
@@ -122,6 +148,16 @@ impl AppError {
         Self::NotFound(msg.into())
     }
 
+    /// Create a precondition failed error
+    pub fn precondition_failed<S: Into<String>>(msg: S) -> Self {
+        Self::PreconditionFailed(msg.into())
+    }
+
+    /// Create a conflict error
+    pub fn conflict<S: Into<String>>(msg: S) -> Self {
+        Self::Conflict(msg.into())
+    }
+
     /// Create an unsupported media type error
     pub fn unsupported_media_type<S: Into<String>>(msg: S) -> Self {
         Self::UnsupportedMediaType(msg.into())
@@ -132,6 +168,11 @@ impl AppError {
         Self::PayloadTooLarge(msg.into())
     }
 
+    /// Create an image too large error
+    pub fn image_too_large<S: Into<String>>(msg: S) -> Self {
+        Self::ImageTooLarge(msg.into())
+    }
+
     /// Create a rate limit exceeded error
     pub fn rate_limit_exceeded<S: Into<String>>(msg: S) -> Self {
         Self::RateLimitExceeded(msg.into())
@@ -157,6 +198,14 @@ impl AppError {
         Self::Config(msg.into())
     }
 
+    /// Create a service unavailable error asking to retry after some seconds
+    pub fn service_unavailable<S: Into<String>>(msg: S, retry_after_secs: u64) -> Self {
+        Self::ServiceUnavailable {
+            message: msg.into(),
+            retry_after_secs,
+        }
+    }
+
     /// Get the HTTP status code for this error
     pub fn status_code(&self) -> StatusCode {
         match self {
@@ -165,6 +214,7 @@ impl AppError {
             Self::NotFound(_) => StatusCode::NOT_FOUND,
             Self::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
             Self::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::ImageTooLarge(_) => StatusCode::UNPROCESSABLE_ENTITY,
             Self::RateLimitExceeded(_) => StatusCode::TOO_MANY_REQUESTS,
             Self::UploadSessionError(_) => StatusCode::BAD_REQUEST,
             Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
@@ -172,6 +222,9 @@ impl AppError {
             Self::UpdateCooldown(_) => StatusCode::TOO_MANY_REQUESTS,
             Self::InvalidSignature(_) => StatusCode::BAD_REQUEST,
             Self::NotAuthorized(_) => StatusCode::FORBIDDEN,
+            Self::NearDuplicate(_) => StatusCode::CONFLICT,
+            Self::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            Self::Conflict(_) => StatusCode::CONFLICT,
 
             // 5xx Server Errors
             Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
@@ -179,6 +232,7 @@ impl AppError {
             Self::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::ImageProcessing(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::Config(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::ServiceUnavailable { .. } => StatusCode::SERVICE_UNAVAILABLE,
         }
     }
 
@@ -239,6 +293,7 @@ impl IntoResponse for AppError {
             Self::NotFound(_) => "not_found",
             Self::UnsupportedMediaType(_) => "unsupported_media_type",
             Self::PayloadTooLarge(_) => "payload_too_large",
+            Self::ImageTooLarge(_) => "image_too_large",
             Self::RateLimitExceeded(_) => "rate_limit_exceeded",
             Self::UploadSessionError(_) => "upload_session_error",
             Self::Unauthorized(_) => "unauthorized",
@@ -246,23 +301,39 @@ impl IntoResponse for AppError {
             Self::UpdateCooldown(_) => "update_cooldown",
             Self::InvalidSignature(_) => "invalid_signature",
             Self::NotAuthorized(_) => "not_authorized",
+            Self::NearDuplicate(_) => "near_duplicate",
+            Self::PreconditionFailed(_) => "precondition_failed",
+            Self::Conflict(_) => "conflict",
             Self::Internal(_) => "internal_error",
             Self::Io(_) => "io_error",
             Self::Database(_) => "database_error",
             Self::ImageProcessing(_) => "image_processing_error",
             Self::Config(_) => "config_error",
+            Self::ServiceUnavailable { .. } => "service_unavailable",
         };
 
         // For server errors, don't expose internal details to clients
-        let message = if self.is_server_error() {
-            "An internal error occurred. Please try again later.".to_string()
-        } else {
-            self.to_string()
+        let message = match &self {
+            Self::ServiceUnavailable { message, .. } => message.clone(),
+            _ if self.is_server_error() => {
+                "An internal error occurred. Please try again later.".to_string()
+            }
+            _ => self.to_string(),
         };
 
         let body = ErrorResponse::new(error_type, message).with_status(status);
 
-        (status, Json(body)).into_response()
+        match self {
+            Self::ServiceUnavailable {
+                retry_after_secs, ..
+            } => (
+                status,
+                [(header::RETRY_AFTER, retry_after_secs.to_string())],
+                Json(body),
+            )
+                .into_response(),
+            _ => (status, Json(body)).into_response(),
+        }
     }
 }
 
@@ -272,7 +343,10 @@ impl IntoResponse for AppError {
 
 impl From<image::ImageError> for AppError {
     fn from(err: image::ImageError) -> Self {
-        Self::ImageProcessing(err.to_string())
+        match err {
+            image::ImageError::Limits(e) => Self::ImageTooLarge(e.to_string()),
+            err => Self::ImageProcessing(err.to_string()),
+        }
     }
 }
 
@@ -306,6 +380,30 @@ mod tests {
             AppError::internal("test").status_code(),
             StatusCode::INTERNAL_SERVER_ERROR
         );
+        assert_eq!(
+            AppError::image_too_large("test").status_code(),
+            StatusCode::UNPROCESSABLE_ENTITY
+        );
+        assert_eq!(
+            AppError::NearDuplicate("test".into()).status_code(),
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            AppError::precondition_failed("test").status_code(),
+            StatusCode::PRECONDITION_FAILED
+        );
+        assert_eq!(
+            AppError::conflict("test").status_code(),
+            StatusCode::CONFLICT
+        );
+    }
+
+    #[test]
+    fn test_service_unavailable_response() {
+        let response = AppError::service_unavailable("busy", 7).into_response();
+
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "7");
     }
 
     #[test]
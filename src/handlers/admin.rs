@@ -5,8 +5,13 @@
 //!
 //! ## Endpoints
 //!
-//! - `DELETE /admin/media/{id}` - Delete a media file
+//! - `DELETE /admin/media/{id}` - Release a reference (or `?force=true` to purge)
 //! - `GET /admin/media/{id}` - Get detailed media info
+//! - `GET /admin/media/{id}/similar` - Find visually similar media
+//! - `PUT /admin/media/{id}/focal-point` - Set the point cover crops center on
+//! - `POST /admin/media/{id}/signed-url` - Sign URLs of (private) media for a chosen TTL
+//! - `POST /admin/reprocess` - Re-encode all images into the configured output format
+//! - `GET /admin/reprocess` - Progress of the reprocess job
 //!
 //! ## Security
 //!
@@ -14,29 +19,38 @@
 //! exposed to the public internet.
 
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::StatusCode,
     routing::{delete, get},
     Json, Router,
 };
-use serde::Serialize;
-use tracing::{info, warn};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tracing::{error, info, warn};
 use uuid::Uuid;
 
 use crate::error::{AppError, Result};
-use crate::models::MediaInfoResponse;
-use crate::services::image_processor::ImageProcessor;
+use crate::handlers::pipeline::{delete_media_files, reprocess_media, set_focal_point};
+use crate::models::{
+    FocalPoint, Media, MediaInfoResponse, MediaType, ReprocessState, ReprocessStatus,
+    UploadResponse,
+};
 use crate::state::AppState;
 
 /// Delete a media file
 ///
 /// DELETE /admin/media/{id}
 ///
-/// Permanently removes a media file and all associated data.
-/// This is used for content moderation (e.g., removing illegal content).
+/// Deduplicated media can be shared by several uploads or tokens, so by
+/// default this drops one reference and removes the files only when the last
+/// one goes away. `?force=true` removes the media immediately regardless of
+/// references (e.g. removing illegal content).
 async fn delete_media(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
+    Query(query): Query<DeleteQuery>,
 ) -> Result<(StatusCode, Json<DeleteResponse>)> {
     // Get media record first to know file extensions
     let media = state
@@ -44,22 +58,34 @@ async fn delete_media(
         .get_media(id)?
         .ok_or_else(|| AppError::not_found(format!("Media not found: {}", id)))?;
 
-    // Delete files using output extension from config
-    let original_ext = ImageProcessor::mime_to_extension(&media.original_mime_type);
-    let optimized_ext = state.output_extension();
+    let deleted = if query.force {
+        state.db.delete_media(id)?
+    } else {
+        state.db.release_media(id)?.is_some()
+    };
 
-    if let Err(e) = state
-        .storage
-        .delete_media_files(id, original_ext, optimized_ext)
-        .await
-    {
-        warn!(id = %id, error = %e, "Failed to delete some media files");
+    if !deleted {
+        let remaining_refs = state.db.get_media_refs(id)?;
+        info!(id = %id, remaining_refs = remaining_refs, "Released media reference");
+
+        return Ok((
+            StatusCode::OK,
+            Json(DeleteResponse {
+                success: true,
+                message: format!(
+                    "Media {} is still referenced {} time(s), files kept",
+                    id, remaining_refs
+                ),
+                id,
+                deleted: false,
+                remaining_refs,
+            }),
+        ));
     }
 
-    // Delete database record
-    state.db.delete_media(id)?;
+    delete_media_files(&state, &media).await;
 
-    info!(id = %id, filename = %media.original_filename, "Deleted media");
+    info!(id = %id, filename = %media.original_filename, force = query.force, "Deleted media");
 
     Ok((
         StatusCode::OK,
@@ -67,16 +93,30 @@ async fn delete_media(
             success: true,
             message: format!("Media {} deleted successfully", id),
             id,
+            deleted: true,
+            remaining_refs: 0,
         }),
     ))
 }
 
+/// Query parameters for media deletion
+#[derive(Debug, Default, Deserialize)]
+pub struct DeleteQuery {
+    /// Delete even if other references remain
+    #[serde(default)]
+    pub force: bool,
+}
+
 /// Delete response
 #[derive(Debug, Serialize)]
 pub struct DeleteResponse {
     pub success: bool,
     pub message: String,
     pub id: Uuid,
+    /// Whether the media record and files were removed
+    pub deleted: bool,
+    /// References still held after this request
+    pub remaining_refs: u64,
 }
 
 /// Get detailed media information
@@ -93,7 +133,206 @@ async fn get_media_info(
         .get_media(id)?
         .ok_or_else(|| AppError::not_found(format!("Media not found: {}", id)))?;
 
-    Ok(Json(MediaInfoResponse::from_media(&media, state.base_url())))
+    Ok(Json(media_info(&state, &media)?))
+}
+
+/// Media info with reference count and views (stored plus not yet flushed)
+fn media_info(state: &AppState, media: &Media) -> Result<MediaInfoResponse> {
+    let mut access = state.db.get_media_access(media.id)?;
+    access.merge(&state.access_tracker.pending(media.id));
+
+    let refs = state.db.get_media_refs(media.id)?;
+    Ok(MediaInfoResponse::from_media(media, refs, &access, state.base_url()))
+}
+
+/// Set the focal point of an image
+///
+/// PUT /admin/media/{id}/focal-point
+///
+/// Overrides the detected focal point. Cached variants are dropped, so cover
+/// crops with `focus=auto` follow the new point.
+async fn update_focal_point(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(request): Json<FocalPointRequest>,
+) -> Result<Json<MediaInfoResponse>> {
+    if !FocalPoint::is_valid(request.x, request.y) {
+        return Err(AppError::validation("x and y must be between 0 and 1"));
+    }
+
+    let media = state
+        .db
+        .get_media(id)?
+        .ok_or_else(|| AppError::not_found(format!("Media not found: {}", id)))?;
+    if media.media_type != MediaType::Image {
+        return Err(AppError::validation("Focal points are only available for images"));
+    }
+
+    let media = set_focal_point(&state, id, FocalPoint::new(request.x, request.y))
+        .await?
+        .ok_or_else(|| AppError::not_found(format!("Media not found: {}", id)))?;
+
+    info!(id = %id, x = request.x, y = request.y, "Updated focal point");
+
+    Ok(Json(media_info(&state, &media)?))
+}
+
+/// Focal point update request (fractions of width and height)
+#[derive(Debug, Deserialize)]
+pub struct FocalPointRequest {
+    pub x: f32,
+    pub y: f32,
+}
+
+/// Sign URLs of a media item
+///
+/// POST /admin/media/{id}/signed-url
+///
+/// Returns the media URLs signed until `ttl_seconds` from now (default
+/// `auth.signed_url_ttl_seconds`, at most `auth.max_signed_url_ttl_seconds`).
+/// Public media is served without a signature, but can be signed all the same.
+async fn create_signed_url(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Json(request): Json<SignedUrlRequest>,
+) -> Result<Json<SignedUrlResponse>> {
+    let signer = &state.url_signer;
+    if !signer.is_enabled() {
+        return Err(AppError::validation(
+            "URL signing is not enabled (no auth.url_signing_keys configured)",
+        ));
+    }
+
+    let ttl = request.ttl_seconds.unwrap_or(signer.default_ttl());
+    if ttl == 0 || ttl > signer.max_ttl() {
+        return Err(AppError::validation(format!(
+            "ttl_seconds must be between 1 and {}",
+            signer.max_ttl()
+        )));
+    }
+
+    let media = state
+        .db
+        .get_media(id)?
+        .ok_or_else(|| AppError::not_found(format!("Media not found: {}", id)))?;
+
+    let signed = signer
+        .signed_query(id, ttl)
+        .ok_or_else(|| AppError::internal("Failed to sign URL"))?;
+    let urls = UploadResponse::from_media(&media, state.base_url(), state.keep_originals())
+        .with_variants(state.config.processing.variants.keys(), state.base_url())
+        .with_signature(&signed.query, signed.expires_at);
+
+    info!(id = %id, ttl = ttl, "Signed media URL");
+
+    Ok(Json(SignedUrlResponse {
+        id,
+        url: urls.url,
+        original_url: urls.original_url,
+        variants: urls.variants,
+        expires_at: signed.expires_at,
+    }))
+}
+
+/// Signed URL request
+#[derive(Debug, Deserialize)]
+pub struct SignedUrlRequest {
+    /// Lifetime of the URLs in seconds
+    #[serde(default)]
+    pub ttl_seconds: Option<u64>,
+}
+
+/// Signed URL response
+#[derive(Debug, Serialize)]
+pub struct SignedUrlResponse {
+    pub id: Uuid,
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_url: Option<String>,
+    /// Signed URLs of named variant presets
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub variants: BTreeMap<String, String>,
+    /// When the signature stops being accepted
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Find media that looks like the given media
+///
+/// GET /admin/media/{id}/similar?max_distance=10
+///
+/// Compares perceptual hashes, so re-encoded, resized or recompressed copies
+/// of the same picture are found even though their bytes differ. Matches are
+/// ordered by distance (number of differing hash bits, 0-64).
+async fn find_similar_media(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<SimilarQuery>,
+) -> Result<Json<SimilarResponse>> {
+    if query.max_distance > 64 {
+        return Err(AppError::validation("max_distance must be between 0 and 64"));
+    }
+
+    let media = state
+        .db
+        .get_media(id)?
+        .ok_or_else(|| AppError::not_found(format!("Media not found: {}", id)))?;
+    let hash = media.perceptual_hash.ok_or_else(|| {
+        AppError::validation(format!("Media {} has no perceptual hash", id))
+    })?;
+
+    let matches = state
+        .db
+        .find_similar(hash, query.max_distance)?
+        .into_iter()
+        .filter(|(other, _)| *other != id)
+        .map(|(other, distance)| SimilarMedia {
+            id: other,
+            distance,
+            url: format!("{}/m/{}", state.base_url(), other),
+        })
+        .collect();
+
+    Ok(Json(SimilarResponse {
+        id,
+        perceptual_hash: format!("{:016x}", hash),
+        max_distance: query.max_distance,
+        matches,
+    }))
+}
+
+/// Default hash distance for `GET /admin/media/{id}/similar`
+const DEFAULT_SIMILAR_DISTANCE: u32 = 10;
+
+fn default_similar_distance() -> u32 {
+    DEFAULT_SIMILAR_DISTANCE
+}
+
+/// Query parameters for similar media search
+#[derive(Debug, Deserialize)]
+pub struct SimilarQuery {
+    /// Largest hash distance still counted as similar (0-64)
+    #[serde(default = "default_similar_distance")]
+    pub max_distance: u32,
+}
+
+/// Similar media response
+#[derive(Debug, Serialize)]
+pub struct SimilarResponse {
+    pub id: Uuid,
+    /// Perceptual hash of the media (hex)
+    pub perceptual_hash: String,
+    pub max_distance: u32,
+    /// Closest matches first
+    pub matches: Vec<SimilarMedia>,
+}
+
+/// One match of a similar media search
+#[derive(Debug, Serialize)]
+pub struct SimilarMedia {
+    pub id: Uuid,
+    /// Differing hash bits (0 = same picture)
+    pub distance: u32,
+    pub url: String,
 }
 
 /// Get storage statistics
@@ -102,10 +341,13 @@ async fn get_media_info(
 async fn get_stats(State(state): State<AppState>) -> Result<Json<AdminStatsResponse>> {
     let storage_stats = state.storage.get_stats().await?;
     let media_count = state.db.get_media_count()?;
+    let formats = state.db.count_media_by_format()?;
 
     Ok(Json(AdminStatsResponse {
         media_count,
+        formats,
         storage: storage_stats,
+        processing: state.processing_pool.stats(),
     }))
 }
 
@@ -113,7 +355,101 @@ async fn get_stats(State(state): State<AppState>) -> Result<Json<AdminStatsRespo
 #[derive(Debug, Serialize)]
 pub struct AdminStatsResponse {
     pub media_count: u64,
+    /// Media count per stored (optimized) MIME type
+    pub formats: BTreeMap<String, u64>,
     pub storage: crate::services::storage::StorageStats,
+    /// Processing pool load (queue length, processing times)
+    pub processing: crate::services::ProcessingStats,
+}
+
+/// Start re-encoding the library into the configured output format
+///
+/// POST /admin/reprocess
+///
+/// Runs in the background; poll `GET /admin/reprocess` for progress. Media
+/// stays servable in its old format until its new file is written.
+async fn start_reprocess(
+    State(state): State<AppState>,
+) -> Result<(StatusCode, Json<ReprocessStatus>)> {
+    let status = {
+        let mut status = state.reprocess.lock().unwrap_or_else(|e| e.into_inner());
+        if status.is_running() {
+            return Err(AppError::conflict("A reprocess job is already running"));
+        }
+        *status = ReprocessStatus::started(state.output_mime_type());
+        status.clone()
+    };
+
+    info!(target_mime_type = %state.output_mime_type(), "Starting reprocess job");
+    tokio::spawn(run_reprocess(state));
+
+    Ok((StatusCode::ACCEPTED, Json(status)))
+}
+
+/// Get reprocess job progress
+///
+/// GET /admin/reprocess
+async fn get_reprocess_status(State(state): State<AppState>) -> Json<ReprocessStatus> {
+    let status = state.reprocess.lock().unwrap_or_else(|e| e.into_inner());
+    Json(status.clone())
+}
+
+/// Reprocess every media record, recording progress in the app state
+async fn run_reprocess(state: AppState) {
+    let ids = match state.db.list_media_ids() {
+        Ok(ids) => ids,
+        Err(e) => {
+            error!(error = %e, "Reprocess job failed to list media");
+            update_reprocess(&state, |status| {
+                status.state = ReprocessState::Failed;
+                status.error = Some(e.to_string());
+                status.finished_at = Some(Utc::now());
+            });
+            return;
+        }
+    };
+
+    update_reprocess(&state, |status| status.total = ids.len() as u64);
+
+    for id in ids {
+        let result = loop {
+            match reprocess_media(&state, id).await {
+                // Uploads keep the pool busy: wait for room instead of failing
+                Err(AppError::ServiceUnavailable {
+                    retry_after_secs, ..
+                }) => tokio::time::sleep(Duration::from_secs(retry_after_secs)).await,
+                result => break result,
+            }
+        };
+        if let Err(e) = &result {
+            warn!(id = %id, error = %e, "Failed to reprocess media");
+        }
+
+        update_reprocess(&state, |status| {
+            status.processed += 1;
+            match result {
+                Ok(true) => status.converted += 1,
+                Ok(false) => status.skipped += 1,
+                Err(_) => status.failed += 1,
+            }
+        });
+    }
+
+    update_reprocess(&state, |status| {
+        status.state = ReprocessState::Completed;
+        status.finished_at = Some(Utc::now());
+        info!(
+            converted = status.converted,
+            skipped = status.skipped,
+            failed = status.failed,
+            "Reprocess job completed"
+        );
+    });
+}
+
+fn update_reprocess(state: &AppState, update: impl FnOnce(&mut ReprocessStatus)) {
+    let mut status = state.reprocess.lock().unwrap_or_else(|e| e.into_inner());
+    update(&mut status);
 }
 
 /// Cleanup expired upload sessions
@@ -135,10 +471,16 @@ async fn cleanup_sessions(State(state): State<AppState>) -> Result<Json<CleanupR
         }
     }
 
-    // Also cleanup any orphaned temp directories
+    // Also cleanup any orphaned temp directories (queued uploads still need theirs)
+    let queued = state
+        .db
+        .list_processing_jobs()?
+        .into_iter()
+        .map(|job| job.session_id)
+        .collect();
     let orphaned = state
         .storage
-        .cleanup_expired_sessions(state.upload_session_timeout())
+        .cleanup_expired_sessions(state.upload_session_timeout(), &queued)
         .await
         .unwrap_or(0);
 
@@ -169,7 +511,14 @@ pub fn admin_routes() -> Router<AppState> {
     Router::new()
         .route("/media/{id}", delete(delete_media))
         .route("/media/{id}", get(get_media_info))
+        .route("/media/{id}/similar", get(find_similar_media))
+        .route("/media/{id}/focal-point", axum::routing::put(update_focal_point))
+        .route("/media/{id}/signed-url", axum::routing::post(create_signed_url))
         .route("/stats", get(get_stats))
         .route("/cleanup", axum::routing::post(cleanup_sessions))
+        .route(
+            "/reprocess",
+            axum::routing::post(start_reprocess).get(get_reprocess_status),
+        )
 }
 
@@ -0,0 +1,310 @@
+//! Conditional requests (RFC 9110, section 13).
+//!
+//! [`Validators`] describe the representation about to be sent: its strong
+//! `ETag` and, where known, when it was last modified. [`FileResponse`]
+//! evaluates the request's preconditions against them before sending
+//! anything, in the order of RFC 9110, section 13.2.2:
+//!
+//! 1. `If-Match`: `412` unless an entity tag matches (strong comparison)
+//! 2. otherwise `If-Unmodified-Since`: `412` if modified after the date
+//! 3. `If-None-Match`: `304` for `GET`/`HEAD` if an entity tag matches (weak
+//!    comparison), `412` for other methods
+//! 4. otherwise `If-Modified-Since` (`GET`/`HEAD` only): `304` unless
+//!    modified after the date
+//!
+//! Entity tag lists, `W/` prefixes, `*` and repeated header lines are
+//! understood. Dates that are not valid HTTP dates are ignored. `If-Range`
+//! is evaluated with the range (see [`super::range`]).
+//!
+//! [`FileResponse`]: super::range::FileResponse
+
+use axum::http::{header, HeaderMap, HeaderName, Method};
+use chrono::{DateTime, Utc};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Validators of a representation
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Validators<'a> {
+    /// Quoted strong ETag, e.g. `"abc123"`
+    pub etag: &'a str,
+    /// When the representation last changed (sent as `Last-Modified`)
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+/// Outcome of evaluating the preconditions of a request
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Precondition {
+    /// Send the representation (or the requested ranges)
+    Proceed,
+    /// Send `304 Not Modified`
+    NotModified,
+    /// Send `412 Precondition Failed`
+    Failed,
+}
+
+/// One member of an `If-Match` / `If-None-Match` list
+#[derive(Debug, PartialEq, Eq)]
+struct EntityTag<'a> {
+    weak: bool,
+    /// The tag without quotes
+    opaque: &'a str,
+}
+
+impl<'a> Validators<'a> {
+    /// Evaluate the preconditions of a request
+    pub fn evaluate(&self, method: &Method, headers: &HeaderMap) -> Precondition {
+        let safe = method == Method::GET || method == Method::HEAD;
+
+        if headers.contains_key(header::IF_MATCH) {
+            if !self.any_tag_matches(headers, header::IF_MATCH, true) {
+                return Precondition::Failed;
+            }
+        } else if let Some(since) = http_date(headers, header::IF_UNMODIFIED_SINCE) {
+            if self.modified_since(since) {
+                return Precondition::Failed;
+            }
+        }
+
+        if headers.contains_key(header::IF_NONE_MATCH) {
+            if self.any_tag_matches(headers, header::IF_NONE_MATCH, false) {
+                return if safe {
+                    Precondition::NotModified
+                } else {
+                    Precondition::Failed
+                };
+            }
+        } else if safe {
+            // Dates in the future are no valid validator and ignored
+            let since = http_date(headers, header::IF_MODIFIED_SINCE)
+                .filter(|since| *since <= Utc::now().timestamp());
+            if let Some(since) = since {
+                if self.last_modified.is_some() && !self.modified_since(since) {
+                    return Precondition::NotModified;
+                }
+            }
+        }
+
+        Precondition::Proceed
+    }
+
+    /// Whether an `If-Range` value still describes this representation
+    ///
+    /// Entity tags must match strongly; dates must equal `Last-Modified`.
+    pub fn matches_if_range(&self, value: &str) -> bool {
+        let value = value.trim();
+
+        if value.starts_with('"') || value.starts_with("W/") {
+            return entity_tags(value)
+                .first()
+                .is_some_and(|tag| self.tag_matches(tag, true));
+        }
+
+        match (parse_http_date(value), self.last_modified) {
+            (Some(date), Some(modified)) => date == modified.timestamp(),
+            _ => false,
+        }
+    }
+
+    /// `Last-Modified` header value
+    pub fn last_modified_header(&self) -> Option<String> {
+        let secs = u64::try_from(self.last_modified?.timestamp()).ok()?;
+        Some(httpdate::fmt_http_date(
+            UNIX_EPOCH + Duration::from_secs(secs),
+        ))
+    }
+
+    /// Whether any value of an `If-Match` / `If-None-Match` header matches
+    fn any_tag_matches(&self, headers: &HeaderMap, name: HeaderName, strong: bool) -> bool {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| {
+                value.trim() == "*"
+                    || entity_tags(value)
+                        .iter()
+                        .any(|tag| self.tag_matches(tag, strong))
+            })
+    }
+
+    /// Compare an entity tag with ours (which is always strong)
+    fn tag_matches(&self, tag: &EntityTag<'_>, strong: bool) -> bool {
+        (!strong || !tag.weak) && tag.opaque == self.etag.trim_matches('"')
+    }
+
+    /// Whether the representation changed after `since` (Unix seconds)
+    ///
+    /// Unknown modification times count as changed.
+    fn modified_since(&self, since: i64) -> bool {
+        self.last_modified
+            .is_none_or(|modified| modified.timestamp() > since)
+    }
+}
+
+/// Parse a comma-separated list of entity tags, skipping malformed members
+fn entity_tags(value: &str) -> Vec<EntityTag<'_>> {
+    let mut tags = Vec::new();
+    let mut rest = value;
+
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ',']);
+        if rest.is_empty() {
+            return tags;
+        }
+
+        let (weak, tail) = match rest.strip_prefix("W/") {
+            Some(tail) => (true, tail),
+            None => (false, rest),
+        };
+
+        let Some(tail) = tail.strip_prefix('"') else {
+            // Not a quoted tag: skip to the next member
+            rest = rest.split_once(',').map_or("", |(_, next)| next);
+            continue;
+        };
+        let Some(end) = tail.find('"') else {
+            return tags;
+        };
+
+        tags.push(EntityTag {
+            weak,
+            opaque: &tail[..end],
+        });
+        rest = &tail[end + 1..];
+    }
+}
+
+/// An HTTP date header as Unix seconds, if present and valid
+fn http_date(headers: &HeaderMap, name: HeaderName) -> Option<i64> {
+    parse_http_date(headers.get(name)?.to_str().ok()?)
+}
+
+/// Parse an HTTP date (IMF-fixdate, RFC 850 or asctime) to Unix seconds
+fn parse_http_date(value: &str) -> Option<i64> {
+    let time: SystemTime = httpdate::parse_http_date(value.trim()).ok()?;
+    let secs = time.duration_since(UNIX_EPOCH).ok()?.as_secs();
+    i64::try_from(secs).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    const MODIFIED: &str = "Wed, 21 Oct 2015 07:28:00 GMT";
+
+    fn validators() -> Validators<'static> {
+        Validators {
+            etag: "\"abc\"",
+            last_modified: Some(Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap()),
+        }
+    }
+
+    fn evaluate(pairs: &[(HeaderName, &str)]) -> Precondition {
+        evaluate_with(&Method::GET, pairs)
+    }
+
+    fn evaluate_with(method: &Method, pairs: &[(HeaderName, &str)]) -> Precondition {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(name.clone(), value.parse().unwrap());
+        }
+        validators().evaluate(method, &headers)
+    }
+
+    #[test]
+    fn test_entity_tags() {
+        let tag = |weak, opaque| EntityTag { weak, opaque };
+
+        assert_eq!(
+            entity_tags(r#""a", W/"b",,"c,d" "#),
+            vec![tag(false, "a"), tag(true, "b"), tag(false, "c,d")]
+        );
+        assert_eq!(entity_tags(r#"abc, "x""#), vec![tag(false, "x")]);
+        assert_eq!(entity_tags(r#""unterminated"#), vec![]);
+        assert_eq!(entity_tags(""), vec![]);
+    }
+
+    #[test]
+    fn test_if_none_match() {
+        use Precondition::*;
+        let inm = header::IF_NONE_MATCH;
+
+        assert_eq!(evaluate(&[]), Proceed);
+        assert_eq!(evaluate(&[(inm.clone(), "\"abc\"")]), NotModified);
+        assert_eq!(evaluate(&[(inm.clone(), "W/\"abc\"")]), NotModified);
+        assert_eq!(evaluate(&[(inm.clone(), "\"x\", \"abc\"")]), NotModified);
+        assert_eq!(evaluate(&[(inm.clone(), "*")]), NotModified);
+        assert_eq!(evaluate(&[(inm.clone(), "\"x\""), (inm.clone(), "\"abc\"")]), NotModified);
+        assert_eq!(evaluate(&[(inm.clone(), "\"x\", W/\"y\"")]), Proceed);
+        assert_eq!(evaluate_with(&Method::HEAD, &[(inm.clone(), "\"abc\"")]), NotModified);
+        assert_eq!(evaluate_with(&Method::POST, &[(inm, "\"abc\"")]), Failed);
+    }
+
+    #[test]
+    fn test_if_match() {
+        use Precondition::*;
+        let im = header::IF_MATCH;
+
+        assert_eq!(evaluate(&[(im.clone(), "\"abc\"")]), Proceed);
+        assert_eq!(evaluate(&[(im.clone(), "\"x\", \"abc\"")]), Proceed);
+        assert_eq!(evaluate(&[(im.clone(), "*")]), Proceed);
+        // Strong comparison: weak tags never match
+        assert_eq!(evaluate(&[(im.clone(), "W/\"abc\"")]), Failed);
+        assert_eq!(evaluate(&[(im.clone(), "\"x\"")]), Failed);
+
+        // If-Match takes precedence over If-Unmodified-Since
+        let ius = header::IF_UNMODIFIED_SINCE;
+        let before = "Tue, 20 Oct 2015 07:28:00 GMT";
+        assert_eq!(evaluate(&[(im, "\"abc\""), (ius, before)]), Proceed);
+    }
+
+    #[test]
+    fn test_dates() {
+        use Precondition::*;
+        let ims = header::IF_MODIFIED_SINCE;
+        let ius = header::IF_UNMODIFIED_SINCE;
+        let before = "Tue, 20 Oct 2015 07:28:00 GMT";
+        let after = "Thu, 22 Oct 2015 07:28:00 GMT";
+
+        assert_eq!(evaluate(&[(ims.clone(), MODIFIED)]), NotModified);
+        assert_eq!(evaluate(&[(ims.clone(), after)]), NotModified);
+        assert_eq!(evaluate(&[(ims.clone(), before)]), Proceed);
+        // RFC 850 and asctime forms
+        assert_eq!(evaluate(&[(ims.clone(), "Wednesday, 21-Oct-15 07:28:00 GMT")]), NotModified);
+        assert_eq!(evaluate(&[(ims.clone(), "Wed Oct 21 07:28:00 2015")]), NotModified);
+        // Invalid and future dates are ignored
+        assert_eq!(evaluate(&[(ims.clone(), "yesterday")]), Proceed);
+        assert_eq!(evaluate(&[(ims.clone(), "Fri, 01 Jan 2100 00:00:00 GMT")]), Proceed);
+        // If-None-Match takes precedence over If-Modified-Since
+        assert_eq!(evaluate(&[(header::IF_NONE_MATCH, "\"x\""), (ims.clone(), after)]), Proceed);
+        assert_eq!(evaluate_with(&Method::POST, &[(ims, after)]), Proceed);
+
+        assert_eq!(evaluate(&[(ius.clone(), MODIFIED)]), Proceed);
+        assert_eq!(evaluate(&[(ius.clone(), after)]), Proceed);
+        assert_eq!(evaluate(&[(ius.clone(), before)]), Failed);
+        assert_eq!(evaluate(&[(ius, "not a date")]), Proceed);
+
+        // Without a modification time, dates never match
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_MODIFIED_SINCE, after.parse().unwrap());
+        let unknown = Validators {
+            etag: "\"abc\"",
+            last_modified: None,
+        };
+        assert_eq!(unknown.evaluate(&Method::GET, &headers), Proceed);
+    }
+
+    #[test]
+    fn test_if_range_and_last_modified() {
+        let validators = validators();
+
+        assert!(validators.matches_if_range("\"abc\""));
+        assert!(!validators.matches_if_range("W/\"abc\""));
+        assert!(!validators.matches_if_range("\"old\""));
+        assert!(validators.matches_if_range(MODIFIED));
+        assert!(!validators.matches_if_range("Thu, 22 Oct 2015 07:28:00 GMT"));
+
+        assert_eq!(validators.last_modified_header().as_deref(), Some(MODIFIED));
+    }
+}
@@ -61,6 +61,8 @@ async fn stats(State(state): State<AppState>) -> Json<StatsResponse> {
     Json(StatsResponse {
         media_count,
         storage: storage_stats,
+        processing: state.processing_pool.stats(),
+        media_cache: state.media_cache.stats(),
     })
 }
 
@@ -69,6 +71,10 @@ async fn stats(State(state): State<AppState>) -> Json<StatsResponse> {
 pub struct StatsResponse {
     pub media_count: u64,
     pub storage: Option<crate::services::storage::StorageStats>,
+    /// Processing pool load (queue length, processing times)
+    pub processing: crate::services::ProcessingStats,
+    /// In-memory media cache size and hit/miss counters
+    pub media_cache: crate::services::MediaCacheStats,
 }
 
 /// Create health check routes
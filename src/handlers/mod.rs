@@ -6,9 +6,15 @@
 //! - `admin`: Administrative endpoints (local only)
 //! - `health`: Health check endpoints
 //! - `rexpump`: RexPump token metadata endpoints
+//! - `pipeline`: Shared image/video ingestion used by upload and rexpump
+//! - `range`: Byte-range (`206`/`416`) file responses used by serve
+//! - `conditional`: Precondition evaluation (`304`/`412`) for those responses
 
 pub mod admin;
+mod conditional;
 pub mod health;
+mod pipeline;
+mod range;
 pub mod rexpump;
 pub mod serve;
 pub mod upload;
@@ -0,0 +1,621 @@
+//! Shared media ingestion pipeline.
+//!
+//! Used by both the upload handlers and the RexPump metadata handlers:
+//! deduplicate by content hash, process the image, store the files, and
+//! pre-generate all configured variant presets.
+//!
+//! Images are stored once per configured output format: the default
+//! `output_format` plus one rendition per `output_formats` entry, all next to
+//! each other in the optimized directory. `/m/{id}` picks one by `Accept`.
+//! Animations are stored as a single animated WebP or GIF, without
+//! renditions.
+//!
+//! Videos (uploads only) are probed for their metadata and stored untouched
+//! as the optimized file; they have no separate original and no presets.
+//! With `ffmpeg_path` configured, a poster frame is extracted and stored in
+//! the default output format next to the variants (key [`POSTER_KEY`]).
+//!
+//! Video probing, decoding, encoding and preset rendering run on the
+//! [`ProcessingPool`] (`state.processing_pool`); hashing and sniffing use
+//! `spawn_blocking`. None of it runs on the async workers.
+//!
+//! [`ProcessingPool`]: crate::services::ProcessingPool
+//!
+//! Deduplicated media is reference counted: every call to one of the
+//! `process_and_store_*` functions holds one reference, which is given back
+//! with [`release_media`]. Files are removed only when the last reference goes.
+//!
+//! Deduplication is per visibility: public and private uploads of the same
+//! content are stored as separate records, and an upload never changes the
+//! visibility of existing media.
+
+use bytes::Bytes;
+use chrono::Utc;
+use image::DynamicImage;
+use std::io::{BufReader, Cursor, Read};
+use std::path::Path;
+use std::sync::Arc;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::error::{AppError, Result};
+use crate::models::{extension_for_mime, FocalPoint, Media, MediaType, Visibility};
+use crate::services::image_processor::{calculate_hash, ProcessedImage};
+use crate::services::VideoProcessor;
+use crate::state::AppState;
+
+/// Where the uploaded bytes live
+#[derive(Clone, Copy)]
+enum Source<'a> {
+    /// Fully buffered upload (simple upload, RexPump images)
+    Bytes(&'a Bytes),
+    /// Assembled chunked upload on disk
+    File(&'a Path),
+}
+
+/// Number of leading bytes inspected to tell videos from images
+const SNIFF_LEN: u64 = 8192;
+
+/// Variant key of a video's poster frame
+pub(crate) const POSTER_KEY: &str = "poster";
+
+/// Process and store an uploaded image or video
+///
+/// Returns the existing media record if identical content was uploaded before,
+/// taking an additional reference on it.
+pub(crate) async fn process_and_store_upload(
+    state: &AppState,
+    filename: &str,
+    data: Bytes,
+    visibility: Visibility,
+) -> Result<Media> {
+    // Calculate content hash for deduplication
+    let content_hash = hash_upload(&data).await?;
+
+    let source = Source::Bytes(&data);
+    process_and_store(state, filename, source, content_hash, true, visibility).await
+}
+
+/// Process and store an uploaded image, rejecting videos
+///
+/// Same as [`process_and_store_upload`], for places that only take images
+/// (RexPump token images).
+pub(crate) async fn process_and_store_image(
+    state: &AppState,
+    filename: &str,
+    data: Bytes,
+) -> Result<Media> {
+    let content_hash = hash_upload(&data).await?;
+
+    let source = Source::Bytes(&data);
+    process_and_store(state, filename, source, content_hash, false, Visibility::Public).await
+}
+
+/// Process and store an uploaded image or video from a file, streaming from disk
+///
+/// `content_hash` must be the SHA-256 of the file (computed incrementally
+/// while the chunks were received). The file may be moved into storage.
+pub(crate) async fn process_and_store_file(
+    state: &AppState,
+    filename: &str,
+    path: &Path,
+    content_hash: String,
+    visibility: Visibility,
+) -> Result<Media> {
+    let source = Source::File(path);
+    process_and_store(state, filename, source, content_hash, true, visibility).await
+}
+
+async fn process_and_store(
+    state: &AppState,
+    filename: &str,
+    source: Source<'_>,
+    content_hash: String,
+    accept_video: bool,
+    visibility: Visibility,
+) -> Result<Media> {
+    // Tell videos apart before deduplication so an image-only caller never
+    // gets a video back
+    let is_video = VideoProcessor::detect_mime_type(&sniff(source).await?).is_some();
+    if is_video && !accept_video {
+        return Err(AppError::unsupported_media_type(
+            "Videos are not accepted here, expected an image",
+        ));
+    }
+
+    // Check for duplicate
+    if let Some(existing) = state.db.acquire_by_hash(&content_hash, visibility)? {
+        info!(
+            existing_id = %existing.id,
+            hash = %content_hash,
+            "Found duplicate content, returning existing media"
+        );
+        return Ok(existing);
+    }
+
+    if is_video {
+        return store_video(state, filename, source, content_hash, visibility).await;
+    }
+
+    // Process the image
+    let processor = Arc::clone(&state.image_processor);
+    let config = Arc::clone(&state.config);
+    let processed = match source {
+        Source::Bytes(data) => {
+            let data = data.clone();
+            state
+                .processing_pool
+                .run(move || processor.process(Cursor::new(data), &config.upload))
+                .await?
+        }
+        Source::File(path) => {
+            let path = path.to_path_buf();
+            state
+                .processing_pool
+                .run(move || {
+                    let file = std::fs::File::open(path)?;
+                    processor.process(BufReader::new(file), &config.upload)
+                })
+                .await?
+        }
+    };
+
+    // Create media record with the format the image was encoded to
+    let mut media = Media::new(
+        filename.to_string(),
+        processed.original_mime.clone(),
+        processed.optimized_mime.clone(),
+        processed.original_size,
+        processed.optimized_data.len() as u64,
+        processed.width,
+        processed.height,
+        content_hash,
+    );
+    media.renditions = processed
+        .renditions
+        .iter()
+        .map(|r| r.format.mime_type().to_string())
+        .collect();
+    media.quality = processed.quality;
+    media.frame_count = processed.frame_count;
+    media.duration_ms = processed.duration_ms;
+    media.exif = processed.exif.clone();
+    media.blurhash = Some(processed.placeholder.blurhash.clone());
+    media.dominant_color = Some(processed.placeholder.dominant_color.clone());
+    media.perceptual_hash = Some(processed.perceptual_hash);
+    media.focal_point = Some(processed.focal_point);
+    media.visibility = visibility;
+
+    // Save files
+    let output_ext = optimized_extension(&media);
+
+    if state.keep_originals() {
+        save_original(state, &media, &processed, source).await?;
+    }
+
+    state
+        .storage
+        .save_optimized(media.id, output_ext, &processed.optimized_data)
+        .await?;
+
+    for rendition in &processed.renditions {
+        state
+            .storage
+            .save_optimized(media.id, rendition.format.extension(), &rendition.data)
+            .await?;
+    }
+
+    // Pre-generate presets from the decoded image
+    generate_presets(state, &media, processed.image).await;
+
+    // Save to database
+    state.db.insert_media(&media)?;
+
+    info!(
+        id = %media.id,
+        original_size = processed.original_size,
+        optimized_size = processed.optimized_data.len(),
+        "Stored media"
+    );
+
+    Ok(media)
+}
+
+/// Probe a video and store it untouched as the optimized file
+async fn store_video(
+    state: &AppState,
+    filename: &str,
+    source: Source<'_>,
+    content_hash: String,
+    visibility: Visibility,
+) -> Result<Media> {
+    let video_processor = Arc::clone(&state.video_processor);
+    let config = Arc::clone(&state.config);
+    let probed = match source {
+        Source::Bytes(data) => {
+            let data = data.clone();
+            state
+                .processing_pool
+                .run(move || video_processor.probe(Cursor::new(data), &config.upload))
+                .await?
+        }
+        Source::File(path) => {
+            let path = path.to_path_buf();
+            state
+                .processing_pool
+                .run(move || {
+                    let file = std::fs::File::open(path)?;
+                    video_processor.probe(BufReader::new(file), &config.upload)
+                })
+                .await?
+        }
+    };
+
+    let mut media = Media::new(
+        filename.to_string(),
+        probed.mime.clone(),
+        probed.mime.clone(),
+        probed.size,
+        probed.size,
+        probed.width,
+        probed.height,
+        content_hash,
+    );
+    media.duration_ms = probed.duration_ms;
+    media.video_codec = probed.codec;
+    media.visibility = visibility;
+
+    let ext = optimized_extension(&media);
+    match source {
+        Source::Bytes(data) => {
+            state.storage.save_optimized(media.id, ext, data).await?;
+        }
+        Source::File(path) => {
+            state
+                .storage
+                .save_optimized_from_file(media.id, ext, path)
+                .await?;
+        }
+    }
+
+    // A video without a poster is still a valid upload
+    match store_poster(state, &media).await {
+        Ok(poster_mime_type) => media.poster_mime_type = poster_mime_type,
+        Err(e) => warn!(id = %media.id, error = %e, "Failed to extract poster frame"),
+    }
+
+    state.db.insert_media(&media)?;
+
+    info!(
+        id = %media.id,
+        size = probed.size,
+        duration_ms = ?media.duration_ms,
+        codec = ?media.video_codec,
+        poster = media.poster_mime_type.is_some(),
+        "Stored video"
+    );
+
+    Ok(media)
+}
+
+/// Extract the poster frame of a stored video and save it in the output format
+///
+/// Returns the poster's MIME type, or `None` if posters are not configured.
+async fn store_poster(state: &AppState, media: &Media) -> Result<Option<String>> {
+    let video_path = state
+        .storage
+        .optimized_path(media.id, optimized_extension(media));
+    let Some(frame) = state
+        .video_processor
+        .extract_poster(&video_path, media.duration_ms)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let processor = Arc::clone(&state.image_processor);
+    let poster = state
+        .processing_pool
+        .run(move || processor.reencode(&frame))
+        .await?;
+    state
+        .storage
+        .save_variant(media.id, POSTER_KEY, state.output_extension(), &poster.optimized_data)
+        .await?;
+
+    Ok(Some(state.output_mime_type().to_string()))
+}
+
+/// SHA-256 of a buffered upload, computed on the blocking pool
+async fn hash_upload(data: &Bytes) -> Result<String> {
+    let data = data.clone();
+    tokio::task::spawn_blocking(move || calculate_hash(&data))
+        .await
+        .map_err(|e| AppError::internal(format!("Hashing task panicked: {}", e)))
+}
+
+/// Read the leading bytes of the source for magic-byte detection
+///
+/// A file is read on the blocking pool.
+async fn sniff(source: Source<'_>) -> Result<Vec<u8>> {
+    match source {
+        Source::Bytes(data) => Ok(data[..data.len().min(SNIFF_LEN as usize)].to_vec()),
+        Source::File(path) => {
+            let path = path.to_path_buf();
+            tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
+                let mut header = Vec::new();
+                std::fs::File::open(path)?
+                    .take(SNIFF_LEN)
+                    .read_to_end(&mut header)?;
+                Ok(header)
+            })
+            .await
+            .map_err(|e| AppError::internal(format!("Sniffing task panicked: {}", e)))?
+        }
+    }
+}
+
+/// Extension of the stored optimized file
+///
+/// Taken from the record, not the config: media keeps the format it was
+/// stored in until it is reprocessed, and videos keep their container.
+pub(crate) fn optimized_extension(media: &Media) -> &'static str {
+    extension_for_mime(&media.optimized_mime_type)
+}
+
+/// Extensions of the stored alternate renditions, in order of preference
+pub(crate) fn rendition_extensions(media: &Media) -> Vec<&'static str> {
+    media
+        .renditions
+        .iter()
+        .map(|mime| extension_for_mime(mime))
+        .collect()
+}
+
+/// Read the best available source for re-encoding an image
+///
+/// Prefers the original (highest quality); falls back to the optimized file
+/// when originals are not kept.
+pub(crate) async fn read_image_source(state: &AppState, media: &Media) -> Result<Vec<u8>> {
+    if state.keep_originals() {
+        let ext = extension_for_mime(&media.original_mime_type);
+        if state.storage.original_exists(media.id, ext).await {
+            return state.storage.read_original(media.id, ext).await;
+        }
+    }
+
+    state
+        .storage
+        .read_optimized(media.id, optimized_extension(media))
+        .await
+}
+
+/// Re-encode a stored image into the configured output formats
+///
+/// The new files are written before the record is switched over, and the old
+/// ones removed only afterwards, so the media stays servable throughout.
+///
+/// Returns `false` if there was nothing to do (video, animation, already in
+/// the target formats and quality, or deleted meanwhile). Animations are
+/// skipped because re-encoding would keep only their first frame. Images
+/// stored before perceptual hashing are re-encoded to backfill their hash.
+pub(crate) async fn reprocess_media(state: &AppState, id: Uuid) -> Result<bool> {
+    let target_mime = state.output_mime_type();
+    let target_quality = state.image_processor.output_quality();
+    let target_renditions: Vec<String> = state
+        .image_processor
+        .alternate_formats()
+        .iter()
+        .map(|f| f.mime_type().to_string())
+        .collect();
+
+    let media = match state.db.get_media(id)? {
+        Some(media) => media,
+        None => return Ok(false),
+    };
+    if media.media_type != MediaType::Image
+        || media.frame_count.is_some()
+        || (media.optimized_mime_type == target_mime
+            && media.renditions == target_renditions
+            && media.quality == target_quality
+            && media.perceptual_hash.is_some())
+    {
+        return Ok(false);
+    }
+
+    let source = read_image_source(state, &media).await?;
+    let processor = Arc::clone(&state.image_processor);
+    let reencoded = state
+        .processing_pool
+        .run(move || processor.reencode(&source))
+        .await?;
+
+    let mut old_exts = rendition_extensions(&media);
+    old_exts.push(optimized_extension(&media));
+
+    let mut new_exts = vec![state.output_extension()];
+    state
+        .storage
+        .save_optimized(id, new_exts[0], &reencoded.optimized_data)
+        .await?;
+    for rendition in &reencoded.renditions {
+        let ext = rendition.format.extension();
+        state.storage.save_optimized(id, ext, &rendition.data).await?;
+        new_exts.push(ext);
+    }
+
+    let updated = state.db.update_media(id, |media| {
+        media.optimized_mime_type = target_mime.to_string();
+        media.optimized_size = reencoded.optimized_data.len() as u64;
+        media.renditions = target_renditions;
+        media.quality = target_quality;
+        media.width = reencoded.width;
+        media.height = reencoded.height;
+        media.blurhash = Some(reencoded.placeholder.blurhash.clone());
+        media.dominant_color = Some(reencoded.placeholder.dominant_color.clone());
+        // Keep a focal point that was set by hand
+        media.focal_point.get_or_insert(reencoded.focal_point);
+        media.perceptual_hash = Some(reencoded.perceptual_hash);
+        media.updated_at = Utc::now();
+    })?;
+
+    state.media_cache.invalidate(id);
+
+    if updated.is_none() {
+        // Deleted while we were encoding
+        for ext in new_exts {
+            state.storage.delete_optimized(id, ext).await?;
+        }
+        return Ok(false);
+    }
+
+    for ext in old_exts.into_iter().filter(|ext| !new_exts.contains(ext)) {
+        if let Err(e) = state.storage.delete_optimized(id, ext).await {
+            warn!(id = %id, error = %e, "Failed to delete previous optimized file");
+        }
+    }
+
+    debug!(id = %id, from = %media.optimized_mime_type, to = %target_mime, "Reprocessed media");
+    Ok(true)
+}
+
+/// Store the original: re-encoded bytes if EXIF was stripped, else the source
+async fn save_original(
+    state: &AppState,
+    media: &Media,
+    processed: &ProcessedImage,
+    source: Source<'_>,
+) -> Result<()> {
+    let ext = extension_for_mime(&processed.original_mime);
+
+    match (processed.original_data.as_deref(), source) {
+        (Some(data), _) => {
+            state.storage.save_original(media.id, ext, data).await?;
+        }
+        (None, Source::Bytes(data)) => {
+            state.storage.save_original(media.id, ext, data).await?;
+        }
+        (None, Source::File(path)) => {
+            state
+                .storage
+                .save_original_from_file(media.id, ext, path)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Render and store all configured variant presets for a media item
+///
+/// Failures are logged and skipped: a missing preset file is rendered lazily
+/// on first request to `/m/{id}/v/{name}`. That includes a full processing
+/// queue, so presets never fail an upload.
+pub(crate) async fn generate_presets(state: &AppState, media: &Media, image: DynamicImage) {
+    let mut specs = Vec::new();
+    for name in state.config.processing.variants.keys() {
+        match state.config.processing.preset_spec(name) {
+            Some(Ok(spec)) => specs.push((name.clone(), spec)),
+            Some(Err(e)) => warn!(preset = %name, error = %e, "Invalid variant preset"),
+            None => {}
+        }
+    }
+    if specs.is_empty() {
+        return;
+    }
+
+    let processor = Arc::clone(&state.image_processor);
+    let focal_point = media.focal_point;
+    let rendered = state
+        .processing_pool
+        .run(move || {
+            Ok(specs
+                .into_iter()
+                .map(|(name, spec)| {
+                    let data = processor.render_variant_image(&image, &spec, focal_point);
+                    (name, spec, data)
+                })
+                .collect::<Vec<_>>())
+        })
+        .await;
+    let rendered = match rendered {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            warn!(id = %media.id, error = %e, "Failed to generate presets");
+            return;
+        }
+    };
+
+    for (name, spec, data) in rendered {
+        let key = spec.cache_key();
+        let ext = spec.format.extension();
+
+        let result = match data {
+            Ok(data) => state.storage.save_variant(media.id, &key, ext, &data).await,
+            Err(e) => Err(e),
+        };
+
+        match result {
+            Ok(_) => debug!(id = %media.id, preset = %name, key = %key, "Generated preset"),
+            Err(e) => warn!(id = %media.id, preset = %name, error = %e, "Failed to generate preset"),
+        }
+    }
+}
+
+/// Set the focal point of an image and drop its cached variants
+///
+/// Variants (presets included) are rendered again around the new point on
+/// their next request. Returns `None` if the media does not exist.
+pub(crate) async fn set_focal_point(
+    state: &AppState,
+    id: Uuid,
+    focal_point: FocalPoint,
+) -> Result<Option<Media>> {
+    let updated = state
+        .db
+        .update_media(id, |media| {
+            media.focal_point = Some(focal_point);
+            media.updated_at = Utc::now();
+        })?;
+
+    if updated.is_some() {
+        state.media_cache.invalidate(id);
+        state.storage.delete_variants(id).await?;
+        debug!(id = %id, x = focal_point.x, y = focal_point.y, "Set focal point");
+    }
+
+    Ok(updated)
+}
+
+/// Drop one reference on a media item, deleting its files with the last one
+pub(crate) async fn release_media(state: &AppState, id: Uuid) {
+    match state.db.release_media(id) {
+        Ok(Some(media)) => delete_media_files(state, &media).await,
+        Ok(None) => debug!(id = %id, "Media still referenced, keeping files"),
+        Err(e) => warn!(id = %id, error = %e, "Failed to release media reference"),
+    }
+}
+
+/// Delete all stored files (original, optimized, renditions, variants) of a
+/// media item
+pub(crate) async fn delete_media_files(state: &AppState, media: &Media) {
+    state.media_cache.invalidate(media.id);
+    state.access_tracker.forget(media.id);
+
+    let original_ext = extension_for_mime(&media.original_mime_type);
+    let output_ext = optimized_extension(media);
+
+    for ext in rendition_extensions(media) {
+        if let Err(e) = state.storage.delete_optimized(media.id, ext).await {
+            warn!(id = %media.id, error = %e, "Failed to delete rendition file");
+        }
+    }
+
+    if let Err(e) = state
+        .storage
+        .delete_media_files(media.id, original_ext, output_ext)
+        .await
+    {
+        warn!(id = %media.id, error = %e, "Failed to delete some media files");
+    }
+}
@@ -0,0 +1,395 @@
+//! Byte-range support for file responses (RFC 9110, section 14).
+//!
+//! [`FileResponse`] streams a stored file (or an in-memory asset) honoring
+//! `Range` and `If-Range`:
+//!
+//! - no (or ignored) `Range` → `200` with the full file
+//! - one satisfiable range → `206` with `Content-Range`
+//! - several ranges → `206` with a `multipart/byteranges` body
+//! - no satisfiable range → `416` with `Content-Range: bytes */{length}`
+//!
+//! Every response carries `Content-Length` and `Accept-Ranges: bytes`.
+//! `HEAD` requests get the same headers without the file being opened.
+//!
+//! Preconditions (`If-None-Match`, `If-Modified-Since`, `If-Match`,
+//! `If-Unmodified-Since`) are evaluated first and may answer `304` or `412`
+//! instead (see [`super::conditional`]).
+
+use axum::{
+    body::{Body, Bytes},
+    http::{header, response::Builder, HeaderMap, Method, StatusCode},
+    response::Response,
+};
+use std::io::{Cursor, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+use std::pin::Pin;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+use crate::error::{AppError, Result};
+use crate::handlers::conditional::{Precondition, Validators};
+
+/// More ranges than this in one request are ignored (full response)
+const MAX_RANGES: usize = 16;
+
+/// Outcome of evaluating a `Range` header against a file length
+#[derive(Debug, PartialEq, Eq)]
+enum RangeOutcome {
+    /// Serve the whole file (no header, unknown unit or invalid syntax)
+    Full,
+    /// Serve these byte ranges (end exclusive, sorted, non-overlapping)
+    Partial(Vec<Range<u64>>),
+    /// Valid header, but no range overlaps the file
+    Unsatisfiable,
+}
+
+/// Where the bytes of a [`FileResponse`] come from
+pub(crate) enum FileContent<'a> {
+    /// File on disk
+    Path(&'a Path),
+    /// Data held in memory (built-in assets, cached media)
+    Bytes(Bytes),
+}
+
+/// A stored file to be sent with range support
+pub(crate) struct FileResponse<'a> {
+    /// File contents
+    pub content: FileContent<'a>,
+    /// Content type of the file
+    pub content_type: &'a str,
+    /// `ETag` and `Last-Modified`, used to evaluate preconditions and `If-Range`
+    pub validators: Validators<'a>,
+    /// `Cache-Control` value
+    pub cache_control: &'a str,
+    /// Optional `Content-Disposition` value
+    pub content_disposition: Option<String>,
+}
+
+impl FileResponse<'_> {
+    /// Build the response for a `GET` or `HEAD` request
+    pub async fn into_response(self, method: &Method, headers: &HeaderMap) -> Result<Response> {
+        match self.validators.evaluate(method, headers) {
+            Precondition::Proceed => {}
+            Precondition::NotModified => return self.not_modified(),
+            Precondition::Failed => {
+                return Err(AppError::precondition_failed(
+                    "The representation does not match the request's preconditions",
+                ))
+            }
+        }
+
+        let len = match &self.content {
+            FileContent::Path(path) => tokio::fs::metadata(path).await?.len(),
+            FileContent::Bytes(data) => data.len() as u64,
+        };
+        let head = method == Method::HEAD;
+
+        let outcome = match requested_range(headers, &self.validators) {
+            Some(value) => parse_range(value, len),
+            None => RangeOutcome::Full,
+        };
+
+        let mut builder = self
+            .validator_headers(Response::builder())
+            .header(header::ACCEPT_RANGES, "bytes")
+            .header("X-Content-Type-Options", "nosniff");
+        if let Some(disposition) = &self.content_disposition {
+            builder = builder.header(header::CONTENT_DISPOSITION, disposition);
+        }
+
+        let response = match outcome {
+            RangeOutcome::Full => {
+                let body = if head {
+                    Body::empty()
+                } else {
+                    match &self.content {
+                        FileContent::Path(path) => {
+                            let file = File::open(path).await?;
+                            Body::from_stream(ReaderStream::new(file))
+                        }
+                        FileContent::Bytes(data) => Body::from(data.clone()),
+                    }
+                };
+
+                builder
+                    .status(StatusCode::OK)
+                    .header(header::CONTENT_TYPE, self.content_type)
+                    .header(header::CONTENT_LENGTH, len)
+                    .body(body)
+            }
+            RangeOutcome::Unsatisfiable => builder
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+                .header(header::CONTENT_LENGTH, 0)
+                .body(Body::empty()),
+            RangeOutcome::Partial(ranges) if ranges.len() == 1 => {
+                let range = ranges[0].clone();
+                let body = if head {
+                    Body::empty()
+                } else {
+                    let reader = self.open_range(&range).await?;
+                    Body::from_stream(ReaderStream::new(reader))
+                };
+
+                builder
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(header::CONTENT_TYPE, self.content_type)
+                    .header(header::CONTENT_RANGE, content_range(&range, len))
+                    .header(header::CONTENT_LENGTH, range.end - range.start)
+                    .body(body)
+            }
+            RangeOutcome::Partial(ranges) => {
+                let boundary = Uuid::new_v4().simple().to_string();
+                let parts: Vec<(String, Range<u64>)> = ranges
+                    .into_iter()
+                    .map(|range| {
+                        let part_header = format!(
+                            "--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+                            boundary,
+                            self.content_type,
+                            content_range(&range, len)
+                        );
+                        (part_header, range)
+                    })
+                    .collect();
+                let closing = format!("--{}--\r\n", boundary);
+
+                // Each part is its header, the bytes and a trailing CRLF
+                let content_length = parts
+                    .iter()
+                    .map(|(part_header, range)| {
+                        part_header.len() as u64 + range.end - range.start + 2
+                    })
+                    .sum::<u64>()
+                    + closing.len() as u64;
+
+                let body = if head {
+                    Body::empty()
+                } else {
+                    let mut reader: Pin<Box<dyn AsyncRead + Send>> = Box::pin(tokio::io::empty());
+                    for (part_header, range) in parts {
+                        let part = self.open_range(&range).await?;
+                        reader = Box::pin(
+                            reader
+                                .chain(Cursor::new(part_header.into_bytes()))
+                                .chain(part)
+                                .chain(Cursor::new(b"\r\n".as_slice())),
+                        );
+                    }
+                    reader = Box::pin(reader.chain(Cursor::new(closing.into_bytes())));
+                    Body::from_stream(ReaderStream::new(reader))
+                };
+
+                builder
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(
+                        header::CONTENT_TYPE,
+                        format!("multipart/byteranges; boundary={}", boundary),
+                    )
+                    .header(header::CONTENT_LENGTH, content_length)
+                    .body(body)
+            }
+        };
+
+        response.map_err(|e| AppError::internal(format!("Failed to build response: {}", e)))
+    }
+
+    /// `304 Not Modified`, with the headers a `200` would have carried for caching
+    fn not_modified(&self) -> Result<Response> {
+        self.validator_headers(Response::builder())
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Body::empty())
+            .map_err(|e| AppError::internal(format!("Failed to build response: {}", e)))
+    }
+
+    /// Add `Cache-Control`, `ETag` and `Last-Modified`
+    fn validator_headers(&self, builder: Builder) -> Builder {
+        let builder = builder
+            .header(header::CACHE_CONTROL, self.cache_control)
+            .header(header::ETAG, self.validators.etag);
+
+        match self.validators.last_modified_header() {
+            Some(last_modified) => builder.header(header::LAST_MODIFIED, last_modified),
+            None => builder,
+        }
+    }
+
+    /// Open the content positioned at the start of a range, limited to its
+    /// length
+    async fn open_range(&self, range: &Range<u64>) -> Result<Pin<Box<dyn AsyncRead + Send>>> {
+        match &self.content {
+            FileContent::Path(path) => {
+                let mut file = File::open(path).await?;
+                file.seek(SeekFrom::Start(range.start)).await?;
+                Ok(Box::pin(file.take(range.end - range.start)))
+            }
+            FileContent::Bytes(data) => Ok(Box::pin(Cursor::new(
+                data.slice(range.start as usize..range.end as usize),
+            ))),
+        }
+    }
+}
+
+/// `Content-Range` value for a satisfied range
+fn content_range(range: &Range<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{}", range.start, range.end - 1, len)
+}
+
+/// The `Range` header to honor, if any
+///
+/// With `If-Range`, the range only applies while the representation is
+/// unchanged: the validator must be the current strong ETag or exactly the
+/// `Last-Modified` date.
+fn requested_range<'h>(headers: &'h HeaderMap, validators: &Validators<'_>) -> Option<&'h str> {
+    let range = headers.get(header::RANGE)?.to_str().ok()?;
+
+    match headers.get(header::IF_RANGE) {
+        Some(if_range) if !validators.matches_if_range(if_range.to_str().ok()?) => None,
+        _ => Some(range),
+    }
+}
+
+/// Evaluate a `Range` header value against a file of `len` bytes
+fn parse_range(value: &str, len: u64) -> RangeOutcome {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return RangeOutcome::Full;
+    };
+
+    let mut specs = 0;
+    let mut ranges = Vec::new();
+
+    for part in spec
+        .split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+    {
+        specs += 1;
+        if specs > MAX_RANGES {
+            return RangeOutcome::Full;
+        }
+
+        let Some((first, last)) = part.split_once('-') else {
+            return RangeOutcome::Full;
+        };
+
+        let range = if first.is_empty() {
+            // Suffix range: the last N bytes
+            let Ok(suffix) = last.parse::<u64>() else {
+                return RangeOutcome::Full;
+            };
+            len.saturating_sub(suffix)..len
+        } else {
+            let Ok(start) = first.parse::<u64>() else {
+                return RangeOutcome::Full;
+            };
+            let end = if last.is_empty() {
+                len
+            } else {
+                match last.parse::<u64>() {
+                    Ok(last) if last >= start => last.saturating_add(1).min(len),
+                    _ => return RangeOutcome::Full,
+                }
+            };
+            start..end
+        };
+
+        if range.start < range.end {
+            ranges.push(range);
+        }
+    }
+
+    if specs == 0 {
+        return RangeOutcome::Full;
+    }
+    if ranges.is_empty() {
+        return RangeOutcome::Unsatisfiable;
+    }
+
+    // Coalesce overlapping and adjacent ranges
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+
+    RangeOutcome::Partial(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn single(range: Range<u64>) -> RangeOutcome {
+        RangeOutcome::Partial(vec![range])
+    }
+
+    #[test]
+    fn test_parse_range() {
+        use RangeOutcome::*;
+
+        assert_eq!(parse_range("bytes=0-99", 1000), single(0..100));
+        assert_eq!(parse_range("bytes=900-", 1000), single(900..1000));
+        assert_eq!(parse_range("bytes=-100", 1000), single(900..1000));
+        assert_eq!(parse_range("bytes=-5000", 1000), single(0..1000));
+        assert_eq!(parse_range("bytes=990-2000", 1000), single(990..1000));
+
+        // Multiple ranges are sorted and coalesced
+        assert_eq!(
+            parse_range("bytes=500-599, 0-9, 10-19, 550-700", 1000),
+            Partial(vec![0..20, 500..701])
+        );
+
+        // Unsatisfiable
+        assert_eq!(parse_range("bytes=1000-", 1000), Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 1000), Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-", 0), Unsatisfiable);
+
+        // Invalid syntax or unknown units are ignored
+        assert_eq!(parse_range("items=0-1", 1000), Full);
+        assert_eq!(parse_range("bytes=5-1", 1000), Full);
+        assert_eq!(parse_range("bytes=abc", 1000), Full);
+        assert_eq!(parse_range("bytes=", 1000), Full);
+        assert_eq!(
+            parse_range(&format!("bytes={}", "0-0,".repeat(17)), 1000),
+            Full
+        );
+    }
+
+    #[test]
+    fn test_if_range() {
+        use chrono::{TimeZone, Utc};
+
+        let validators = Validators {
+            etag: "\"abc\"",
+            last_modified: Some(Utc.with_ymd_and_hms(2015, 10, 21, 7, 28, 0).unwrap()),
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, "bytes=0-1".parse().unwrap());
+        assert_eq!(requested_range(&headers, &validators), Some("bytes=0-1"));
+
+        headers.insert(header::IF_RANGE, "\"abc\"".parse().unwrap());
+        assert_eq!(requested_range(&headers, &validators), Some("bytes=0-1"));
+
+        headers.insert(header::IF_RANGE, "\"old\"".parse().unwrap());
+        assert_eq!(requested_range(&headers, &validators), None);
+
+        headers.insert(
+            header::IF_RANGE,
+            "Wed, 21 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(requested_range(&headers, &validators), Some("bytes=0-1"));
+
+        headers.insert(
+            header::IF_RANGE,
+            "Thu, 22 Oct 2015 07:28:00 GMT".parse().unwrap(),
+        );
+        assert_eq!(requested_range(&headers, &validators), None);
+    }
+}
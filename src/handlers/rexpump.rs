@@ -10,18 +10,20 @@ use axum::{
     routing::{delete, get, post, put},
     Json, Router,
 };
+use bytes::Bytes;
 use chrono::Utc;
 use std::time::{SystemTime, UNIX_EPOCH};
-use tracing::{debug, info, warn};
+use tracing::{info, warn};
 use uuid::Uuid;
 
+use crate::config::NearDuplicatePolicy;
 use crate::error::{AppError, Result};
 use crate::models::{
     validate_address, validate_metadata_input, LockRequest, MetadataInput, MetadataResponse,
-    TokenLock, TokenLockType, TokenMetadata,
+    NearDuplicate, TokenLock, TokenLockType, TokenMetadata,
 };
+use crate::handlers::pipeline::{process_and_store_image, release_media};
 use crate::services::evm_service::EvmService;
-use crate::services::image_processor::{calculate_hash, ImageProcessor};
 use crate::state::AppState;
 
 // =============================================================================
@@ -55,8 +57,8 @@ async fn upsert_metadata(
     let mut timestamp: Option<u64> = None;
     let mut signature: Option<String> = None;
     let mut metadata_json: Option<String> = None;
-    let mut image_light_data: Option<Vec<u8>> = None;
-    let mut image_dark_data: Option<Vec<u8>> = None;
+    let mut image_light_data: Option<Bytes> = None;
+    let mut image_dark_data: Option<Bytes> = None;
 
     while let Some(field) = multipart
         .next_field()
@@ -98,14 +100,14 @@ async fn upsert_metadata(
                 let data = field.bytes().await
                     .map_err(|e| AppError::validation(format!("Failed to read image_light: {}", e)))?;
                 if !data.is_empty() {
-                    image_light_data = Some(data.to_vec());
+                    image_light_data = Some(data);
                 }
             }
             "image_dark" => {
                 let data = field.bytes().await
                     .map_err(|e| AppError::validation(format!("Failed to read image_dark: {}", e)))?;
                 if !data.is_empty() {
-                    image_dark_data = Some(data.to_vec());
+                    image_dark_data = Some(data);
                 }
             }
             _ => {
@@ -213,24 +215,62 @@ async fn upsert_metadata(
     }
 
     // Process and store images
-    if let Some(data) = image_light_data {
-        // Delete old image if exists
-        if let Some(old_id) = metadata.image_light_id {
-            delete_media_files(&state, old_id).await;
+    let image_light = match image_light_data {
+        Some(data) => Some(process_and_store_image(&state, "token_image", data).await?),
+        None => None,
+    };
+    let image_dark = match image_dark_data {
+        Some(data) => match process_and_store_image(&state, "token_image", data).await {
+            Ok(media) => Some(media),
+            Err(e) => {
+                if let Some(media) = &image_light {
+                    release_media(&state, media.id).await;
+                }
+                return Err(e);
+            }
+        },
+        None => None,
+    };
+    let uploaded: Vec<Uuid> = image_light.iter().chain(&image_dark).map(|m| m.id).collect();
+
+    // Reused logos: compare against the images of locked tokens
+    let policy = state.config.rexpump.near_duplicate_policy;
+    if policy == NearDuplicatePolicy::Reject {
+        if let Some(found) = find_near_duplicate(&state, &metadata, &uploaded)? {
+            for id in &uploaded {
+                release_media(&state, *id).await;
+            }
+            return Err(AppError::NearDuplicate(format!(
+                "Image resembles the image of locked token {}:{}",
+                found.chain_id, found.token_address
+            )));
         }
-        
-        let media = process_and_store_image(&state, &data).await?;
-        metadata.image_light_id = Some(media.id);
     }
 
-    if let Some(data) = image_dark_data {
-        // Delete old image if exists
-        if let Some(old_id) = metadata.image_dark_id {
-            delete_media_files(&state, old_id).await;
+    // Old images are released once the new metadata is saved
+    let mut replaced = Vec::new();
+    if let Some(media) = image_light {
+        replaced.extend(metadata.image_light_id.replace(media.id));
+    }
+    if let Some(media) = image_dark {
+        replaced.extend(metadata.image_dark_id.replace(media.id));
+    }
+
+    if policy == NearDuplicatePolicy::Flag && !uploaded.is_empty() {
+        let current: Vec<Uuid> = [metadata.image_light_id, metadata.image_dark_id]
+            .into_iter()
+            .flatten()
+            .collect();
+        metadata.near_duplicate_of = find_near_duplicate(&state, &metadata, &current)?;
+        if let Some(found) = &metadata.near_duplicate_of {
+            warn!(
+                chain_id = chain_id,
+                token = %token_address,
+                similar_to = %found.token_address,
+                distance = found.distance,
+                "Token image resembles a locked token's image"
+            );
         }
-        
-        let media = process_and_store_image(&state, &data).await?;
-        metadata.image_dark_id = Some(media.id);
     }
 
     // Update timestamps
@@ -238,7 +278,17 @@ async fn upsert_metadata(
     metadata.last_update_by = token_owner;
 
     // Save metadata
-    state.db.upsert_token_metadata(&metadata)?;
+    if let Err(e) = state.db.upsert_token_metadata(&metadata) {
+        for id in &uploaded {
+            release_media(&state, *id).await;
+        }
+        return Err(e);
+    }
+
+    // Release old images (files go away if nothing else references them)
+    for id in replaced {
+        release_media(&state, id).await;
+    }
 
     // Record update for rate limiting
     state.db.record_token_update(chain_id, &token_address)?;
@@ -249,7 +299,7 @@ async fn upsert_metadata(
         "Updated token metadata"
     );
 
-    let response = MetadataResponse::from_metadata(&metadata, state.base_url());
+    let response = metadata_response(&state, &metadata)?;
     Ok((StatusCode::OK, Json(response)))
 }
 
@@ -273,6 +323,7 @@ async fn get_metadata(
                 chain_id,
                 &token_address,
                 state.base_url(),
+                state.config.rexpump.default_images(chain_id),
             )));
         }
     }
@@ -283,7 +334,7 @@ async fn get_metadata(
             "Metadata not found for {}:{}", chain_id, token_address
         )))?;
 
-    Ok(Json(MetadataResponse::from_metadata(&metadata, state.base_url())))
+    Ok(Json(metadata_response(&state, &metadata)?))
 }
 
 // =============================================================================
@@ -303,10 +354,10 @@ async fn admin_lock_token(
         // Get existing metadata to delete images
         if let Some(metadata) = state.db.get_token_metadata(chain_id, &token_address)? {
             if let Some(id) = metadata.image_light_id {
-                delete_media_files(&state, id).await;
+                release_media(&state, id).await;
             }
             if let Some(id) = metadata.image_dark_id {
-                delete_media_files(&state, id).await;
+                release_media(&state, id).await;
             }
         }
 
@@ -398,8 +449,8 @@ async fn admin_update_metadata(
 
     // Parse multipart
     let mut metadata_json: Option<String> = None;
-    let mut image_light_data: Option<Vec<u8>> = None;
-    let mut image_dark_data: Option<Vec<u8>> = None;
+    let mut image_light_data: Option<Bytes> = None;
+    let mut image_dark_data: Option<Bytes> = None;
     let mut remove_image_light = false;
     let mut remove_image_dark = false;
 
@@ -419,14 +470,14 @@ async fn admin_update_metadata(
                 let data = field.bytes().await
                     .map_err(|e| AppError::validation(format!("Failed to read image_light: {}", e)))?;
                 if !data.is_empty() {
-                    image_light_data = Some(data.to_vec());
+                    image_light_data = Some(data);
                 }
             }
             "image_dark" => {
                 let data = field.bytes().await
                     .map_err(|e| AppError::validation(format!("Failed to read image_dark: {}", e)))?;
                 if !data.is_empty() {
-                    image_dark_data = Some(data.to_vec());
+                    image_dark_data = Some(data);
                 }
             }
             "remove_image_light" => {
@@ -462,39 +513,54 @@ async fn admin_update_metadata(
         metadata.social_networks = input.social_networks;
     }
 
-    // Handle image removal
+    // Process new images first, so a failure leaves the stored metadata as is
+    let image_light = match image_light_data {
+        Some(data) => Some(process_and_store_image(&state, "token_image", data).await?),
+        None => None,
+    };
+    let image_dark = match image_dark_data {
+        Some(data) => match process_and_store_image(&state, "token_image", data).await {
+            Ok(media) => Some(media),
+            Err(e) => {
+                if let Some(media) = &image_light {
+                    release_media(&state, media.id).await;
+                }
+                return Err(e);
+            }
+        },
+        None => None,
+    };
+    let uploaded: Vec<Uuid> = image_light.iter().chain(&image_dark).map(|m| m.id).collect();
+
+    // Removed and replaced images are released once the new metadata is saved
+    let mut replaced = Vec::new();
     if remove_image_light {
-        if let Some(id) = metadata.image_light_id.take() {
-            delete_media_files(&state, id).await;
-        }
+        replaced.extend(metadata.image_light_id.take());
     }
     if remove_image_dark {
-        if let Some(id) = metadata.image_dark_id.take() {
-            delete_media_files(&state, id).await;
-        }
+        replaced.extend(metadata.image_dark_id.take());
     }
-
-    // Handle new images
-    if let Some(data) = image_light_data {
-        if let Some(old_id) = metadata.image_light_id {
-            delete_media_files(&state, old_id).await;
-        }
-        let media = process_and_store_image(&state, &data).await?;
-        metadata.image_light_id = Some(media.id);
+    if let Some(media) = image_light {
+        replaced.extend(metadata.image_light_id.replace(media.id));
     }
-    if let Some(data) = image_dark_data {
-        if let Some(old_id) = metadata.image_dark_id {
-            delete_media_files(&state, old_id).await;
-        }
-        let media = process_and_store_image(&state, &data).await?;
-        metadata.image_dark_id = Some(media.id);
+    if let Some(media) = image_dark {
+        replaced.extend(metadata.image_dark_id.replace(media.id));
     }
 
     // Update timestamps
     metadata.updated_at = Utc::now();
     metadata.last_update_by = "admin".to_string();
 
-    state.db.upsert_token_metadata(&metadata)?;
+    if let Err(e) = state.db.upsert_token_metadata(&metadata) {
+        for id in &uploaded {
+            release_media(&state, *id).await;
+        }
+        return Err(e);
+    }
+
+    for id in replaced {
+        release_media(&state, id).await;
+    }
 
     info!(
         chain_id = chain_id,
@@ -502,7 +568,7 @@ async fn admin_update_metadata(
         "Admin updated token metadata"
     );
 
-    Ok(Json(MetadataResponse::from_metadata(&metadata, state.base_url())))
+    Ok(Json(metadata_response(&state, &metadata)?))
 }
 
 /// DELETE /admin/rexpump/metadata/{chain_id}/{token_address}
@@ -515,10 +581,10 @@ async fn admin_delete_metadata(
     // Delete associated images
     if let Some(metadata) = state.db.get_token_metadata(chain_id, &token_address)? {
         if let Some(id) = metadata.image_light_id {
-            delete_media_files(&state, id).await;
+            release_media(&state, id).await;
         }
         if let Some(id) = metadata.image_dark_id {
-            delete_media_files(&state, id).await;
+            release_media(&state, id).await;
         }
     }
 
@@ -544,64 +610,48 @@ async fn admin_delete_metadata(
 // Helper Functions
 // =============================================================================
 
-/// Process and store an uploaded image (reuses existing logic)
-async fn process_and_store_image(state: &AppState, data: &[u8]) -> Result<crate::models::Media> {
-    use crate::models::Media;
-
-    // Calculate hash for deduplication
-    let content_hash = calculate_hash(data);
-
-    // Check for duplicate
-    if let Some(existing) = state.db.find_by_hash(&content_hash)? {
-        debug!(id = %existing.id, "Found duplicate image");
-        return Ok(existing);
-    }
-
-    // Process image
-    let processed = state.image_processor.process(data, &state.config.upload)?;
-
-    // Create media record
-    let media = Media::new(
-        "token_image".to_string(),
-        processed.original_mime.clone(),
-        state.output_mime_type().to_string(),
-        processed.original_data.len() as u64,
-        processed.optimized_data.len() as u64,
-        processed.width,
-        processed.height,
-        content_hash,
-    );
-
-    // Save files
-    let original_ext = ImageProcessor::mime_to_extension(&processed.original_mime);
-    let output_ext = state.output_extension();
-
-    if state.keep_originals() {
-        state.storage.save_original(media.id, original_ext, &processed.original_data).await?;
+/// Closest locked token image that one of `images` resembles
+///
+/// The token itself is excluded, so re-uploading its own logo never matches.
+fn find_near_duplicate(
+    state: &AppState,
+    metadata: &TokenMetadata,
+    images: &[Uuid],
+) -> Result<Option<NearDuplicate>> {
+    let max_distance = state.config.rexpump.near_duplicate_max_distance;
+    let mut closest: Option<NearDuplicate> = None;
+
+    for id in images {
+        let Some(hash) = state.db.get_media(*id)?.and_then(|m| m.perceptual_hash) else {
+            continue;
+        };
+        if let Some(found) =
+            state
+                .db
+                .find_locked_near_duplicate(hash, max_distance, &metadata.storage_key())?
+        {
+            if closest.as_ref().is_none_or(|c| found.distance < c.distance) {
+                closest = Some(found);
+            }
+        }
     }
-    state.storage.save_optimized(media.id, output_ext, &processed.optimized_data).await?;
-
-    // Save to database
-    state.db.insert_media(&media)?;
 
-    debug!(id = %media.id, "Stored token image");
-    Ok(media)
+    Ok(closest)
 }
 
-/// Delete media files for a given ID
-async fn delete_media_files(state: &AppState, id: Uuid) {
-    if let Ok(Some(media)) = state.db.get_media(id) {
-        let original_ext = ImageProcessor::mime_to_extension(&media.original_mime_type);
-        let output_ext = state.output_extension();
-        
-        if let Err(e) = state.storage.delete_media_files(id, original_ext, output_ext).await {
-            warn!(id = %id, error = %e, "Failed to delete media files");
-        }
-        
-        if let Err(e) = state.db.delete_media(id) {
-            warn!(id = %id, error = %e, "Failed to delete media record");
-        }
-    }
+/// Metadata response including the placeholders of the referenced images
+fn metadata_response(state: &AppState, metadata: &TokenMetadata) -> Result<MetadataResponse> {
+    let light = match metadata.image_light_id {
+        Some(id) => state.db.get_media(id)?,
+        None => None,
+    };
+    let dark = match metadata.image_dark_id {
+        Some(id) => state.db.get_media(id)?,
+        None => None,
+    };
+
+    Ok(MetadataResponse::from_metadata(metadata, state.base_url())
+        .with_placeholders(light.as_ref(), dark.as_ref()))
 }
 
 // =============================================================================
@@ -4,97 +4,278 @@
 //!
 //! ## Endpoints
 //!
-//! - `GET /m/{id}` - Serve optimized version (format from config)
+//! - `GET /m/{id}` - Serve optimized version (format recorded per media; videos as uploaded)
+//! - `GET /m/{id}?w=&h=&fit=&focus=&fmt=&q=` - Serve a resized/converted variant
+//! - `GET /m/{id}/v/{name}` - Serve a named variant preset from config
 //! - `GET /m/{id}/original` - Serve original version (if available)
+//! - `GET /m/{id}/poster` - Serve the poster frame of a video (if extracted)
+//! - `GET /m/{name}` - Serve a built-in asset such as `default` (see
+//!   [`crate::services::assets`])
+//!
+//! All routes also answer `HEAD` and honor `Range` / `If-Range`
+//! (see [`super::range`]), so videos can be seeked and downloads resumed.
+//!
+//! ## Variants
+//!
+//! Variants are rendered on first request from the original (or the
+//! optimized file if originals are not kept) and cached on disk, keyed by
+//! the normalized parameter set. See [`crate::models::VariantQuery`].
+//!
+//! Named presets (`[processing.variants.<name>]`) normalize to the same keys
+//! and are pre-generated at upload time, so `/m/{id}/v/{name}` is normally a
+//! plain file read.
+//!
+//! ## Private Media
+//!
+//! Media uploaded as private is only served with a valid, unexpired
+//! signature (`?exp=...&sig=...`, see [`crate::services::url_signer`]) on any
+//! of the routes above; otherwise the response is `403`. Signature and
+//! variant parameters can be combined.
 //!
 //! ## Caching
 //!
 //! Responses include appropriate cache headers:
 //! - `Cache-Control: public, max-age={from config}, immutable`
-//! - `ETag` based on content hash
+//! - `Cache-Control: private, max-age={until the signature expires}` for
+//!   private media
+//! - `ETag` based on content hash, plus the stored format and quality (or
+//!   the variant key for variants)
+//! - `Last-Modified` from the time the stored files were last written
+//!   (upload, reprocess or a new focal point)
+//!
+//! `If-None-Match`, `If-Modified-Since`, `If-Match` and `If-Unmodified-Since`
+//! are evaluated per RFC 9110 (see [`super::conditional`]) on every route.
+//!
+//! Popular files of `GET /m/{id}` are kept in memory with their record
+//! (see [`crate::services::media_cache`]), so repeated hits skip the
+//! database and the disk.
+//!
+//! ## Access Tracking
+//!
+//! Every authorized request counts as a view of the media. Views and the
+//! last access time are collected in memory and written to the database
+//! periodically (see [`crate::services::access_tracker`]).
 //!
 //! ## Content Negotiation
 //!
-//! The server checks `Accept` header and may serve original format
-//! if optimized format is not supported (future enhancement).
+//! Images stored with alternate renditions (`output_formats`) are served
+//! from `GET /m/{id}` in the rendition the `Accept` header ranks highest,
+//! the default `output_format` included. Only explicitly listed types count,
+//! so clients sending just `*/*` get the default. Such responses carry
+//! `Vary: Accept`.
 
 use axum::{
-    body::Body,
-    extract::{Path, State},
-    http::{header, HeaderMap, StatusCode},
-    response::{IntoResponse, Response},
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, Method},
+    response::Response,
     routing::get,
     Router,
 };
-use tokio::fs::File;
-use tokio_util::io::ReaderStream;
+use bytes::Bytes;
+use chrono::Utc;
+use serde::Deserialize;
+use std::sync::Arc;
 use tracing::debug;
 use uuid::Uuid;
 
 use crate::error::{AppError, Result};
-use crate::services::image_processor::ImageProcessor;
+use crate::handlers::conditional::{Precondition, Validators};
+use crate::handlers::pipeline::{
+    optimized_extension, read_image_source, rendition_extensions, POSTER_KEY,
+};
+use crate::handlers::range::{FileContent, FileResponse};
+use crate::models::{
+    extension_for_mime, Focus, Media, MediaType, VariantQuery, VariantSpec, Visibility, SVG_MIME,
+};
 use crate::state::AppState;
 
+/// Content-Security-Policy for SVG originals: no scripts, no external loads
+const SVG_CONTENT_SECURITY_POLICY: &str = "default-src 'none'; style-src 'unsafe-inline'; sandbox";
+
+/// Longest `max-age` for built-in assets, which may change between
+/// deployments under the same name
+const ASSET_MAX_AGE: u64 = 3600;
+
+/// Signature of a private media URL (`?exp=...&sig=...`)
+#[derive(Debug, Default, Deserialize)]
+struct SignatureQuery {
+    /// Expiry (Unix seconds)
+    exp: Option<i64>,
+    /// Hex HMAC-SHA256 signature
+    sig: Option<String>,
+}
+
 /// Serve optimized media file
 ///
 /// GET /m/{id}
 ///
-/// Returns the optimized version of the uploaded media.
+/// Returns the optimized version of the uploaded media, or a variant
+/// if any variant query parameters are present. Ids that are not UUIDs
+/// name built-in assets.
 async fn serve_media(
     State(state): State<AppState>,
-    Path(id): Path<Uuid>,
+    Path(id): Path<String>,
+    Query(query): Query<VariantQuery>,
+    Query(signature): Query<SignatureQuery>,
+    method: Method,
     headers: HeaderMap,
 ) -> Result<Response> {
-    // Get media record
-    let media = state
-        .db
-        .get_media(id)?
-        .ok_or_else(|| AppError::not_found(format!("Media not found: {}", id)))?;
+    let Ok(id) = Uuid::parse_str(&id) else {
+        return serve_asset(&state, &id, &query, &method, &headers).await;
+    };
 
-    // Check ETag for caching
-    let etag = format!("\"{}\"", &media.content_hash);
-    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
-        if if_none_match.to_str().unwrap_or("") == etag {
-            return Ok(StatusCode::NOT_MODIFIED.into_response());
+    // Popular files are answered from memory, record included
+    let cached = if query.is_empty() {
+        state
+            .media_cache
+            .get(id, |media| negotiate_rendition(&headers, media))
+    } else {
+        None
+    };
+    let generation = state.media_cache.generation();
+
+    let (media, rendition, cached_data) = match cached {
+        Some(file) => (file.media, file.rendition, Some(file.data)),
+        None => {
+            let media = state
+                .db
+                .get_media(id)?
+                .ok_or_else(|| AppError::not_found(format!("Media not found: {}", id)))?;
+            let rendition = negotiate_rendition(&headers, &media);
+            (Arc::new(media), rendition, None)
         }
+    };
+
+    let cache_control = authorize(&state, &media, &signature)?;
+    state.access_tracker.record(id);
+
+    if !query.is_empty() {
+        return serve_variant(&state, &media, &query, &cache_control, &method, &headers).await;
     }
 
-    // Get file path using output extension from config
-    let output_ext = state.output_extension();
+    let (content_type, output_ext) = match rendition {
+        Some(index) => (
+            media.renditions[index].as_str(),
+            rendition_extensions(&media)[index],
+        ),
+        // Use the format recorded at upload (or reprocess), not the current config
+        None => (
+            media.optimized_mime_type.as_str(),
+            optimized_extension(&media),
+        ),
+    };
+    let etag = stored_file_etag(&media, output_ext);
+    let vary = !media.renditions.is_empty();
+
     let file_path = state.storage.optimized_path(id, output_ext);
 
-    if !file_path.exists() {
-        return Err(AppError::not_found(format!(
-            "Optimized file not found for media: {}",
-            id
-        )));
+    let content = match cached_data {
+        Some(data) => FileContent::Bytes(data),
+        None => {
+            let size = match tokio::fs::metadata(&file_path).await {
+                Ok(metadata) => metadata.len(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    return Err(AppError::not_found(format!(
+                        "Optimized file not found for media: {}",
+                        id
+                    )));
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            if state.media_cache.admits(size) {
+                let data = Bytes::from(tokio::fs::read(&file_path).await?);
+                state
+                    .media_cache
+                    .insert(generation, &media, rendition, data.clone());
+                FileContent::Bytes(data)
+            } else {
+                FileContent::Path(&file_path)
+            }
+        }
+    };
+
+    let mut response = FileResponse {
+        content,
+        content_type,
+        validators: Validators {
+            etag: &etag,
+            last_modified: Some(media.updated_at),
+        },
+        cache_control: &cache_control,
+        content_disposition: None,
+    }
+    .into_response(&method, &headers)
+    .await?;
+
+    if vary {
+        response
+            .headers_mut()
+            .insert(header::VARY, HeaderValue::from_static("accept"));
     }
 
-    // Update last accessed time (fire and forget)
-    let db = state.db.clone();
-    tokio::spawn(async move {
-        let _ = db.update_last_accessed(id);
-    });
+    debug!(
+        id = %id,
+        content_type = %content_type,
+        status = %response.status(),
+        "Served optimized media"
+    );
+
+    Ok(response)
+}
+
+/// Serve a built-in asset
+///
+/// GET /m/{name}
+///
+/// Assets are served from memory with a content-hash ETag. They are not
+/// `immutable` and cached for at most [`ASSET_MAX_AGE`], since a deployment
+/// may replace the file behind a name.
+async fn serve_asset(
+    state: &AppState,
+    name: &str,
+    query: &VariantQuery,
+    method: &Method,
+    headers: &HeaderMap,
+) -> Result<Response> {
+    let asset = state
+        .assets
+        .get(name)
+        .ok_or_else(|| AppError::not_found(format!("Media not found: {}", name)))?;
+
+    if !query.is_empty() {
+        return Err(AppError::validation(
+            "Variants are not available for built-in assets",
+        ));
+    }
 
-    // Open file and create stream
-    let file = File::open(&file_path).await?;
-    let stream = ReaderStream::new(file);
-    let body = Body::from_stream(stream);
+    let etag = format!("\"{}\"", &asset.content_hash);
+    let cache_control = format!(
+        "public, max-age={}",
+        state.cache_max_age().min(ASSET_MAX_AGE)
+    );
 
-    // Build cache control header from config
-    let cache_control = format!("public, max-age={}, immutable", state.cache_max_age());
+    let mut response = FileResponse {
+        content: FileContent::Bytes(asset.data.clone()),
+        content_type: asset.mime_type,
+        validators: Validators {
+            etag: &etag,
+            last_modified: None,
+        },
+        cache_control: &cache_control,
+        content_disposition: None,
+    }
+    .into_response(method, headers)
+    .await?;
 
-    // Build response with headers
-    let response = Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, state.output_mime_type())
-        .header(header::CACHE_CONTROL, cache_control)
-        .header(header::ETAG, etag)
-        .header("X-Content-Type-Options", "nosniff")
-        .body(body)
-        .map_err(|e| AppError::internal(format!("Failed to build response: {}", e)))?;
+    if asset.mime_type == SVG_MIME {
+        response.headers_mut().insert(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(SVG_CONTENT_SECURITY_POLICY),
+        );
+    }
 
-    debug!(id = %id, "Served optimized media");
+    debug!(name = %name, status = %response.status(), "Served built-in asset");
 
     Ok(response)
 }
@@ -107,6 +288,8 @@ async fn serve_media(
 async fn serve_original(
     State(state): State<AppState>,
     Path(id): Path<Uuid>,
+    Query(signature): Query<SignatureQuery>,
+    method: Method,
     headers: HeaderMap,
 ) -> Result<Response> {
     // Check if originals are kept
@@ -122,16 +305,13 @@ async fn serve_original(
         .get_media(id)?
         .ok_or_else(|| AppError::not_found(format!("Media not found: {}", id)))?;
 
-    // Check ETag for caching
+    let cache_control = authorize(&state, &media, &signature)?;
+    state.access_tracker.record(id);
+
     let etag = format!("\"{}\"", &media.content_hash);
-    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
-        if if_none_match.to_str().unwrap_or("") == etag {
-            return Ok(StatusCode::NOT_MODIFIED.into_response());
-        }
-    }
 
     // Get file path
-    let ext = ImageProcessor::mime_to_extension(&media.original_mime_type);
+    let ext = extension_for_mime(&media.original_mime_type);
     let file_path = state.storage.original_path(id, ext);
 
     if !file_path.exists() {
@@ -141,36 +321,303 @@ async fn serve_original(
         )));
     }
 
-    // Open file and create stream
-    let file = File::open(&file_path).await?;
-    let stream = ReaderStream::new(file);
-    let body = Body::from_stream(stream);
-
-    // Build cache control header from config
-    let cache_control = format!("public, max-age={}, immutable", state.cache_max_age());
-
-    // Build response with headers
-    let response = Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, &media.original_mime_type)
-        .header(header::CACHE_CONTROL, cache_control)
-        .header(header::ETAG, etag)
-        .header("X-Content-Type-Options", "nosniff")
-        .header(
-            header::CONTENT_DISPOSITION,
-            format!(
-                "inline; filename=\"{}\"",
-                sanitize_filename(&media.original_filename)
-            ),
-        )
-        .body(body)
-        .map_err(|e| AppError::internal(format!("Failed to build response: {}", e)))?;
-
-    debug!(id = %id, "Served original media");
+    let mut response = FileResponse {
+        content: FileContent::Path(&file_path),
+        content_type: &media.original_mime_type,
+        validators: Validators {
+            etag: &etag,
+            last_modified: Some(media.updated_at),
+        },
+        cache_control: &cache_control,
+        content_disposition: Some(format!(
+            "inline; filename=\"{}\"",
+            sanitize_filename(&media.original_filename)
+        )),
+    }
+    .into_response(&method, &headers)
+    .await?;
+
+    // Stored SVG is sanitized already; this keeps browsers from running
+    // anything that might have slipped through when opened directly
+    if media.original_mime_type == SVG_MIME {
+        response.headers_mut().insert(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(SVG_CONTENT_SECURITY_POLICY),
+        );
+    }
+
+    debug!(id = %id, status = %response.status(), "Served original media");
 
     Ok(response)
 }
 
+/// Serve the poster frame of a video
+///
+/// GET /m/{id}/poster
+///
+/// Posters are extracted at upload when `ffmpeg_path` is configured; other
+/// media answer 404.
+async fn serve_poster(
+    State(state): State<AppState>,
+    Path(id): Path<Uuid>,
+    Query(signature): Query<SignatureQuery>,
+    method: Method,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let media = state
+        .db
+        .get_media(id)?
+        .ok_or_else(|| AppError::not_found(format!("Media not found: {}", id)))?;
+
+    let cache_control = authorize(&state, &media, &signature)?;
+    let content_type = media
+        .poster_mime_type
+        .as_deref()
+        .ok_or_else(|| AppError::not_found(format!("No poster frame for media: {}", id)))?;
+    state.access_tracker.record(id);
+
+    let ext = extension_for_mime(content_type);
+    let etag = format!("\"{}-{}.{}\"", media.content_hash, POSTER_KEY, ext);
+    let file_path = state.storage.variant_path(id, POSTER_KEY, ext);
+
+    if !file_path.exists() {
+        return Err(AppError::not_found(format!(
+            "Poster file not found for media: {}",
+            id
+        )));
+    }
+
+    let response = FileResponse {
+        content: FileContent::Path(&file_path),
+        content_type,
+        validators: Validators {
+            etag: &etag,
+            last_modified: Some(media.updated_at),
+        },
+        cache_control: &cache_control,
+        content_disposition: None,
+    }
+    .into_response(&method, &headers)
+    .await?;
+
+    debug!(id = %id, status = %response.status(), "Served poster frame");
+
+    Ok(response)
+}
+
+/// Serve a variant of an image, rendering and caching it on first request
+async fn serve_variant(
+    state: &AppState,
+    media: &Media,
+    query: &VariantQuery,
+    cache_control: &str,
+    method: &Method,
+    headers: &HeaderMap,
+) -> Result<Response> {
+    let spec = state.config.processing.variant_spec(query)?;
+    serve_variant_spec(state, media, &spec, cache_control, method, headers).await
+}
+
+/// Serve a named variant preset
+///
+/// GET /m/{id}/v/{name}
+///
+/// Presets are pre-generated at upload time, so this is normally a static
+/// read. Missing files (e.g. presets added after upload) are rendered lazily.
+async fn serve_preset(
+    State(state): State<AppState>,
+    Path((id, name)): Path<(Uuid, String)>,
+    Query(signature): Query<SignatureQuery>,
+    method: Method,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let spec = state
+        .config
+        .processing
+        .preset_spec(&name)
+        .ok_or_else(|| AppError::not_found(format!("Unknown variant preset: {}", name)))??;
+
+    let media = state
+        .db
+        .get_media(id)?
+        .ok_or_else(|| AppError::not_found(format!("Media not found: {}", id)))?;
+
+    let cache_control = authorize(&state, &media, &signature)?;
+    state.access_tracker.record(id);
+    serve_variant_spec(&state, &media, &spec, &cache_control, &method, &headers).await
+}
+
+/// Serve a normalized variant, rendering and caching it if missing
+async fn serve_variant_spec(
+    state: &AppState,
+    media: &Media,
+    spec: &VariantSpec,
+    cache_control: &str,
+    method: &Method,
+    headers: &HeaderMap,
+) -> Result<Response> {
+    if media.media_type != MediaType::Image {
+        return Err(AppError::validation("Variants are only available for images"));
+    }
+
+    let key = spec.cache_key();
+    let ext = spec.format.extension();
+
+    // Each variant gets its own ETag so caches never mix renditions. An auto
+    // crop also follows the focal point, which can be moved after upload
+    let focus = match (spec.focus, media.focal_point) {
+        (Focus::Auto, Some(point)) => Focus::Point(point).key_suffix(),
+        _ => String::new(),
+    };
+    let etag = format!("\"{}-{}{}.{}\"", media.content_hash, key, focus, ext);
+    let validators = Validators {
+        etag: &etag,
+        last_modified: Some(media.updated_at),
+    };
+
+    let file_path = state.storage.variant_path(media.id, &key, ext);
+
+    // No need to render a missing variant for a 304 or 412
+    if !file_path.exists() && validators.evaluate(method, headers) == Precondition::Proceed {
+        let source = read_image_source(state, media).await?;
+        let processor = Arc::clone(&state.image_processor);
+        let render_spec = spec.clone();
+        let focal_point = media.focal_point;
+        let data = state
+            .processing_pool
+            .run(move || processor.render_variant(&source, &render_spec, focal_point))
+            .await?;
+        state
+            .storage
+            .save_variant(media.id, &key, ext, &data)
+            .await?;
+
+        debug!(id = %media.id, key = %key, "Generated variant");
+    }
+
+    let response = FileResponse {
+        content: FileContent::Path(&file_path),
+        content_type: spec.format.mime_type(),
+        validators,
+        cache_control,
+        content_disposition: None,
+    }
+    .into_response(method, headers)
+    .await?;
+
+    debug!(id = %media.id, key = %key, "Served variant");
+
+    Ok(response)
+}
+
+/// ETag of a stored optimized file or rendition
+///
+/// Reprocessing re-encodes the same content into another format or quality
+/// under the same URL, so both are part of the tag next to the content hash.
+fn stored_file_etag(media: &Media, ext: &str) -> String {
+    match media.quality {
+        Some(quality) => format!("\"{}.{}-q{}\"", media.content_hash, ext, quality),
+        None => format!("\"{}.{}\"", media.content_hash, ext),
+    }
+}
+
+/// Check that the request may fetch the media and pick its `Cache-Control`
+///
+/// Public media is cached for `cache_max_age` as immutable. Private media
+/// needs a valid signature and is cached privately, no longer than the
+/// signature lasts.
+fn authorize(state: &AppState, media: &Media, signature: &SignatureQuery) -> Result<String> {
+    if media.visibility == Visibility::Public {
+        return Ok(format!("public, max-age={}, immutable", state.cache_max_age()));
+    }
+
+    let (Some(exp), Some(sig)) = (signature.exp, signature.sig.as_deref()) else {
+        return Err(AppError::NotAuthorized(
+            "Private media needs a signed URL".to_string(),
+        ));
+    };
+
+    if !state.url_signer.verify(media.id, exp, sig) {
+        return Err(AppError::NotAuthorized("Invalid URL signature".to_string()));
+    }
+
+    let remaining = exp.saturating_sub(Utc::now().timestamp());
+    if remaining <= 0 {
+        return Err(AppError::NotAuthorized("Signed URL has expired".to_string()));
+    }
+
+    Ok(format!(
+        "private, max-age={}",
+        (remaining as u64).min(state.cache_max_age())
+    ))
+}
+
+/// Pick the stored rendition the client prefers, by index into `media.renditions`
+///
+/// The default file (`optimized_mime_type`) and the renditions are ranked
+/// together by the `q` the `Accept` header gives their exact type. Wildcards
+/// are ignored on purpose: `*/*` does not mean a client can decode AVIF. Ties
+/// go to the rendition listed first in `output_formats`, then the default.
+///
+/// Returns `None` (serve the default) unless a rendition ranks highest. If
+/// nothing is listed with a non-zero `q` but the default is refused with
+/// `q=0`, the first rendition not refused as well is served instead.
+fn negotiate_rendition(headers: &HeaderMap, media: &Media) -> Option<usize> {
+    if media.renditions.is_empty() {
+        return None;
+    }
+
+    let accept = headers.get(header::ACCEPT)?.to_str().ok()?;
+    let ranges: Vec<(&str, f32)> = accept
+        .split(',')
+        .map(|range| {
+            let mut params = range.split(';');
+            let mime = params.next().unwrap_or("").trim();
+            let quality = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            (mime, quality)
+        })
+        .collect();
+
+    // Quality the header explicitly gives a type, if it lists it
+    let quality_of = |mime: &str| {
+        ranges
+            .iter()
+            .find(|(range, _)| range.eq_ignore_ascii_case(mime))
+            .map(|&(_, quality)| quality)
+    };
+
+    // Renditions in order of preference, then the default file
+    let candidates = media
+        .renditions
+        .iter()
+        .enumerate()
+        .map(|(index, mime)| (Some(index), quality_of(mime)))
+        .chain(std::iter::once((None, quality_of(&media.optimized_mime_type))));
+
+    let mut best: Option<(Option<usize>, f32)> = None;
+    let mut fallback = None;
+    for (rendition, quality) in candidates {
+        match quality {
+            Some(quality) if quality > 0.0 => {
+                if best.is_none_or(|(_, best_quality)| quality > best_quality) {
+                    best = Some((rendition, quality));
+                }
+            }
+            Some(_) => {}
+            None => fallback = fallback.or(rendition),
+        }
+    }
+
+    match best {
+        Some((rendition, _)) => rendition,
+        None if quality_of(&media.optimized_mime_type) == Some(0.0) => fallback,
+        None => None,
+    }
+}
+
 /// Sanitize filename for Content-Disposition header
 fn sanitize_filename(filename: &str) -> String {
     filename
@@ -184,5 +631,7 @@ pub fn serve_routes() -> Router<AppState> {
     Router::new()
         .route("/{id}", get(serve_media))
         .route("/{id}/original", get(serve_original))
+        .route("/{id}/poster", get(serve_poster))
+        .route("/{id}/v/{name}", get(serve_preset))
 }
 
@@ -11,6 +11,10 @@
 //! - `POST /api/upload/{id}/complete` - Complete the upload
 //! - `GET /api/upload/{id}/status` - Get upload status (for resuming)
 //!
+//! With `upload.async_processing` enabled, `complete` returns `202 Accepted`
+//! and the upload is processed by background workers; the status endpoint
+//! then reports the resulting `media_id` or the failure.
+//!
 //! # Example: Simple Upload
 //!
 //! ```bash
@@ -18,6 +22,15 @@
 //!   -F "file=@image.jpg"
 //! ```
 //!
+//! An optional `focal_point` field (`x,y` fractions, e.g. `0.3,0.25`)
+//! replaces the detected focal point of a newly stored image. Fields may come
+//! in any order; only the first `file` field is stored.
+//!
+//! A `visibility` field (`public` or `private`; chunked uploads take it in
+//! the init request) marks the media private: it is then only served to
+//! signed URLs, and the response carries URLs signed for
+//! `auth.signed_url_ttl_seconds` (see [`crate::services::url_signer`]).
+//!
 //! # Example: Chunked Upload
 //!
 //! ```bash
@@ -38,19 +51,31 @@
 use axum::{
     extract::{Multipart, Path, State},
     http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     routing::{get, patch, post},
     Json, Router,
 };
+use bytes::Bytes;
+use std::time::Duration;
 use tracing::{debug, info, warn};
 use uuid::Uuid;
 
 use crate::error::{AppError, Result};
+use crate::handlers::pipeline::{
+    process_and_store_file, process_and_store_upload, set_focal_point,
+};
 use crate::models::{
-    InitUploadRequest, Media, UploadResponse, UploadSession, UploadSessionResponse,
+    FocalPoint, InitUploadRequest, Media, MediaType, UploadResponse, UploadSession,
+    UploadSessionResponse, UploadSessionStatus, Visibility,
 };
-use crate::services::image_processor::{calculate_hash, ImageProcessor};
+use crate::services::image_processor::calculate_file_hash;
 use crate::state::AppState;
 
+/// Number of times a queued upload is started before it is marked failed
+///
+/// Keeps an upload that takes the server down from being retried forever.
+const MAX_PROCESSING_ATTEMPTS: u32 = 3;
+
 // =============================================================================
 // Simple Upload
 // =============================================================================
@@ -59,14 +84,18 @@ use crate::state::AppState;
 ///
 /// POST /api/upload
 ///
-/// Accepts a multipart form with a `file` field containing the image.
+/// Accepts a multipart form with a `file` field containing the image or video,
+/// and optionally `focal_point` (`x,y`) and `visibility` fields, in any order.
+/// Further `file` fields are skipped.
 /// Returns the media ID and URL on success.
 async fn simple_upload(
     State(state): State<AppState>,
     mut multipart: Multipart,
 ) -> Result<(StatusCode, Json<UploadResponse>)> {
     // Extract file from multipart
-    let mut file_data: Option<(String, Vec<u8>)> = None;
+    let mut file_data: Option<(String, Bytes)> = None;
+    let mut focal_point: Option<FocalPoint> = None;
+    let mut visibility = Visibility::Public;
 
     while let Some(field) = multipart
         .next_field()
@@ -76,6 +105,11 @@ async fn simple_upload(
         let name = field.name().unwrap_or("").to_string();
 
         if name == "file" {
+            // A later file never replaces the first one
+            if file_data.is_some() {
+                continue;
+            }
+
             let filename = field
                 .file_name()
                 .map(|s| s.to_string())
@@ -95,24 +129,56 @@ async fn simple_upload(
                 )));
             }
 
-            file_data = Some((filename, data.to_vec()));
-            break;
+            file_data = Some((filename, data));
+        } else if name == "focal_point" {
+            let value = field
+                .text()
+                .await
+                .map_err(|e| AppError::validation(format!("Failed to read focal_point: {}", e)))?;
+
+            focal_point = Some(FocalPoint::parse(&value).ok_or_else(|| {
+                AppError::validation(format!(
+                    "Invalid focal_point '{}': expected x,y between 0 and 1",
+                    value
+                ))
+            })?);
+        } else if name == "visibility" {
+            let value = field
+                .text()
+                .await
+                .map_err(|e| AppError::validation(format!("Failed to read visibility: {}", e)))?;
+
+            visibility = Visibility::parse(&value).ok_or_else(|| {
+                AppError::validation(format!(
+                    "Invalid visibility '{}': expected public or private",
+                    value
+                ))
+            })?;
         }
     }
 
+    check_visibility(&state, visibility)?;
+
     let (filename, data) = file_data.ok_or_else(|| {
         AppError::validation("No file field found in multipart request")
     })?;
 
     info!(filename = %filename, size = data.len(), "Received upload");
 
-    // Process the image
-    let media = process_and_store_image(&state, &filename, &data).await?;
+    // Process the image (or probe the video)
+    let mut media = process_and_store_upload(&state, &filename, data, visibility).await?;
 
-    // Return response
-    let response = UploadResponse::from_media(&media, state.base_url(), state.keep_originals());
+    // The uploader only decides for media nobody else references (not a
+    // duplicate of an earlier upload)
+    if let Some(point) = focal_point {
+        if media.media_type == MediaType::Image && state.db.get_media_refs(media.id)? == 1 {
+            if let Some(updated) = set_focal_point(&state, media.id, point).await? {
+                media = updated;
+            }
+        }
+    }
 
-    Ok((StatusCode::CREATED, Json(response)))
+    Ok((StatusCode::CREATED, Json(upload_response(&state, &media))))
 }
 
 // =============================================================================
@@ -148,14 +214,17 @@ async fn init_chunked_upload(
         )));
     }
 
+    check_visibility(&state, request.visibility)?;
+
     // Create session
-    let session = UploadSession::new(
+    let mut session = UploadSession::new(
         request.filename,
         request.mime_type,
         request.total_size,
         state.chunk_size(),
         state.upload_session_timeout(),
     );
+    session.visibility = request.visibility;
 
     // Create temp directory for chunks
     state.storage.create_temp_session_dir(session.id).await?;
@@ -169,7 +238,7 @@ async fn init_chunked_upload(
         "Created upload session"
     );
 
-    let response = UploadSessionResponse::from_session(&session, Some(state.base_url()));
+    let response = session_response(&state, &session);
 
     Ok((StatusCode::CREATED, Json(response)))
 }
@@ -218,10 +287,7 @@ async fn upload_chunk(
             "Chunk offset mismatch"
         );
         // Return current status so client can resume correctly
-        return Ok(Json(UploadSessionResponse::from_session(
-            &session,
-            Some(state.base_url()),
-        )));
+        return Ok(Json(session_response(&state, &session)));
     }
 
     // Save chunk data
@@ -230,8 +296,8 @@ async fn upload_chunk(
         .append_to_temp_file(session_id, &body)
         .await?;
 
-    // Update session
-    session.add_received_bytes(body.len() as u64);
+    // Update session (byte count and running content hash)
+    session.add_chunk(&body);
     state.db.update_session(&session)?;
 
     debug!(
@@ -242,21 +308,23 @@ async fn upload_chunk(
         "Chunk received"
     );
 
-    Ok(Json(UploadSessionResponse::from_session(
-        &session,
-        Some(state.base_url()),
-    )))
+    Ok(Json(session_response(&state, &session)))
 }
 
 /// Complete a chunked upload
 ///
 /// POST /api/upload/{id}/complete
 ///
-/// Assembles all chunks and processes the final file.
+/// Processes the assembled file from disk. The content hash was computed
+/// while the chunks arrived, so duplicates are resolved without reading
+/// the file at all.
+///
+/// In async mode the session is queued instead and `202 Accepted` is
+/// returned with the session status.
 async fn complete_upload(
     State(state): State<AppState>,
     Path(session_id): Path<Uuid>,
-) -> Result<Json<UploadResponse>> {
+) -> Result<Response> {
     // Get session
     let mut session = state
         .db
@@ -271,15 +339,28 @@ async fn complete_upload(
         )));
     }
 
+    // Already queued (repeated complete): report progress, don't queue twice
+    if session.status == UploadSessionStatus::Processing
+        && state.db.get_processing_job(session_id)?.is_some()
+    {
+        return Ok(accepted(&state, &session));
+    }
+
     // Mark as processing
     session.mark_processing();
-    state.db.update_session(&session)?;
 
-    // Read assembled file
-    let data = state.storage.read_temp_file(session_id).await?;
+    if state.config.upload.async_processing {
+        state.db.enqueue_processing_job(&session)?;
+        state.processing_jobs.push(session_id);
+
+        info!(session_id = %session_id, "Queued chunked upload for processing");
+        return Ok(accepted(&state, &session));
+    }
+
+    state.db.update_session(&session)?;
 
-    // Process the image
-    let media = match process_and_store_image(&state, &session.filename, &data).await {
+    // Process the assembled file straight from disk
+    let media = match finalize_upload(&state, &session).await {
         Ok(media) => {
             // Mark session as completed
             session.mark_completed(media.id);
@@ -306,16 +387,15 @@ async fn complete_upload(
         "Completed chunked upload"
     );
 
-    let response = UploadResponse::from_media(&media, state.base_url(), state.keep_originals());
-
-    Ok(Json(response))
+    Ok(Json(upload_response(&state, &media)).into_response())
 }
 
 /// Get upload session status
 ///
 /// GET /api/upload/{id}/status
 ///
-/// Returns current upload progress for resuming interrupted uploads.
+/// Returns current upload progress for resuming interrupted uploads, and
+/// the `media_id` or error once a queued upload has been processed.
 async fn get_upload_status(
     State(state): State<AppState>,
     Path(session_id): Path<Uuid>,
@@ -325,77 +405,159 @@ async fn get_upload_status(
         .get_session(session_id)?
         .ok_or_else(|| AppError::not_found(format!("Upload session not found: {}", session_id)))?;
 
-    Ok(Json(UploadSessionResponse::from_session(
-        &session,
-        Some(state.base_url()),
-    )))
+    Ok(Json(session_response(&state, &session)))
 }
 
 // =============================================================================
-// Helper Functions
+// Background Processing
 // =============================================================================
 
-/// Process and store an uploaded image
-async fn process_and_store_image(
-    state: &AppState,
-    filename: &str,
-    data: &[u8],
-) -> Result<Media> {
-    // Calculate content hash for deduplication
-    let content_hash = calculate_hash(data);
-
-    // Check for duplicate
-    if let Some(existing) = state.db.find_by_hash(&content_hash)? {
-        info!(
-            existing_id = %existing.id,
-            hash = %content_hash,
-            "Found duplicate content, returning existing media"
+/// Process a queued chunked upload (async mode)
+///
+/// Runs the same pipeline as a synchronous `complete` and records the
+/// outcome on the session. Jobs that no longer exist are ignored, so a
+/// session may safely be queued more than once.
+pub async fn run_processing_job(state: &AppState, session_id: Uuid) -> Result<()> {
+    let Some(mut job) = state.db.get_processing_job(session_id)? else {
+        return Ok(());
+    };
+
+    let Some(mut session) = state.db.get_session(session_id)? else {
+        warn!(session_id = %session_id, "Dropping processing job of a missing session");
+        state.db.delete_processing_job(session_id)?;
+        return Ok(());
+    };
+
+    if job.attempts >= MAX_PROCESSING_ATTEMPTS {
+        warn!(
+            session_id = %session_id,
+            attempts = job.attempts,
+            "Giving up on repeatedly interrupted upload"
         );
-        return Ok(existing);
+        session.mark_failed(format!(
+            "Processing was interrupted {} times",
+            job.attempts
+        ));
+        state.db.finish_processing_job(&session)?;
+        return Ok(());
     }
 
-    // Process the image
-    let processed = state.image_processor.process(data, &state.config.upload)?;
-
-    // Create media record using output format from config
-    let media = Media::new(
-        filename.to_string(),
-        processed.original_mime.clone(),
-        state.output_mime_type().to_string(),
-        processed.original_data.len() as u64,
-        processed.optimized_data.len() as u64,
-        processed.width,
-        processed.height,
-        content_hash,
-    );
+    // Counted before starting, so a crash mid-processing still counts
+    job.attempts += 1;
+    state.db.update_processing_job(&job)?;
+
+    let outcome = loop {
+        match finalize_upload(state, &session).await {
+            // Direct uploads keep the pool busy: wait for room instead of failing
+            Err(AppError::ServiceUnavailable {
+                retry_after_secs, ..
+            }) => tokio::time::sleep(Duration::from_secs(retry_after_secs)).await,
+            outcome => break outcome,
+        }
+    };
 
-    // Save files
-    let original_ext = ImageProcessor::mime_to_extension(&processed.original_mime);
-    let output_ext = state.output_extension();
+    match outcome {
+        Ok(media) => {
+            session.mark_completed(media.id);
+            state.db.finish_processing_job(&session)?;
+
+            if let Err(e) = state.storage.delete_temp_session(session_id).await {
+                warn!(session_id = %session_id, error = %e, "Failed to cleanup temp session");
+            }
 
-    if state.keep_originals() {
-        state
-            .storage
-            .save_original(media.id, original_ext, &processed.original_data)
-            .await?;
+            info!(
+                session_id = %session_id,
+                media_id = %media.id,
+                "Completed queued chunked upload"
+            );
+        }
+        Err(e) => {
+            warn!(session_id = %session_id, error = %e, "Queued chunked upload failed");
+            session.mark_failed(e.to_string());
+            state.db.finish_processing_job(&session)?;
+        }
     }
 
-    state
-        .storage
-        .save_optimized(media.id, output_ext, &processed.optimized_data)
-        .await?;
+    Ok(())
+}
 
-    // Save to database
-    state.db.insert_media(&media)?;
+// =============================================================================
+// Helper Functions
+// =============================================================================
 
-    info!(
-        id = %media.id,
-        original_size = processed.original_data.len(),
-        optimized_size = processed.optimized_data.len(),
-        "Stored media"
-    );
+/// `202 Accepted` with the session status, for uploads processed in the background
+fn accepted(state: &AppState, session: &UploadSession) -> Response {
+    let response = session_response(state, session);
+    (StatusCode::ACCEPTED, Json(response)).into_response()
+}
 
-    Ok(media)
+/// Private uploads need a key to sign their URLs with
+fn check_visibility(state: &AppState, visibility: Visibility) -> Result<()> {
+    if visibility == Visibility::Private && !state.url_signer.is_enabled() {
+        return Err(AppError::validation(
+            "Private uploads are not enabled (no auth.url_signing_keys configured)",
+        ));
+    }
+    Ok(())
+}
+
+/// Response for stored media, with signed URLs if it is private
+fn upload_response(state: &AppState, media: &Media) -> UploadResponse {
+    let response = UploadResponse::from_media(media, state.base_url(), state.keep_originals())
+        .with_variants(state.config.processing.variants.keys(), state.base_url());
+
+    if media.visibility != Visibility::Private {
+        return response;
+    }
+    match state
+        .url_signer
+        .signed_query(media.id, state.url_signer.default_ttl())
+    {
+        Some(signed) => response.with_signature(&signed.query, signed.expires_at),
+        None => response,
+    }
+}
+
+/// Session status, with a signed media URL for private uploads
+fn session_response(state: &AppState, session: &UploadSession) -> UploadSessionResponse {
+    let mut response = UploadSessionResponse::from_session(session, Some(state.base_url()));
+
+    if session.visibility == Visibility::Private {
+        let signed = session
+            .media_id
+            .and_then(|id| state.url_signer.signed_query(id, state.url_signer.default_ttl()));
+        if let (Some(url), Some(signed)) = (response.media_url.as_mut(), signed) {
+            url.push('?');
+            url.push_str(&signed.query);
+        }
+    }
+    response
+}
+
+/// Hash (if needed) and process the assembled temp file of a session
+async fn finalize_upload(state: &AppState, session: &UploadSession) -> Result<Media> {
+    let temp_path = state.storage.temp_file_path(session.id);
+    if !temp_path.exists() {
+        return Err(AppError::not_found(format!(
+            "Temp file not found for session: {}",
+            session.id
+        )));
+    }
+
+    // Sessions from before incremental hashing fall back to a streaming hash
+    let content_hash = match session.content_hash() {
+        Some(hash) => hash,
+        None => calculate_file_hash(&temp_path).await?,
+    };
+
+    process_and_store_file(
+        state,
+        &session.filename,
+        &temp_path,
+        content_hash,
+        session.visibility,
+    )
+    .await
 }
 
 /// Parse Content-Range header
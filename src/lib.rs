@@ -56,9 +56,12 @@ pub use middleware::{ApiKeyAuth, RateLimiter};
 pub use state::AppState;
 
 use axum::Router;
+use std::collections::HashSet;
 use std::net::SocketAddr;
 use std::time::Duration;
 use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
 use tower_http::{
     cors::{Any, CorsLayer},
     limit::RequestBodyLimitLayer,
@@ -99,30 +102,90 @@ pub async fn run(config: Config) -> anyhow::Result<()> {
         "Admin API server starting"
     );
 
+    // Rehash legacy media records (no-op once completed)
+    let migration_state = state.clone();
+    tokio::task::spawn_blocking(move || migrate_content_hashes(&migration_state));
+
+    // Start background processing (resumes jobs queued before a restart)
+    start_processing_workers(&state);
+
     // Start cleanup task
     let cleanup_state = state.clone();
     tokio::spawn(async move {
         cleanup_task(cleanup_state).await;
     });
 
-    // Run both servers concurrently
+    // Start writing view counts to the database
+    tokio::spawn(access_flush_task(state.clone()));
+
+    // Run both servers concurrently. On Ctrl+C or SIGTERM, or if either
+    // server fails, both stop accepting and finish their open requests
     let public_listener = TcpListener::bind(public_addr).await?;
     let admin_listener = TcpListener::bind(admin_addr).await?;
+    let (stop, stopped) = watch::channel(false);
 
-    tokio::select! {
-        result = axum::serve(public_listener, public_app) => {
-            if let Err(e) = result {
-                tracing::error!(error = %e, "Public server error");
-            }
+    let public = async {
+        let result = axum::serve(public_listener, public_app)
+            .with_graceful_shutdown(wait_for_stop(stopped.clone()))
+            .await;
+        if let Err(e) = result {
+            tracing::error!(error = %e, "Public server error");
+        }
+        stop.send_replace(true);
+    };
+    let admin = async {
+        let result = axum::serve(admin_listener, admin_app)
+            .with_graceful_shutdown(wait_for_stop(stopped.clone()))
+            .await;
+        if let Err(e) = result {
+            tracing::error!(error = %e, "Admin server error");
+        }
+        stop.send_replace(true);
+    };
+    let signal = async {
+        tokio::select! {
+            _ = shutdown_signal() => info!("Shutting down"),
+            _ = wait_for_stop(stopped.clone()) => {}
         }
-        result = axum::serve(admin_listener, admin_app) => {
-            if let Err(e) = result {
-                tracing::error!(error = %e, "Admin server error");
+        stop.send_replace(true);
+    };
+    tokio::join!(public, admin, signal);
+
+    // Views counted since the last periodic flush
+    flush_media_access(&state).await;
+
+    Ok(())
+}
+
+/// Resolve once Ctrl+C or (on Unix) SIGTERM is received
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
             }
+            Err(_) => std::future::pending::<()>().await,
         }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
     }
+}
 
-    Ok(())
+/// Resolve once the servers are told to stop
+async fn wait_for_stop(mut stopped: watch::Receiver<bool>) {
+    let _ = stopped.wait_for(|stop| *stop).await;
 }
 
 /// Create the public API router
@@ -182,6 +245,93 @@ pub fn create_admin_router(state: AppState) -> Router {
         .with_state(state)
 }
 
+/// One-shot migration of legacy content hashes to SHA-256
+///
+/// Rehashes each legacy record's original file, if it is stored byte for
+/// byte as uploaded (its legacy hash still matches). Originals that were
+/// re-encoded (EXIF stripped) or not kept cannot produce the hash of a
+/// re-upload, so those records keep their legacy hash and no longer dedup.
+fn migrate_content_hashes(state: &AppState) {
+    use services::image_processor::{calculate_hash, legacy_hash};
+
+    let result = state.db.migrate_content_hashes(|media| {
+        let ext = models::extension_for_mime(&media.original_mime_type);
+        let data = std::fs::read(state.storage.original_path(media.id, ext)).ok()?;
+        (legacy_hash(&data) == media.content_hash).then(|| calculate_hash(&data))
+    });
+
+    if let Err(e) = result {
+        tracing::error!(error = %e, "Content hash migration failed");
+    }
+}
+
+/// Start the workers that process queued chunked uploads
+///
+/// Jobs persisted by an earlier run (queued or interrupted mid-processing)
+/// are queued again first. Workers run until their handles are aborted.
+pub fn start_processing_workers(state: &AppState) -> Vec<JoinHandle<()>> {
+    match state.db.list_processing_jobs() {
+        Ok(jobs) => {
+            if !jobs.is_empty() {
+                info!(count = jobs.len(), "Resuming queued upload processing");
+            }
+            for job in jobs {
+                state.processing_jobs.push(job.session_id);
+            }
+        }
+        Err(e) => tracing::error!(error = %e, "Failed to load queued processing jobs"),
+    }
+
+    (0..state.config.upload.processing_workers)
+        .map(|_| tokio::spawn(processing_worker(state.clone())))
+        .collect()
+}
+
+/// Background worker draining the processing queue
+async fn processing_worker(state: AppState) {
+    while let Some(session_id) = state.processing_jobs.next().await {
+        if let Err(e) = handlers::upload::run_processing_job(&state, session_id).await {
+            tracing::error!(session_id = %session_id, error = %e, "Processing job failed");
+        }
+    }
+}
+
+/// Background task writing view counts collected in memory to the database
+async fn access_flush_task(state: AppState) {
+    let interval = Duration::from_secs(state.access_flush_interval());
+
+    loop {
+        tokio::time::sleep(interval).await;
+        flush_media_access(&state).await;
+    }
+}
+
+/// Write the view counts collected so far, on the blocking pool
+///
+/// A failed flush is put back into the tracker for the next attempt.
+async fn flush_media_access(state: &AppState) {
+    let batch = state.access_tracker.take();
+    if batch.is_empty() {
+        return;
+    }
+
+    let db = state.db.clone();
+    let flushed = tokio::task::spawn_blocking(move || {
+        let result = db.flush_media_access(&batch);
+        (result, batch)
+    })
+    .await;
+
+    match flushed {
+        Ok((Ok(()), _)) => {}
+        Ok((Err(e), batch)) => {
+            tracing::warn!(error = %e, "Failed to flush media access, retrying later");
+            state.access_tracker.restore(batch);
+        }
+        Err(e) => tracing::error!(error = %e, "Media access flush panicked"),
+    }
+}
+
 /// Background task for periodic cleanup
 async fn cleanup_task(state: AppState) {
     let interval = Duration::from_secs(state.cleanup_interval());
@@ -195,9 +345,14 @@ async fn cleanup_task(state: AppState) {
             }
         }
 
+        let queued: HashSet<_> = state
+            .db
+            .list_processing_jobs()
+            .map(|jobs| jobs.into_iter().map(|job| job.session_id).collect())
+            .unwrap_or_default();
         let _ = state
             .storage
-            .cleanup_expired_sessions(state.upload_session_timeout())
+            .cleanup_expired_sessions(state.upload_session_timeout(), &queued)
             .await;
     }
 }
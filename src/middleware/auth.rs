@@ -234,6 +234,7 @@ mod tests {
             api_keys: vec![],
             protected_paths: vec![],
             public_paths: vec![],
+            ..Default::default()
         };
 
         let auth = ApiKeyAuth::new(&config);
@@ -247,6 +248,7 @@ mod tests {
             api_keys: vec!["secret123".to_string()],
             protected_paths: vec![], // Empty = all paths protected
             public_paths: vec!["/health".to_string()],
+            ..Default::default()
         };
 
         let auth = ApiKeyAuth::new(&config);
@@ -271,6 +273,7 @@ mod tests {
             api_keys: vec!["key1".to_string()],
             protected_paths: vec!["/api/upload".to_string()],
             public_paths: vec![],
+            ..Default::default()
         };
 
         let auth = ApiKeyAuth::new(&config);
@@ -36,11 +36,14 @@ use tracing::warn;
 
 use crate::config::RateLimitConfig;
 
+/// Direct (single-key) governor rate limiter used per IP
+type IpRateLimiter = GovRateLimiter<NotKeyed, InMemoryState, DefaultClock>;
+
 /// Rate limiter state shared across requests
 #[derive(Clone)]
 pub struct RateLimiter {
     /// Per-IP rate limiters
-    limiters: Arc<DashMap<IpAddr, Arc<GovRateLimiter<NotKeyed, InMemoryState, DefaultClock>>>>,
+    limiters: Arc<DashMap<IpAddr, Arc<IpRateLimiter>>>,
     /// Requests per window
     requests_per_window: u32,
     /// Window duration
@@ -79,10 +82,7 @@ impl RateLimiter {
     }
 
     /// Get or create a rate limiter for an IP
-    fn get_or_create_limiter(
-        &self,
-        ip: IpAddr,
-    ) -> Arc<GovRateLimiter<NotKeyed, InMemoryState, DefaultClock>> {
+    fn get_or_create_limiter(&self, ip: IpAddr) -> Arc<IpRateLimiter> {
         if let Some(limiter) = self.limiters.get(&ip) {
             return Arc::clone(&limiter);
         }
@@ -3,10 +3,13 @@
 //! This module defines the core `Media` entity that represents uploaded files
 //! stored in the system.
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use uuid::Uuid;
 
+use crate::models::extension_for_mime;
+
 /// Media type classification
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -38,6 +41,7 @@ impl MediaType {
     }
 
     /// Parse from database string representation
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
         match s {
             "image" => Some(Self::Image),
@@ -47,6 +51,94 @@ impl MediaType {
     }
 }
 
+/// Who may fetch a media item
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    /// Anyone with the URL
+    #[default]
+    Public,
+    /// Only with a signed, unexpired URL (see [`crate::services::url_signer`])
+    Private,
+}
+
+impl Visibility {
+    /// Parse `public` or `private`
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim() {
+            "public" => Some(Self::Public),
+            "private" => Some(Self::Private),
+            _ => None,
+        }
+    }
+}
+
+/// Camera details read from an image's EXIF data at upload
+///
+/// Kept for admins even when the stored files are stripped of metadata.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExifInfo {
+    /// Camera manufacturer (EXIF Make)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera_make: Option<String>,
+
+    /// Camera model (EXIF Model)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera_model: Option<String>,
+
+    /// When the photo was taken, camera local time (EXIF DateTimeOriginal)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taken_at: Option<NaiveDateTime>,
+
+    /// Whether the upload carried GPS coordinates
+    #[serde(default)]
+    pub has_gps: bool,
+}
+
+impl ExifInfo {
+    /// Whether nothing was extracted
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Point of interest in an image, as fractions of the upright width and
+/// height (`0.0..=1.0`, top left is `0,0`)
+///
+/// Cover crops keep this point in frame (see `focus` on variants).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FocalPoint {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl FocalPoint {
+    /// Middle of the image
+    pub const CENTER: Self = Self { x: 0.5, y: 0.5 };
+
+    /// Create a focal point, clamped into the image and rounded to 0.001
+    pub fn new(x: f32, y: f32) -> Self {
+        let round = |v: f32| (v.clamp(0.0, 1.0) * 1000.0).round() / 1000.0;
+        Self {
+            x: round(x),
+            y: round(y),
+        }
+    }
+
+    /// Parse `x,y` (e.g. `0.25,0.4`), rejecting values outside `0.0..=1.0`
+    pub fn parse(s: &str) -> Option<Self> {
+        let (x, y) = s.split_once(',')?;
+        let x: f32 = x.trim().parse().ok()?;
+        let y: f32 = y.trim().parse().ok()?;
+        Self::is_valid(x, y).then(|| Self::new(x, y))
+    }
+
+    /// Whether both coordinates lie within the image
+    pub fn is_valid(x: f32, y: f32) -> bool {
+        (0.0..=1.0).contains(&x) && (0.0..=1.0).contains(&y)
+    }
+}
+
 /// Media entity representing an uploaded file
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Media {
@@ -77,18 +169,65 @@ pub struct Media {
     /// Image/video height in pixels
     pub height: u32,
 
-    /// SHA-256 hash of the original file (for deduplication)
+    /// Hash of the original file (for deduplication and ETags)
     pub content_hash: String,
 
+    /// Algorithm that produced `content_hash`
+    pub hash_algorithm: HashAlgorithm,
+
+    /// Duration in milliseconds (videos and animated images)
+    pub duration_ms: Option<u64>,
+
+    /// Number of frames (animated images only)
+    pub frame_count: Option<u32>,
+
+    /// Codec of the video track, e.g. "h264" (videos only)
+    pub video_codec: Option<String>,
+
+    /// MIME type of the poster frame (videos only, when `ffmpeg_path` is set)
+    pub poster_mime_type: Option<String>,
+
+    /// MIME types of additional optimized renditions, in order of preference
+    /// (images only; see `output_formats`)
+    pub renditions: Vec<String>,
+
+    /// Encoder quality of the optimized file (lossy images only)
+    pub quality: Option<u8>,
+
+    /// Camera details from the upload's EXIF data (images only)
+    pub exif: ExifInfo,
+
+    /// BlurHash for rendering a placeholder while loading (images only)
+    pub blurhash: Option<String>,
+
+    /// Most common color as `#rrggbb` (images only)
+    pub dominant_color: Option<String>,
+
+    /// Perceptual hash for near-duplicate search (images only)
+    pub perceptual_hash: Option<u64>,
+
+    /// Point cover crops center on: detected at upload, or set by the
+    /// uploader or an admin (images only)
+    pub focal_point: Option<FocalPoint>,
+
+    /// Whether serving needs a signed URL
+    pub visibility: Visibility,
+
     /// Creation timestamp
     pub created_at: DateTime<Utc>,
 
-    /// Last access timestamp (for potential cleanup)
+    /// When the stored files were last rewritten (reprocess, new focal
+    /// point); the upload time until then
+    pub updated_at: DateTime<Utc>,
+
+    /// Last access written into the record by older versions; access is
+    /// tracked separately now (see [`MediaAccess`])
     pub last_accessed_at: Option<DateTime<Utc>>,
 }
 
 impl Media {
     /// Create a new Media instance
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         original_filename: String,
         original_mime_type: String,
@@ -100,6 +239,7 @@ impl Media {
         content_hash: String,
     ) -> Self {
         let media_type = MediaType::from_mime(&original_mime_type).unwrap_or(MediaType::Image);
+        let now = Utc::now();
 
         Self {
             id: Uuid::new_v4(),
@@ -112,34 +252,67 @@ impl Media {
             width,
             height,
             content_hash,
-            created_at: Utc::now(),
+            hash_algorithm: HashAlgorithm::CURRENT,
+            duration_ms: None,
+            frame_count: None,
+            video_codec: None,
+            poster_mime_type: None,
+            renditions: Vec::new(),
+            quality: None,
+            exif: ExifInfo::default(),
+            blurhash: None,
+            dominant_color: None,
+            perceptual_hash: None,
+            focal_point: None,
+            visibility: Visibility::Public,
+            created_at: now,
+            updated_at: now,
             last_accessed_at: None,
         }
     }
 
     /// Get the filename for the original file in storage
     pub fn original_storage_filename(&self) -> String {
-        let ext = self.get_extension_for_mime(&self.original_mime_type);
+        let ext = extension_for_mime(&self.original_mime_type);
         format!("{}.{}", self.id, ext)
     }
 
     /// Get the filename for the optimized file in storage
     pub fn optimized_storage_filename(&self) -> String {
-        let ext = self.get_extension_for_mime(&self.optimized_mime_type);
+        let ext = extension_for_mime(&self.optimized_mime_type);
         format!("{}.{}", self.id, ext)
     }
+}
 
-    /// Get file extension for a MIME type
-    fn get_extension_for_mime(&self, mime: &str) -> &'static str {
-        match mime {
-            "image/jpeg" => "jpg",
-            "image/png" => "png",
-            "image/gif" => "gif",
-            "image/webp" => "webp",
-            "video/mp4" => "mp4",
-            "video/webm" => "webm",
-            "video/quicktime" => "mov",
-            _ => "bin",
+/// Algorithm used to compute `Media::content_hash`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    /// 64-bit `DefaultHasher` digest used by early releases (not collision resistant)
+    Legacy,
+    /// SHA-256, hex encoded
+    Sha256,
+}
+
+impl HashAlgorithm {
+    /// Algorithm used for newly stored media
+    pub const CURRENT: Self = Self::Sha256;
+
+    /// Get string representation
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Legacy => "legacy",
+            Self::Sha256 => "sha256",
+        }
+    }
+
+    /// Parse from string
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "legacy" => Some(Self::Legacy),
+            "sha256" => Some(Self::Sha256),
+            _ => None,
         }
     }
 }
@@ -157,6 +330,10 @@ pub struct UploadResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub original_url: Option<String>,
 
+    /// URL of the poster frame (videos with a poster only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poster_url: Option<String>,
+
     /// Media type
     pub media_type: MediaType,
 
@@ -171,28 +348,141 @@ pub struct UploadResponse {
 
     /// Height in pixels
     pub height: u32,
+
+    /// Duration in milliseconds (videos and animated images)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+
+    /// Number of frames (animated images only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame_count: Option<u32>,
+
+    /// Video codec (videos only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video_codec: Option<String>,
+
+    /// BlurHash placeholder (images only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blurhash: Option<String>,
+
+    /// Most common color as `#rrggbb` (images only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dominant_color: Option<String>,
+
+    /// Point of interest, e.g. for CSS `object-position` (images only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub focal_point: Option<FocalPoint>,
+
+    /// Whether the URLs need a signature
+    pub visibility: Visibility,
+
+    /// When the signed URLs stop working (private media only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub urls_expire_at: Option<DateTime<Utc>>,
+
+    /// URLs of named variant presets (preset name -> URL)
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub variants: BTreeMap<String, String>,
 }
 
 impl UploadResponse {
     /// Create upload response from Media entity and base URL
+    ///
+    /// Videos are stored untouched, so they never have a separate original.
     pub fn from_media(media: &Media, base_url: &str, include_original: bool) -> Self {
         let url = format!("{}/m/{}", base_url, media.id);
-        let original_url = if include_original {
+        let original_url = if include_original && media.media_type == MediaType::Image {
             Some(format!("{}/m/{}/original", base_url, media.id))
         } else {
             None
         };
+        let poster_url = media
+            .poster_mime_type
+            .as_ref()
+            .map(|_| format!("{}/m/{}/poster", base_url, media.id));
 
         Self {
             id: media.id,
             url,
             original_url,
+            poster_url,
             media_type: media.media_type,
             mime_type: media.optimized_mime_type.clone(),
             size: media.optimized_size,
             width: media.width,
             height: media.height,
+            duration_ms: media.duration_ms,
+            frame_count: media.frame_count,
+            video_codec: media.video_codec.clone(),
+            blurhash: media.blurhash.clone(),
+            dominant_color: media.dominant_color.clone(),
+            focal_point: media.focal_point,
+            visibility: media.visibility,
+            urls_expire_at: None,
+            variants: BTreeMap::new(),
+        }
+    }
+
+    /// Add URLs for the given variant preset names
+    ///
+    /// Presets only apply to images; videos are left without variants.
+    pub fn with_variants<'a>(
+        mut self,
+        names: impl IntoIterator<Item = &'a String>,
+        base_url: &str,
+    ) -> Self {
+        if self.media_type != MediaType::Image {
+            return self;
+        }
+
+        self.variants = names
+            .into_iter()
+            .map(|name| {
+                (
+                    name.clone(),
+                    format!("{}/m/{}/v/{}", base_url, self.id, name),
+                )
+            })
+            .collect();
+        self
+    }
+
+    /// Append a signature query (`exp=...&sig=...`) to every URL
+    ///
+    /// Call after [`Self::with_variants`], so preset URLs are signed too.
+    pub fn with_signature(mut self, query: &str, expires_at: DateTime<Utc>) -> Self {
+        let sign = |url: &mut String| {
+            url.push('?');
+            url.push_str(query);
+        };
+
+        sign(&mut self.url);
+        if let Some(url) = &mut self.original_url {
+            sign(url);
+        }
+        if let Some(url) = &mut self.poster_url {
+            sign(url);
         }
+        self.variants.values_mut().for_each(sign);
+        self.urls_expire_at = Some(expires_at);
+        self
+    }
+}
+
+/// How often a media item was served, and when last
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MediaAccess {
+    /// Number of requests served
+    pub views: u64,
+    /// Time of the latest request
+    pub last_accessed_at: Option<DateTime<Utc>>,
+}
+
+impl MediaAccess {
+    /// Add the views of `other`, keeping the later access time
+    pub fn merge(&mut self, other: &MediaAccess) {
+        self.views += other.views;
+        self.last_accessed_at = self.last_accessed_at.max(other.last_accessed_at);
     }
 }
 
@@ -229,19 +519,79 @@ pub struct MediaInfoResponse {
     /// Content hash
     pub content_hash: String,
 
+    /// Algorithm that produced the content hash
+    pub hash_algorithm: HashAlgorithm,
+
+    /// Duration in milliseconds (videos and animated images)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+
+    /// Number of frames (animated images only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame_count: Option<u32>,
+
+    /// Video codec (videos only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video_codec: Option<String>,
+
+    /// MIME type of the poster frame (videos with a poster only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub poster_mime_type: Option<String>,
+
+    /// MIME types of additional renditions served by content negotiation
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub renditions: Vec<String>,
+
+    /// Encoder quality of the optimized file (lossy images only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<u8>,
+
+    /// Camera details from EXIF (images with EXIF data only)
+    #[serde(skip_serializing_if = "ExifInfo::is_empty")]
+    pub exif: ExifInfo,
+
+    /// BlurHash placeholder (images only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blurhash: Option<String>,
+
+    /// Most common color as `#rrggbb` (images only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dominant_color: Option<String>,
+
+    /// Perceptual hash as 16 hex digits (images only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub perceptual_hash: Option<String>,
+
+    /// Point cover crops center on (images only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub focal_point: Option<FocalPoint>,
+
+    /// Whether serving needs a signed URL
+    pub visibility: Visibility,
+
     /// Creation timestamp
     pub created_at: DateTime<Utc>,
 
+    /// When the stored files were last rewritten
+    pub updated_at: DateTime<Utc>,
+
     /// Last access timestamp
     pub last_accessed_at: Option<DateTime<Utc>>,
 
+    /// Number of times the media was served (all routes)
+    pub views: u64,
+
+    /// Number of uploads/tokens referencing this media
+    pub refs: u64,
+
     /// Public URL
     pub url: String,
 }
 
 impl MediaInfoResponse {
-    /// Create info response from Media entity and base URL
-    pub fn from_media(media: &Media, base_url: &str) -> Self {
+    /// Create info response from Media entity, its reference count, access
+    /// and base URL
+    pub fn from_media(media: &Media, refs: u64, access: &MediaAccess, base_url: &str) -> Self {
         Self {
             id: media.id,
             original_filename: media.original_filename.clone(),
@@ -253,8 +603,24 @@ impl MediaInfoResponse {
             width: media.width,
             height: media.height,
             content_hash: media.content_hash.clone(),
+            hash_algorithm: media.hash_algorithm,
+            duration_ms: media.duration_ms,
+            frame_count: media.frame_count,
+            video_codec: media.video_codec.clone(),
+            poster_mime_type: media.poster_mime_type.clone(),
+            renditions: media.renditions.clone(),
+            quality: media.quality,
+            exif: media.exif.clone(),
+            blurhash: media.blurhash.clone(),
+            dominant_color: media.dominant_color.clone(),
+            perceptual_hash: media.perceptual_hash.map(|hash| format!("{:016x}", hash)),
+            focal_point: media.focal_point,
+            visibility: media.visibility,
             created_at: media.created_at,
-            last_accessed_at: media.last_accessed_at,
+            updated_at: media.updated_at,
+            last_accessed_at: access.last_accessed_at.or(media.last_accessed_at),
+            views: access.views,
+            refs,
             url: format!("{}/m/{}", base_url, media.id),
         }
     }
@@ -288,5 +654,37 @@ mod tests {
         assert!(media.original_storage_filename().ends_with(".jpg"));
         assert!(media.optimized_storage_filename().ends_with(".webp"));
     }
-}
 
+    #[test]
+    fn test_upload_response_variants() {
+        let media = Media::new(
+            "test.jpg".to_string(),
+            "image/jpeg".to_string(),
+            "image/webp".to_string(),
+            1000,
+            500,
+            100,
+            100,
+            "abc123".to_string(),
+        );
+
+        let plain = UploadResponse::from_media(&media, "http://localhost", false);
+        assert!(plain.variants.is_empty());
+
+        let names = vec!["thumb".to_string()];
+        let response = plain.with_variants(&names, "http://localhost");
+        assert_eq!(
+            response.variants.get("thumb").unwrap(),
+            &format!("http://localhost/m/{}/v/thumb", media.id)
+        );
+
+        let expires_at = Utc::now();
+        let signed = UploadResponse::from_media(&media, "http://localhost", true)
+            .with_variants(&names, "http://localhost")
+            .with_signature("exp=1&sig=ab", expires_at);
+        assert_eq!(signed.url, format!("http://localhost/m/{}?exp=1&sig=ab", media.id));
+        assert!(signed.original_url.unwrap().ends_with("/original?exp=1&sig=ab"));
+        assert!(signed.variants["thumb"].ends_with("/v/thumb?exp=1&sig=ab"));
+        assert_eq!(signed.urls_expire_at, Some(expires_at));
+    }
+}
@@ -0,0 +1,164 @@
+//! File formats known to the server.
+//!
+//! One table maps MIME types to storage extensions (and back) and records
+//! which image formats this build can decode. Optional decoders are behind
+//! Cargo features:
+//!
+//! | Feature | Formats |
+//! |---------|---------|
+//! | `bmp` (default) | BMP |
+//! | `tiff` (default) | TIFF |
+//! | `ico` (default) | ICO |
+//! | `svg` (default) | SVG, sanitized and rasterized |
+//! | `avif-decode` | AVIF (needs the system `dav1d` library) |
+//!
+//! JPEG, PNG, GIF and WebP are always available. Uploads in a known but
+//! disabled format are rejected with 415 like unknown ones.
+
+use crate::models::MediaType;
+
+/// A file format the server can store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MediaFormat {
+    /// Canonical MIME type (as detected from the file's content)
+    pub mime_type: &'static str,
+    /// Extension of stored files
+    pub extension: &'static str,
+    /// Other extensions that map to this format
+    pub extension_aliases: &'static [&'static str],
+    /// Other MIME types that name this format
+    pub mime_aliases: &'static [&'static str],
+    /// Image or video
+    pub media_type: MediaType,
+    /// Whether uploads in this format can be processed by this build
+    pub enabled: bool,
+}
+
+/// MIME type of SVG documents
+pub const SVG_MIME: &str = "image/svg+xml";
+
+/// Every known format
+pub const MEDIA_FORMATS: &[MediaFormat] = &[
+    image("image/jpeg", "jpg", &["jpeg", "jpe"], &["image/jpg"], true),
+    image("image/png", "png", &[], &[], true),
+    image("image/gif", "gif", &[], &[], true),
+    image("image/webp", "webp", &[], &[], true),
+    image("image/avif", "avif", &[], &[], cfg!(feature = "avif-decode")),
+    image("image/bmp", "bmp", &[], &["image/x-ms-bmp"], cfg!(feature = "bmp")),
+    image("image/tiff", "tiff", &["tif"], &[], cfg!(feature = "tiff")),
+    image(
+        "image/vnd.microsoft.icon",
+        "ico",
+        &[],
+        &["image/x-icon"],
+        cfg!(feature = "ico"),
+    ),
+    image(SVG_MIME, "svg", &[], &[], cfg!(feature = "svg")),
+    video("video/mp4", "mp4", &["m4v"]),
+    video("video/webm", "webm", &[]),
+    video("video/x-matroska", "mkv", &[]),
+    video("video/quicktime", "mov", &[]),
+];
+
+const fn image(
+    mime_type: &'static str,
+    extension: &'static str,
+    extension_aliases: &'static [&'static str],
+    mime_aliases: &'static [&'static str],
+    enabled: bool,
+) -> MediaFormat {
+    MediaFormat {
+        mime_type,
+        extension,
+        extension_aliases,
+        mime_aliases,
+        media_type: MediaType::Image,
+        enabled,
+    }
+}
+
+const fn video(
+    mime_type: &'static str,
+    extension: &'static str,
+    extension_aliases: &'static [&'static str],
+) -> MediaFormat {
+    MediaFormat {
+        mime_type,
+        extension,
+        extension_aliases,
+        mime_aliases: &[],
+        media_type: MediaType::Video,
+        enabled: true,
+    }
+}
+
+impl MediaFormat {
+    /// Look up a format by MIME type (canonical or alias, case-insensitive)
+    pub fn from_mime(mime: &str) -> Option<&'static Self> {
+        MEDIA_FORMATS.iter().find(|f| {
+            f.mime_type.eq_ignore_ascii_case(mime)
+                || f.mime_aliases.iter().any(|m| m.eq_ignore_ascii_case(mime))
+        })
+    }
+
+    /// Look up a format by file extension (without the dot, case-insensitive)
+    pub fn from_extension(ext: &str) -> Option<&'static Self> {
+        MEDIA_FORMATS.iter().find(|f| {
+            f.extension.eq_ignore_ascii_case(ext)
+                || f.extension_aliases.iter().any(|e| e.eq_ignore_ascii_case(ext))
+        })
+    }
+}
+
+/// Storage extension for a MIME type (`bin` for unknown types)
+pub fn extension_for_mime(mime: &str) -> &'static str {
+    MediaFormat::from_mime(mime).map_or("bin", |f| f.extension)
+}
+
+/// MIME type for a file extension (`application/octet-stream` for unknown ones)
+pub fn mime_for_extension(ext: &str) -> &'static str {
+    MediaFormat::from_extension(ext).map_or("application/octet-stream", |f| f.mime_type)
+}
+
+/// Canonical form of a MIME type (aliases resolved, unknown types as given)
+pub fn canonical_mime(mime: &str) -> &str {
+    MediaFormat::from_mime(mime).map_or(mime, |f| f.mime_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extension_mapping_round_trips() {
+        for format in MEDIA_FORMATS {
+            assert_eq!(extension_for_mime(format.mime_type), format.extension);
+            assert_eq!(mime_for_extension(format.extension), format.mime_type);
+        }
+
+        assert_eq!(extension_for_mime("image/x-icon"), "ico");
+        assert_eq!(mime_for_extension("JPEG"), "image/jpeg");
+        assert_eq!(mime_for_extension("tif"), "image/tiff");
+        assert_eq!(extension_for_mime("application/pdf"), "bin");
+        assert_eq!(mime_for_extension("exe"), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_canonical_mime() {
+        assert_eq!(canonical_mime("image/x-icon"), "image/vnd.microsoft.icon");
+        assert_eq!(canonical_mime("image/jpg"), "image/jpeg");
+        assert_eq!(canonical_mime("image/png"), "image/png");
+        assert_eq!(canonical_mime("image/heic"), "image/heic");
+    }
+
+    #[test]
+    fn test_base_formats_always_enabled() {
+        for mime in ["image/jpeg", "image/png", "image/gif", "image/webp", "video/mp4"] {
+            assert!(MediaFormat::from_mime(mime).unwrap().enabled, "{}", mime);
+        }
+        assert_eq!(
+            MediaFormat::from_mime("image/avif").unwrap().enabled,
+            cfg!(feature = "avif-decode")
+        );
+    }
+}
@@ -4,10 +4,16 @@
 //! used throughout the application.
 
 mod media;
+pub mod media_format;
+mod reprocess;
 mod upload_session;
+mod variant;
 pub mod token_metadata;
 
 pub use media::*;
+pub use media_format::*;
+pub use reprocess::*;
 pub use upload_session::*;
 pub use token_metadata::*;
+pub use variant::*;
 
@@ -0,0 +1,69 @@
+//! Reprocess job status model.
+//!
+//! The admin reprocess job re-encodes stored images into the currently
+//! configured output format. Its progress is kept in memory and reported
+//! by `GET /admin/reprocess`.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Lifecycle state of the reprocess job
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReprocessState {
+    /// No job has run since startup
+    #[default]
+    Idle,
+    /// Job is converting media
+    Running,
+    /// Job went through the whole library
+    Completed,
+    /// Job stopped early (see `error`)
+    Failed,
+}
+
+/// Progress of the reprocess job
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReprocessStatus {
+    /// Current state
+    pub state: ReprocessState,
+    /// MIME type media is converted to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_mime_type: Option<String>,
+    /// Number of media records to examine
+    pub total: u64,
+    /// Records examined so far
+    pub processed: u64,
+    /// Records re-encoded into the target format
+    pub converted: u64,
+    /// Records already in the target format, videos, or deleted meanwhile
+    pub skipped: u64,
+    /// Records that could not be converted (kept in their old format)
+    pub failed: u64,
+    /// When the job started
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<DateTime<Utc>>,
+    /// When the job finished
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Error that stopped the job
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ReprocessStatus {
+    /// Status of a freshly started job
+    pub fn started(target_mime_type: &str) -> Self {
+        Self {
+            state: ReprocessState::Running,
+            target_mime_type: Some(target_mime_type.to_string()),
+            started_at: Some(Utc::now()),
+            ..Default::default()
+        }
+    }
+
+    /// Whether a job is currently running
+    pub fn is_running(&self) -> bool {
+        self.state == ReprocessState::Running
+    }
+}
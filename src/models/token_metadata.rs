@@ -8,6 +8,7 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::error::{AppError, Result};
+use crate::models::Media;
 
 // =============================================================================
 // Validation Constants
@@ -45,6 +46,10 @@ pub struct TokenMetadata {
     pub updated_at: DateTime<Utc>,
     /// Address that last updated the metadata
     pub last_update_by: String,
+    /// Locked token whose image one of this token's images resembles
+    /// (set by the `flag` near-duplicate policy)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub near_duplicate_of: Option<NearDuplicate>,
 }
 
 impl TokenMetadata {
@@ -67,6 +72,7 @@ impl TokenMetadata {
             created_at: now,
             updated_at: now,
             last_update_by: owner_address,
+            near_duplicate_of: None,
         }
     }
 
@@ -81,6 +87,19 @@ impl TokenMetadata {
     }
 }
 
+/// Image of a locked token that an uploaded image resembles
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NearDuplicate {
+    /// Chain ID of the locked token
+    pub chain_id: u64,
+    /// Address of the locked token
+    pub token_address: String,
+    /// The locked token's image
+    pub media_id: Uuid,
+    /// Hamming distance between the perceptual hashes
+    pub distance: u32,
+}
+
 /// Social network link
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SocialNetwork {
@@ -213,6 +232,18 @@ pub struct MetadataResponse {
     pub image_light_url: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub image_dark_url: Option<String>,
+    /// BlurHash of the light theme image
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_light_blurhash: Option<String>,
+    /// Dominant color (`#rrggbb`) of the light theme image
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_light_dominant_color: Option<String>,
+    /// BlurHash of the dark theme image
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_dark_blurhash: Option<String>,
+    /// Dominant color (`#rrggbb`) of the dark theme image
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_dark_dominant_color: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
@@ -227,20 +258,48 @@ impl MetadataResponse {
             social_networks: meta.social_networks.clone(),
             image_light_url: meta.image_light_id.map(|id| format!("{}/m/{}", base_url, id)),
             image_dark_url: meta.image_dark_id.map(|id| format!("{}/m/{}", base_url, id)),
+            image_light_blurhash: None,
+            image_light_dominant_color: None,
+            image_dark_blurhash: None,
+            image_dark_dominant_color: None,
             created_at: meta.created_at,
             updated_at: meta.updated_at,
         }
     }
 
+    /// Add the placeholders of the light and dark theme images
+    pub fn with_placeholders(mut self, light: Option<&Media>, dark: Option<&Media>) -> Self {
+        if let Some(media) = light {
+            self.image_light_blurhash = media.blurhash.clone();
+            self.image_light_dominant_color = media.dominant_color.clone();
+        }
+        if let Some(media) = dark {
+            self.image_dark_blurhash = media.blurhash.clone();
+            self.image_dark_dominant_color = media.dominant_color.clone();
+        }
+        self
+    }
+
     /// Create default/locked response
-    pub fn default_locked(chain_id: u64, token_address: &str, base_url: &str) -> Self {
+    ///
+    /// `images` are the names of the built-in light and dark theme assets.
+    pub fn default_locked(
+        chain_id: u64,
+        token_address: &str,
+        base_url: &str,
+        images: (&str, &str),
+    ) -> Self {
         Self {
             chain_id,
             token_address: token_address.to_string(),
             description: String::new(),
             social_networks: vec![],
-            image_light_url: Some(format!("{}/m/default", base_url)),
-            image_dark_url: Some(format!("{}/m/default", base_url)),
+            image_light_url: Some(format!("{}/m/{}", base_url, images.0)),
+            image_dark_url: Some(format!("{}/m/{}", base_url, images.1)),
+            image_light_blurhash: None,
+            image_light_dominant_color: None,
+            image_dark_blurhash: None,
+            image_dark_dominant_color: None,
             created_at: Utc::now(),
             updated_at: Utc::now(),
         }
@@ -2,11 +2,102 @@
 //!
 //! This module defines the `UploadSession` entity that tracks the state
 //! of chunked/resumable uploads.
+//!
+//! The content hash is computed incrementally as chunks arrive
+//! ([`HashState`]) and persisted with the session, so completing an upload
+//! never has to read the whole file back just to hash it.
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::models::Visibility;
+
+/// SHA-256 block size in bytes
+const SHA256_BLOCK: usize = 64;
+
+/// SHA-256 initial hash values (FIPS 180-4, 5.3.3)
+const SHA256_IV: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+    0x5be0cd19,
+];
+
+/// Resumable SHA-256 state that can be persisted between chunk requests
+///
+/// Produces the same digest as [`crate::services::image_processor::calculate_hash`]
+/// over the concatenated input.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HashState {
+    /// Chaining value after all complete blocks
+    state: [u32; 8],
+    /// Total number of bytes hashed
+    length: u64,
+    /// Trailing bytes that do not yet fill a block
+    pending: Vec<u8>,
+}
+
+impl Default for HashState {
+    fn default() -> Self {
+        Self {
+            state: SHA256_IV,
+            length: 0,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl HashState {
+    /// Number of bytes hashed so far
+    pub fn hashed_bytes(&self) -> u64 {
+        self.length
+    }
+
+    /// Feed more data
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+
+        if !self.pending.is_empty() {
+            let take = (SHA256_BLOCK - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+
+            if self.pending.len() < SHA256_BLOCK {
+                return;
+            }
+            let block = std::mem::take(&mut self.pending);
+            compress_blocks(&mut self.state, &block);
+        }
+
+        let full = data.len() - data.len() % SHA256_BLOCK;
+        compress_blocks(&mut self.state, &data[..full]);
+        self.pending.extend_from_slice(&data[full..]);
+    }
+
+    /// Compute the final digest as lowercase hex (state is left untouched)
+    pub fn finalize(&self) -> String {
+        let mut state = self.state;
+
+        // Padding: 0x80, zeros, then the message length in bits (big endian)
+        let mut tail = self.pending.clone();
+        tail.push(0x80);
+        while tail.len() % SHA256_BLOCK != SHA256_BLOCK - 8 {
+            tail.push(0);
+        }
+        tail.extend_from_slice(&(self.length * 8).to_be_bytes());
+        compress_blocks(&mut state, &tail);
+
+        state.iter().map(|word| format!("{:08x}", word)).collect()
+    }
+}
+
+/// Run the SHA-256 compression function over whole blocks
+fn compress_blocks(state: &mut [u32; 8], data: &[u8]) {
+    for block in data.chunks_exact(SHA256_BLOCK) {
+        let block: [u8; SHA256_BLOCK] = block.try_into().expect("exact block size");
+        sha2::compress256(state, &[block.into()]);
+    }
+}
+
 /// Status of an upload session
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -39,6 +130,7 @@ impl UploadSessionStatus {
     }
 
     /// Parse from database string representation
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
         match s {
             "in_progress" => Some(Self::InProgress),
@@ -103,6 +195,13 @@ pub struct UploadSession {
 
     /// Expiration timestamp
     pub expires_at: DateTime<Utc>,
+
+    /// Running content hash of the received bytes (`None` for sessions
+    /// created before incremental hashing)
+    pub hash_state: Option<HashState>,
+
+    /// Visibility of the media stored from this upload
+    pub visibility: Visibility,
 }
 
 impl UploadSession {
@@ -130,17 +229,19 @@ impl UploadSession {
             created_at: now,
             updated_at: now,
             expires_at,
+            hash_state: Some(HashState::default()),
+            visibility: Visibility::Public,
         }
     }
 
     /// Calculate the expected number of chunks
     pub fn total_chunks(&self) -> u64 {
-        (self.total_size + self.chunk_size - 1) / self.chunk_size
+        self.total_size.div_ceil(self.chunk_size)
     }
 
     /// Calculate the number of chunks received
     pub fn received_chunks(&self) -> u64 {
-        (self.received_bytes + self.chunk_size - 1) / self.chunk_size
+        self.received_bytes.div_ceil(self.chunk_size)
     }
 
     /// Calculate upload progress as a percentage
@@ -167,6 +268,22 @@ impl UploadSession {
         self.updated_at = Utc::now();
     }
 
+    /// Record a received chunk: update the running hash and byte count
+    pub fn add_chunk(&mut self, data: &[u8]) {
+        if let Some(hash) = self.hash_state.as_mut() {
+            hash.update(data);
+        }
+        self.add_received_bytes(data.len() as u64);
+    }
+
+    /// Final content hash, if the running hash covers all received bytes
+    pub fn content_hash(&self) -> Option<String> {
+        self.hash_state
+            .as_ref()
+            .filter(|hash| hash.hashed_bytes() == self.received_bytes)
+            .map(HashState::finalize)
+    }
+
     /// Mark session as processing
     pub fn mark_processing(&mut self) {
         self.status = UploadSessionStatus::Processing;
@@ -200,6 +317,34 @@ impl UploadSession {
     }
 }
 
+/// Queued processing of a completed chunked upload (async mode)
+///
+/// Persisted until the session reaches a terminal state, so uploads that
+/// were still queued or processing when the server stopped are picked up
+/// again on startup.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessingJob {
+    /// Session whose assembled file is processed
+    pub session_id: Uuid,
+
+    /// When the job was queued
+    pub enqueued_at: DateTime<Utc>,
+
+    /// Number of times processing was started
+    pub attempts: u32,
+}
+
+impl ProcessingJob {
+    /// Create a job for a session
+    pub fn new(session_id: Uuid) -> Self {
+        Self {
+            session_id,
+            enqueued_at: Utc::now(),
+            attempts: 0,
+        }
+    }
+}
+
 /// Request DTO for initiating a chunked upload
 #[derive(Debug, Deserialize)]
 pub struct InitUploadRequest {
@@ -211,6 +356,10 @@ pub struct InitUploadRequest {
 
     /// Total file size in bytes
     pub total_size: u64,
+
+    /// `public` (default) or `private`
+    #[serde(default)]
+    pub visibility: Visibility,
 }
 
 /// Response DTO for upload session status
@@ -325,5 +474,49 @@ mod tests {
         assert!(session.status.is_terminal());
         assert_eq!(session.media_id, Some(media_id));
     }
-}
 
+    #[test]
+    fn test_incremental_hash_matches_one_shot() {
+        use crate::services::image_processor::calculate_hash;
+
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7 % 251) as u8).collect();
+
+        // Split at awkward boundaries, including empty and sub-block chunks
+        for split in [0, 1, 63, 64, 65, 127, 500, 1000] {
+            let mut hash = HashState::default();
+            hash.update(&data[..split]);
+            hash.update(&data[split..]);
+            assert_eq!(hash.finalize(), calculate_hash(&data), "split at {}", split);
+        }
+
+        // Persisted state resumes correctly
+        let mut hash = HashState::default();
+        hash.update(&data[..300]);
+        let mut resumed: HashState =
+            serde_json::from_str(&serde_json::to_string(&hash).unwrap()).unwrap();
+        resumed.update(&data[300..]);
+        assert_eq!(resumed.finalize(), calculate_hash(&data));
+        assert_eq!(HashState::default().finalize(), calculate_hash(b""));
+    }
+
+    #[test]
+    fn test_session_content_hash() {
+        let mut session = UploadSession::new(
+            "test.jpg".to_string(),
+            "image/jpeg".to_string(),
+            6,
+            3,
+            3600,
+        );
+        session.add_chunk(b"abc");
+        session.add_chunk(b"def");
+        assert_eq!(
+            session.content_hash().unwrap(),
+            crate::services::image_processor::calculate_hash(b"abcdef")
+        );
+
+        // Bytes recorded without hashing invalidate the running hash
+        session.add_received_bytes(1);
+        assert!(session.content_hash().is_none());
+    }
+}
@@ -0,0 +1,417 @@
+//! Image variant model for on-the-fly transformations.
+//!
+//! Variants are derived renditions of a stored image (thumbnails, avatar
+//! crops, retina sizes) requested via query parameters on `/m/{id}`:
+//!
+//! ```text
+//! /m/{id}?w=256&h=256&fit=cover&fmt=jpeg&q=70
+//! /m/{id}?w=256&h=256&fit=cover&focus=auto
+//! ```
+//!
+//! Query parameters are normalized into a [`VariantSpec`] so that equivalent
+//! requests (e.g. `fmt=jpg` and `fmt=jpeg`) share one cached file on disk.
+
+use serde::Deserialize;
+
+use crate::error::{AppError, Result};
+use crate::models::FocalPoint;
+
+/// Resize mode for variants
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitMode {
+    /// Scale down to fit within the box, preserving aspect ratio
+    Contain,
+    /// Scale to fill the box, cropping the overflow
+    Cover,
+    /// Stretch to exactly the requested size, ignoring aspect ratio
+    Fill,
+}
+
+impl FitMode {
+    /// Parse from query parameter value
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "contain" => Some(Self::Contain),
+            "cover" => Some(Self::Cover),
+            "fill" => Some(Self::Fill),
+            _ => None,
+        }
+    }
+
+    /// Canonical string representation (used in cache keys)
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Contain => "contain",
+            Self::Cover => "cover",
+            Self::Fill => "fill",
+        }
+    }
+}
+
+/// Where a cover crop is centered
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Focus {
+    /// Middle of the image (default)
+    Center,
+    /// The media's focal point (detected at upload or set explicitly)
+    Auto,
+    /// A point given with the request
+    Point(FocalPoint),
+}
+
+impl Focus {
+    /// Parse from query parameter value: `auto`, `center` or `x,y`
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "auto" => Some(Self::Auto),
+            "center" => Some(Self::Center),
+            point => FocalPoint::parse(point).map(Self::Point),
+        }
+    }
+
+    /// Suffix of the cache key (empty for the center, so older keys stay valid)
+    pub(crate) fn key_suffix(&self) -> String {
+        match self {
+            Self::Center => String::new(),
+            Self::Auto => "-auto".to_string(),
+            Self::Point(p) => format!(
+                "-at{}_{}",
+                (p.x * 1000.0).round() as u32,
+                (p.y * 1000.0).round() as u32
+            ),
+        }
+    }
+}
+
+/// Encoded output format for optimized images and variants
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    WebP,
+    Jpeg,
+    Png,
+    Avif,
+}
+
+impl OutputFormat {
+    /// Parse from config or query parameter value
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "webp" => Some(Self::WebP),
+            "jpeg" | "jpg" => Some(Self::Jpeg),
+            "png" => Some(Self::Png),
+            "avif" => Some(Self::Avif),
+            _ => None,
+        }
+    }
+
+    /// File extension for this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::WebP => "webp",
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+            Self::Avif => "avif",
+        }
+    }
+
+    /// MIME type for this format
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Self::WebP => "image/webp",
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::Avif => "image/avif",
+        }
+    }
+
+    /// Whether the encoder for this format honors a quality setting
+    pub fn supports_quality(&self) -> bool {
+        matches!(self, Self::Jpeg | Self::WebP | Self::Avif)
+    }
+
+    /// Encoder quality for this format given the configured settings
+    ///
+    /// Returns `None` for lossless output (PNG, or WebP in lossless mode).
+    pub fn encoder_quality(&self, quality: u8, webp_lossless: bool) -> Option<u8> {
+        match self {
+            Self::WebP if webp_lossless => None,
+            format if format.supports_quality() => Some(quality),
+            _ => None,
+        }
+    }
+}
+
+/// Raw variant query parameters from `/m/{id}?...`
+#[derive(Debug, Default, Deserialize)]
+pub struct VariantQuery {
+    /// Target width in pixels
+    pub w: Option<u32>,
+    /// Target height in pixels
+    pub h: Option<u32>,
+    /// Fit mode: contain (default), cover, fill
+    pub fit: Option<String>,
+    /// Crop focus for cover: center (default), auto, or `x,y` fractions
+    pub focus: Option<String>,
+    /// Output format: webp, jpeg, png, avif
+    pub fmt: Option<String>,
+    /// Quality for lossy formats (1-100)
+    pub q: Option<u8>,
+}
+
+impl VariantQuery {
+    /// Check if no variant parameters were provided
+    pub fn is_empty(&self) -> bool {
+        self.w.is_none()
+            && self.h.is_none()
+            && self.fit.is_none()
+            && self.focus.is_none()
+            && self.fmt.is_none()
+            && self.q.is_none()
+    }
+
+    /// Validate and normalize query parameters into a variant spec
+    ///
+    /// # Arguments
+    /// * `default_format` - Format used when `fmt` is not given
+    /// * `default_quality` - Quality used when `q` is not given
+    /// * `max_dimension` - Maximum allowed width or height
+    pub fn normalize(
+        &self,
+        default_format: OutputFormat,
+        default_quality: u8,
+        max_dimension: u32,
+    ) -> Result<VariantSpec> {
+        for (name, value) in [("w", self.w), ("h", self.h)] {
+            match value {
+                Some(0) => {
+                    return Err(AppError::validation(format!(
+                        "{} must be greater than 0",
+                        name
+                    )))
+                }
+                Some(v) if v > max_dimension => {
+                    return Err(AppError::validation(format!(
+                        "{} must not exceed {}",
+                        name, max_dimension
+                    )))
+                }
+                _ => {}
+            }
+        }
+
+        let fit = match &self.fit {
+            Some(s) => FitMode::parse(s).ok_or_else(|| {
+                AppError::validation(format!(
+                    "Invalid fit '{}': expected contain, cover or fill",
+                    s
+                ))
+            })?,
+            None => FitMode::Contain,
+        };
+
+        let focus = match &self.focus {
+            Some(s) => Focus::parse(s).ok_or_else(|| {
+                AppError::validation(format!(
+                    "Invalid focus '{}': expected auto, center or x,y between 0 and 1",
+                    s
+                ))
+            })?,
+            None => Focus::Center,
+        };
+
+        let format = match &self.fmt {
+            Some(s) => OutputFormat::parse(s).ok_or_else(|| {
+                AppError::validation(format!("Invalid fmt '{}': expected webp, jpeg, png or avif", s))
+            })?,
+            None => default_format,
+        };
+
+        if let Some(q) = self.q {
+            if q == 0 || q > 100 {
+                return Err(AppError::validation("q must be between 1 and 100"));
+            }
+        }
+
+        // Cover and fill only make sense with both dimensions
+        let fit = if self.w.is_some() && self.h.is_some() {
+            fit
+        } else {
+            FitMode::Contain
+        };
+
+        // Only cover crops anything
+        let focus = if fit == FitMode::Cover {
+            focus
+        } else {
+            Focus::Center
+        };
+
+        // Quality is irrelevant for lossless encoders
+        let quality = if format.supports_quality() {
+            Some(self.q.unwrap_or(default_quality))
+        } else {
+            None
+        };
+
+        Ok(VariantSpec {
+            width: self.w,
+            height: self.h,
+            fit,
+            focus,
+            format,
+            quality,
+        })
+    }
+}
+
+/// Normalized, validated variant parameters
+#[derive(Debug, Clone, PartialEq)]
+pub struct VariantSpec {
+    /// Target width (None = derived from height)
+    pub width: Option<u32>,
+    /// Target height (None = derived from width)
+    pub height: Option<u32>,
+    /// Fit mode
+    pub fit: FitMode,
+    /// Crop focus (always `Center` unless `fit` is cover)
+    pub focus: Focus,
+    /// Output format
+    pub format: OutputFormat,
+    /// Encoder quality (None for lossless formats)
+    pub quality: Option<u8>,
+}
+
+impl VariantSpec {
+    /// Stable key identifying this variant, used for the cached filename
+    ///
+    /// Example: `256x256-cover-q70`, `256x256-cover-auto-q70`
+    pub fn cache_key(&self) -> String {
+        let dim = |v: Option<u32>| v.map_or_else(|| "auto".to_string(), |v| v.to_string());
+        let mut key = format!(
+            "{}x{}-{}{}",
+            dim(self.width),
+            dim(self.height),
+            self.fit.as_str(),
+            self.focus.key_suffix()
+        );
+        if let Some(q) = self.quality {
+            key.push_str(&format!("-q{}", q));
+        }
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(w: Option<u32>, h: Option<u32>, fit: Option<&str>, fmt: Option<&str>) -> VariantQuery {
+        VariantQuery {
+            w,
+            h,
+            fit: fit.map(String::from),
+            focus: None,
+            fmt: fmt.map(String::from),
+            q: None,
+        }
+    }
+
+    #[test]
+    fn test_normalize_defaults() {
+        let spec = query(Some(256), None, None, None)
+            .normalize(OutputFormat::WebP, 85, 4096)
+            .unwrap();
+
+        assert_eq!(spec.width, Some(256));
+        assert_eq!(spec.height, None);
+        assert_eq!(spec.fit, FitMode::Contain);
+        assert_eq!(spec.format, OutputFormat::WebP);
+        assert_eq!(spec.quality, Some(85));
+        assert_eq!(spec.cache_key(), "256xauto-contain-q85");
+
+        let spec = query(Some(256), None, None, Some("png"))
+            .normalize(OutputFormat::WebP, 85, 4096)
+            .unwrap();
+        assert_eq!(spec.quality, None);
+        assert_eq!(spec.cache_key(), "256xauto-contain");
+    }
+
+    #[test]
+    fn test_normalize_equivalent_queries_share_key() {
+        let a = query(Some(100), Some(100), Some("cover"), Some("jpg"))
+            .normalize(OutputFormat::WebP, 85, 4096)
+            .unwrap();
+        let b = query(Some(100), Some(100), Some("COVER"), Some("jpeg"))
+            .normalize(OutputFormat::WebP, 85, 4096)
+            .unwrap();
+
+        assert_eq!(a, b);
+        assert_eq!(a.cache_key(), "100x100-cover-q85");
+
+        // Cover without both dimensions degrades to contain
+        let c = query(Some(100), None, Some("cover"), None)
+            .normalize(OutputFormat::WebP, 85, 4096)
+            .unwrap();
+        assert_eq!(c.fit, FitMode::Contain);
+    }
+
+    #[test]
+    fn test_normalize_rejects_invalid() {
+        let max = 1024;
+        assert!(query(Some(0), None, None, None)
+            .normalize(OutputFormat::WebP, 85, max)
+            .is_err());
+        assert!(query(Some(2048), None, None, None)
+            .normalize(OutputFormat::WebP, 85, max)
+            .is_err());
+        assert!(query(None, None, Some("stretch"), None)
+            .normalize(OutputFormat::WebP, 85, max)
+            .is_err());
+        assert!(query(None, None, None, Some("bmp"))
+            .normalize(OutputFormat::WebP, 85, max)
+            .is_err());
+
+        let bad_quality = VariantQuery {
+            q: Some(101),
+            ..Default::default()
+        };
+        assert!(bad_quality.normalize(OutputFormat::Jpeg, 85, max).is_err());
+
+        for focus in ["middle", "0.5", "1.5,0.5", "0.5,-0.1", "a,b"] {
+            let bad_focus = VariantQuery {
+                focus: Some(focus.to_string()),
+                ..query(Some(10), Some(10), Some("cover"), None)
+            };
+            assert!(bad_focus.normalize(OutputFormat::WebP, 85, max).is_err(), "{}", focus);
+        }
+    }
+
+    #[test]
+    fn test_normalize_focus() {
+        let with_focus = |fit: &str, focus: &str| {
+            VariantQuery {
+                focus: Some(focus.to_string()),
+                ..query(Some(100), Some(100), Some(fit), None)
+            }
+            .normalize(OutputFormat::WebP, 85, 4096)
+            .unwrap()
+        };
+
+        let spec = with_focus("cover", "AUTO");
+        assert_eq!(spec.focus, Focus::Auto);
+        assert_eq!(spec.cache_key(), "100x100-cover-auto-q85");
+
+        // Points are rounded, so near-identical requests share a file
+        let spec = with_focus("cover", "0.25,0.6666");
+        assert_eq!(spec.focus, Focus::Point(FocalPoint::new(0.25, 0.667)));
+        assert_eq!(spec.cache_key(), "100x100-cover-at250_667-q85");
+
+        // Center keeps the key of a plain cover crop
+        assert_eq!(with_focus("cover", "center").cache_key(), "100x100-cover-q85");
+
+        // Nothing to crop without cover
+        let spec = with_focus("contain", "auto");
+        assert_eq!(spec.focus, Focus::Center);
+        assert_eq!(spec.cache_key(), "100x100-contain-q85");
+    }
+}
@@ -0,0 +1,103 @@
+//! In-memory aggregation of media access.
+//!
+//! Serving a file only bumps a counter here. The counts are flushed to the
+//! `media_access` column family every `server.access_flush_interval_seconds`
+//! (see [`DatabaseService::flush_media_access`]), one batched write for all
+//! media instead of a read-modify-write of the media record per request.
+//!
+//! A last flush runs on graceful shutdown; views not flushed yet are lost only
+//! if the process is killed. Readers add [`AccessTracker::pending`] to the
+//! stored counts to see the current value. Access of deleted media is never
+//! written back, even when it was already taken for a flush.
+//!
+//! [`DatabaseService::flush_media_access`]: crate::services::DatabaseService::flush_media_access
+
+use chrono::Utc;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use crate::models::MediaAccess;
+
+/// Views and access times collected since the last flush
+#[derive(Debug, Default)]
+pub struct AccessTracker {
+    pending: Mutex<HashMap<Uuid, MediaAccess>>,
+}
+
+impl AccessTracker {
+    /// Create an empty tracker
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count one view of a media item
+    pub fn record(&self, id: Uuid) {
+        let mut pending = self.lock();
+        let access = pending.entry(id).or_default();
+        access.views += 1;
+        access.last_accessed_at = Some(Utc::now());
+    }
+
+    /// Access of a media item not flushed yet
+    pub fn pending(&self, id: Uuid) -> MediaAccess {
+        self.lock().get(&id).copied().unwrap_or_default()
+    }
+
+    /// Take everything collected so far, to be flushed
+    pub fn take(&self) -> Vec<(Uuid, MediaAccess)> {
+        self.lock().drain().collect()
+    }
+
+    /// Put back access whose flush failed, merging with newer views
+    pub fn restore(&self, batch: Vec<(Uuid, MediaAccess)>) {
+        let mut pending = self.lock();
+        for (id, access) in batch {
+            pending.entry(id).or_default().merge(&access);
+        }
+    }
+
+    /// Drop pending access of a deleted media item
+    ///
+    /// A batch already taken by a running flush may still hold the item; the
+    /// flush skips media without a record, and the delete removes the stored
+    /// counts in the same write as the record.
+    pub fn forget(&self, id: Uuid) {
+        self.lock().remove(&id);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Uuid, MediaAccess>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_take() {
+        let tracker = AccessTracker::new();
+        let id = Uuid::new_v4();
+
+        tracker.record(id);
+        tracker.record(id);
+        tracker.record(Uuid::new_v4());
+
+        let access = tracker.pending(id);
+        assert_eq!(access.views, 2);
+        assert!(access.last_accessed_at.is_some());
+
+        let batch = tracker.take();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(tracker.pending(id), MediaAccess::default());
+
+        // A failed flush goes back, on top of views made meanwhile
+        tracker.record(id);
+        tracker.restore(batch);
+        assert_eq!(tracker.pending(id).views, 3);
+
+        tracker.forget(id);
+        assert_eq!(tracker.pending(id).views, 0);
+    }
+}
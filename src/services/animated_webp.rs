@@ -0,0 +1,186 @@
+//! Animated WebP encoding with libwebp's `WebPAnimEncoder`.
+//!
+//! The `webp` crate's `AnimEncoder` closes the animation at timestamp 0,
+//! which leaves libwebp to guess how long the last frame is shown. Here each
+//! frame is added once at its start time and the animation is closed at its
+//! real end time, so every frame keeps its delay and the work done is
+//! proportional to the number of frames, whatever their delays.
+
+use image::RgbaImage;
+use libwebp_sys as sys;
+use std::ffi::{c_int, CStr};
+use std::mem::MaybeUninit;
+use std::ptr;
+
+use crate::error::{AppError, Result};
+
+/// Owned `WebPAnimEncoder`, deleted on drop
+struct Encoder(*mut sys::WebPAnimEncoder);
+
+impl Drop for Encoder {
+    fn drop(&mut self) {
+        // SAFETY: the pointer came from `WebPAnimEncoderNewInternal` and is
+        // deleted exactly once
+        unsafe { sys::WebPAnimEncoderDelete(self.0) }
+    }
+}
+
+impl Encoder {
+    /// Error recorded by the last failed call
+    fn error(&self, action: &str) -> AppError {
+        // SAFETY: the encoder is valid; the returned string is owned by it
+        // and copied before the encoder can be dropped
+        let message = unsafe {
+            let message = sys::WebPAnimEncoderGetError(self.0);
+            if message.is_null() {
+                "unknown error".to_string()
+            } else {
+                CStr::from_ptr(message).to_string_lossy().into_owned()
+            }
+        };
+        AppError::image_processing(format!("{} failed: {}", action, message))
+    }
+}
+
+/// Owned `WebPPicture`, its pixel buffer freed on drop
+struct Picture(sys::WebPPicture);
+
+impl Drop for Picture {
+    fn drop(&mut self) {
+        // SAFETY: the picture was initialized by `WebPPicture::new`; freeing
+        // an empty picture is a no-op
+        unsafe { sys::WebPPictureFree(&mut self.0) }
+    }
+}
+
+/// Encode frames of equal size as an animated WebP that loops forever
+///
+/// Each frame is given with the time it is shown, in milliseconds. `config`
+/// decides lossy or lossless encoding for all frames.
+///
+/// # Errors
+/// Returns error if there are no frames, the canvas or total duration does
+/// not fit libwebp's limits, or encoding fails
+pub fn encode<'a>(
+    frames: impl IntoIterator<Item = (&'a RgbaImage, u32)>,
+    config: &sys::WebPConfig,
+) -> Result<Vec<u8>> {
+    let mut frames = frames.into_iter().peekable();
+    let (width, height) = frames
+        .peek()
+        .map(|(image, _)| image.dimensions())
+        .ok_or_else(|| AppError::image_processing("Animation has no frames"))?;
+    let too_large = || AppError::image_processing("Animation too large for WebP");
+    let width = c_int::try_from(width).map_err(|_| too_large())?;
+    let height = c_int::try_from(height).map_err(|_| too_large())?;
+
+    let mut options = MaybeUninit::<sys::WebPAnimEncoderOptions>::uninit();
+    // SAFETY: initializes every field of `options` (loop count 0 = forever)
+    let ok = unsafe {
+        sys::WebPAnimEncoderOptionsInitInternal(options.as_mut_ptr(), sys::WebPGetMuxABIVersion())
+    };
+    if ok == 0 {
+        return Err(AppError::internal("Failed to initialize WebP animation encoder"));
+    }
+    // SAFETY: initialized above
+    let options = unsafe { options.assume_init() };
+
+    // SAFETY: `options` is initialized and outlives the call
+    let encoder = Encoder(unsafe {
+        sys::WebPAnimEncoderNewInternal(width, height, &options, sys::WebPGetMuxABIVersion())
+    });
+    if encoder.0.is_null() {
+        return Err(too_large());
+    }
+
+    let mut timestamp: c_int = 0;
+    for (image, delay_ms) in frames {
+        if image.dimensions() != (width as u32, height as u32) {
+            return Err(AppError::image_processing(
+                "Animation frames differ in size",
+            ));
+        }
+
+        let mut picture = Picture(
+            sys::WebPPicture::new()
+                .map_err(|_| AppError::internal("Failed to initialize WebP picture"))?,
+        );
+        picture.0.use_argb = 1;
+        picture.0.width = width;
+        picture.0.height = height;
+
+        // SAFETY: `image` holds `width * height` RGBA pixels; libwebp copies
+        // them into the picture's own buffer
+        let ok =
+            unsafe { sys::WebPPictureImportRGBA(&mut picture.0, image.as_ptr(), width * 4) };
+        if ok == 0 {
+            return Err(AppError::internal("Out of memory importing animation frame"));
+        }
+
+        // SAFETY: encoder, picture and config are valid for the call
+        let ok = unsafe { sys::WebPAnimEncoderAdd(encoder.0, &mut picture.0, timestamp, config) };
+        if ok == 0 {
+            return Err(encoder.error("Encoding animated WebP frame"));
+        }
+
+        timestamp = c_int::try_from(delay_ms)
+            .ok()
+            .and_then(|delay| timestamp.checked_add(delay))
+            .ok_or_else(|| AppError::image_processing("Animation too long for WebP"))?;
+    }
+
+    // A null frame ends the animation; its timestamp sets the last frame's duration
+    // SAFETY: libwebp accepts a null frame and config to flush the encoder
+    let ok = unsafe { sys::WebPAnimEncoderAdd(encoder.0, ptr::null_mut(), timestamp, ptr::null()) };
+    if ok == 0 {
+        return Err(encoder.error("Finishing animated WebP"));
+    }
+
+    let mut data = sys::WebPData::default();
+    // SAFETY: on success `data` points to a libwebp allocation of `data.size`
+    // bytes, copied and then freed with `WebPDataClear`
+    unsafe {
+        if sys::WebPAnimEncoderAssemble(encoder.0, &mut data) == 0 {
+            return Err(encoder.error("Assembling animated WebP"));
+        }
+        let bytes = std::slice::from_raw_parts(data.bytes, data.size).to_vec();
+        sys::WebPDataClear(&mut data);
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::webp::WebPDecoder;
+    use image::{AnimationDecoder, Rgba};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_frames_keep_their_delays() {
+        let red = RgbaImage::from_pixel(8, 8, Rgba([255, 0, 0, 255]));
+        let blue = RgbaImage::from_pixel(8, 8, Rgba([0, 0, 255, 255]));
+        let mut config = sys::WebPConfig::new().unwrap();
+        config.lossless = 1;
+
+        // A tick-based encoder would add the second frame 6000 times
+        let data = encode([(&red, 10), (&blue, 60_000), (&red, 30)], &config).unwrap();
+
+        let delays: Vec<u32> = WebPDecoder::new(Cursor::new(data))
+            .unwrap()
+            .into_frames()
+            .map(|frame| frame.unwrap().delay().numer_denom_ms().0)
+            .collect();
+        assert_eq!(delays, vec![10, 60_000, 30]);
+    }
+
+    #[test]
+    fn test_rejects_mismatched_frames() {
+        let config = sys::WebPConfig::new().unwrap();
+        let small = RgbaImage::new(4, 4);
+        let large = RgbaImage::new(8, 8);
+
+        assert!(encode(std::iter::empty(), &config).is_err());
+        assert!(encode([(&small, 10), (&large, 10)], &config).is_err());
+    }
+}
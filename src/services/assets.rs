@@ -0,0 +1,154 @@
+//! Built-in assets served at `/m/{name}`.
+//!
+//! Named images that are not uploads, such as the image shown for tokens
+//! locked with defaults. [`DEFAULT_ASSET`] is embedded in the binary
+//! (`assets/default_token.webp`); `[assets.<name>]` entries in the config add
+//! more, or replace it, with files read once at startup. Networks pick their
+//! light and dark theme defaults by name (see
+//! [`crate::config::EvmNetworkConfig`]).
+
+use bytes::Bytes;
+use std::collections::HashMap;
+
+use crate::config::AssetConfig;
+use crate::error::{AppError, Result};
+use crate::models::media_format::SVG_MIME;
+use crate::models::{MediaFormat, MediaType};
+use crate::services::image_processor::calculate_hash;
+use crate::services::svg;
+
+/// Name of the bundled default token image
+pub const DEFAULT_ASSET: &str = "default";
+
+/// The bundled default token image
+const DEFAULT_TOKEN_IMAGE: &[u8] = include_bytes!("../../assets/default_token.webp");
+
+/// A built-in image held in memory
+#[derive(Debug, Clone)]
+pub struct Asset {
+    /// File contents
+    pub data: Bytes,
+    /// MIME type detected from the contents
+    pub mime_type: &'static str,
+    /// SHA-256 of the contents (hex), used for the ETag
+    pub content_hash: String,
+}
+
+impl Asset {
+    /// Wrap image data, detecting its type from the content
+    fn new(data: Bytes) -> Result<Self> {
+        let mime_type = detect_image_mime(&data)
+            .ok_or_else(|| AppError::unsupported_media_type("Not a known image format"))?;
+
+        Ok(Self {
+            content_hash: calculate_hash(&data),
+            mime_type,
+            data,
+        })
+    }
+}
+
+/// Built-in assets by name
+#[derive(Debug)]
+pub struct AssetRegistry {
+    assets: HashMap<String, Asset>,
+}
+
+impl AssetRegistry {
+    /// Load the bundled default and the configured assets
+    ///
+    /// # Errors
+    /// Returns `AppError::Config` if a configured file cannot be read or is
+    /// not an image
+    pub fn load(configured: &HashMap<String, AssetConfig>) -> Result<Self> {
+        let mut assets = HashMap::new();
+        assets.insert(
+            DEFAULT_ASSET.to_string(),
+            Asset::new(Bytes::from_static(DEFAULT_TOKEN_IMAGE))?,
+        );
+
+        for (name, config) in configured {
+            let asset = std::fs::read(&config.path)
+                .map_err(AppError::from)
+                .and_then(|data| Asset::new(Bytes::from(data)))
+                .map_err(|e| {
+                    AppError::config(format!(
+                        "Failed to load asset '{}' from {}: {}",
+                        name,
+                        config.path.display(),
+                        e
+                    ))
+                })?;
+            assets.insert(name.clone(), asset);
+        }
+
+        Ok(Self { assets })
+    }
+
+    /// Look up an asset by name
+    pub fn get(&self, name: &str) -> Option<&Asset> {
+        self.assets.get(name)
+    }
+
+    /// Number of registered assets
+    pub fn len(&self) -> usize {
+        self.assets.len()
+    }
+
+    /// Whether no assets are registered (never the case after [`Self::load`])
+    pub fn is_empty(&self) -> bool {
+        self.assets.is_empty()
+    }
+}
+
+/// Image MIME type of the data, if it is a known image format
+fn detect_image_mime(data: &[u8]) -> Option<&'static str> {
+    if svg::is_svg(data) {
+        return Some(SVG_MIME);
+    }
+
+    let format = MediaFormat::from_mime(infer::get(data)?.mime_type())?;
+    (format.media_type == MediaType::Image).then_some(format.mime_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_bundled_default() {
+        let registry = AssetRegistry::load(&HashMap::new()).unwrap();
+        let asset = registry.get(DEFAULT_ASSET).unwrap();
+
+        assert_eq!(asset.mime_type, "image/webp");
+        assert_eq!(asset.data.len(), DEFAULT_TOKEN_IMAGE.len());
+        assert_eq!(asset.content_hash.len(), 64);
+        assert!(registry.get("missing").is_none());
+    }
+
+    #[test]
+    fn test_configured_assets() {
+        let dir = TempDir::new().unwrap();
+        let svg_path = dir.path().join("dark.svg");
+        std::fs::write(&svg_path, r#"<svg xmlns="http://www.w3.org/2000/svg"/>"#).unwrap();
+        let text_path = dir.path().join("notes.txt");
+        std::fs::write(&text_path, "not an image").unwrap();
+
+        let asset = |path: PathBuf| AssetConfig { path };
+
+        // Configured entries add to the bundled default
+        let configured = HashMap::from([("default-dark".to_string(), asset(svg_path))]);
+        let registry = AssetRegistry::load(&configured).unwrap();
+        assert_eq!(registry.len(), 2);
+        assert_eq!(registry.get("default-dark").unwrap().mime_type, SVG_MIME);
+
+        let configured = HashMap::from([("notes".to_string(), asset(text_path))]);
+        let err = AssetRegistry::load(&configured).unwrap_err();
+        assert!(matches!(err, AppError::Config(_)), "{:?}", err);
+
+        let configured = HashMap::from([("gone".to_string(), asset(dir.path().join("gone.png")))]);
+        assert!(AssetRegistry::load(&configured).is_err());
+    }
+}
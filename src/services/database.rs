@@ -7,25 +7,38 @@
 //!
 //! Uses column families to separate data types:
 //! - `media`: Media records (key: UUID)
-//! - `hash_index`: Content hash → UUID mapping (for deduplication)
+//! - `hash_index`: Content hash → UUID mapping (for deduplication; private
+//!   media is keyed `private:hash`, so it never dedups with public media)
+//! - `media_refs`: Reference count per media (key: UUID, value: u64 BE)
+//! - `perceptual_hashes`: Perceptual hash per image (key: UUID, value: u64 BE)
+//! - `media_access`: View count and last access per media (key: UUID)
 //! - `sessions`: Upload sessions (key: UUID)
 //! - `session_expires`: Expiration index (key: timestamp:uuid)
+//! - `processing_jobs`: Queued async processing of uploads (key: session UUID)
 //! - `token_metadata`: RexPump token metadata (key: chainid:address)
 //! - `token_locks`: RexPump token locks (key: chainid:address)
 //! - `token_rate_limits`: RexPump rate limiting (key: chainid:address)
+//! - `locked_images`: Perceptual hash per image of a locked token
+//!   (key: chainid:address/UUID, value: u64 BE)
+//! - `locked_images_by_media`: Reverse of `locked_images`
+//!   (key: UUID/chainid:address, empty value)
+//! - `meta`: Internal bookkeeping such as completed migrations (key: name)
 
 use crate::config::StorageConfig;
 use crate::error::{AppError, Result};
 use crate::models::{
-    Media, MediaType, TokenLock, TokenMetadata, TokenUpdateRecord,
-    UploadSession, UploadSessionStatus,
+    ExifInfo, FocalPoint, HashAlgorithm, HashState, Media, MediaAccess, MediaType, NearDuplicate,
+    ProcessingJob, TokenLock, TokenMetadata, TokenUpdateRecord, UploadSession,
+    UploadSessionStatus, Visibility,
 };
+use crate::services::perceptual_hash;
 use chrono::{DateTime, Utc};
 use rocksdb::{ColumnFamilyDescriptor, DBWithThreadMode, MultiThreaded, Options, WriteBatch};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::PathBuf;
-use std::sync::Arc;
-use tracing::{debug, info};
+use std::sync::{Arc, Mutex};
+use tracing::{debug, info, warn};
 use uuid::Uuid;
 
 type DB = DBWithThreadMode<MultiThreaded>;
@@ -33,12 +46,37 @@ type DB = DBWithThreadMode<MultiThreaded>;
 /// Column family names
 const CF_MEDIA: &str = "media";
 const CF_HASH_INDEX: &str = "hash_index";
+const CF_MEDIA_REFS: &str = "media_refs";
+const CF_PERCEPTUAL_HASHES: &str = "perceptual_hashes";
+const CF_MEDIA_ACCESS: &str = "media_access";
 const CF_SESSIONS: &str = "sessions";
 const CF_SESSION_EXPIRES: &str = "session_expires";
+const CF_PROCESSING_JOBS: &str = "processing_jobs";
 // RexPump column families
 const CF_TOKEN_METADATA: &str = "token_metadata";
 const CF_TOKEN_LOCKS: &str = "token_locks";
 const CF_TOKEN_RATE_LIMITS: &str = "token_rate_limits";
+const CF_LOCKED_IMAGES: &str = "locked_images";
+const CF_LOCKED_IMAGES_BY_MEDIA: &str = "locked_images_by_media";
+const CF_META: &str = "meta";
+
+/// Meta key marking the SHA-256 content hash migration as done
+const MIGRATION_CONTENT_HASH_SHA256: &str = "migration:content_hash_sha256";
+
+/// Meta key marking the locked images index as built
+const MIGRATION_LOCKED_IMAGES: &str = "migration:locked_images";
+
+/// Result of the content hash migration
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HashMigrationReport {
+    /// Records rehashed and re-indexed
+    pub migrated: u64,
+    /// Legacy records left as-is (e.g. original file not kept)
+    pub skipped: u64,
+    /// Migrated records whose new hash was already indexed for another
+    /// record: the two keep separate files and references and no longer dedup
+    pub duplicates: u64,
+}
 
 /// Database service for managing media metadata
 ///
@@ -48,6 +86,9 @@ pub struct DatabaseService {
     db: Arc<DB>,
     #[allow(dead_code)]
     db_path: PathBuf,
+    /// Serializes read-modify-write cycles on reference counts, media records
+    /// and the locked images index
+    refs_lock: Arc<Mutex<()>>,
 }
 
 impl std::fmt::Debug for DatabaseService {
@@ -82,11 +123,18 @@ impl DatabaseService {
         let cf_names = [
             CF_MEDIA,
             CF_HASH_INDEX,
+            CF_MEDIA_REFS,
+            CF_PERCEPTUAL_HASHES,
+            CF_MEDIA_ACCESS,
             CF_SESSIONS,
             CF_SESSION_EXPIRES,
+            CF_PROCESSING_JOBS,
             CF_TOKEN_METADATA,
             CF_TOKEN_LOCKS,
             CF_TOKEN_RATE_LIMITS,
+            CF_LOCKED_IMAGES,
+            CF_LOCKED_IMAGES_BY_MEDIA,
+            CF_META,
         ];
         let cf_descriptors: Vec<_> = cf_names
             .iter()
@@ -103,10 +151,13 @@ impl DatabaseService {
 
         info!(path = %db_path.display(), "Database initialized (RocksDB)");
 
-        Ok(Self {
+        let service = Self {
             db: Arc::new(db),
             db_path,
-        })
+            refs_lock: Arc::new(Mutex::new(())),
+        };
+        service.migrate_locked_images()?;
+        Ok(service)
     }
 
     fn cf_media(&self) -> Arc<rocksdb::BoundColumnFamily<'_>> {
@@ -119,6 +170,24 @@ impl DatabaseService {
             .expect("CF hash_index must exist")
     }
 
+    fn cf_media_refs(&self) -> Arc<rocksdb::BoundColumnFamily<'_>> {
+        self.db
+            .cf_handle(CF_MEDIA_REFS)
+            .expect("CF media_refs must exist")
+    }
+
+    fn cf_perceptual_hashes(&self) -> Arc<rocksdb::BoundColumnFamily<'_>> {
+        self.db
+            .cf_handle(CF_PERCEPTUAL_HASHES)
+            .expect("CF perceptual_hashes must exist")
+    }
+
+    fn cf_media_access(&self) -> Arc<rocksdb::BoundColumnFamily<'_>> {
+        self.db
+            .cf_handle(CF_MEDIA_ACCESS)
+            .expect("CF media_access must exist")
+    }
+
     fn cf_sessions(&self) -> Arc<rocksdb::BoundColumnFamily<'_>> {
         self.db
             .cf_handle(CF_SESSIONS)
@@ -131,6 +200,12 @@ impl DatabaseService {
             .expect("CF session_expires must exist")
     }
 
+    fn cf_processing_jobs(&self) -> Arc<rocksdb::BoundColumnFamily<'_>> {
+        self.db
+            .cf_handle(CF_PROCESSING_JOBS)
+            .expect("CF processing_jobs must exist")
+    }
+
     fn cf_token_metadata(&self) -> Arc<rocksdb::BoundColumnFamily<'_>> {
         self.db
             .cf_handle(CF_TOKEN_METADATA)
@@ -149,6 +224,22 @@ impl DatabaseService {
             .expect("CF token_rate_limits must exist")
     }
 
+    fn cf_locked_images(&self) -> Arc<rocksdb::BoundColumnFamily<'_>> {
+        self.db
+            .cf_handle(CF_LOCKED_IMAGES)
+            .expect("CF locked_images must exist")
+    }
+
+    fn cf_locked_images_by_media(&self) -> Arc<rocksdb::BoundColumnFamily<'_>> {
+        self.db
+            .cf_handle(CF_LOCKED_IMAGES_BY_MEDIA)
+            .expect("CF locked_images_by_media must exist")
+    }
+
+    fn cf_meta(&self) -> Arc<rocksdb::BoundColumnFamily<'_>> {
+        self.db.cf_handle(CF_META).expect("CF meta must exist")
+    }
+
     // =========================================================================
     // Media operations
     // =========================================================================
@@ -163,9 +254,21 @@ impl DatabaseService {
         batch.put_cf(&self.cf_media(), media.id.to_string().as_bytes(), &data);
         batch.put_cf(
             &self.cf_hash_index(),
-            media.content_hash.as_bytes(),
+            hash_index_key(&media.content_hash, media.visibility).as_bytes(),
             media.id.to_string().as_bytes(),
         );
+        batch.put_cf(
+            &self.cf_media_refs(),
+            media.id.to_string().as_bytes(),
+            1u64.to_be_bytes(),
+        );
+        if let Some(hash) = media.perceptual_hash {
+            batch.put_cf(
+                &self.cf_perceptual_hashes(),
+                media.id.to_string().as_bytes(),
+                hash.to_be_bytes(),
+            );
+        }
 
         self.db
             .write(batch)
@@ -191,62 +294,360 @@ impl DatabaseService {
         }
     }
 
-    /// Delete a media record by ID
+    /// Delete a media record by ID, regardless of remaining references
     pub fn delete_media(&self, id: Uuid) -> Result<bool> {
+        let _guard = self.refs_lock.lock().unwrap_or_else(|e| e.into_inner());
+
         // First get the media to find its hash
         let media = match self.get_media(id)? {
             Some(m) => m,
             None => return Ok(false),
         };
 
-        // Atomic delete of both record and hash index
+        self.delete_media_unlocked(&media)?;
+        Ok(true)
+    }
+
+    /// Atomically delete a media record, its hash index entry and refcount
+    ///
+    /// Caller must hold `refs_lock`.
+    fn delete_media_unlocked(&self, media: &Media) -> Result<()> {
+        let key = media.id.to_string();
+
         let mut batch = WriteBatch::default();
-        batch.delete_cf(&self.cf_media(), id.to_string().as_bytes());
-        batch.delete_cf(&self.cf_hash_index(), media.content_hash.as_bytes());
+        batch.delete_cf(&self.cf_media(), key.as_bytes());
+        batch.delete_cf(&self.cf_media_refs(), key.as_bytes());
+        batch.delete_cf(&self.cf_perceptual_hashes(), key.as_bytes());
+        batch.delete_cf(&self.cf_media_access(), key.as_bytes());
+        for token_key in self.locking_tokens(media.id)? {
+            batch.delete_cf(&self.cf_locked_images(), format!("{}/{}", token_key, key));
+            batch.delete_cf(&self.cf_locked_images_by_media(), format!("{}/{}", key, token_key));
+        }
+        let hash_key = hash_index_key(&media.content_hash, media.visibility);
+        if self.hash_index_points_to(&hash_key, media.id)? {
+            batch.delete_cf(&self.cf_hash_index(), hash_key.as_bytes());
+        }
 
         self.db
             .write(batch)
             .map_err(|e| AppError::internal(format!("RocksDB delete failed: {}", e)))?;
 
-        debug!(id = %id, "Deleted media record");
-        Ok(true)
+        debug!(id = %media.id, "Deleted media record");
+        Ok(())
+    }
+
+    // =========================================================================
+    // Reference counting
+    // =========================================================================
+
+    /// Get the number of references held on a media record
+    ///
+    /// Records created before reference counting count as one reference.
+    pub fn get_media_refs(&self, id: Uuid) -> Result<u64> {
+        let value = self
+            .db
+            .get_cf(&self.cf_media_refs(), id.to_string().as_bytes())
+            .map_err(|e| AppError::internal(format!("RocksDB read failed: {}", e)))?;
+
+        Ok(value
+            .and_then(|bytes| bytes.as_slice().try_into().ok())
+            .map(u64::from_be_bytes)
+            .unwrap_or(1))
+    }
+
+    fn put_media_refs(&self, id: Uuid, count: u64) -> Result<()> {
+        self.db
+            .put_cf(
+                &self.cf_media_refs(),
+                id.to_string().as_bytes(),
+                count.to_be_bytes(),
+            )
+            .map_err(|e| AppError::internal(format!("RocksDB write failed: {}", e)))
+    }
+
+    /// Find media by content hash and visibility and take a reference on it
+    /// (dedup hit)
+    pub fn acquire_by_hash(&self, hash: &str, visibility: Visibility) -> Result<Option<Media>> {
+        let _guard = self.refs_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let media = match self.find_by_hash(hash, visibility)? {
+            Some(m) => m,
+            None => return Ok(None),
+        };
+
+        let refs = self.get_media_refs(media.id)? + 1;
+        self.put_media_refs(media.id, refs)?;
+
+        debug!(id = %media.id, refs = refs, "Acquired media reference");
+        Ok(Some(media))
+    }
+
+    /// Drop one reference on a media record
+    ///
+    /// When the last reference goes away the record is deleted and returned,
+    /// so the caller can remove its files. Returns `None` otherwise, or if
+    /// the record does not exist.
+    pub fn release_media(&self, id: Uuid) -> Result<Option<Media>> {
+        let _guard = self.refs_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let media = match self.get_media(id)? {
+            Some(m) => m,
+            None => return Ok(None),
+        };
+
+        let refs = self.get_media_refs(id)?.saturating_sub(1);
+        if refs > 0 {
+            self.put_media_refs(id, refs)?;
+            debug!(id = %id, refs = refs, "Released media reference");
+            return Ok(None);
+        }
+
+        self.delete_media_unlocked(&media)?;
+        Ok(Some(media))
     }
 
-    /// Update last_accessed_at timestamp
-    pub fn update_last_accessed(&self, id: Uuid) -> Result<()> {
+    /// Modify a media record in place
+    ///
+    /// Serialized with deletes, so a concurrently deleted record is never
+    /// written back. A changed perceptual hash is written to the hash indexes
+    /// along with the record. Returns the updated media, or `None` if it no
+    /// longer exists.
+    pub fn update_media<F>(&self, id: Uuid, update: F) -> Result<Option<Media>>
+    where
+        F: FnOnce(&mut Media),
+    {
+        let _guard = self.refs_lock.lock().unwrap_or_else(|e| e.into_inner());
+
         let mut media = match self.get_media(id)? {
             Some(m) => m,
-            None => return Ok(()),
+            None => return Ok(None),
         };
+        let previous_hash = media.perceptual_hash;
 
-        media.last_accessed_at = Some(Utc::now());
+        update(&mut media);
 
-        let record = MediaRecord::from(&media);
-        let data = serde_json::to_vec(&record)?;
+        let key = id.to_string();
+        let mut batch = WriteBatch::default();
+        let data = serde_json::to_vec(&MediaRecord::from(&media))?;
+        batch.put_cf(&self.cf_media(), key.as_bytes(), data);
+        if media.perceptual_hash != previous_hash {
+            let hash = media.perceptual_hash.map(u64::to_be_bytes);
+            match hash {
+                Some(bytes) => batch.put_cf(&self.cf_perceptual_hashes(), key.as_bytes(), bytes),
+                None => batch.delete_cf(&self.cf_perceptual_hashes(), key.as_bytes()),
+            }
+            let value: &[u8] = hash.as_ref().map_or(&[], |bytes| bytes);
+            for token_key in self.locking_tokens(id)? {
+                batch.put_cf(&self.cf_locked_images(), format!("{}/{}", token_key, key), value);
+            }
+        }
 
         self.db
-            .put_cf(&self.cf_media(), id.to_string().as_bytes(), data)
+            .write(batch)
             .map_err(|e| AppError::internal(format!("RocksDB write failed: {}", e)))?;
 
-        Ok(())
+        Ok(Some(media))
+    }
+
+    /// List the IDs of all media records
+    pub fn list_media_ids(&self) -> Result<Vec<Uuid>> {
+        let iter = self.db.iterator_cf(&self.cf_media(), rocksdb::IteratorMode::Start);
+
+        let mut ids = Vec::new();
+        for item in iter {
+            let (key, _) =
+                item.map_err(|e| AppError::internal(format!("RocksDB iteration failed: {}", e)))?;
+            ids.push(Uuid::parse_str(&String::from_utf8_lossy(&key))?);
+        }
+
+        Ok(ids)
     }
 
-    /// Find media by content hash (for deduplication)
-    pub fn find_by_hash(&self, hash: &str) -> Result<Option<Media>> {
+    /// Count media records by optimized MIME type
+    pub fn count_media_by_format(&self) -> Result<BTreeMap<String, u64>> {
+        let iter = self.db.iterator_cf(&self.cf_media(), rocksdb::IteratorMode::Start);
+
+        let mut counts = BTreeMap::new();
+        for item in iter {
+            let (_, value) =
+                item.map_err(|e| AppError::internal(format!("RocksDB iteration failed: {}", e)))?;
+            let record: MediaRecord = serde_json::from_slice(&value)?;
+            *counts.entry(record.optimized_mime_type).or_insert(0) += 1;
+        }
+
+        Ok(counts)
+    }
+
+    /// Find media by content hash and visibility (for deduplication)
+    ///
+    /// Public and private uploads of the same content are separate records,
+    /// so deduplication never changes who may fetch a media item.
+    pub fn find_by_hash(&self, hash: &str, visibility: Visibility) -> Result<Option<Media>> {
         match self
             .db
-            .get_cf(&self.cf_hash_index(), hash.as_bytes())
+            .get_cf(&self.cf_hash_index(), hash_index_key(hash, visibility).as_bytes())
             .map_err(|e| AppError::internal(format!("RocksDB read failed: {}", e)))?
         {
             Some(id_bytes) => {
                 let id_str = String::from_utf8_lossy(&id_bytes);
                 let id = Uuid::parse_str(&id_str)?;
-                self.get_media(id)
+                Ok(self.get_media(id)?.filter(|m| m.visibility == visibility))
             }
             None => Ok(None),
         }
     }
 
+    /// Check whether the hash index entry under `key` refers to `id`
+    fn hash_index_points_to(&self, key: &str, id: Uuid) -> Result<bool> {
+        let entry = self
+            .db
+            .get_cf(&self.cf_hash_index(), key.as_bytes())
+            .map_err(|e| AppError::internal(format!("RocksDB read failed: {}", e)))?;
+
+        Ok(entry.is_some_and(|bytes| bytes.as_slice() == id.to_string().as_bytes()))
+    }
+
+    /// Rehash legacy media records and rewrite the hash index (one-shot)
+    ///
+    /// `rehash` returns the new SHA-256 content hash for a legacy record,
+    /// typically by hashing its original file, or `None` if the uploaded
+    /// bytes are unavailable. Such records keep their legacy hash and stay
+    /// servable, but are dropped from the hash index: no upload hashes to a
+    /// legacy value any more, so they no longer dedup.
+    ///
+    /// Safe to run while serving: `rehash` is called without holding
+    /// `refs_lock`, then each record is re-read under the lock and left alone
+    /// if it was deleted or rehashed meanwhile. Other changes to the record
+    /// are kept.
+    ///
+    /// Returns `None` if the migration has already completed.
+    pub fn migrate_content_hashes<F>(&self, mut rehash: F) -> Result<Option<HashMigrationReport>>
+    where
+        F: FnMut(&Media) -> Option<String>,
+    {
+        if self.get_meta(MIGRATION_CONTENT_HASH_SHA256)?.is_some() {
+            return Ok(None);
+        }
+
+        let mut report = HashMigrationReport::default();
+        let iter = self.db.iterator_cf(&self.cf_media(), rocksdb::IteratorMode::Start);
+
+        for item in iter {
+            let (_, value) =
+                item.map_err(|e| AppError::internal(format!("RocksDB iteration failed: {}", e)))?;
+            let record: MediaRecord = serde_json::from_slice(&value)?;
+            let snapshot = record.into_media()?;
+
+            if snapshot.hash_algorithm != HashAlgorithm::Legacy {
+                continue;
+            }
+
+            let new_hash = rehash(&snapshot);
+
+            let _guard = self.refs_lock.lock().unwrap_or_else(|e| e.into_inner());
+            let mut media = match self.get_media(snapshot.id)? {
+                Some(m)
+                    if m.hash_algorithm == HashAlgorithm::Legacy
+                        && m.content_hash == snapshot.content_hash =>
+                {
+                    m
+                }
+                // Deleted or rehashed since the snapshot
+                _ => continue,
+            };
+
+            let mut batch = WriteBatch::default();
+            let legacy_key = hash_index_key(&media.content_hash, media.visibility);
+            if self.hash_index_points_to(&legacy_key, media.id)? {
+                batch.delete_cf(&self.cf_hash_index(), legacy_key.as_bytes());
+            }
+
+            let new_hash = match new_hash {
+                Some(hash) => hash,
+                None => {
+                    warn!(
+                        id = %media.id,
+                        "Cannot rehash media, keeping legacy hash without dedup"
+                    );
+                    self.db
+                        .write(batch)
+                        .map_err(|e| AppError::internal(format!("RocksDB write failed: {}", e)))?;
+                    report.skipped += 1;
+                    continue;
+                }
+            };
+
+            media.content_hash = new_hash;
+            media.hash_algorithm = HashAlgorithm::Sha256;
+
+            // Identical content may already be indexed under another record
+            match self.find_by_hash(&media.content_hash, media.visibility)? {
+                None => batch.put_cf(
+                    &self.cf_hash_index(),
+                    hash_index_key(&media.content_hash, media.visibility).as_bytes(),
+                    media.id.to_string().as_bytes(),
+                ),
+                Some(indexed) => {
+                    warn!(
+                        id = %media.id,
+                        indexed_id = %indexed.id,
+                        "Rehashed media duplicates another record, left out of dedup"
+                    );
+                    report.duplicates += 1;
+                }
+            }
+
+            let data = serde_json::to_vec(&MediaRecord::from(&media))?;
+            batch.put_cf(&self.cf_media(), media.id.to_string().as_bytes(), &data);
+
+            self.db
+                .write(batch)
+                .map_err(|e| AppError::internal(format!("RocksDB write failed: {}", e)))?;
+
+            report.migrated += 1;
+        }
+
+        self.put_meta(MIGRATION_CONTENT_HASH_SHA256, Utc::now().to_rfc3339().as_bytes())?;
+
+        info!(
+            migrated = report.migrated,
+            skipped = report.skipped,
+            duplicates = report.duplicates,
+            "Content hash migration completed"
+        );
+        Ok(Some(report))
+    }
+
+    /// Images whose perceptual hash is within `max_distance` bits of `hash`
+    ///
+    /// Returns `(id, distance)` pairs, closest first. Scans the whole
+    /// index, which holds 8 bytes per image.
+    pub fn find_similar(&self, hash: u64, max_distance: u32) -> Result<Vec<(Uuid, u32)>> {
+        let iter = self
+            .db
+            .iterator_cf(&self.cf_perceptual_hashes(), rocksdb::IteratorMode::Start);
+
+        let mut matches = Vec::new();
+        for item in iter {
+            let (key, value) =
+                item.map_err(|e| AppError::internal(format!("RocksDB iterator error: {}", e)))?;
+            let (Ok(id), Ok(bytes)) = (
+                Uuid::parse_str(&String::from_utf8_lossy(&key)),
+                <[u8; 8]>::try_from(value.as_ref()),
+            ) else {
+                continue;
+            };
+
+            let distance = perceptual_hash::distance(hash, u64::from_be_bytes(bytes));
+            if distance <= max_distance {
+                matches.push((id, distance));
+            }
+        }
+
+        matches.sort_by_key(|&(id, distance)| (distance, id));
+        Ok(matches)
+    }
+
     /// Get total media count
     pub fn get_media_count(&self) -> Result<u64> {
         let mut count = 0u64;
@@ -261,6 +662,70 @@ impl DatabaseService {
         Ok(count)
     }
 
+    // =========================================================================
+    // Access tracking
+    // =========================================================================
+
+    /// Get the stored view count and last access of a media item
+    pub fn get_media_access(&self, id: Uuid) -> Result<MediaAccess> {
+        match self
+            .db
+            .get_cf(&self.cf_media_access(), id.to_string().as_bytes())
+            .map_err(|e| AppError::internal(format!("RocksDB read failed: {}", e)))?
+        {
+            Some(data) => {
+                let record: AccessRecord = serde_json::from_slice(&data)?;
+                Ok(record.into_access())
+            }
+            None => Ok(MediaAccess::default()),
+        }
+    }
+
+    /// Add access collected in memory to the stored counts in one batch
+    ///
+    /// Serialized with deletes; access of media deleted meanwhile is dropped.
+    /// Blocks on RocksDB, so async callers run it on the blocking pool.
+    pub fn flush_media_access(&self, batch: &[(Uuid, MediaAccess)]) -> Result<()> {
+        let _guard = self.refs_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        let keys: Vec<String> = batch.iter().map(|(id, _)| id.to_string()).collect();
+        let (cf_media, cf_access) = (self.cf_media(), self.cf_media_access());
+        let records = self
+            .db
+            .multi_get_cf(keys.iter().map(|key| (&cf_media, key.as_bytes())));
+        let stored = self
+            .db
+            .multi_get_cf(keys.iter().map(|key| (&cf_access, key.as_bytes())));
+
+        let read_failed = |e: rocksdb::Error| AppError::internal(format!("RocksDB read failed: {}", e));
+        let rows = keys.iter().zip(batch).zip(records.into_iter().zip(stored));
+
+        let mut write = WriteBatch::default();
+        for ((key, (_, access)), (record, stored)) in rows {
+            if record.map_err(read_failed)?.is_none() {
+                continue;
+            }
+
+            let mut stored = match stored.map_err(read_failed)? {
+                Some(data) => serde_json::from_slice::<AccessRecord>(&data)?.into_access(),
+                None => MediaAccess::default(),
+            };
+            stored.merge(access);
+            write.put_cf(
+                &cf_access,
+                key.as_bytes(),
+                serde_json::to_vec(&AccessRecord::from(&stored))?,
+            );
+        }
+
+        self.db
+            .write(write)
+            .map_err(|e| AppError::internal(format!("RocksDB write failed: {}", e)))?;
+
+        debug!(count = batch.len(), "Flushed media access");
+        Ok(())
+    }
+
     // =========================================================================
     // Upload session operations
     // =========================================================================
@@ -424,17 +889,128 @@ impl DatabaseService {
         Ok(expired_ids)
     }
 
+    // =========================================================================
+    // Processing job operations
+    // =========================================================================
+
+    /// Save a session together with a new processing job for it
+    ///
+    /// Both are written in one batch, so a session marked as processing
+    /// always has a job that will eventually settle it.
+    pub fn enqueue_processing_job(&self, session: &UploadSession) -> Result<ProcessingJob> {
+        let job = ProcessingJob::new(session.id);
+        let key = session.id.to_string();
+
+        let mut batch = WriteBatch::default();
+        batch.put_cf(
+            &self.cf_sessions(),
+            key.as_bytes(),
+            serde_json::to_vec(&SessionRecord::from(session))?,
+        );
+        batch.put_cf(
+            &self.cf_processing_jobs(),
+            key.as_bytes(),
+            serde_json::to_vec(&job)?,
+        );
+
+        self.db
+            .write(batch)
+            .map_err(|e| AppError::internal(format!("RocksDB write failed: {}", e)))?;
+
+        debug!(id = %session.id, "Queued processing job");
+        Ok(job)
+    }
+
+    /// Get the processing job of a session
+    pub fn get_processing_job(&self, session_id: Uuid) -> Result<Option<ProcessingJob>> {
+        match self
+            .db
+            .get_cf(&self.cf_processing_jobs(), session_id.to_string().as_bytes())
+            .map_err(|e| AppError::internal(format!("RocksDB read failed: {}", e)))?
+        {
+            Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Update a processing job (e.g. its attempt count)
+    pub fn update_processing_job(&self, job: &ProcessingJob) -> Result<()> {
+        let data = serde_json::to_vec(job)?;
+
+        self.db
+            .put_cf(
+                &self.cf_processing_jobs(),
+                job.session_id.to_string().as_bytes(),
+                &data,
+            )
+            .map_err(|e| AppError::internal(format!("RocksDB write failed: {}", e)))
+    }
+
+    /// Remove a processing job without touching its session
+    pub fn delete_processing_job(&self, session_id: Uuid) -> Result<()> {
+        self.db
+            .delete_cf(&self.cf_processing_jobs(), session_id.to_string().as_bytes())
+            .map_err(|e| AppError::internal(format!("RocksDB delete failed: {}", e)))
+    }
+
+    /// All pending processing jobs, oldest first
+    pub fn list_processing_jobs(&self) -> Result<Vec<ProcessingJob>> {
+        let iter = self
+            .db
+            .iterator_cf(&self.cf_processing_jobs(), rocksdb::IteratorMode::Start);
+
+        let mut jobs = Vec::new();
+        for item in iter {
+            let (_, value) =
+                item.map_err(|e| AppError::internal(format!("RocksDB iterator error: {}", e)))?;
+            match serde_json::from_slice::<ProcessingJob>(&value) {
+                Ok(job) => jobs.push(job),
+                Err(e) => warn!(error = %e, "Skipping unreadable processing job"),
+            }
+        }
+
+        jobs.sort_by_key(|job| job.enqueued_at);
+        Ok(jobs)
+    }
+
+    /// Save the settled session and remove its processing job in one batch
+    pub fn finish_processing_job(&self, session: &UploadSession) -> Result<()> {
+        let key = session.id.to_string();
+
+        let mut batch = WriteBatch::default();
+        batch.put_cf(
+            &self.cf_sessions(),
+            key.as_bytes(),
+            serde_json::to_vec(&SessionRecord::from(session))?,
+        );
+        batch.delete_cf(&self.cf_processing_jobs(), key.as_bytes());
+
+        self.db
+            .write(batch)
+            .map_err(|e| AppError::internal(format!("RocksDB write failed: {}", e)))?;
+
+        debug!(id = %session.id, status = ?session.status, "Finished processing job");
+        Ok(())
+    }
+
     // =========================================================================
     // Token metadata operations (RexPump)
     // =========================================================================
 
     /// Upsert token metadata (create or update)
     pub fn upsert_token_metadata(&self, meta: &TokenMetadata) -> Result<()> {
+        let _guard = self.refs_lock.lock().unwrap_or_else(|e| e.into_inner());
+
         let key = meta.storage_key();
         let data = serde_json::to_vec(meta)?;
+        let locked = self.is_token_locked(meta.chain_id, &meta.token_address)?;
+
+        let mut batch = WriteBatch::default();
+        batch.put_cf(&self.cf_token_metadata(), key.as_bytes(), &data);
+        self.index_locked_images(&mut batch, &key, Some(meta), locked)?;
 
         self.db
-            .put_cf(&self.cf_token_metadata(), key.as_bytes(), &data)
+            .write(batch)
             .map_err(|e| AppError::internal(format!("RocksDB write failed: {}", e)))?;
 
         debug!(key = %key, "Upserted token metadata");
@@ -460,6 +1036,7 @@ impl DatabaseService {
 
     /// Delete token metadata
     pub fn delete_token_metadata(&self, chain_id: u64, address: &str) -> Result<bool> {
+        let _guard = self.refs_lock.lock().unwrap_or_else(|e| e.into_inner());
         let key = TokenMetadata::make_key(chain_id, address);
         
         // Check if exists first
@@ -467,11 +1044,12 @@ impl DatabaseService {
             return Ok(false);
         }
 
-        // Delete metadata, lock, and rate limit atomically
+        // Delete metadata, lock, rate limit and indexed images atomically
         let mut batch = WriteBatch::default();
         batch.delete_cf(&self.cf_token_metadata(), key.as_bytes());
         batch.delete_cf(&self.cf_token_locks(), key.as_bytes());
         batch.delete_cf(&self.cf_token_rate_limits(), key.as_bytes());
+        self.index_locked_images(&mut batch, &key, None, false)?;
 
         self.db
             .write(batch)
@@ -487,11 +1065,18 @@ impl DatabaseService {
 
     /// Lock a token
     pub fn lock_token(&self, lock: &TokenLock) -> Result<()> {
+        let _guard = self.refs_lock.lock().unwrap_or_else(|e| e.into_inner());
+
         let key = lock.storage_key();
         let data = serde_json::to_vec(lock)?;
+        let meta = self.get_token_metadata(lock.chain_id, &lock.token_address)?;
+
+        let mut batch = WriteBatch::default();
+        batch.put_cf(&self.cf_token_locks(), key.as_bytes(), &data);
+        self.index_locked_images(&mut batch, &key, meta.as_ref(), true)?;
 
         self.db
-            .put_cf(&self.cf_token_locks(), key.as_bytes(), &data)
+            .write(batch)
             .map_err(|e| AppError::internal(format!("RocksDB write failed: {}", e)))?;
 
         info!(key = %key, lock_type = ?lock.lock_type, "Locked token");
@@ -500,6 +1085,7 @@ impl DatabaseService {
 
     /// Unlock a token
     pub fn unlock_token(&self, chain_id: u64, address: &str) -> Result<bool> {
+        let _guard = self.refs_lock.lock().unwrap_or_else(|e| e.into_inner());
         let key = TokenMetadata::make_key(chain_id, address);
         
         // Check if locked first
@@ -507,8 +1093,12 @@ impl DatabaseService {
             return Ok(false);
         }
 
+        let mut batch = WriteBatch::default();
+        batch.delete_cf(&self.cf_token_locks(), key.as_bytes());
+        self.index_locked_images(&mut batch, &key, None, false)?;
+
         self.db
-            .delete_cf(&self.cf_token_locks(), key.as_bytes())
+            .write(batch)
             .map_err(|e| AppError::internal(format!("RocksDB delete failed: {}", e)))?;
 
         info!(key = %key, "Unlocked token");
@@ -532,6 +1122,168 @@ impl DatabaseService {
         }
     }
 
+    /// Closest image of a locked token within `max_distance` bits of `hash`
+    ///
+    /// The token with key `exclude_key` (`chainid:address`) is skipped, so a
+    /// token never matches its own images. Scans the locked images index,
+    /// which holds only the images of locked tokens.
+    pub fn find_locked_near_duplicate(
+        &self,
+        hash: u64,
+        max_distance: u32,
+        exclude_key: &str,
+    ) -> Result<Option<NearDuplicate>> {
+        let iter = self
+            .db
+            .iterator_cf(&self.cf_locked_images(), rocksdb::IteratorMode::Start);
+
+        let mut closest: Option<NearDuplicate> = None;
+        for item in iter {
+            let (key, value) =
+                item.map_err(|e| AppError::internal(format!("RocksDB iterator error: {}", e)))?;
+            let key = String::from_utf8_lossy(&key);
+            let Some((token_key, media_id)) = key.rsplit_once('/') else {
+                continue;
+            };
+            if token_key == exclude_key {
+                continue;
+            }
+            let (Some((chain_id, token_address)), Ok(media_id), Ok(bytes)) = (
+                token_key.split_once(':'),
+                Uuid::parse_str(media_id),
+                <[u8; 8]>::try_from(value.as_ref()),
+            ) else {
+                continue;
+            };
+            let Ok(chain_id) = chain_id.parse::<u64>() else {
+                continue;
+            };
+
+            let distance = perceptual_hash::distance(hash, u64::from_be_bytes(bytes));
+            if distance <= max_distance && closest.as_ref().is_none_or(|c| distance < c.distance) {
+                closest = Some(NearDuplicate {
+                    chain_id,
+                    token_address: token_address.to_string(),
+                    media_id,
+                    distance,
+                });
+            }
+        }
+
+        Ok(closest)
+    }
+
+    /// Replace the locked images index entries of a token in `batch`
+    ///
+    /// Drops the token's entries, then adds one per image of `meta` if the
+    /// token is `locked`. Images without a perceptual hash get an empty value,
+    /// filled in by [`DatabaseService::update_media`] once they are hashed.
+    /// The reverse index is kept in step. Caller must hold `refs_lock`.
+    fn index_locked_images(
+        &self,
+        batch: &mut WriteBatch,
+        key: &str,
+        meta: Option<&TokenMetadata>,
+        locked: bool,
+    ) -> Result<()> {
+        let prefix = format!("{}/", key);
+        let iter = self.db.iterator_cf(
+            &self.cf_locked_images(),
+            rocksdb::IteratorMode::From(prefix.as_bytes(), rocksdb::Direction::Forward),
+        );
+        for item in iter {
+            let (entry, _) =
+                item.map_err(|e| AppError::internal(format!("RocksDB iterator error: {}", e)))?;
+            if !entry.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            let media_id = String::from_utf8_lossy(&entry[prefix.len()..]);
+            batch.delete_cf(
+                &self.cf_locked_images_by_media(),
+                format!("{}/{}", media_id, key).as_bytes(),
+            );
+            batch.delete_cf(&self.cf_locked_images(), entry);
+        }
+
+        let Some(meta) = meta.filter(|_| locked) else {
+            return Ok(());
+        };
+        for media_id in [meta.image_light_id, meta.image_dark_id].into_iter().flatten() {
+            let hash = self
+                .db
+                .get_cf(&self.cf_perceptual_hashes(), media_id.to_string().as_bytes())
+                .map_err(|e| AppError::internal(format!("RocksDB read failed: {}", e)))?;
+            batch.put_cf(
+                &self.cf_locked_images(),
+                format!("{}{}", prefix, media_id).as_bytes(),
+                hash.unwrap_or_default(),
+            );
+            batch.put_cf(
+                &self.cf_locked_images_by_media(),
+                format!("{}/{}", media_id, key).as_bytes(),
+                b"",
+            );
+        }
+        Ok(())
+    }
+
+    /// Keys (`chainid:address`) of the locked tokens using a media item
+    fn locking_tokens(&self, id: Uuid) -> Result<Vec<String>> {
+        let prefix = format!("{}/", id);
+        let iter = self.db.iterator_cf(
+            &self.cf_locked_images_by_media(),
+            rocksdb::IteratorMode::From(prefix.as_bytes(), rocksdb::Direction::Forward),
+        );
+
+        let mut tokens = Vec::new();
+        for item in iter {
+            let (key, _) =
+                item.map_err(|e| AppError::internal(format!("RocksDB iterator error: {}", e)))?;
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            tokens.push(String::from_utf8_lossy(&key[prefix.len()..]).into_owned());
+        }
+        Ok(tokens)
+    }
+
+    /// Index the images of tokens locked before the locked images index existed
+    fn migrate_locked_images(&self) -> Result<()> {
+        if self.get_meta(MIGRATION_LOCKED_IMAGES)?.is_some() {
+            return Ok(());
+        }
+
+        let _guard = self.refs_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let iter = self
+            .db
+            .iterator_cf(&self.cf_token_locks(), rocksdb::IteratorMode::Start);
+
+        let mut batch = WriteBatch::default();
+        let mut locked = 0u64;
+        for item in iter {
+            let (_, value) =
+                item.map_err(|e| AppError::internal(format!("RocksDB iterator error: {}", e)))?;
+            let lock: TokenLock = serde_json::from_slice(&value)?;
+            let meta = self.get_token_metadata(lock.chain_id, &lock.token_address)?;
+            self.index_locked_images(&mut batch, &lock.storage_key(), meta.as_ref(), true)?;
+            locked += 1;
+        }
+        batch.put_cf(
+            &self.cf_meta(),
+            MIGRATION_LOCKED_IMAGES.as_bytes(),
+            Utc::now().to_rfc3339().as_bytes(),
+        );
+
+        self.db
+            .write(batch)
+            .map_err(|e| AppError::internal(format!("RocksDB write failed: {}", e)))?;
+
+        if locked > 0 {
+            info!(tokens = locked, "Indexed images of locked tokens");
+        }
+        Ok(())
+    }
+
     /// Check if token is locked
     pub fn is_token_locked(&self, chain_id: u64, address: &str) -> Result<bool> {
         Ok(self.get_token_lock(chain_id, address)?.is_some())
@@ -588,6 +1340,22 @@ impl DatabaseService {
         debug!(key = %key, "Recorded token update time");
         Ok(())
     }
+
+    // =========================================================================
+    // Meta operations
+    // =========================================================================
+
+    fn get_meta(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.db
+            .get_cf(&self.cf_meta(), key.as_bytes())
+            .map_err(|e| AppError::internal(format!("RocksDB read failed: {}", e)))
+    }
+
+    fn put_meta(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.db
+            .put_cf(&self.cf_meta(), key.as_bytes(), value)
+            .map_err(|e| AppError::internal(format!("RocksDB write failed: {}", e)))
+    }
 }
 
 // =============================================================================
@@ -606,10 +1374,53 @@ struct MediaRecord {
     width: u32,
     height: u32,
     content_hash: String,
+    /// Missing in records written before SHA-256 hashing
+    #[serde(default = "default_hash_algorithm")]
+    hash_algorithm: String,
+    #[serde(default)]
+    duration_ms: Option<u64>,
+    #[serde(default)]
+    frame_count: Option<u32>,
+    #[serde(default)]
+    video_codec: Option<String>,
+    #[serde(default)]
+    poster_mime_type: Option<String>,
+    #[serde(default)]
+    renditions: Vec<String>,
+    #[serde(default)]
+    quality: Option<u8>,
+    #[serde(default)]
+    exif: ExifInfo,
+    #[serde(default)]
+    blurhash: Option<String>,
+    #[serde(default)]
+    dominant_color: Option<String>,
+    #[serde(default)]
+    perceptual_hash: Option<u64>,
+    #[serde(default)]
+    focal_point: Option<FocalPoint>,
+    #[serde(default)]
+    visibility: Visibility,
     created_at: String,
+    #[serde(default)]
+    updated_at: Option<String>,
     last_accessed_at: Option<String>,
 }
 
+/// Key of a content hash in the hash index
+///
+/// Public media keeps the bare hash, as before visibility existed.
+fn hash_index_key(hash: &str, visibility: Visibility) -> String {
+    match visibility {
+        Visibility::Public => hash.to_string(),
+        Visibility::Private => format!("private:{}", hash),
+    }
+}
+
+fn default_hash_algorithm() -> String {
+    HashAlgorithm::Legacy.as_str().to_string()
+}
+
 impl From<&Media> for MediaRecord {
     fn from(media: &Media) -> Self {
         Self {
@@ -623,7 +1434,21 @@ impl From<&Media> for MediaRecord {
             width: media.width,
             height: media.height,
             content_hash: media.content_hash.clone(),
+            hash_algorithm: media.hash_algorithm.as_str().to_string(),
+            duration_ms: media.duration_ms,
+            frame_count: media.frame_count,
+            video_codec: media.video_codec.clone(),
+            poster_mime_type: media.poster_mime_type.clone(),
+            renditions: media.renditions.clone(),
+            quality: media.quality,
+            exif: media.exif.clone(),
+            blurhash: media.blurhash.clone(),
+            dominant_color: media.dominant_color.clone(),
+            perceptual_hash: media.perceptual_hash,
+            focal_point: media.focal_point,
+            visibility: media.visibility,
             created_at: media.created_at.to_rfc3339(),
+            updated_at: Some(media.updated_at.to_rfc3339()),
             last_accessed_at: media.last_accessed_at.map(|dt| dt.to_rfc3339()),
         }
     }
@@ -631,6 +1456,15 @@ impl From<&Media> for MediaRecord {
 
 impl MediaRecord {
     fn into_media(self) -> Result<Media> {
+        let created_at = DateTime::parse_from_rfc3339(&self.created_at)
+            .map_err(|e| AppError::internal(format!("Invalid date: {}", e)))?
+            .with_timezone(&Utc);
+        // Records written before `updated_at` existed were never rewritten
+        let updated_at = self
+            .updated_at
+            .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
+            .map_or(created_at, |dt| dt.with_timezone(&Utc));
+
         Ok(Media {
             id: Uuid::parse_str(&self.id)?,
             original_filename: self.original_filename,
@@ -642,9 +1476,22 @@ impl MediaRecord {
             width: self.width,
             height: self.height,
             content_hash: self.content_hash,
-            created_at: DateTime::parse_from_rfc3339(&self.created_at)
-                .map_err(|e| AppError::internal(format!("Invalid date: {}", e)))?
-                .with_timezone(&Utc),
+            hash_algorithm: HashAlgorithm::from_str(&self.hash_algorithm)
+                .unwrap_or(HashAlgorithm::Legacy),
+            duration_ms: self.duration_ms,
+            frame_count: self.frame_count,
+            video_codec: self.video_codec,
+            poster_mime_type: self.poster_mime_type,
+            renditions: self.renditions,
+            quality: self.quality,
+            exif: self.exif,
+            blurhash: self.blurhash,
+            dominant_color: self.dominant_color,
+            perceptual_hash: self.perceptual_hash,
+            focal_point: self.focal_point,
+            visibility: self.visibility,
+            created_at,
+            updated_at,
             last_accessed_at: self
                 .last_accessed_at
                 .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
@@ -653,6 +1500,33 @@ impl MediaRecord {
     }
 }
 
+#[derive(Serialize, Deserialize)]
+struct AccessRecord {
+    views: u64,
+    last_accessed_at: Option<String>,
+}
+
+impl From<&MediaAccess> for AccessRecord {
+    fn from(access: &MediaAccess) -> Self {
+        Self {
+            views: access.views,
+            last_accessed_at: access.last_accessed_at.map(|dt| dt.to_rfc3339()),
+        }
+    }
+}
+
+impl AccessRecord {
+    fn into_access(self) -> MediaAccess {
+        MediaAccess {
+            views: self.views,
+            last_accessed_at: self
+                .last_accessed_at
+                .and_then(|s| DateTime::parse_from_rfc3339(&s).ok())
+                .map(|dt| dt.with_timezone(&Utc)),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct SessionRecord {
     id: String,
@@ -667,6 +1541,11 @@ struct SessionRecord {
     created_at: String,
     updated_at: String,
     expires_at: String,
+    /// Missing in sessions created before incremental hashing
+    #[serde(default)]
+    hash_state: Option<HashState>,
+    #[serde(default)]
+    visibility: Visibility,
 }
 
 impl From<&UploadSession> for SessionRecord {
@@ -684,6 +1563,8 @@ impl From<&UploadSession> for SessionRecord {
             created_at: session.created_at.to_rfc3339(),
             updated_at: session.updated_at.to_rfc3339(),
             expires_at: session.expires_at.to_rfc3339(),
+            hash_state: session.hash_state.clone(),
+            visibility: session.visibility,
         }
     }
 }
@@ -710,6 +1591,8 @@ impl SessionRecord {
             expires_at: DateTime::parse_from_rfc3339(&self.expires_at)
                 .map_err(|e| AppError::internal(format!("Invalid date: {}", e)))?
                 .with_timezone(&Utc),
+            hash_state: self.hash_state,
+            visibility: self.visibility,
         })
     }
 }
@@ -726,6 +1609,7 @@ mod tests {
             originals_dir: "originals".to_string(),
             optimized_dir: "optimized".to_string(),
             temp_dir: "temp".to_string(),
+            variants_dir: "variants".to_string(),
             directory_levels: 2,
             database_file: "unused".to_string(),
         };
@@ -758,13 +1642,361 @@ mod tests {
         assert_eq!(retrieved.original_filename, "test.jpg");
 
         // Find by hash
-        let found = db.find_by_hash("abc123").unwrap().unwrap();
+        let found = db.find_by_hash("abc123", Visibility::Public).unwrap().unwrap();
         assert_eq!(found.id, media.id);
 
         // Delete
         assert!(db.delete_media(media.id).unwrap());
         assert!(db.get_media(media.id).unwrap().is_none());
-        assert!(db.find_by_hash("abc123").unwrap().is_none());
+        assert!(db.find_by_hash("abc123", Visibility::Public).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_media_refcount() {
+        let (db, _temp) = create_test_db();
+
+        let media = Media::new(
+            "test.jpg".to_string(),
+            "image/jpeg".to_string(),
+            "image/webp".to_string(),
+            1000,
+            500,
+            100,
+            100,
+            "abc123".to_string(),
+        );
+        db.insert_media(&media).unwrap();
+        assert_eq!(db.get_media_refs(media.id).unwrap(), 1);
+
+        // Dedup hit takes a second reference
+        let acquired = db.acquire_by_hash("abc123", Visibility::Public).unwrap().unwrap();
+        assert_eq!(acquired.id, media.id);
+        assert_eq!(db.get_media_refs(media.id).unwrap(), 2);
+        assert!(db.acquire_by_hash("missing", Visibility::Public).unwrap().is_none());
+
+        // Private uploads of the same content get their own record
+        assert!(db.acquire_by_hash("abc123", Visibility::Private).unwrap().is_none());
+        let mut private = media.clone();
+        private.id = Uuid::new_v4();
+        private.visibility = Visibility::Private;
+        db.insert_media(&private).unwrap();
+        let acquired = db.acquire_by_hash("abc123", Visibility::Private).unwrap().unwrap();
+        assert_eq!(acquired.id, private.id);
+        assert_eq!(db.find_by_hash("abc123", Visibility::Public).unwrap().unwrap().id, media.id);
+
+        // First release keeps the record
+        assert!(db.release_media(media.id).unwrap().is_none());
+        assert!(db.get_media(media.id).unwrap().is_some());
+
+        // Last release deletes it
+        let released = db.release_media(media.id).unwrap().unwrap();
+        assert_eq!(released.id, media.id);
+        assert!(db.get_media(media.id).unwrap().is_none());
+        assert!(db.find_by_hash("abc123", Visibility::Public).unwrap().is_none());
+        assert!(db.release_media(media.id).unwrap().is_none());
+    }
+
+    /// Insert an image with the given perceptual hash
+    fn insert_hashed_media(db: &DatabaseService, content_hash: &str, hash: u64) -> Media {
+        let mut media = Media::new(
+            "test.png".to_string(),
+            "image/png".to_string(),
+            "image/webp".to_string(),
+            1000,
+            500,
+            100,
+            100,
+            content_hash.to_string(),
+        );
+        media.perceptual_hash = Some(hash);
+        db.insert_media(&media).unwrap();
+        media
+    }
+
+    #[test]
+    fn test_find_similar() {
+        let (db, _temp) = create_test_db();
+
+        let original = insert_hashed_media(&db, "a", 0xFFFF_0000_FFFF_0000);
+        let resaved = insert_hashed_media(&db, "b", 0xFFFF_0000_FFFF_0003);
+        insert_hashed_media(&db, "c", 0x0000_FFFF_0000_FFFF);
+
+        let matches = db.find_similar(0xFFFF_0000_FFFF_0000, 4).unwrap();
+        assert_eq!(matches, vec![(original.id, 0), (resaved.id, 2)]);
+
+        // Deleting the media drops it from the index
+        db.delete_media(resaved.id).unwrap();
+        assert_eq!(db.find_similar(0xFFFF_0000_FFFF_0000, 4).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_find_locked_near_duplicate() {
+        let (db, _temp) = create_test_db();
+        let logo = insert_hashed_media(&db, "logo", 0x0F0F_0F0F_0F0F_0F0F);
+
+        let address = "0x1111111111111111111111111111111111111111";
+        let mut meta = TokenMetadata::new(1, address.to_string(), String::new(), vec![], "owner".to_string());
+        meta.image_light_id = Some(logo.id);
+        db.upsert_token_metadata(&meta).unwrap();
+
+        // Unlocked tokens are not protected
+        let hash = 0x0F0F_0F0F_0F0F_0F0E;
+        assert!(db.find_locked_near_duplicate(hash, 8, "1:0xother").unwrap().is_none());
+
+        db.lock_token(&TokenLock::new(
+            1,
+            address.to_string(),
+            crate::models::TokenLockType::Locked,
+            "admin".to_string(),
+            None,
+        ))
+        .unwrap();
+
+        let found = db.find_locked_near_duplicate(hash, 8, "1:0xother").unwrap().unwrap();
+        assert_eq!((found.media_id, found.distance), (logo.id, 1));
+        assert_eq!(found.token_address, address);
+
+        // A token never matches its own images, nor anything too far away
+        assert!(db
+            .find_locked_near_duplicate(hash, 8, &meta.storage_key())
+            .unwrap()
+            .is_none());
+        assert!(db.find_locked_near_duplicate(!hash, 8, "1:0xother").unwrap().is_none());
+
+        // A new hash of the locked image is picked up by the index
+        db.update_media(logo.id, |m| m.perceptual_hash = Some(!hash)).unwrap();
+        assert!(db.find_locked_near_duplicate(hash, 8, "1:0xother").unwrap().is_none());
+        let found = db.find_locked_near_duplicate(!hash, 8, "1:0xother").unwrap().unwrap();
+        assert_eq!((found.media_id, found.distance), (logo.id, 0));
+
+        db.unlock_token(1, address).unwrap();
+        assert!(db.find_locked_near_duplicate(!hash, 8, "1:0xother").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_locked_images_index() {
+        let (db, _temp) = create_test_db();
+        let lock = |address: &str| {
+            db.lock_token(&TokenLock::new(
+                1,
+                address.to_string(),
+                crate::models::TokenLockType::Locked,
+                "admin".to_string(),
+                None,
+            ))
+            .unwrap();
+        };
+
+        // Locked before its image was hashed, then backfilled
+        let unhashed = Media::new(
+            "old.png".to_string(),
+            "image/png".to_string(),
+            "image/webp".to_string(),
+            1000,
+            500,
+            100,
+            100,
+            "old".to_string(),
+        );
+        db.insert_media(&unhashed).unwrap();
+        let first = "0x1111111111111111111111111111111111111111";
+        let mut meta = TokenMetadata::new(1, first.to_string(), String::new(), vec![], "owner".to_string());
+        meta.image_dark_id = Some(unhashed.id);
+        db.upsert_token_metadata(&meta).unwrap();
+        lock(first);
+        assert!(db.find_locked_near_duplicate(42, 0, "1:0xother").unwrap().is_none());
+        db.update_media(unhashed.id, |m| m.perceptual_hash = Some(42)).unwrap();
+        let found = db.find_locked_near_duplicate(42, 0, "1:0xother").unwrap().unwrap();
+        assert_eq!((found.chain_id, found.media_id), (1, unhashed.id));
+
+        // Metadata saved after the lock replaces the token's images
+        let logo = insert_hashed_media(&db, "logo", 7);
+        meta.image_dark_id = None;
+        meta.image_light_id = Some(logo.id);
+        db.upsert_token_metadata(&meta).unwrap();
+        assert!(db.find_locked_near_duplicate(42, 0, "1:0xother").unwrap().is_none());
+        assert!(db.find_locked_near_duplicate(7, 0, "1:0xother").unwrap().is_some());
+
+        // Deleting the media or the token drops their entries
+        let second = "0x2222222222222222222222222222222222222222";
+        let mut other = TokenMetadata::new(1, second.to_string(), String::new(), vec![], "owner".to_string());
+        other.image_light_id = Some(logo.id);
+        db.upsert_token_metadata(&other).unwrap();
+        lock(second);
+        db.delete_token_metadata(1, first).unwrap();
+        assert!(db.find_locked_near_duplicate(7, 0, "1:0xother").unwrap().is_some());
+        assert_eq!(db.locking_tokens(logo.id).unwrap(), vec![format!("1:{}", second)]);
+        db.delete_media(logo.id).unwrap();
+        assert!(db.find_locked_near_duplicate(7, 0, "1:0xother").unwrap().is_none());
+        assert!(db.locking_tokens(logo.id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_update_media() {
+        let (db, _temp) = create_test_db();
+
+        let media = Media::new(
+            "test.png".to_string(),
+            "image/png".to_string(),
+            "image/webp".to_string(),
+            1000,
+            500,
+            100,
+            100,
+            "def456".to_string(),
+        );
+        db.insert_media(&media).unwrap();
+        assert_eq!(db.list_media_ids().unwrap(), vec![media.id]);
+        assert_eq!(db.count_media_by_format().unwrap()["image/webp"], 1);
+
+        let updated = db
+            .update_media(media.id, |m| m.optimized_mime_type = "image/jpeg".to_string())
+            .unwrap()
+            .unwrap();
+        assert_eq!(updated.optimized_mime_type, "image/jpeg");
+        assert_eq!(
+            db.get_media(media.id).unwrap().unwrap().optimized_mime_type,
+            "image/jpeg"
+        );
+        // Refcount is untouched
+        assert_eq!(db.get_media_refs(media.id).unwrap(), 1);
+
+        // Deleted records are never written back
+        db.delete_media(media.id).unwrap();
+        assert!(db.update_media(media.id, |_| {}).unwrap().is_none());
+        assert!(db.get_media(media.id).unwrap().is_none());
+        assert!(db.count_media_by_format().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_media_access() {
+        let (db, _temp) = create_test_db();
+
+        let media = Media::new(
+            "test.png".to_string(),
+            "image/png".to_string(),
+            "image/webp".to_string(),
+            1000,
+            500,
+            100,
+            100,
+            "access".to_string(),
+        );
+        db.insert_media(&media).unwrap();
+        assert_eq!(db.get_media_access(media.id).unwrap(), MediaAccess::default());
+
+        let earlier = Utc::now() - chrono::Duration::minutes(5);
+        let later = Utc::now();
+        let access = |views, at| MediaAccess {
+            views,
+            last_accessed_at: Some(at),
+        };
+
+        // Flushes add up and keep the latest access time
+        db.flush_media_access(&[(media.id, access(3, later))]).unwrap();
+        db.flush_media_access(&[(media.id, access(2, earlier)), (Uuid::new_v4(), access(1, later))])
+            .unwrap();
+        let stored = db.get_media_access(media.id).unwrap();
+        assert_eq!(stored.views, 5);
+        assert_eq!(
+            stored.last_accessed_at.map(|dt| dt.timestamp()),
+            Some(later.timestamp())
+        );
+
+        // Deleting the media drops its counts, and late flushes are ignored
+        db.delete_media(media.id).unwrap();
+        assert_eq!(db.get_media_access(media.id).unwrap(), MediaAccess::default());
+        db.flush_media_access(&[(media.id, access(1, later))]).unwrap();
+        assert_eq!(db.get_media_access(media.id).unwrap(), MediaAccess::default());
+    }
+
+    #[test]
+    fn test_migrate_content_hashes() {
+        let (db, _temp) = create_test_db();
+
+        let mut legacy = Media::new(
+            "old.jpg".to_string(),
+            "image/jpeg".to_string(),
+            "image/webp".to_string(),
+            1000,
+            500,
+            100,
+            100,
+            "0123456789abcdef".to_string(),
+        );
+        legacy.hash_algorithm = HashAlgorithm::Legacy;
+        db.insert_media(&legacy).unwrap();
+
+        let mut orphan = legacy.clone();
+        orphan.id = Uuid::new_v4();
+        orphan.content_hash = "fedcba9876543210".to_string();
+        db.insert_media(&orphan).unwrap();
+
+        let current = Media::new(
+            "new.jpg".to_string(),
+            "image/jpeg".to_string(),
+            "image/webp".to_string(),
+            1000,
+            500,
+            100,
+            100,
+            "a".repeat(64),
+        );
+        db.insert_media(&current).unwrap();
+
+        // Same content as `current`, uploaded before the switch to SHA-256
+        let mut duplicate = legacy.clone();
+        duplicate.id = Uuid::new_v4();
+        duplicate.content_hash = "1111111111111111".to_string();
+        db.insert_media(&duplicate).unwrap();
+
+        // Deleted while the migration runs
+        let mut deleted = legacy.clone();
+        deleted.id = Uuid::new_v4();
+        deleted.content_hash = "2222222222222222".to_string();
+        db.insert_media(&deleted).unwrap();
+
+        let report = db
+            .migrate_content_hashes(|media| {
+                assert_eq!(media.hash_algorithm, HashAlgorithm::Legacy);
+                if media.id == deleted.id {
+                    assert!(db.delete_media(deleted.id).unwrap());
+                    return Some("c".repeat(64));
+                }
+                (media.id == legacy.id)
+                    .then(|| "b".repeat(64))
+                    .or_else(|| (media.id == duplicate.id).then(|| "a".repeat(64)))
+            })
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.migrated, 2);
+        assert_eq!(report.skipped, 1);
+        assert_eq!(report.duplicates, 1);
+
+        // The deleted record is not written back
+        assert!(db.get_media(deleted.id).unwrap().is_none());
+        assert!(db.find_by_hash(&"c".repeat(64), Visibility::Public).unwrap().is_none());
+
+        // The index keeps pointing at the record that already had the hash
+        let indexed = db.find_by_hash(&"a".repeat(64), Visibility::Public).unwrap().unwrap();
+        assert_eq!(indexed.id, current.id);
+        let rehashed = db.get_media(duplicate.id).unwrap().unwrap();
+        assert_eq!(rehashed.hash_algorithm, HashAlgorithm::Sha256);
+
+        // Legacy index entry replaced by the new hash
+        assert!(db.find_by_hash("0123456789abcdef", Visibility::Public).unwrap().is_none());
+        let migrated = db.find_by_hash(&"b".repeat(64), Visibility::Public).unwrap().unwrap();
+        assert_eq!(migrated.id, legacy.id);
+        assert_eq!(migrated.hash_algorithm, HashAlgorithm::Sha256);
+
+        // Unavailable source keeps its legacy hash but leaves the index
+        let kept = db.get_media(orphan.id).unwrap().unwrap();
+        assert_eq!(kept.hash_algorithm, HashAlgorithm::Legacy);
+        assert_eq!(kept.content_hash, "fedcba9876543210");
+        assert!(db.find_by_hash("fedcba9876543210", Visibility::Public).unwrap().is_none());
+
+        // One-shot: a second run is a no-op
+        assert!(db.migrate_content_hashes(|_| unreachable!()).unwrap().is_none());
     }
 
     #[test]
@@ -791,5 +2023,43 @@ mod tests {
         assert!(db.delete_session(session.id).unwrap());
         assert!(db.get_session(session.id).unwrap().is_none());
     }
+
+    #[test]
+    fn test_processing_job_lifecycle() {
+        let (db, _temp) = create_test_db();
+
+        let mut session = UploadSession::new(
+            "test.png".to_string(),
+            "image/png".to_string(),
+            1000,
+            512,
+            3600,
+        );
+        db.insert_session(&session).unwrap();
+
+        // Queueing stores the job and the processing status together
+        session.mark_processing();
+        let mut job = db.enqueue_processing_job(&session).unwrap();
+        assert_eq!(
+            db.get_session(session.id).unwrap().unwrap().status,
+            UploadSessionStatus::Processing
+        );
+        assert_eq!(db.list_processing_jobs().unwrap(), vec![job.clone()]);
+
+        job.attempts += 1;
+        db.update_processing_job(&job).unwrap();
+        assert_eq!(db.get_processing_job(session.id).unwrap().unwrap().attempts, 1);
+
+        // Finishing settles the session and drops the job
+        let media_id = Uuid::new_v4();
+        session.mark_completed(media_id);
+        db.finish_processing_job(&session).unwrap();
+
+        let stored = db.get_session(session.id).unwrap().unwrap();
+        assert_eq!(stored.status, UploadSessionStatus::Completed);
+        assert_eq!(stored.media_id, Some(media_id));
+        assert!(db.get_processing_job(session.id).unwrap().is_none());
+        assert!(db.list_processing_jobs().unwrap().is_empty());
+    }
 }
 
@@ -31,8 +31,8 @@ impl EvmService {
 
         // Index by chain_id for fast lookup
         let networks_by_chain_id: HashMap<u64, EvmNetworkConfig> = networks
-            .into_iter()
-            .map(|(_, v)| (v.chain_id, v))
+            .into_values()
+            .map(|v| (v.chain_id, v))
             .collect();
 
         Self {
@@ -287,6 +287,8 @@ mod tests {
                 chain_id: 1,
                 rpc_url: "http://localhost:8545".to_string(),
                 fallback_rpc_url: None,
+                default_image_light: None,
+                default_image_dark: None,
             },
         );
         
@@ -0,0 +1,156 @@
+//! EXIF metadata helpers for uploaded images.
+//!
+//! Reads the camera details admins get to see ([`ExifInfo`]) and builds the
+//! reduced EXIF block kept under the `keep_copyright` metadata policy. Input
+//! is the raw TIFF-structured EXIF payload as returned by the image decoders.
+//!
+//! Malformed EXIF is never an upload error: it just yields no information.
+
+use crate::models::ExifInfo;
+use chrono::NaiveDate;
+use exif::experimental::Writer;
+use exif::{Context, Exif, Field, In, Reader, Tag, Value};
+use std::io::Cursor;
+
+/// Tags kept by the `keep_copyright` policy
+const COPYRIGHT_TAGS: [Tag; 2] = [Tag::Artist, Tag::Copyright];
+
+/// Parse a raw EXIF payload, ignoring unreadable data
+fn parse(exif: &[u8]) -> Option<Exif> {
+    Reader::new().read_raw(exif.to_vec()).ok()
+}
+
+/// Extract camera, capture date and GPS presence from a raw EXIF payload
+pub fn summarize(exif: &[u8]) -> ExifInfo {
+    let Some(exif) = parse(exif) else {
+        return ExifInfo::default();
+    };
+
+    let taken_at = [Tag::DateTimeOriginal, Tag::DateTime]
+        .into_iter()
+        .find_map(|tag| date_field(&exif, tag));
+    let has_gps = exif.fields().any(|f| f.tag.context() == Context::Gps);
+
+    ExifInfo {
+        camera_make: ascii_field(&exif, Tag::Make),
+        camera_model: ascii_field(&exif, Tag::Model),
+        taken_at,
+        has_gps,
+    }
+}
+
+/// Build an EXIF payload holding only the Artist and Copyright tags
+///
+/// Returns `None` if the source has neither tag (or cannot be parsed).
+pub fn copyright_only(exif: &[u8]) -> Option<Vec<u8>> {
+    let exif = parse(exif)?;
+    let fields: Vec<&Field> = COPYRIGHT_TAGS
+        .iter()
+        .filter_map(|&tag| exif.get_field(tag, In::PRIMARY))
+        .collect();
+
+    if fields.is_empty() {
+        return None;
+    }
+
+    let mut writer = Writer::new();
+    for field in fields {
+        writer.push_field(field);
+    }
+
+    let mut buffer = Cursor::new(Vec::new());
+    writer.write(&mut buffer, exif.little_endian()).ok()?;
+    Some(buffer.into_inner())
+}
+
+/// Read a non-empty ASCII tag from the primary image
+fn ascii_field(exif: &Exif, tag: Tag) -> Option<String> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => {
+            let text = String::from_utf8_lossy(values.first()?).trim().to_string();
+            (!text.is_empty()).then_some(text)
+        }
+        _ => None,
+    }
+}
+
+/// Read an EXIF date/time tag ("YYYY:MM:DD HH:MM:SS")
+fn date_field(exif: &Exif, tag: Tag) -> Option<chrono::NaiveDateTime> {
+    let Value::Ascii(values) = &exif.get_field(tag, In::PRIMARY)?.value else {
+        return None;
+    };
+    let dt = exif::DateTime::from_ascii(values.first()?).ok()?;
+
+    NaiveDate::from_ymd_opt(dt.year.into(), dt.month.into(), dt.day.into())?.and_hms_opt(
+        dt.hour.into(),
+        dt.minute.into(),
+        dt.second.into(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ascii(tag: Tag, text: &str) -> Field {
+        Field {
+            tag,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![text.as_bytes().to_vec()]),
+        }
+    }
+
+    /// Encode fields into a raw EXIF payload
+    fn build(fields: &[Field]) -> Vec<u8> {
+        let mut writer = Writer::new();
+        for field in fields {
+            writer.push_field(field);
+        }
+        let mut buffer = Cursor::new(Vec::new());
+        writer.write(&mut buffer, false).unwrap();
+        buffer.into_inner()
+    }
+
+    #[test]
+    fn test_summarize() {
+        let gps = Field {
+            tag: Tag::GPSLatitudeRef,
+            ifd_num: In::PRIMARY,
+            value: Value::Ascii(vec![b"N".to_vec()]),
+        };
+        let exif = build(&[
+            ascii(Tag::Make, "Canon"),
+            ascii(Tag::Model, "EOS R5"),
+            ascii(Tag::DateTimeOriginal, "2024:05:17 14:03:21"),
+            gps,
+        ]);
+
+        let info = summarize(&exif);
+        assert_eq!(info.camera_make.as_deref(), Some("Canon"));
+        assert_eq!(info.camera_model.as_deref(), Some("EOS R5"));
+        assert_eq!(
+            info.taken_at.unwrap().to_string(),
+            "2024-05-17 14:03:21"
+        );
+        assert!(info.has_gps);
+
+        assert!(summarize(b"not exif").is_empty());
+    }
+
+    #[test]
+    fn test_copyright_only() {
+        let exif = build(&[
+            ascii(Tag::Make, "Canon"),
+            ascii(Tag::Artist, "Jane Doe"),
+            ascii(Tag::Copyright, "(c) 2024 Jane Doe"),
+        ]);
+
+        let kept = parse(&copyright_only(&exif).unwrap()).unwrap();
+        assert!(kept.get_field(Tag::Make, In::PRIMARY).is_none());
+        assert!(kept.get_field(Tag::Artist, In::PRIMARY).is_some());
+        assert!(kept.get_field(Tag::Copyright, In::PRIMARY).is_some());
+
+        let no_copyright = build(&[ascii(Tag::Make, "Canon")]);
+        assert!(copyright_only(&no_copyright).is_none());
+    }
+}
@@ -2,28 +2,105 @@
 //!
 //! This module handles all image manipulation operations:
 //! - Format detection and validation
-//! - Conversion to configurable output format
+//! - Rejecting images over the decode limits (see `max_decode_width`,
+//!   `max_decode_height`, `max_decode_alloc`) before decoding them
+//! - Conversion to configurable output format, plus alternate renditions
+//!   for content negotiation
 //! - Resizing to maximum dimensions
-//! - EXIF stripping
+//! - Keeping GIF/WebP animations animated (see `preserve_animation`)
+//! - Applying EXIF orientation and filtering metadata (see `MetadataPolicy`)
+//! - Rendering on-the-fly variants (thumbnails, crops, format conversion),
+//!   with cover crops centered on a focal point (see `smart_crop`)
+//!
+//! Input is read from any `BufRead + Seek` source, so large chunked uploads
+//! are decoded straight from the temp file. All methods are blocking and
+//! memory-heavy; callers run them on the `ProcessingPool`.
 //!
 //! # Supported Formats
 //!
-//! Input: Configurable via allowed_image_types in config
-//! Output: Configurable via output_format in config (webp, jpeg, png)
+//! Input: Configurable via allowed_image_types in config, limited to the
+//! decoders compiled in (see [`crate::models::media_format`]); SVG is
+//! sanitized and rasterized (see [`svg`])
+//! Output: Configurable via output_format and output_formats in config
+//! (webp, jpeg, png, avif)
+//!
+//! JPEG, WebP and AVIF are encoded with `output_quality` (or a variant's
+//! `q`); WebP can be switched to lossless with `webp_lossless`.
+//!
+//! Animations are re-encoded to animated WebP when that is the output
+//! format (or the upload already is WebP); otherwise a GIF stays a GIF.
+//! They get no alternate renditions, and variants are stills of the first
+//! frame.
 
-use crate::config::{ProcessingConfig, UploadConfig};
+use crate::config::{MetadataPolicy, ProcessingConfig, UploadConfig};
 use crate::error::{AppError, Result};
-use image::{DynamicImage, GenericImageView, ImageFormat};
-use std::io::Cursor;
+use crate::models::media_format::{canonical_mime, SVG_MIME};
+use crate::models::{
+    ExifInfo, FitMode, FocalPoint, Focus, MediaFormat, OutputFormat, VariantSpec,
+};
+use crate::services::animated_webp;
+use crate::services::image_metadata;
+use crate::services::perceptual_hash;
+use crate::services::placeholder::{self, Placeholder};
+use crate::services::smart_crop;
+use crate::services::svg;
+use image::codecs::avif::AvifEncoder;
+use image::codecs::gif::{GifDecoder, GifEncoder, Repeat};
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::{WebPDecoder, WebPEncoder};
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{
+    AnimationDecoder, Delay, DynamicImage, Frame, GenericImageView, ImageDecoder, ImageEncoder,
+    ImageError, ImageFormat, ImageReader, ImageResult, Limits, RgbaImage,
+};
+use std::io::{BufRead, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+use tokio::io::AsyncReadExt;
 use tracing::{debug, info};
 
+/// Number of leading bytes inspected for magic-byte detection
+const SNIFF_LEN: u64 = 8192;
+
+/// AVIF encoder speed (1 slowest - 10 fastest); encoding runs at upload time
+const AVIF_SPEED: u8 = 8;
+
+/// AVIF quality when none is requested (the encoder's own default)
+const DEFAULT_AVIF_QUALITY: u8 = 80;
+
+/// GIF encoder speed (1 best palette - 30 fastest)
+const GIF_SPEED: i32 = 10;
+
+/// Resolution of animation frame delays in milliseconds (GIF stores
+/// centiseconds); also the shortest delay a frame is given
+const FRAME_TICK_MS: u32 = 10;
+
+/// An encoded image in one output format
+#[derive(Debug, Clone)]
+pub struct Rendition {
+    /// Encoded format
+    pub format: OutputFormat,
+    /// Encoded bytes
+    pub data: Vec<u8>,
+}
+
 /// Result of image processing
 #[derive(Debug)]
 pub struct ProcessedImage {
-    /// Original image data (may be modified for EXIF stripping)
-    pub original_data: Vec<u8>,
-    /// Optimized image in configured output format
+    /// Re-encoded original (EXIF stripped), or `None` if the source should
+    /// be stored verbatim
+    pub original_data: Option<Vec<u8>>,
+    /// Size of the stored original in bytes
+    pub original_size: u64,
+    /// Optimized image in configured output format (animations: WebP or GIF)
     pub optimized_data: Vec<u8>,
+    /// MIME type of the optimized image
+    pub optimized_mime: String,
+    /// Encoder quality of the optimized image (`None` if lossless)
+    pub quality: Option<u8>,
+    /// Additional renditions in the configured alternate formats
+    pub renditions: Vec<Rendition>,
     /// Detected MIME type of original
     pub original_mime: String,
     /// Image width in pixels
@@ -32,20 +109,92 @@ pub struct ProcessedImage {
     pub height: u32,
     /// Whether the image was resized
     pub was_resized: bool,
+    /// Number of frames (animations only)
+    pub frame_count: Option<u32>,
+    /// Total animation duration in milliseconds (animations only)
+    pub duration_ms: Option<u64>,
+    /// Camera details read from the upload's EXIF data
+    pub exif: ExifInfo,
+    /// BlurHash and dominant color (of the first frame for animations)
+    pub placeholder: Placeholder,
+    /// Perceptual hash for near-duplicate search (first frame for animations)
+    pub perceptual_hash: u64,
+    /// Detected point of interest (first frame for animations)
+    pub focal_point: FocalPoint,
+    /// Decoded (and resized) image, reused to render presets without
+    /// decoding again; the first frame for animations
+    pub image: DynamicImage,
+}
+
+/// One animation frame, composited onto the full canvas
+#[derive(Debug)]
+struct AnimationFrame {
+    /// Frame pixels
+    image: RgbaImage,
+    /// How long the frame is shown, a multiple of `FRAME_TICK_MS`
+    delay_ms: u32,
+}
+
+/// Metadata read from an upload before its pixels are decoded
+#[derive(Debug, Default)]
+struct SourceMetadata {
+    /// Embedded ICC color profile
+    icc_profile: Option<Vec<u8>>,
+    /// Raw EXIF payload
+    exif: Option<Vec<u8>>,
+    /// EXIF orientation, applied to the pixels after decoding
+    orientation: Option<Orientation>,
+}
+
+impl SourceMetadata {
+    /// Read metadata from a decoder; unreadable metadata is treated as absent
+    fn read(decoder: &mut impl ImageDecoder) -> Self {
+        Self {
+            icc_profile: decoder.icc_profile().ok().flatten(),
+            exif: decoder.exif_metadata().ok().flatten(),
+            orientation: decoder.orientation().ok(),
+        }
+    }
+}
+
+/// Result of re-encoding a stored image
+#[derive(Debug)]
+pub struct ReencodedImage {
+    /// Image in the configured output format
+    pub optimized_data: Vec<u8>,
+    /// Additional renditions in the configured alternate formats
+    pub renditions: Vec<Rendition>,
+    /// Image width in pixels
+    pub width: u32,
+    /// Image height in pixels
+    pub height: u32,
+    /// BlurHash and dominant color
+    pub placeholder: Placeholder,
+    /// Detected point of interest
+    pub focal_point: FocalPoint,
+    /// 64-bit dHash for near-duplicate detection
+    pub perceptual_hash: u64,
 }
 
 /// Service for image processing operations
 #[derive(Debug, Clone)]
 pub struct ImageProcessor {
-    /// Output format (webp, jpeg, png)
+    /// Output format (webp, jpeg, png, avif)
     output_format: String,
-    /// Output quality setting (0-100) - reserved for future JPEG quality support
-    #[allow(dead_code)]
+    /// Additional rendition formats, in order of preference
+    alternate_formats: Vec<OutputFormat>,
+    /// Output quality setting (0-100) for lossy encoders
     output_quality: u8,
+    /// Encode WebP losslessly instead of with `output_quality`
+    webp_lossless: bool,
     /// Maximum allowed dimension (width or height)
     max_dimension: u32,
-    /// Whether to strip EXIF metadata
-    strip_exif: bool,
+    /// Which metadata survives in re-encoded originals
+    metadata_policy: MetadataPolicy,
+    /// Keep animations animated instead of storing their first frame
+    preserve_animation: bool,
+    /// Decoder limits (dimensions and allocation)
+    limits: Limits,
 }
 
 impl ImageProcessor {
@@ -53,34 +202,61 @@ impl ImageProcessor {
     pub fn new(config: &ProcessingConfig) -> Self {
         Self {
             output_format: config.output_format.clone(),
+            alternate_formats: config.alternate_formats(),
             output_quality: config.output_quality,
+            webp_lossless: config.webp_lossless,
             max_dimension: config.max_image_dimension,
-            strip_exif: config.strip_exif,
+            metadata_policy: config.metadata_policy(),
+            preserve_animation: config.preserve_animation,
+            limits: Self::decode_limits(config),
         }
     }
 
+    /// Build decoder limits from the configuration
+    fn decode_limits(config: &ProcessingConfig) -> Limits {
+        let mut limits = Limits::default();
+        limits.max_image_width = Some(config.max_decode_width);
+        limits.max_image_height = Some(config.max_decode_height);
+        limits.max_alloc = Some(config.max_decode_alloc);
+        limits
+    }
+
     /// Process an uploaded image
     ///
     /// This method:
     /// 1. Validates the image format using magic bytes and config
-    /// 2. Decodes the image
-    /// 3. Resizes if necessary
-    /// 4. Strips EXIF data if configured
-    /// 5. Encodes to configured output format
+    /// 2. Checks the dimensions from the header against the decode limits,
+    ///    then decodes the image (every frame of an animation, see
+    ///    [`Self::process_animation`])
+    /// 3. Applies the EXIF orientation and resizes if necessary
+    /// 4. Re-encodes the original with the metadata allowed by the policy
+    /// 5. Encodes to configured output format and alternate formats
     ///
     /// # Arguments
-    /// * `data` - Raw image bytes
+    /// * `reader` - Encoded image (e.g. `Cursor` over bytes or a buffered file)
     /// * `upload_config` - Upload configuration for allowed types validation
     ///
     /// # Returns
     /// `ProcessedImage` containing original and optimized versions
     ///
     /// # Errors
-    /// Returns error if image format is unsupported or processing fails
-    pub fn process(&self, data: &[u8], upload_config: &UploadConfig) -> Result<ProcessedImage> {
+    /// Returns error if image format is unsupported or processing fails, and
+    /// `AppError::ImageTooLarge` if the image exceeds the decode limits
+    pub fn process<R: BufRead + Seek>(
+        &self,
+        mut reader: R,
+        upload_config: &UploadConfig,
+    ) -> Result<ProcessedImage> {
         // Step 1: Detect format using magic bytes
-        let detected_mime = self.detect_mime_type(data)?;
-        debug!(mime = %detected_mime, size = data.len(), "Detected image format");
+        let source_size = reader.seek(SeekFrom::End(0))?;
+        reader.rewind()?;
+
+        let mut header = Vec::new();
+        reader.by_ref().take(SNIFF_LEN).read_to_end(&mut header)?;
+        reader.rewind()?;
+
+        let detected_mime = self.detect_mime_type(&header)?;
+        debug!(mime = %detected_mime, size = source_size, "Detected image format");
 
         // Step 1b: Validate against allowed types from config
         if !upload_config.is_allowed_image_type(&detected_mime) {
@@ -90,16 +266,38 @@ impl ImageProcessor {
             )));
         }
 
-        // Step 2: Decode image
+        if detected_mime == SVG_MIME {
+            return self.process_svg(reader, detected_mime);
+        }
+
+        // Step 2: Check the declared size, then decode image, reading its
+        // metadata first
         let format = Self::mime_to_format(&detected_mime)?;
-        let mut img = image::load_from_memory_with_format(data, format)
-            .map_err(|e| AppError::image_processing(format!("Failed to decode image: {}", e)))?;
+        self.check_dimensions(&mut reader, format)?;
+        reader.rewind()?;
+
+        if self.preserve_animation {
+            if let Some(frames) = self.decode_animation(&mut reader, format)? {
+                return self.process_animation(reader, format, frames, detected_mime, source_size);
+            }
+            reader.rewind()?;
+        }
+
+        let mut image_reader = ImageReader::with_format(reader, format);
+        image_reader.limits(self.limits.clone());
+        let mut decoder = image_reader.into_decoder().map_err(Self::decode_error)?;
+        let metadata = SourceMetadata::read(&mut decoder);
+        let mut img = DynamicImage::from_decoder(decoder).map_err(Self::decode_error)?;
 
         let original_width = img.width();
         let original_height = img.height();
         debug!(width = original_width, height = original_height, "Decoded image");
 
-        // Step 3: Resize if necessary
+        // Step 3: Rotate/flip upright, then resize if necessary
+        if let Some(orientation) = metadata.orientation {
+            img.apply_orientation(orientation);
+        }
+
         let was_resized = self.should_resize(&img);
         if was_resized {
             img = self.resize_image(img);
@@ -110,47 +308,374 @@ impl ImageProcessor {
             );
         }
 
-        // Step 4: Prepare original data (with EXIF stripped if configured)
-        let original_data = if self.strip_exif {
-            self.strip_exif_and_reencode(&img, format)?
-        } else {
-            data.to_vec()
+        // Step 4: Prepare original data (stored verbatim only with keep_all)
+        let original_data = match self.metadata_policy {
+            MetadataPolicy::KeepAll => None,
+            policy => Some(Self::reencode_original(&img, format, policy, &metadata)?),
         };
+        let original_size = original_data
+            .as_ref()
+            .map_or(source_size, |data| data.len() as u64);
+
+        // Step 5: Encode to configured output format and alternates
+        let exif = metadata
+            .exif
+            .as_deref()
+            .map(image_metadata::summarize)
+            .unwrap_or_default();
+
+        self.finish_still(img, detected_mime, original_data, original_size, was_resized, exif)
+    }
 
-        // Step 5: Encode to configured output format
+    /// Sanitize and rasterize an SVG upload
+    ///
+    /// The sanitized document is stored as the original, the rendering (at
+    /// most `max_image_dimension`) is encoded like any still image.
+    fn process_svg<R: BufRead + Seek>(
+        &self,
+        mut reader: R,
+        detected_mime: String,
+    ) -> Result<ProcessedImage> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        let max_alloc = self.limits.max_alloc.unwrap_or(u64::MAX);
+        let svg = svg::rasterize(&data, self.max_dimension, max_alloc)?;
+        let original_size = svg.sanitized.len() as u64;
+
+        self.finish_still(
+            svg.image,
+            detected_mime,
+            Some(svg.sanitized),
+            original_size,
+            false,
+            ExifInfo::default(),
+        )
+    }
+
+    /// Encode a decoded, upright and resized still image into the output
+    /// formats and compute its placeholders, hashes and focal point
+    fn finish_still(
+        &self,
+        img: DynamicImage,
+        original_mime: String,
+        original_data: Option<Vec<u8>>,
+        original_size: u64,
+        was_resized: bool,
+        exif: ExifInfo,
+    ) -> Result<ProcessedImage> {
         let optimized_data = self.encode_output(&img)?;
+        let renditions = self.encode_alternates(&img)?;
 
         info!(
-            original_size = original_data.len(),
+            original_size = original_size,
             optimized_size = optimized_data.len(),
             width = img.width(),
             height = img.height(),
             output_format = %self.output_format,
-            compression_ratio = format!("{:.1}%", (optimized_data.len() as f64 / original_data.len() as f64) * 100.0),
+            compression_ratio = format!("{:.1}%", (optimized_data.len() as f64 / original_size as f64) * 100.0),
             "Processed image"
         );
 
         Ok(ProcessedImage {
             original_data,
+            original_size,
             optimized_data,
-            original_mime: detected_mime,
+            optimized_mime: self.output_format().mime_type().to_string(),
+            quality: self.output_quality(),
+            renditions,
+            original_mime,
             width: img.width(),
             height: img.height(),
             was_resized,
+            frame_count: None,
+            duration_ms: None,
+            exif,
+            placeholder: placeholder::compute(&img),
+            perceptual_hash: perceptual_hash::dhash(&img),
+            focal_point: smart_crop::detect(&img),
+            image: img,
+        })
+    }
+
+    /// Process an animation, keeping every frame
+    ///
+    /// Frames are resized to the maximum dimension and encoded as animated
+    /// WebP, or as GIF when the output format cannot animate and the upload
+    /// is a GIF (kept byte for byte if it needed no resizing). GIF carries no
+    /// EXIF, so GIF originals are stored as uploaded; animated WebP originals
+    /// are re-encoded losslessly unless the policy keeps all metadata.
+    fn process_animation<R: BufRead + Seek>(
+        &self,
+        mut reader: R,
+        format: ImageFormat,
+        frames: Vec<AnimationFrame>,
+        detected_mime: String,
+        source_size: u64,
+    ) -> Result<ProcessedImage> {
+        let frame_count = frames.len() as u32;
+        let duration_ms = frames.iter().map(|f| u64::from(f.delay_ms)).sum();
+        debug!(frames = frame_count, duration_ms = duration_ms, "Decoded animation");
+
+        let original_data = match (format, self.metadata_policy) {
+            (ImageFormat::WebP, policy) if policy != MetadataPolicy::KeepAll => {
+                Some(Self::encode_animated_webp(&frames, None)?)
+            }
+            _ => None,
+        };
+        let original_size = original_data
+            .as_ref()
+            .map_or(source_size, |data| data.len() as u64);
+
+        let (width, height) = frames[0].image.dimensions();
+        let was_resized = width > self.max_dimension || height > self.max_dimension;
+        let frames: Vec<AnimationFrame> = if was_resized {
+            frames
+                .into_iter()
+                .map(|frame| AnimationFrame {
+                    image: self
+                        .resize_image(DynamicImage::ImageRgba8(frame.image))
+                        .into_rgba8(),
+                    delay_ms: frame.delay_ms,
+                })
+                .collect()
+        } else {
+            frames
+        };
+        let poster = DynamicImage::ImageRgba8(frames[0].image.clone());
+
+        let (optimized_mime, optimized_data, quality) =
+            if self.output_format() == OutputFormat::WebP || format == ImageFormat::WebP {
+                let quality = self.quality_for(OutputFormat::WebP);
+                let data = Self::encode_animated_webp(&frames, quality)?;
+                (OutputFormat::WebP.mime_type(), data, quality)
+            } else if was_resized {
+                ("image/gif", Self::encode_gif(frames)?, None)
+            } else {
+                let mut data = Vec::new();
+                reader.rewind()?;
+                reader.read_to_end(&mut data)?;
+                ("image/gif", data, None)
+            };
+
+        info!(
+            original_size = original_size,
+            optimized_size = optimized_data.len(),
+            width = poster.width(),
+            height = poster.height(),
+            frames = frame_count,
+            output_mime = %optimized_mime,
+            "Processed animation"
+        );
+
+        Ok(ProcessedImage {
+            original_data,
+            original_size,
+            optimized_data,
+            optimized_mime: optimized_mime.to_string(),
+            quality,
+            renditions: Vec::new(),
+            original_mime: detected_mime,
+            width: poster.width(),
+            height: poster.height(),
+            was_resized,
+            frame_count: Some(frame_count),
+            duration_ms: Some(duration_ms),
+            exif: ExifInfo::default(),
+            placeholder: placeholder::compute(&poster),
+            perceptual_hash: perceptual_hash::dhash(&poster),
+            focal_point: smart_crop::detect(&poster),
+            image: poster,
         })
     }
 
+    /// Decode every frame of an animated GIF or WebP
+    ///
+    /// Returns `None` for other formats and for single-frame images; the
+    /// reader is left at an unspecified position. All frames together must
+    /// fit in `max_decode_alloc`.
+    fn decode_animation<R: BufRead + Seek>(
+        &self,
+        reader: R,
+        format: ImageFormat,
+    ) -> Result<Option<Vec<AnimationFrame>>> {
+        let (canvas, frames) = match format {
+            ImageFormat::Gif => {
+                let mut decoder = GifDecoder::new(reader).map_err(Self::decode_error)?;
+                decoder.set_limits(self.limits.clone())?;
+                (decoder.dimensions(), decoder.into_frames())
+            }
+            ImageFormat::WebP => {
+                let mut decoder = WebPDecoder::new(reader).map_err(Self::decode_error)?;
+                if !decoder.has_animation() {
+                    return Ok(None);
+                }
+                decoder.set_limits(self.limits.clone())?;
+                (decoder.dimensions(), decoder.into_frames())
+            }
+            _ => return Ok(None),
+        };
+
+        // Every frame is a full RGBA canvas
+        let frame_bytes = u64::from(canvas.0) * u64::from(canvas.1) * 4;
+        let max_frames = self
+            .limits
+            .max_alloc
+            .map_or(u64::MAX, |max| max / frame_bytes.max(1));
+        let mut decoded = Vec::new();
+        for frame in frames {
+            if decoded.len() as u64 >= max_frames {
+                return Err(AppError::image_too_large(format!(
+                    "Animation needs more than {} bytes to decode",
+                    self.limits.max_alloc.unwrap_or_default()
+                )));
+            }
+            decoded.push(frame.map_err(Self::decode_error)?);
+        }
+        if decoded.len() < 2 {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            decoded
+                .into_iter()
+                .map(|frame| {
+                    let (numer, denom) = frame.delay().numer_denom_ms();
+                    let ticks = (f64::from(numer) / f64::from(denom) / f64::from(FRAME_TICK_MS))
+                        .round() as u32;
+                    AnimationFrame {
+                        delay_ms: ticks.max(1) * FRAME_TICK_MS,
+                        image: frame.into_buffer(),
+                    }
+                })
+                .collect(),
+        ))
+    }
+
+    /// Check the dimensions declared in the image header against the limits
+    ///
+    /// Only the header is read, so oversized images are rejected before any
+    /// pixel memory is allocated.
+    fn check_dimensions<R: BufRead + Seek>(&self, reader: R, format: ImageFormat) -> Result<()> {
+        let mut image_reader = ImageReader::with_format(reader, format);
+        image_reader.no_limits();
+        let (width, height) = image_reader.into_dimensions().map_err(Self::decode_error)?;
+
+        let max_width = self.limits.max_image_width.unwrap_or(u32::MAX);
+        let max_height = self.limits.max_image_height.unwrap_or(u32::MAX);
+        if width > max_width || height > max_height {
+            return Err(AppError::image_too_large(format!(
+                "{}x{} exceeds the maximum of {}x{} pixels",
+                width, height, max_width, max_height
+            )));
+        }
+
+        let max_alloc = self.limits.max_alloc.unwrap_or(u64::MAX);
+        if u64::from(width) * u64::from(height) * 4 > max_alloc {
+            return Err(AppError::image_too_large(format!(
+                "{}x{} needs more than {} bytes to decode",
+                width, height, max_alloc
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Map a decoding error, reporting limit violations as too large
+    fn decode_error(e: ImageError) -> AppError {
+        match e {
+            ImageError::Limits(e) => AppError::image_too_large(e.to_string()),
+            e => AppError::image_processing(format!("Failed to decode image: {}", e)),
+        }
+    }
+
+    /// Encode frames as an animated WebP with libwebp (`None` = lossless)
+    fn encode_animated_webp(frames: &[AnimationFrame], quality: Option<u8>) -> Result<Vec<u8>> {
+        let mut config = webp::WebPConfig::new()
+            .map_err(|_| AppError::internal("Failed to initialize WebP encoder"))?;
+        match quality {
+            Some(q) => config.quality = f32::from(q),
+            None => config.lossless = 1,
+        }
+
+        animated_webp::encode(
+            frames.iter().map(|frame| (&frame.image, frame.delay_ms)),
+            &config,
+        )
+    }
+
+    /// Encode frames as a GIF that loops forever
+    fn encode_gif(frames: Vec<AnimationFrame>) -> Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        let mut encoder = GifEncoder::new_with_speed(&mut buffer, GIF_SPEED);
+        encoder.set_repeat(Repeat::Infinite)?;
+        encoder.encode_frames(frames.into_iter().map(|frame| {
+            Frame::from_parts(
+                frame.image,
+                0,
+                0,
+                Delay::from_numer_denom_ms(frame.delay_ms, 1),
+            )
+        }))?;
+        drop(encoder);
+
+        Ok(buffer)
+    }
+
+    /// Re-encode a stored image into the configured output formats
+    ///
+    /// Used to migrate existing media after `output_format` or
+    /// `output_formats` change. The source is an already accepted file
+    /// (original or optimized), so only the resize limit is applied again.
+    pub fn reencode(&self, source: &[u8]) -> Result<ReencodedImage> {
+        let mut img = self.decode_upright(source)?;
+
+        if self.should_resize(&img) {
+            img = self.resize_image(img);
+        }
+
+        Ok(ReencodedImage {
+            optimized_data: self.encode_output(&img)?,
+            renditions: self.encode_alternates(&img)?,
+            width: img.width(),
+            height: img.height(),
+            placeholder: placeholder::compute(&img),
+            focal_point: smart_crop::detect(&img),
+            perceptual_hash: perceptual_hash::dhash(&img),
+        })
+    }
+
+    /// Configured alternate rendition formats, in order of preference
+    pub fn alternate_formats(&self) -> &[OutputFormat] {
+        &self.alternate_formats
+    }
+
+    /// Encoder quality of the optimized file (`None` if lossless)
+    pub fn output_quality(&self) -> Option<u8> {
+        self.quality_for(self.output_format())
+    }
+
+    /// Encoder quality used for a format at upload time
+    fn quality_for(&self, format: OutputFormat) -> Option<u8> {
+        format.encoder_quality(self.output_quality, self.webp_lossless)
+    }
+
     /// Detect MIME type using magic bytes
     ///
     /// This is more reliable than trusting the Content-Type header or file extension.
     /// Note: This only detects the type, validation against allowed types is done in process()
     pub fn detect_mime_type(&self, data: &[u8]) -> Result<String> {
+        // SVG is text, which magic bytes cannot identify
+        if svg::is_svg(data) {
+            return Ok(SVG_MIME.to_string());
+        }
+
         // Use infer crate for reliable magic byte detection
         let kind = infer::get(data).ok_or_else(|| {
             AppError::unsupported_media_type("Could not detect file type from content")
         })?;
 
-        let mime = kind.mime_type();
+        let mime = canonical_mime(kind.mime_type());
 
         // Basic check that it's an image
         if !mime.starts_with("image/") {
@@ -163,29 +688,31 @@ impl ImageProcessor {
         Ok(mime.to_string())
     }
 
-    /// Convert MIME type to image format
+    /// Convert MIME type to image format, if this build can decode it
     fn mime_to_format(mime: &str) -> Result<ImageFormat> {
-        match mime {
-            "image/jpeg" => Ok(ImageFormat::Jpeg),
-            "image/png" => Ok(ImageFormat::Png),
-            "image/gif" => Ok(ImageFormat::Gif),
-            "image/webp" => Ok(ImageFormat::WebP),
-            "image/bmp" => Ok(ImageFormat::Bmp),
-            "image/tiff" => Ok(ImageFormat::Tiff),
+        let enabled = MediaFormat::from_mime(mime).is_some_and(|f| f.enabled);
+
+        match ImageFormat::from_mime_type(mime) {
+            Some(format) if enabled => Ok(format),
             _ => Err(AppError::unsupported_media_type(format!(
-                "Unsupported format: {}",
+                "Unsupported format: {} (no decoder in this build)",
                 mime
             ))),
         }
     }
 
-    /// Get output ImageFormat based on configuration
-    fn output_image_format(&self) -> ImageFormat {
-        match self.output_format.as_str() {
-            "webp" => ImageFormat::WebP,
-            "jpeg" | "jpg" => ImageFormat::Jpeg,
-            "png" => ImageFormat::Png,
-            _ => ImageFormat::WebP,
+    /// Get configured output format
+    fn output_format(&self) -> OutputFormat {
+        OutputFormat::parse(&self.output_format).unwrap_or(OutputFormat::WebP)
+    }
+
+    /// Map an output format to the encoder format of the image crate
+    fn to_image_format(format: OutputFormat) -> ImageFormat {
+        match format {
+            OutputFormat::WebP => ImageFormat::WebP,
+            OutputFormat::Jpeg => ImageFormat::Jpeg,
+            OutputFormat::Png => ImageFormat::Png,
+            OutputFormat::Avif => ImageFormat::Avif,
         }
     }
 
@@ -213,30 +740,229 @@ impl ImageProcessor {
 
     /// Encode image to configured output format
     fn encode_output(&self, img: &DynamicImage) -> Result<Vec<u8>> {
+        Self::encode(img, self.output_format(), self.output_quality())
+    }
+
+    /// Encode image to every configured alternate format
+    fn encode_alternates(&self, img: &DynamicImage) -> Result<Vec<Rendition>> {
+        self.alternate_formats
+            .iter()
+            .map(|&format| {
+                Ok(Rendition {
+                    format,
+                    data: Self::encode(img, format, self.quality_for(format))?,
+                })
+            })
+            .collect()
+    }
+
+    /// Encode image to the given format
+    ///
+    /// `quality` is only honored by lossy encoders; `None` uses the
+    /// encoder default, which for WebP means lossless.
+    fn encode(img: &DynamicImage, format: OutputFormat, quality: Option<u8>) -> Result<Vec<u8>> {
         let mut buffer = Vec::new();
-        let output_format = self.output_image_format();
 
-        img.write_to(&mut Cursor::new(&mut buffer), output_format)
-            .map_err(|e| AppError::image_processing(format!(
+        let result = match (format, quality) {
+            (OutputFormat::Jpeg, Some(q)) => {
+                img.write_with_encoder(JpegEncoder::new_with_quality(&mut buffer, q))
+            }
+            (OutputFormat::WebP, Some(q)) => return Self::encode_lossy_webp(img, q),
+            (OutputFormat::Avif, q) => img.write_with_encoder(AvifEncoder::new_with_speed_quality(
+                &mut buffer,
+                AVIF_SPEED,
+                q.unwrap_or(DEFAULT_AVIF_QUALITY),
+            )),
+            _ => img.write_to(&mut Cursor::new(&mut buffer), Self::to_image_format(format)),
+        };
+
+        result.map_err(|e| {
+            AppError::image_processing(format!(
                 "Encoding to {} failed: {}",
-                self.output_format, e
-            )))?;
+                format.extension(),
+                e
+            ))
+        })?;
 
         Ok(buffer)
     }
 
-    /// Strip EXIF data by re-encoding the image
+    /// Encode lossy WebP with libwebp (the image crate only writes lossless)
+    ///
+    /// libwebp refuses images over 16383 pixels a side; that is an error
+    /// here, not a panic on the processing pool.
+    fn encode_lossy_webp(img: &DynamicImage, quality: u8) -> Result<Vec<u8>> {
+        let encoded = if img.color().has_alpha() {
+            let rgba = img.to_rgba8();
+            webp::Encoder::from_rgba(&rgba, img.width(), img.height())
+                .encode_simple(false, quality as f32)
+        } else {
+            let rgb = img.to_rgb8();
+            webp::Encoder::from_rgb(&rgb, img.width(), img.height())
+                .encode_simple(false, quality as f32)
+        };
+
+        encoded
+            .map(|data| data.to_vec())
+            .map_err(|e| AppError::image_processing(format!("Encoding to webp failed: {:?}", e)))
+    }
+
+    /// Render a variant of a stored image
+    ///
+    /// # Arguments
+    /// * `source` - Encoded source image (original or optimized file)
+    /// * `spec` - Normalized variant parameters
+    /// * `focal_point` - The media's focal point, used for `focus=auto`
+    ///   (detected on the fly if the media has none)
+    ///
+    /// # Returns
+    /// Encoded variant bytes in `spec.format`
+    pub fn render_variant(
+        &self,
+        source: &[u8],
+        spec: &VariantSpec,
+        focal_point: Option<FocalPoint>,
+    ) -> Result<Vec<u8>> {
+        let img = self.decode_upright(source)?;
+
+        self.render_variant_image(&img, spec, focal_point)
+    }
+
+    /// Render a variant from an already decoded image
+    pub fn render_variant_image(
+        &self,
+        img: &DynamicImage,
+        spec: &VariantSpec,
+        focal_point: Option<FocalPoint>,
+    ) -> Result<Vec<u8>> {
+        let resized = match (spec.width, spec.height) {
+            (None, None) => None,
+            (Some(w), Some(h)) => match (spec.fit, spec.focus) {
+                (FitMode::Contain, _) => Self::resize_within(img, w, h),
+                (FitMode::Cover, Focus::Center) => {
+                    Some(img.resize_to_fill(w, h, FilterType::Lanczos3))
+                }
+                (FitMode::Cover, Focus::Auto) => {
+                    let focus = focal_point.unwrap_or_else(|| smart_crop::detect(img));
+                    Some(smart_crop::cover(img, w, h, focus))
+                }
+                (FitMode::Cover, Focus::Point(focus)) => Some(smart_crop::cover(img, w, h, focus)),
+                (FitMode::Fill, _) => Some(img.resize_exact(w, h, FilterType::Lanczos3)),
+            },
+            (Some(w), None) => Self::resize_within(img, w, u32::MAX),
+            (None, Some(h)) => Self::resize_within(img, u32::MAX, h),
+        };
+        let img = resized.as_ref().unwrap_or(img);
+
+        let data = Self::encode(img, spec.format, spec.quality)?;
+
+        debug!(
+            width = img.width(),
+            height = img.height(),
+            key = %spec.cache_key(),
+            size = data.len(),
+            "Rendered variant"
+        );
+
+        Ok(data)
+    }
+
+    /// Scale down to fit within a box, preserving aspect ratio (never upscales)
     ///
-    /// This ensures no metadata is preserved in the original file.
-    fn strip_exif_and_reencode(&self, img: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>> {
+    /// Returns `None` if the image already fits.
+    fn resize_within(img: &DynamicImage, max_width: u32, max_height: u32) -> Option<DynamicImage> {
+        if img.width() <= max_width && img.height() <= max_height {
+            return None;
+        }
+        Some(img.resize(max_width, max_height, FilterType::Lanczos3))
+    }
+
+    /// Decode a stored image and apply its EXIF orientation
+    ///
+    /// Originals kept under `keep_all` still carry the Orientation tag.
+    fn decode_upright(&self, source: &[u8]) -> Result<DynamicImage> {
+        if svg::is_svg(source) {
+            let max_alloc = self.limits.max_alloc.unwrap_or(u64::MAX);
+            return Ok(svg::rasterize(source, self.max_dimension, max_alloc)?.image);
+        }
+
+        let mut image_reader = ImageReader::new(Cursor::new(source)).with_guessed_format()?;
+        image_reader.limits(self.limits.clone());
+        let mut decoder = image_reader.into_decoder().map_err(Self::decode_error)?;
+        let orientation = decoder.orientation().ok();
+
+        let mut img = DynamicImage::from_decoder(decoder).map_err(Self::decode_error)?;
+        if let Some(orientation) = orientation {
+            img.apply_orientation(orientation);
+        }
+
+        Ok(img)
+    }
+
+    /// Re-encode the original, keeping only the metadata the policy allows
+    ///
+    /// The pixels are already upright, so the Orientation tag is never kept.
+    /// Formats without metadata support in the encoder (GIF, BMP, TIFF) are
+    /// always written bare.
+    fn reencode_original(
+        img: &DynamicImage,
+        format: ImageFormat,
+        policy: MetadataPolicy,
+        metadata: &SourceMetadata,
+    ) -> Result<Vec<u8>> {
+        let icc_profile = metadata
+            .icc_profile
+            .clone()
+            .filter(|_| policy >= MetadataPolicy::KeepColorProfile);
+        let exif = metadata
+            .exif
+            .as_deref()
+            .filter(|_| policy >= MetadataPolicy::KeepCopyright)
+            .and_then(image_metadata::copyright_only);
+
         let mut buffer = Vec::new();
+        let result = match format {
+            ImageFormat::Jpeg => Self::write_with_metadata(
+                img,
+                JpegEncoder::new(&mut buffer),
+                icc_profile,
+                exif,
+            ),
+            ImageFormat::Png => {
+                Self::write_with_metadata(img, PngEncoder::new(&mut buffer), icc_profile, exif)
+            }
+            ImageFormat::WebP => Self::write_with_metadata(
+                img,
+                WebPEncoder::new_lossless(&mut buffer),
+                icc_profile,
+                exif,
+            ),
+            _ => img.write_to(&mut Cursor::new(&mut buffer), format),
+        };
 
-        img.write_to(&mut Cursor::new(&mut buffer), format)
-            .map_err(|e| AppError::image_processing(format!("Re-encoding failed: {}", e)))?;
+        result.map_err(|e| AppError::image_processing(format!("Re-encoding failed: {}", e)))?;
 
         Ok(buffer)
     }
 
+    /// Encode with an ICC profile and EXIF payload attached where given
+    fn write_with_metadata(
+        img: &DynamicImage,
+        mut encoder: impl ImageEncoder,
+        icc_profile: Option<Vec<u8>>,
+        exif: Option<Vec<u8>>,
+    ) -> ImageResult<()> {
+        // Attaching is best effort: an encoder lacking support drops it
+        if let Some(icc_profile) = icc_profile {
+            let _ = encoder.set_icc_profile(icc_profile);
+        }
+        if let Some(exif) = exif {
+            let _ = encoder.set_exif_metadata(exif);
+        }
+
+        img.write_with_encoder(encoder)
+    }
+
     /// Validate that data is a supported image type
     ///
     /// This is a quick check without full processing.
@@ -244,53 +970,48 @@ impl ImageProcessor {
         self.detect_mime_type(data)
     }
 
-    /// Get file extension for MIME type
-    pub fn mime_to_extension(mime: &str) -> &'static str {
-        match mime {
-            "image/jpeg" => "jpg",
-            "image/png" => "png",
-            "image/gif" => "gif",
-            "image/webp" => "webp",
-            "image/bmp" => "bmp",
-            "image/tiff" => "tiff",
-            "video/mp4" => "mp4",
-            "video/webm" => "webm",
-            "video/quicktime" => "mov",
-            _ => "bin",
-        }
-    }
-
-    /// Get MIME type for extension
-    pub fn extension_to_mime(ext: &str) -> &'static str {
-        match ext.to_lowercase().as_str() {
-            "jpg" | "jpeg" => "image/jpeg",
-            "png" => "image/png",
-            "gif" => "image/gif",
-            "webp" => "image/webp",
-            "bmp" => "image/bmp",
-            "tiff" | "tif" => "image/tiff",
-            "mp4" => "video/mp4",
-            "webm" => "video/webm",
-            "mov" => "video/quicktime",
-            _ => "application/octet-stream",
-        }
-    }
 }
 
 /// Calculate SHA-256 hash of data
 ///
-/// Used for content deduplication.
+/// Used for content deduplication and ETags. Returns lowercase hex.
 pub fn calculate_hash(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+
+    hex::encode(Sha256::digest(data))
+}
+
+/// Content hash of records stored before SHA-256 (`HashAlgorithm::Legacy`)
+///
+/// Only used to check whether a legacy original is stored as uploaded.
+pub fn legacy_hash(data: &[u8]) -> String {
     use std::collections::hash_map::DefaultHasher;
     use std::hash::{Hash, Hasher};
 
-    // For simplicity, using a basic hasher
-    // In production, you might want SHA-256
     let mut hasher = DefaultHasher::new();
     data.hash(&mut hasher);
     format!("{:016x}", hasher.finish())
 }
 
+/// Calculate SHA-256 hash of a file without loading it into memory
+pub async fn calculate_file_hash(path: &Path) -> Result<String> {
+    use sha2::{Digest, Sha256};
+
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -298,10 +1019,21 @@ mod tests {
     fn create_test_processor() -> ImageProcessor {
         let config = ProcessingConfig {
             output_format: "webp".to_string(),
+            output_formats: Vec::new(),
             output_quality: 85,
+            webp_lossless: false,
             max_image_dimension: 1024,
+            max_decode_width: 16384,
+            max_decode_height: 16384,
+            max_decode_alloc: 512 * 1024 * 1024,
             keep_originals: true,
             strip_exif: true,
+            metadata_policy: None,
+            preserve_animation: true,
+            variants: Default::default(),
+            max_concurrent_decodes: 2,
+            max_queued_decodes: 16,
+            ffmpeg_path: None,
         };
         ImageProcessor::new(&config)
     }
@@ -341,25 +1073,381 @@ mod tests {
     }
 
     #[test]
-    fn test_mime_to_extension() {
-        assert_eq!(ImageProcessor::mime_to_extension("image/jpeg"), "jpg");
-        assert_eq!(ImageProcessor::mime_to_extension("image/png"), "png");
-        assert_eq!(ImageProcessor::mime_to_extension("image/webp"), "webp");
-        assert_eq!(ImageProcessor::mime_to_extension("video/mp4"), "mp4");
+    fn test_lossy_webp_over_limit_is_error() {
+        let fits = DynamicImage::new_rgb8(16383, 1);
+        assert!(ImageProcessor::encode(&fits, OutputFormat::WebP, Some(80)).is_ok());
+
+        let too_wide = DynamicImage::new_rgb8(16384, 1);
+        assert!(ImageProcessor::encode(&too_wide, OutputFormat::WebP, Some(80)).is_err());
+    }
+
+    #[test]
+    fn test_mime_to_format_follows_enabled_decoders() {
+        assert_eq!(ImageProcessor::mime_to_format("image/png").unwrap(), ImageFormat::Png);
+        assert_eq!(
+            ImageProcessor::mime_to_format("image/bmp").is_ok(),
+            cfg!(feature = "bmp")
+        );
+        assert_eq!(
+            ImageProcessor::mime_to_format("image/avif").is_ok(),
+            cfg!(feature = "avif-decode")
+        );
+        assert!(ImageProcessor::mime_to_format("image/heic").is_err());
+        assert!(ImageProcessor::mime_to_format("video/mp4").is_err());
+    }
+
+    #[test]
+    fn test_render_variant() {
+        let processor = create_test_processor();
+
+        let mut source = Vec::new();
+        DynamicImage::new_rgb8(200, 100)
+            .write_to(&mut Cursor::new(&mut source), ImageFormat::Png)
+            .unwrap();
+
+        let spec = VariantSpec {
+            width: Some(50),
+            height: Some(50),
+            fit: FitMode::Cover,
+            focus: Focus::Center,
+            format: OutputFormat::Jpeg,
+            quality: Some(70),
+        };
+        let data = processor.render_variant(&source, &spec, None).unwrap();
+        let img = image::load_from_memory_with_format(&data, ImageFormat::Jpeg).unwrap();
+        assert_eq!(img.dimensions(), (50, 50));
+
+        let spec = VariantSpec {
+            width: Some(50),
+            height: None,
+            fit: FitMode::Contain,
+            focus: Focus::Center,
+            format: OutputFormat::Png,
+            quality: None,
+        };
+        let data = processor.render_variant(&source, &spec, None).unwrap();
+        let img = image::load_from_memory_with_format(&data, ImageFormat::Png).unwrap();
+        assert_eq!(img.dimensions(), (50, 25));
+    }
+
+    #[test]
+    fn test_render_variant_quality() {
+        let processor = create_test_processor();
+
+        let mut source = Vec::new();
+        DynamicImage::ImageRgb8(image::RgbImage::from_fn(128, 128, |x, y| {
+            image::Rgb([(x * 2) as u8, (y * 2) as u8, ((x * y) % 256) as u8])
+        }))
+        .write_to(&mut Cursor::new(&mut source), ImageFormat::Png)
+        .unwrap();
+
+        let render = |format, quality| {
+            let spec = VariantSpec {
+                width: None,
+                height: None,
+                fit: FitMode::Contain,
+                focus: Focus::Center,
+                format,
+                quality,
+            };
+            processor.render_variant(&source, &spec, None).unwrap()
+        };
+
+        for (format, image_format) in [
+            (OutputFormat::Jpeg, ImageFormat::Jpeg),
+            (OutputFormat::WebP, ImageFormat::WebP),
+        ] {
+            let low = render(format, Some(20));
+            let high = render(format, Some(95));
+            assert!(low.len() < high.len(), "{:?}", format);
+            assert!(image::load_from_memory_with_format(&low, image_format).is_ok());
+        }
+
+        // WebP without a quality is lossless
+        let lossless = render(OutputFormat::WebP, None);
+        let decoded = image::load_from_memory_with_format(&lossless, ImageFormat::WebP).unwrap();
+        assert_eq!(
+            decoded.to_rgb8(),
+            image::load_from_memory(&source).unwrap().to_rgb8()
+        );
+    }
+
+    #[test]
+    fn test_process_from_reader() {
+        let processor = create_test_processor();
+        let upload_config = UploadConfig {
+            max_simple_upload_size: 1024 * 1024,
+            max_chunked_upload_size: 1024 * 1024,
+            chunk_size: 1024,
+            allowed_image_types: vec!["image/png".to_string()],
+            allowed_video_types: vec![],
+            upload_session_timeout: 60,
+            async_processing: false,
+            processing_workers: 2,
+        };
+
+        let mut source = Vec::new();
+        DynamicImage::new_rgb8(2000, 100)
+            .write_to(&mut Cursor::new(&mut source), ImageFormat::Png)
+            .unwrap();
+
+        let processed = processor
+            .process(Cursor::new(&source), &upload_config)
+            .unwrap();
+
+        assert_eq!(processed.original_mime, "image/png");
+        assert!(processed.was_resized);
+        assert!(processed.width <= 1024 && processed.height <= 1024);
+        assert_eq!(processed.image.width(), processed.width);
+        // strip_exif re-encodes the original from the resized image
+        assert_eq!(
+            processed.original_size,
+            processed.original_data.as_ref().unwrap().len() as u64
+        );
+
+        let not_allowed = UploadConfig {
+            allowed_image_types: vec!["image/jpeg".to_string()],
+            ..upload_config
+        };
+        assert!(processor.process(Cursor::new(&source), &not_allowed).is_err());
+    }
+
+    /// Looping GIF with one solid frame per delay
+    fn create_test_gif(width: u32, height: u32, delays_ms: &[u32]) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        let mut encoder = GifEncoder::new(&mut buffer);
+        encoder.set_repeat(Repeat::Infinite).unwrap();
+        encoder
+            .encode_frames(delays_ms.iter().enumerate().map(|(i, &delay)| {
+                let shade = (i * 80 % 256) as u8;
+                Frame::from_parts(
+                    RgbaImage::from_pixel(width, height, image::Rgba([shade, 0, 0, 255])),
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(delay, 1),
+                )
+            }))
+            .unwrap();
+        drop(encoder);
+        buffer
+    }
+
+    fn gif_upload_config() -> UploadConfig {
+        UploadConfig {
+            max_simple_upload_size: 1024 * 1024,
+            max_chunked_upload_size: 1024 * 1024,
+            chunk_size: 1024,
+            allowed_image_types: vec!["image/gif".to_string()],
+            allowed_video_types: vec![],
+            upload_session_timeout: 60,
+            async_processing: false,
+            processing_workers: 2,
+        }
+    }
+
+    #[test]
+    fn test_process_animation_resizes_every_frame() {
+        let processor = create_test_processor();
+        let source = create_test_gif(2048, 16, &[40, 0, 1000]);
+
+        let processed = processor
+            .process(Cursor::new(&source), &gif_upload_config())
+            .unwrap();
+
+        assert_eq!(processed.optimized_mime, "image/webp");
+        assert_eq!(processed.frame_count, Some(3));
+        // Zero delays are shown for one tick
+        assert_eq!(processed.duration_ms, Some(1050));
+        assert!(processed.was_resized);
+        assert_eq!((processed.width, processed.height), (1024, 8));
+        assert!(processed.renditions.is_empty());
+        // GIF originals carry no EXIF and are kept as uploaded
+        assert!(processed.original_data.is_none());
+
+        let frames = WebPDecoder::new(Cursor::new(&processed.optimized_data))
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        let delays: Vec<u32> = frames.iter().map(|f| f.delay().numer_denom_ms().0).collect();
+        assert_eq!(delays, vec![40, 10, 1000]);
+        assert!(frames.iter().all(|f| f.buffer().dimensions() == (1024, 8)));
+    }
+
+    #[test]
+    fn test_process_animation_keeps_gif() {
+        let config = ProcessingConfig {
+            output_format: "jpeg".to_string(),
+            output_formats: vec!["avif".to_string()],
+            output_quality: 90,
+            webp_lossless: false,
+            max_image_dimension: 32,
+            max_decode_width: 16384,
+            max_decode_height: 16384,
+            max_decode_alloc: 512 * 1024 * 1024,
+            keep_originals: true,
+            strip_exif: true,
+            metadata_policy: None,
+            preserve_animation: true,
+            variants: Default::default(),
+            max_concurrent_decodes: 2,
+            max_queued_decodes: 16,
+            ffmpeg_path: None,
+        };
+        let processor = ImageProcessor::new(&config);
+
+        // JPEG cannot animate: a GIF that fits is stored untouched
+        let source = create_test_gif(32, 16, &[100, 100]);
+        let processed = processor
+            .process(Cursor::new(&source), &gif_upload_config())
+            .unwrap();
+        assert_eq!(processed.optimized_mime, "image/gif");
+        assert_eq!(processed.optimized_data, source);
+        assert_eq!(processed.quality, None);
+
+        // ...and one that is too large is re-encoded frame by frame
+        let source = create_test_gif(64, 16, &[100, 100]);
+        let processed = processor
+            .process(Cursor::new(&source), &gif_upload_config())
+            .unwrap();
+        assert_eq!(processed.optimized_mime, "image/gif");
+        let frames = GifDecoder::new(Cursor::new(&processed.optimized_data))
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].buffer().dimensions(), (32, 8));
+    }
+
+    #[test]
+    fn test_process_rejects_over_limits() {
+        let limited = |width, height, alloc| {
+            ImageProcessor::new(&ProcessingConfig {
+                output_format: "webp".to_string(),
+                output_formats: Vec::new(),
+                output_quality: 85,
+                webp_lossless: false,
+                max_image_dimension: 1024,
+                max_decode_width: width,
+                max_decode_height: height,
+                max_decode_alloc: alloc,
+                keep_originals: true,
+                strip_exif: true,
+                metadata_policy: None,
+                preserve_animation: true,
+                variants: Default::default(),
+                max_concurrent_decodes: 2,
+                max_queued_decodes: 16,
+                ffmpeg_path: None,
+            })
+        };
+        let upload_config = UploadConfig {
+            allowed_image_types: vec!["image/png".to_string(), "image/gif".to_string()],
+            ..gif_upload_config()
+        };
+        let too_large = |processor: ImageProcessor, source: &[u8]| {
+            matches!(
+                processor.process(Cursor::new(source), &upload_config),
+                Err(AppError::ImageTooLarge(_))
+            )
+        };
+
+        let mut png = Vec::new();
+        DynamicImage::new_rgb8(200, 50)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        assert!(too_large(limited(100, 1000, 1 << 20), &png));
+        assert!(too_large(limited(1000, 40, 1 << 20), &png));
+        assert!(too_large(limited(1000, 1000, 200 * 50 * 4 - 1), &png));
+        assert!(limited(200, 50, 200 * 50 * 4)
+            .process(Cursor::new(&png), &upload_config)
+            .is_ok());
+
+        // One frame fits, all three do not
+        let gif = create_test_gif(64, 64, &[100, 100, 100]);
+        assert!(too_large(limited(64, 64, 64 * 64 * 4 * 2), &gif));
+    }
+
+    #[test]
+    fn test_calculate_hash() {
+        assert_eq!(
+            calculate_hash(b"hello"),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert_ne!(calculate_hash(b"hello"), calculate_hash(b"hello!"));
+
+        assert_eq!(legacy_hash(b"hello").len(), 16);
+        assert_eq!(legacy_hash(b"hello"), legacy_hash(b"hello"));
+        assert_ne!(legacy_hash(b"hello"), legacy_hash(b"hello!"));
     }
 
     #[test]
     fn test_output_format() {
         let config = ProcessingConfig {
             output_format: "jpeg".to_string(),
+            output_formats: Vec::new(),
             output_quality: 90,
+            webp_lossless: false,
             max_image_dimension: 2048,
+            max_decode_width: 16384,
+            max_decode_height: 16384,
+            max_decode_alloc: 512 * 1024 * 1024,
             keep_originals: false,
             strip_exif: true,
+            metadata_policy: None,
+            preserve_animation: true,
+            variants: Default::default(),
+            max_concurrent_decodes: 2,
+            max_queued_decodes: 16,
+            ffmpeg_path: None,
         };
         let processor = ImageProcessor::new(&config);
 
-        assert_eq!(processor.output_image_format(), ImageFormat::Jpeg);
+        assert_eq!(processor.output_format(), OutputFormat::Jpeg);
     }
-}
 
+    #[test]
+    fn test_alternate_renditions() {
+        let config = ProcessingConfig {
+            output_format: "jpeg".to_string(),
+            output_formats: vec!["avif".to_string(), "webp".to_string()],
+            output_quality: 90,
+            webp_lossless: false,
+            max_image_dimension: 2048,
+            max_decode_width: 16384,
+            max_decode_height: 16384,
+            max_decode_alloc: 512 * 1024 * 1024,
+            keep_originals: true,
+            strip_exif: false,
+            metadata_policy: None,
+            preserve_animation: true,
+            variants: Default::default(),
+            max_concurrent_decodes: 2,
+            max_queued_decodes: 16,
+            ffmpeg_path: None,
+        };
+        let processor = ImageProcessor::new(&config);
+
+        let mut source = Vec::new();
+        DynamicImage::new_rgb8(64, 32)
+            .write_to(&mut Cursor::new(&mut source), ImageFormat::Png)
+            .unwrap();
+
+        let reencoded = processor.reencode(&source).unwrap();
+        assert_eq!((reencoded.width, reencoded.height), (64, 32));
+        assert!(
+            image::load_from_memory_with_format(&reencoded.optimized_data, ImageFormat::Jpeg)
+                .is_ok()
+        );
+
+        let formats: Vec<OutputFormat> = reencoded.renditions.iter().map(|r| r.format).collect();
+        assert_eq!(formats, vec![OutputFormat::Avif, OutputFormat::WebP]);
+        assert_eq!(&reencoded.renditions[0].data[4..12], b"ftypavif");
+        assert!(image::load_from_memory_with_format(
+            &reencoded.renditions[1].data,
+            ImageFormat::WebP
+        )
+        .is_ok());
+    }
+}
@@ -0,0 +1,44 @@
+//! Wake-up queue for background processing of chunked uploads.
+//!
+//! Jobs themselves live in RocksDB (see
+//! [`DatabaseService::enqueue_processing_job`](crate::services::DatabaseService::enqueue_processing_job)),
+//! so they survive restarts. This queue only hands session IDs to the
+//! workers; on startup it is refilled from the stored jobs.
+
+use tokio::sync::{mpsc, Mutex};
+use uuid::Uuid;
+
+/// In-memory queue of session IDs waiting for a worker
+#[derive(Debug)]
+pub struct JobQueue {
+    sender: mpsc::UnboundedSender<Uuid>,
+    receiver: Mutex<mpsc::UnboundedReceiver<Uuid>>,
+}
+
+impl JobQueue {
+    /// Create an empty queue
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        Self {
+            sender,
+            receiver: Mutex::new(receiver),
+        }
+    }
+
+    /// Hand a session to the next free worker
+    pub fn push(&self, session_id: Uuid) {
+        // The receiver lives as long as the queue, so sending cannot fail
+        let _ = self.sender.send(session_id);
+    }
+
+    /// Wait for the next session to process
+    pub async fn next(&self) -> Option<Uuid> {
+        self.receiver.lock().await.recv().await
+    }
+}
+
+impl Default for JobQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
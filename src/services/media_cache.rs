@@ -0,0 +1,351 @@
+//! In-memory cache of popular media files.
+//!
+//! A hit on `GET /m/{id}` otherwise costs a database read, a filesystem
+//! `exists()` and an `open`. The hottest files are kept here instead,
+//! together with the media record their headers (content type, `ETag`,
+//! `Last-Modified`, `Vary`) are built from, so a hit is served from memory
+//! without touching RocksDB or the disk.
+//!
+//! Entries are keyed by media id and hold one slot per rendition (the
+//! default file plus each alternate format), filled as clients ask for them.
+//! The cache is bounded by `server.media_cache_mb`; when full, least
+//! recently used media are evicted. Files larger than an eighth of the
+//! capacity are never cached, so one video cannot flush everything else.
+//!
+//! Anything that changes or deletes a media item's files or record must call
+//! [`MediaCache::invalidate`]. A generation counter keeps a request that read
+//! the record before an invalidation from caching the stale files after it.
+//!
+//! Hit and miss counters are exposed through [`MediaCache::stats`].
+
+use bytes::Bytes;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+use crate::config::ServerConfig;
+use crate::models::Media;
+
+/// Largest cached file, as a fraction of the capacity
+const MAX_ENTRY_FRACTION: u64 = 8;
+
+/// Bounded LRU of media files and their records
+#[derive(Debug)]
+pub struct MediaCache {
+    /// Total size of cached files in bytes (0 disables the cache)
+    capacity: u64,
+    /// Entries and their recency order
+    inner: Mutex<Inner>,
+    /// Lookups answered from memory
+    hits: AtomicU64,
+    /// Lookups that had to go to the database and disk
+    misses: AtomicU64,
+    /// Media dropped to make room
+    evictions: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Cached media by id
+    entries: HashMap<Uuid, Entry>,
+    /// Media ids by last use; the first one is evicted first
+    order: BTreeMap<u64, Uuid>,
+    /// Last use stamp handed out
+    tick: u64,
+    /// Bumped by every invalidation
+    generation: u64,
+    /// Total size of cached files
+    size: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// Record the response headers are built from
+    media: Arc<Media>,
+    /// Default file first, then one slot per alternate rendition
+    files: Vec<Option<Bytes>>,
+    /// Size of the filled slots
+    size: u64,
+    /// Last use stamp (key in `Inner::order`, 0 until first stored)
+    tick: u64,
+}
+
+/// A file answered from the cache
+#[derive(Debug, Clone)]
+pub struct CachedFile {
+    /// Record of the media item
+    pub media: Arc<Media>,
+    /// Rendition picked for the request (`None` is the default file)
+    pub rendition: Option<usize>,
+    /// File contents
+    pub data: Bytes,
+}
+
+/// Snapshot of the cache's size and counters
+#[derive(Debug, Clone, Serialize)]
+pub struct MediaCacheStats {
+    /// Configured capacity in bytes (0 when disabled)
+    pub capacity_bytes: u64,
+    /// Total size of cached files
+    pub size_bytes: u64,
+    /// Number of cached media items
+    pub entries: usize,
+    /// Requests served from memory
+    pub hits: u64,
+    /// Requests served from disk
+    pub misses: u64,
+    /// Media dropped to make room
+    pub evictions: u64,
+}
+
+impl MediaCache {
+    /// Create a cache sized by `media_cache_mb`
+    pub fn new(config: &ServerConfig) -> Self {
+        Self::with_capacity(config.media_cache_mb * 1024 * 1024)
+    }
+
+    fn with_capacity(capacity: u64) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(Inner::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether the cache holds anything at all
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Whether a file of this size would be cached
+    pub fn admits(&self, size: u64) -> bool {
+        self.is_enabled() && size <= self.capacity / MAX_ENTRY_FRACTION
+    }
+
+    /// Current generation, to pass to [`MediaCache::insert`]
+    ///
+    /// Read it before loading the record, so an invalidation that happens
+    /// while the file is read keeps the result out of the cache.
+    pub fn generation(&self) -> u64 {
+        self.lock().generation
+    }
+
+    /// Look up a media file, picking the rendition from the cached record
+    ///
+    /// Counts a hit if the picked rendition is in memory and a miss
+    /// otherwise. A hit makes the media the most recently used.
+    pub fn get(
+        &self,
+        id: Uuid,
+        pick: impl FnOnce(&Media) -> Option<usize>,
+    ) -> Option<CachedFile> {
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let mut guard = self.lock();
+        let inner = &mut *guard;
+        let found = inner.entries.get_mut(&id).and_then(|entry| {
+            let rendition = pick(&entry.media);
+            let data = entry.files.get(slot(rendition))?.clone()?;
+            Some((entry, rendition, data))
+        });
+
+        let Some((entry, rendition, data)) = found else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+
+        inner.tick += 1;
+        inner.order.remove(&entry.tick);
+        inner.order.insert(inner.tick, id);
+        entry.tick = inner.tick;
+
+        let media = Arc::clone(&entry.media);
+        drop(guard);
+
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(CachedFile {
+            media,
+            rendition,
+            data,
+        })
+    }
+
+    /// Cache a file of a media item, evicting old entries to make room
+    ///
+    /// Does nothing if the file is too large or the cache was invalidated
+    /// since `generation` was read.
+    pub fn insert(&self, generation: u64, media: &Media, rendition: Option<usize>, data: Bytes) {
+        let len = data.len() as u64;
+        let index = slot(rendition);
+        if !self.admits(len) || index > media.renditions.len() {
+            return;
+        }
+
+        let mut guard = self.lock();
+        let inner = &mut *guard;
+        if inner.generation != generation {
+            return;
+        }
+
+        inner.tick += 1;
+        let entry = inner.entries.entry(media.id).or_insert_with(|| Entry {
+            media: Arc::new(media.clone()),
+            files: vec![None; media.renditions.len() + 1],
+            size: 0,
+            tick: 0,
+        });
+
+        let Some(file) = entry.files.get_mut(index) else {
+            return;
+        };
+        let replaced = file.replace(data).map_or(0, |old| old.len() as u64);
+        entry.size = entry.size + len - replaced;
+        inner.size = inner.size + len - replaced;
+        inner.order.remove(&entry.tick);
+        inner.order.insert(inner.tick, media.id);
+        entry.tick = inner.tick;
+
+        while inner.size > self.capacity {
+            let Some((_, oldest)) = inner.order.pop_first() else {
+                break;
+            };
+            if let Some(evicted) = inner.entries.remove(&oldest) {
+                inner.size -= evicted.size;
+                self.evictions.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Drop a media item after its files or record changed
+    pub fn invalidate(&self, id: Uuid) {
+        let mut inner = self.lock();
+        inner.generation += 1;
+        if let Some(entry) = inner.entries.remove(&id) {
+            inner.order.remove(&entry.tick);
+            inner.size -= entry.size;
+        }
+    }
+
+    /// Current size and counters
+    pub fn stats(&self) -> MediaCacheStats {
+        let inner = self.lock();
+        MediaCacheStats {
+            capacity_bytes: self.capacity,
+            size_bytes: inner.size,
+            entries: inner.entries.len(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Slot of a rendition in `Entry::files`
+fn slot(rendition: Option<usize>) -> usize {
+    rendition.map_or(0, |index| index + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_media(renditions: &[&str]) -> Media {
+        let mut media = Media::new(
+            "test.png".to_string(),
+            "image/png".to_string(),
+            "image/webp".to_string(),
+            100,
+            10,
+            1,
+            1,
+            "hash".to_string(),
+        );
+        media.renditions = renditions.iter().map(|r| r.to_string()).collect();
+        media
+    }
+
+    #[test]
+    fn test_hit_and_miss() {
+        let cache = MediaCache::with_capacity(1024);
+        let media = test_media(&["image/avif"]);
+
+        assert!(cache.get(media.id, |_| None).is_none());
+        let generation = cache.generation();
+        cache.insert(generation, &media, None, Bytes::from_static(b"webp"));
+
+        let hit = cache.get(media.id, |_| None).unwrap();
+        assert_eq!(hit.data, Bytes::from_static(b"webp"));
+        assert_eq!(hit.rendition, None);
+
+        // The AVIF slot is still empty
+        assert!(cache.get(media.id, |_| Some(0)).is_none());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 2));
+        assert_eq!((stats.entries, stats.size_bytes), (1, 4));
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = MediaCache::with_capacity(64);
+        let data = Bytes::from(vec![0u8; 8]);
+
+        // Fill the cache exactly
+        let media: Vec<Media> = (0..8).map(|_| test_media(&[])).collect();
+        for m in &media {
+            cache.insert(cache.generation(), m, None, data.clone());
+        }
+        assert_eq!(cache.stats().size_bytes, 64);
+
+        // Use the first so the second is the oldest
+        assert!(cache.get(media[0].id, |_| None).is_some());
+        cache.insert(cache.generation(), &test_media(&[]), None, data);
+
+        let stats = cache.stats();
+        assert_eq!((stats.size_bytes, stats.entries, stats.evictions), (64, 8, 1));
+        assert!(cache.get(media[1].id, |_| None).is_none());
+        assert!(cache.get(media[0].id, |_| None).is_some());
+    }
+
+    #[test]
+    fn test_invalidate_and_generation() {
+        let cache = MediaCache::with_capacity(1024);
+        let media = test_media(&[]);
+
+        let stale = cache.generation();
+        cache.insert(stale, &media, None, Bytes::from_static(b"data"));
+        cache.invalidate(media.id);
+        assert!(cache.get(media.id, |_| None).is_none());
+        assert_eq!(cache.stats().size_bytes, 0);
+
+        // A request that read the record before the invalidation
+        cache.insert(stale, &media, None, Bytes::from_static(b"data"));
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let cache = MediaCache::with_capacity(80);
+        let media = test_media(&[]);
+
+        assert!(cache.admits(10));
+        assert!(!cache.admits(11));
+        cache.insert(cache.generation(), &media, None, Bytes::from(vec![0u8; 11]));
+        assert_eq!(cache.stats().entries, 0);
+
+        let disabled = MediaCache::with_capacity(0);
+        assert!(!disabled.admits(0));
+        assert!(disabled.get(media.id, |_| None).is_none());
+        assert_eq!(disabled.stats().misses, 0);
+    }
+}
@@ -2,17 +2,50 @@
 //!
 //! This module contains business logic services that handle:
 //! - File storage operations
+//! - View counts and access times aggregated in memory
+//! - Built-in assets (default token images)
 //! - Image processing and optimization
+//! - Animated WebP encoding (libwebp)
+//! - Bounded blocking pool for processing jobs
+//! - Wake-up queue for background upload processing
+//! - EXIF metadata extraction and filtering
+//! - BlurHash and dominant color placeholders
+//! - Perceptual hashing for near-duplicate detection
+//! - Focal point detection and focus-aware cover crops
+//! - SVG sanitizing and rasterization
+//! - Signed URLs for private media
+//! - In-memory cache of popular media files
+//! - Video probing (container metadata)
 //! - Database operations
 //! - EVM blockchain interactions (RexPump)
 
+pub mod access_tracker;
+pub mod animated_webp;
+pub mod assets;
 pub mod database;
 pub mod evm_service;
+pub mod image_metadata;
 pub mod image_processor;
+pub mod job_queue;
+pub mod media_cache;
+pub mod perceptual_hash;
+pub mod placeholder;
+pub mod processing_pool;
+pub mod smart_crop;
 pub mod storage;
+pub mod svg;
+pub mod url_signer;
+pub mod video_processor;
 
+pub use access_tracker::AccessTracker;
+pub use assets::AssetRegistry;
 pub use database::DatabaseService;
 pub use evm_service::EvmService;
 pub use image_processor::ImageProcessor;
+pub use job_queue::JobQueue;
+pub use media_cache::{MediaCache, MediaCacheStats};
+pub use processing_pool::{ProcessingPool, ProcessingStats};
 pub use storage::{StorageService, StorageStats};
+pub use url_signer::UrlSigner;
+pub use video_processor::VideoProcessor;
 
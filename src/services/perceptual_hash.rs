@@ -0,0 +1,85 @@
+//! Perceptual hashing for near-duplicate detection.
+//!
+//! Content hashes only match byte-identical files. A difference hash
+//! (dHash) instead describes the image's brightness gradients on a 9×8
+//! grid, so the same picture re-saved at another quality, size or format
+//! lands within a few bits. Compare hashes with [`distance`].
+
+use image::{imageops::FilterType, DynamicImage};
+
+/// Grid width; each row yields `HASH_WIDTH - 1` comparisons
+const HASH_WIDTH: u32 = 9;
+
+/// Grid height
+const HASH_HEIGHT: u32 = 8;
+
+/// Compute the 64-bit dHash of an image
+///
+/// Transparent areas are flattened onto white first, so a logo hashes the
+/// same whether it was saved with or without its background.
+pub fn dhash(img: &DynamicImage) -> u64 {
+    let grid = img
+        .resize_exact(HASH_WIDTH, HASH_HEIGHT, FilterType::Triangle)
+        .to_luma_alpha8();
+
+    let luma = |x: u32, y: u32| {
+        let pixel = grid.get_pixel(x, y);
+        let alpha = pixel[1] as u32;
+        (pixel[0] as u32 * alpha + 255 * (255 - alpha)) / 255
+    };
+
+    let mut hash = 0u64;
+    for y in 0..HASH_HEIGHT {
+        for x in 0..HASH_WIDTH - 1 {
+            hash = (hash << 1) | u64::from(luma(x, y) > luma(x + 1, y));
+        }
+    }
+    hash
+}
+
+/// Number of differing bits between two hashes (0 = same picture, 64 = opposite)
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{Rgb, RgbImage};
+
+    /// Diagonal gradient with a bright square, something with structure
+    fn create_test_image(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let in_square = x > width / 4 && x < width / 2 && y > height / 3 && y < height / 2;
+            if in_square {
+                Rgb([250, 250, 250])
+            } else {
+                let v = ((x + y) * 255 / (width + height)) as u8;
+                Rgb([v, v / 2, 255 - v])
+            }
+        }))
+    }
+
+    #[test]
+    fn test_recompressed_copy_is_close() {
+        let img = create_test_image(320, 240);
+
+        let mut jpeg = Vec::new();
+        JpegEncoder::new_with_quality(&mut jpeg, 30)
+            .encode_image(&img.resize(160, 120, FilterType::Triangle))
+            .unwrap();
+        let copy = image::load_from_memory(&jpeg).unwrap();
+
+        assert!(distance(dhash(&img), dhash(&copy)) <= 4);
+    }
+
+    #[test]
+    fn test_different_images_are_far() {
+        let img = create_test_image(320, 240);
+        let flipped = img.fliph();
+
+        assert_eq!(distance(dhash(&img), dhash(&img)), 0);
+        assert!(distance(dhash(&img), dhash(&flipped)) > 16);
+    }
+}
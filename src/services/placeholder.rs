@@ -0,0 +1,224 @@
+//! Placeholder data for images that are still loading.
+//!
+//! Computes a [BlurHash](https://blurha.sh) string and the dominant color of
+//! an image, so clients can paint a blurred preview or a solid box before
+//! `/m/{id}` arrives. Both are derived from a small thumbnail, which keeps
+//! the cost independent of the upload's size.
+
+use image::{imageops::FilterType, DynamicImage, RgbaImage};
+
+/// Longest side of the thumbnail the placeholders are computed from
+const THUMBNAIL_SIZE: u32 = 32;
+
+/// BlurHash components along the longer side of the image
+const COMPONENTS_LONG: u32 = 4;
+
+/// BlurHash components along the shorter side of the image
+const COMPONENTS_SHORT: u32 = 3;
+
+/// Bits per channel when bucketing colors for the dominant color
+const BUCKET_BITS: u32 = 4;
+
+/// Digits of the base 83 encoding used by BlurHash
+const BASE83: &[u8; 83] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+/// Placeholder data for one image
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placeholder {
+    /// BlurHash of the image (4×3 components, 3×4 for portrait images)
+    pub blurhash: String,
+    /// Most common color as `#rrggbb`
+    pub dominant_color: String,
+}
+
+/// Compute the placeholder data of an image
+pub fn compute(img: &DynamicImage) -> Placeholder {
+    let thumbnail = img
+        .resize(THUMBNAIL_SIZE, THUMBNAIL_SIZE, FilterType::Triangle)
+        .to_rgba8();
+
+    Placeholder {
+        blurhash: blurhash(&thumbnail),
+        dominant_color: dominant_color(&thumbnail),
+    }
+}
+
+/// Encode an image as a BlurHash
+fn blurhash(img: &RgbaImage) -> String {
+    let (width, height) = img.dimensions();
+    let (components_x, components_y) = if width >= height {
+        (COMPONENTS_LONG, COMPONENTS_SHORT)
+    } else {
+        (COMPONENTS_SHORT, COMPONENTS_LONG)
+    };
+
+    // Linear RGB, so the basis functions average light rather than sRGB values
+    let linear: Vec<[f64; 3]> = img
+        .pixels()
+        .map(|p| [p[0], p[1], p[2]].map(srgb_to_linear))
+        .collect();
+
+    let mut factors = Vec::with_capacity((components_x * components_y) as usize);
+    for j in 0..components_y {
+        for i in 0..components_x {
+            let normalization = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut factor = [0.0; 3];
+
+            for y in 0..height {
+                let basis_y = (std::f64::consts::PI * j as f64 * y as f64 / height as f64).cos();
+                for x in 0..width {
+                    let basis = basis_y
+                        * (std::f64::consts::PI * i as f64 * x as f64 / width as f64).cos();
+                    let pixel = linear[(y * width + x) as usize];
+                    for c in 0..3 {
+                        factor[c] += basis * pixel[c];
+                    }
+                }
+            }
+
+            let scale = normalization / (width * height) as f64;
+            factors.push(factor.map(|v| v * scale));
+        }
+    }
+
+    let (dc, ac) = factors.split_first().expect("at least one component");
+
+    let mut hash = String::with_capacity(4 + 2 * factors.len());
+    encode_base83(&mut hash, (components_x - 1) + (components_y - 1) * 9, 1);
+
+    let maximum = if ac.is_empty() {
+        encode_base83(&mut hash, 0, 1);
+        1.0
+    } else {
+        let actual = ac
+            .iter()
+            .flat_map(|factor| factor.iter())
+            .fold(0.0f64, |max, v| max.max(v.abs()));
+        let quantised = (actual * 166.0 - 0.5).floor().clamp(0.0, 82.0) as u32;
+        encode_base83(&mut hash, quantised, 1);
+        (quantised + 1) as f64 / 166.0
+    };
+
+    let [r, g, b] = dc.map(linear_to_srgb);
+    encode_base83(&mut hash, (r << 16) | (g << 8) | b, 4);
+
+    for factor in ac {
+        let [r, g, b] = factor.map(|v| {
+            let v = v / maximum;
+            (v.signum() * v.abs().sqrt() * 9.0 + 9.5)
+                .floor()
+                .clamp(0.0, 18.0) as u32
+        });
+        encode_base83(&mut hash, r * 19 * 19 + g * 19 + b, 2);
+    }
+
+    hash
+}
+
+/// Most common color, from coarse color buckets
+///
+/// Averages the pixels of the fullest bucket, so the result is a color that
+/// actually occurs in the image rather than a blend. Mostly transparent
+/// pixels are ignored unless nothing else is left.
+fn dominant_color(img: &RgbaImage) -> String {
+    let shift = 8 - BUCKET_BITS;
+    let mut buckets = vec![(0u32, [0u64; 3]); 1 << (3 * BUCKET_BITS)];
+
+    let opaque = img.pixels().filter(|p| p[3] >= 128).count();
+    for pixel in img.pixels().filter(|p| opaque == 0 || p[3] >= 128) {
+        let key = ((pixel[0] as usize >> shift) << (2 * BUCKET_BITS))
+            | ((pixel[1] as usize >> shift) << BUCKET_BITS)
+            | (pixel[2] as usize >> shift);
+        let (count, sum) = &mut buckets[key];
+        *count += 1;
+        for c in 0..3 {
+            sum[c] += pixel[c] as u64;
+        }
+    }
+
+    let (count, sum) = buckets
+        .iter()
+        .max_by_key(|(count, _)| *count)
+        .filter(|(count, _)| *count > 0)
+        .copied()
+        .unwrap_or((1, [0; 3]));
+    let [r, g, b] = sum.map(|v| (v as f64 / count as f64).round() as u8);
+
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// Append `value` as `length` base 83 digits
+fn encode_base83(out: &mut String, value: u32, length: u32) {
+    for i in (0..length).rev() {
+        let digit = (value / 83u32.pow(i)) % 83;
+        out.push(BASE83[digit as usize] as char);
+    }
+}
+
+/// sRGB channel value to linear light (0.0-1.0)
+fn srgb_to_linear(value: u8) -> f64 {
+    let v = value as f64 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Linear light (0.0-1.0) to sRGB channel value
+fn linear_to_srgb(value: f64) -> u32 {
+    let v = value.clamp(0.0, 1.0);
+    let srgb = if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0 + 0.5) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_solid_color() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(64, 48, Rgba([255, 0, 0, 255])));
+        let placeholder = compute(&img);
+
+        // Size flag 4×3 = (4 - 1) + (3 - 1) * 9 = 21, DC = #ff0000
+        assert_eq!(&placeholder.blurhash[..1], "L");
+        assert_eq!(&placeholder.blurhash[2..6], "TI:j");
+        assert_eq!(placeholder.blurhash.len(), 4 + 2 * 12);
+        assert_eq!(placeholder.dominant_color, "#ff0000");
+    }
+
+    #[test]
+    fn test_portrait_uses_more_vertical_components() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(30, 60, |_, y| {
+            let v = (y * 4) as u8;
+            Rgba([v, v, v, 255])
+        }));
+        let hash = compute(&img).blurhash;
+
+        // Size flag 3×4 = (3 - 1) + (4 - 1) * 9 = 29
+        assert_eq!(hash.chars().next(), Some('T'));
+        assert_eq!(hash.len(), 4 + 2 * 12);
+        assert_ne!(&hash[1..2], "0");
+    }
+
+    #[test]
+    fn test_dominant_color_ignores_transparency() {
+        // Three quarters transparent black, one quarter opaque blue
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(40, 40, |x, _| {
+            if x < 30 {
+                Rgba([0, 0, 0, 0])
+            } else {
+                Rgba([0, 0, 200, 255])
+            }
+        }));
+
+        assert_eq!(compute(&img).dominant_color, "#0000c8");
+    }
+}
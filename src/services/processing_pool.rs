@@ -0,0 +1,296 @@
+//! Bounded worker pool for CPU-heavy image work.
+//!
+//! Decoding, resizing and encoding take from milliseconds to seconds of CPU.
+//! Run on the async runtime they would stall every other request handled by
+//! the same worker thread, so jobs are moved onto Tokio's blocking threads.
+//!
+//! At most `max_concurrent_decodes` jobs run at once (a decoded image takes
+//! width × height × 4 bytes, so this also bounds memory), and up to
+//! `max_queued_decodes` more wait for a slot. When the queue is full, jobs
+//! are refused with `AppError::ServiceUnavailable` (503 with `Retry-After`)
+//! instead of piling up.
+//!
+//! Queue length and processing times are exposed through [`ProcessingPool::stats`].
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{Semaphore, TryAcquireError};
+use tracing::{debug, warn};
+
+use crate::config::ProcessingConfig;
+use crate::error::{AppError, Result};
+
+/// Upper bound for the `Retry-After` estimate in seconds
+const MAX_RETRY_AFTER_SECS: u64 = 60;
+
+/// Pool of processing slots with a bounded wait queue
+#[derive(Debug)]
+pub struct ProcessingPool {
+    /// One permit per concurrently running job
+    slots: Arc<Semaphore>,
+    /// Number of jobs that may run at once
+    workers: usize,
+    /// Number of jobs that may wait for a slot
+    max_queued: usize,
+    /// Jobs currently waiting for a slot
+    queued: AtomicUsize,
+    /// Jobs that finished successfully
+    completed: AtomicU64,
+    /// Jobs that returned an error
+    failed: AtomicU64,
+    /// Jobs refused because the queue was full
+    rejected: AtomicU64,
+    /// Sum of processing times of finished jobs
+    total_processing_ms: AtomicU64,
+    /// Longest processing time of a finished job
+    max_processing_ms: AtomicU64,
+}
+
+/// Snapshot of the pool's load and timings
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessingStats {
+    /// Number of jobs that may run at once
+    pub workers: usize,
+    /// Jobs running right now
+    pub active: usize,
+    /// Jobs waiting for a slot
+    pub queued: usize,
+    /// Maximum number of waiting jobs before new ones are refused
+    pub max_queued: usize,
+    /// Jobs that finished successfully
+    pub completed: u64,
+    /// Jobs that returned an error
+    pub failed: u64,
+    /// Jobs refused with 503 because the queue was full
+    pub rejected: u64,
+    /// Average processing time of finished jobs
+    pub avg_processing_ms: u64,
+    /// Longest processing time of a finished job
+    pub max_processing_ms: u64,
+}
+
+/// Marks a job as waiting until dropped (also when the caller gives up)
+struct QueuedJob<'a>(&'a AtomicUsize);
+
+impl Drop for QueuedJob<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl ProcessingPool {
+    /// Create a pool sized by `max_concurrent_decodes` and `max_queued_decodes`
+    pub fn new(config: &ProcessingConfig) -> Self {
+        let workers = config.max_concurrent_decodes.max(1);
+
+        Self {
+            slots: Arc::new(Semaphore::new(workers)),
+            workers,
+            max_queued: config.max_queued_decodes,
+            queued: AtomicUsize::new(0),
+            completed: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            total_processing_ms: AtomicU64::new(0),
+            max_processing_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Run a job on a blocking thread once a slot is free
+    ///
+    /// Waits in the queue if all slots are busy. The slot is held until the
+    /// job returns, even if the caller stops waiting for the result.
+    ///
+    /// # Errors
+    /// Returns `AppError::ServiceUnavailable` if the queue is full, or the
+    /// job's own error.
+    pub async fn run<T, F>(&self, job: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let permit = match Arc::clone(&self.slots).try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(TryAcquireError::Closed) => {
+                return Err(AppError::internal("Processing pool closed"))
+            }
+            Err(TryAcquireError::NoPermits) => {
+                let joined = self
+                    .queued
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |queued| {
+                        (queued < self.max_queued).then_some(queued + 1)
+                    });
+                if joined.is_err() {
+                    self.rejected.fetch_add(1, Ordering::Relaxed);
+                    let retry_after = self.retry_after_secs();
+                    warn!(
+                        queued = self.max_queued,
+                        retry_after = retry_after,
+                        "Processing queue full, rejecting job"
+                    );
+                    return Err(AppError::service_unavailable(
+                        "Image processing queue is full",
+                        retry_after,
+                    ));
+                }
+
+                let _queued = QueuedJob(&self.queued);
+                Arc::clone(&self.slots)
+                    .acquire_owned()
+                    .await
+                    .map_err(|_| AppError::internal("Processing pool closed"))?
+            }
+        };
+
+        let started = Instant::now();
+        let result = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            job()
+        })
+        .await
+        .map_err(|e| AppError::internal(format!("Processing job panicked: {}", e)))?;
+
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        self.total_processing_ms
+            .fetch_add(elapsed_ms, Ordering::Relaxed);
+        self.max_processing_ms
+            .fetch_max(elapsed_ms, Ordering::Relaxed);
+        match &result {
+            Ok(_) => self.completed.fetch_add(1, Ordering::Relaxed),
+            Err(_) => self.failed.fetch_add(1, Ordering::Relaxed),
+        };
+        debug!(elapsed_ms = elapsed_ms, "Processing job finished");
+
+        result
+    }
+
+    /// Current load and timings
+    pub fn stats(&self) -> ProcessingStats {
+        ProcessingStats {
+            workers: self.workers,
+            active: self.workers - self.slots.available_permits(),
+            queued: self.queued.load(Ordering::SeqCst),
+            max_queued: self.max_queued,
+            completed: self.completed.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            avg_processing_ms: self.avg_processing_ms(),
+            max_processing_ms: self.max_processing_ms.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Average processing time of finished jobs (0 before the first one)
+    fn avg_processing_ms(&self) -> u64 {
+        let finished =
+            self.completed.load(Ordering::Relaxed) + self.failed.load(Ordering::Relaxed);
+        self.total_processing_ms
+            .load(Ordering::Relaxed)
+            .checked_div(finished)
+            .unwrap_or(0)
+    }
+
+    /// Estimate when a slot frees up: the full queue drained by all workers
+    fn retry_after_secs(&self) -> u64 {
+        let rounds = (self.max_queued / self.workers + 1) as u64;
+        (rounds * self.avg_processing_ms())
+            .div_ceil(1000)
+            .clamp(1, MAX_RETRY_AFTER_SECS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    fn create_test_pool(workers: usize, max_queued: usize) -> Arc<ProcessingPool> {
+        let config = ProcessingConfig {
+            output_format: "webp".to_string(),
+            output_formats: Vec::new(),
+            output_quality: 85,
+            webp_lossless: false,
+            max_image_dimension: 1024,
+            max_decode_width: 16384,
+            max_decode_height: 16384,
+            max_decode_alloc: 512 * 1024 * 1024,
+            keep_originals: true,
+            strip_exif: true,
+            metadata_policy: None,
+            preserve_animation: true,
+            variants: Default::default(),
+            max_concurrent_decodes: workers,
+            max_queued_decodes: max_queued,
+            ffmpeg_path: None,
+        };
+        Arc::new(ProcessingPool::new(&config))
+    }
+
+    /// Wait until the pool reports the given number of active and queued jobs
+    async fn wait_for(pool: &ProcessingPool, active: usize, queued: usize) {
+        for _ in 0..200 {
+            let stats = pool.stats();
+            if stats.active == active && stats.queued == queued {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!("pool never reached {} active / {} queued", active, queued);
+    }
+
+    #[tokio::test]
+    async fn test_run_returns_job_result() {
+        let pool = create_test_pool(2, 0);
+
+        assert_eq!(pool.run(|| Ok(21 * 2)).await.unwrap(), 42);
+        assert!(pool
+            .run(|| Err::<(), _>(AppError::validation("bad")))
+            .await
+            .is_err());
+
+        let stats = pool.stats();
+        assert_eq!((stats.completed, stats.failed, stats.active), (1, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_rejects() {
+        let pool = create_test_pool(1, 1);
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+
+        // Occupy the only slot, then the only queue place
+        let running = tokio::spawn({
+            let pool = Arc::clone(&pool);
+            async move {
+                pool.run(move || {
+                    release_rx.recv().ok();
+                    Ok(())
+                })
+                .await
+            }
+        });
+        wait_for(&pool, 1, 0).await;
+
+        let waiting = tokio::spawn({
+            let pool = Arc::clone(&pool);
+            async move { pool.run(|| Ok(())).await }
+        });
+        wait_for(&pool, 1, 1).await;
+
+        match pool.run(|| Ok(())).await {
+            Err(AppError::ServiceUnavailable { retry_after_secs, .. }) => {
+                assert!(retry_after_secs >= 1)
+            }
+            other => panic!("expected ServiceUnavailable, got {:?}", other),
+        }
+        assert_eq!(pool.stats().rejected, 1);
+
+        release_tx.send(()).unwrap();
+        running.await.unwrap().unwrap();
+        waiting.await.unwrap().unwrap();
+
+        let stats = pool.stats();
+        assert_eq!((stats.active, stats.queued, stats.completed), (0, 0, 2));
+    }
+}
@@ -0,0 +1,201 @@
+//! Focal points and focus-aware cropping.
+//!
+//! Cover variants crop the overflow; by default the crop window sits in the
+//! middle, which cuts off subjects that are not centered. A focal point
+//! (fractions of the upright image, `0.0..=1.0` on both axes) moves the
+//! window so the subject stays in frame.
+//!
+//! [`detect`] guesses the focal point with a saliency heuristic: strong
+//! edges and colors that differ from the border (the likely background)
+//! count as interesting, transparent areas do not.
+
+use image::{imageops::FilterType, DynamicImage, GenericImageView};
+
+use crate::models::FocalPoint;
+
+/// Longest side of the thumbnail saliency is computed on
+const ANALYSIS_SIZE: u32 = 64;
+
+/// Weight of the difference from the border color relative to edge strength
+const BACKGROUND_WEIGHT: f32 = 0.5;
+
+/// Guess where the subject of an image is
+///
+/// Returns the saliency-weighted centroid, or the center for images without
+/// any structure (solid colors).
+pub fn detect(img: &DynamicImage) -> FocalPoint {
+    let thumbnail = img
+        .resize(ANALYSIS_SIZE, ANALYSIS_SIZE, FilterType::Triangle)
+        .to_rgba8();
+    let (width, height) = thumbnail.dimensions();
+    if width < 3 || height < 3 {
+        return FocalPoint::CENTER;
+    }
+
+    let luma = |x: u32, y: u32| {
+        let p = thumbnail.get_pixel(x, y);
+        0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32
+    };
+
+    // Mean color of the outermost pixels stands in for the background
+    let border: Vec<[f32; 3]> = thumbnail
+        .enumerate_pixels()
+        .filter(|(x, y, p)| {
+            p[3] >= 128 && (*x == 0 || *y == 0 || *x == width - 1 || *y == height - 1)
+        })
+        .map(|(_, _, p)| [p[0] as f32, p[1] as f32, p[2] as f32])
+        .collect();
+    let background = if border.is_empty() {
+        None
+    } else {
+        let sum = border.iter().fold([0.0; 3], |acc, c| {
+            [acc[0] + c[0], acc[1] + c[1], acc[2] + c[2]]
+        });
+        Some(sum.map(|v| v / border.len() as f32))
+    };
+
+    let (mut total, mut sum_x, mut sum_y) = (0.0f64, 0.0f64, 0.0f64);
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let p = thumbnail.get_pixel(x, y);
+            if p[3] < 128 {
+                continue;
+            }
+
+            let edge = (luma(x + 1, y) - luma(x - 1, y)).abs()
+                + (luma(x, y + 1) - luma(x, y - 1)).abs();
+            let distinct = background.map_or(0.0, |bg| {
+                (0..3).map(|c| (p[c] as f32 - bg[c]).abs()).sum::<f32>() / 3.0
+            });
+
+            // Squared, so a compact subject outweighs faint texture elsewhere
+            let saliency = (edge + BACKGROUND_WEIGHT * distinct) as f64;
+            let weight = saliency * saliency;
+            total += weight;
+            sum_x += weight * (x as f64 + 0.5);
+            sum_y += weight * (y as f64 + 0.5);
+        }
+    }
+
+    if total <= f64::EPSILON {
+        return FocalPoint::CENTER;
+    }
+
+    FocalPoint::new(
+        (sum_x / total / width as f64) as f32,
+        (sum_y / total / height as f64) as f32,
+    )
+}
+
+/// Crop window `(x, y, width, height)` for a cover resize to `target_width`
+/// × `target_height`
+///
+/// The window has the target's aspect ratio, is as large as the image
+/// allows, and is centered on `focus` as far as the image edges permit.
+pub fn crop_window(
+    img: &impl GenericImageView,
+    target_width: u32,
+    target_height: u32,
+    focus: FocalPoint,
+) -> (u32, u32, u32, u32) {
+    let (width, height) = img.dimensions();
+    let scale = f64::max(
+        target_width as f64 / width as f64,
+        target_height as f64 / height as f64,
+    );
+    let crop_width = ((target_width as f64 / scale).round() as u32).clamp(1, width);
+    let crop_height = ((target_height as f64 / scale).round() as u32).clamp(1, height);
+
+    let offset = |focus: f32, size: u32, crop: u32| {
+        let start = (focus as f64 * size as f64 - crop as f64 / 2.0).round();
+        start.clamp(0.0, (size - crop) as f64) as u32
+    };
+
+    (
+        offset(focus.x, width, crop_width),
+        offset(focus.y, height, crop_height),
+        crop_width,
+        crop_height,
+    )
+}
+
+/// Scale and crop an image to exactly fill the target size around `focus`
+pub fn cover(
+    img: &DynamicImage,
+    target_width: u32,
+    target_height: u32,
+    focus: FocalPoint,
+) -> DynamicImage {
+    let (x, y, width, height) = crop_window(img, target_width, target_height, focus);
+    img.crop_imm(x, y, width, height)
+        .resize_exact(target_width, target_height, FilterType::Lanczos3)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    /// White canvas with a dark, detailed square
+    fn subject_at(width: u32, height: u32, left: u32, top: u32, size: u32) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+            let inside = x >= left && x < left + size && y >= top && y < top + size;
+            if inside {
+                let v = if (x / 4 + y / 4) % 2 == 0 { 20 } else { 90 };
+                Rgba([v, v / 2, 160, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        }))
+    }
+
+    #[test]
+    fn test_detect_finds_off_center_subject() {
+        let img = subject_at(400, 200, 280, 40, 80);
+        let focus = detect(&img);
+
+        assert!((focus.x - 0.8).abs() < 0.08, "{:?}", focus);
+        assert!((focus.y - 0.4).abs() < 0.08, "{:?}", focus);
+    }
+
+    #[test]
+    fn test_detect_plain_image_is_centered() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(50, 80, Rgba([9, 99, 199, 255])));
+        assert_eq!(detect(&img), FocalPoint::CENTER);
+
+        // Fully transparent counts as plain too
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(50, 80, Rgba([0, 0, 0, 0])));
+        assert_eq!(detect(&img), FocalPoint::CENTER);
+    }
+
+    #[test]
+    fn test_crop_window_follows_focus_within_bounds() {
+        let img = DynamicImage::new_rgb8(400, 200);
+
+        // Square crop of a landscape image: full height, 200px wide
+        assert_eq!(crop_window(&img, 100, 100, FocalPoint::CENTER), (100, 0, 200, 200));
+        assert_eq!(crop_window(&img, 100, 100, FocalPoint::new(0.75, 0.5)), (200, 0, 200, 200));
+
+        // Clamped at the edges
+        assert_eq!(crop_window(&img, 100, 100, FocalPoint::new(1.0, 0.0)), (200, 0, 200, 200));
+        assert_eq!(crop_window(&img, 100, 100, FocalPoint::new(0.0, 1.0)), (0, 0, 200, 200));
+
+        // Wide crop: full width, vertical offset follows the focus
+        assert_eq!(crop_window(&img, 400, 100, FocalPoint::new(0.5, 0.9)), (0, 100, 400, 100));
+    }
+
+    #[test]
+    fn test_cover_keeps_subject() {
+        let img = subject_at(400, 200, 300, 60, 80);
+        let cropped = cover(&img, 100, 100, detect(&img));
+
+        assert_eq!(cropped.dimensions(), (100, 100));
+        // A center crop (x 100-300) would be all white
+        let dark = cropped
+            .to_rgba8()
+            .pixels()
+            .filter(|p| p[0] < 128)
+            .count();
+        assert!(dark > 1000, "{}", dark);
+    }
+}
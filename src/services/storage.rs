@@ -2,6 +2,7 @@
 //!
 //! This module handles all file system operations including:
 //! - Saving original and optimized files
+//! - Caching generated image variants
 //! - Managing temporary upload files
 //! - Cleaning up expired sessions
 //!
@@ -20,6 +21,10 @@
 //! ├── optimized/           # Optimized/converted files
 //! │   └── ab/cd/
 //! │       └── abcd1234-...-5678.webp
+//! ├── variants/            # Cached on-the-fly variants
+//! │   └── ab/cd/
+//! │       └── abcd1234-...-5678/
+//! │           └── 256x256-cover-q70.jpg
 //! └── temp/                # Temporary chunked upload files
 //!     └── {session_id}/
 //!         ├── chunk_0
@@ -32,6 +37,7 @@
 
 use crate::config::StorageConfig;
 use crate::error::{AppError, Result};
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 use tokio::fs;
 use tokio::io::AsyncWriteExt;
@@ -47,6 +53,8 @@ pub struct StorageService {
     optimized_dir: PathBuf,
     /// Path to temporary files directory
     temp_dir: PathBuf,
+    /// Path to cached variants directory
+    variants_dir: PathBuf,
     /// Number of directory nesting levels (0-4)
     directory_levels: u8,
 }
@@ -64,6 +72,7 @@ impl StorageService {
             originals_dir: config.originals_path(),
             optimized_dir: config.optimized_path(),
             temp_dir: config.temp_path(),
+            variants_dir: config.variants_path(),
             directory_levels: config.directory_levels,
         };
 
@@ -74,6 +83,7 @@ impl StorageService {
             originals = %service.originals_dir.display(),
             optimized = %service.optimized_dir.display(),
             temp = %service.temp_dir.display(),
+            variants = %service.variants_dir.display(),
             directory_levels = service.directory_levels,
             "Storage service initialized"
         );
@@ -83,7 +93,12 @@ impl StorageService {
 
     /// Initialize storage directories
     async fn init_directories(&self) -> Result<()> {
-        for dir in [&self.originals_dir, &self.optimized_dir, &self.temp_dir] {
+        for dir in [
+            &self.originals_dir,
+            &self.optimized_dir,
+            &self.temp_dir,
+            &self.variants_dir,
+        ] {
             if !dir.exists() {
                 fs::create_dir_all(dir).await?;
                 debug!(path = %dir.display(), "Created storage directory");
@@ -173,6 +188,34 @@ impl StorageService {
         Ok(path)
     }
 
+    /// Store an original by moving an existing file (e.g. an assembled upload)
+    ///
+    /// Falls back to copying if the file cannot be renamed (different
+    /// filesystem), so the source may or may not still exist afterwards.
+    pub async fn save_original_from_file(
+        &self,
+        id: Uuid,
+        extension: &str,
+        source: &Path,
+    ) -> Result<PathBuf> {
+        let path = self.build_file_path(&self.originals_dir, id, extension);
+
+        // Ensure subdirectory exists
+        self.ensure_subdir(&path).await?;
+
+        if fs::rename(source, &path).await.is_err() {
+            fs::copy(source, &path).await?;
+        }
+
+        debug!(
+            id = %id,
+            path = %path.display(),
+            "Saved original file from temp file"
+        );
+
+        Ok(path)
+    }
+
     /// Get path to an original file
     pub fn original_path(&self, id: Uuid, extension: &str) -> PathBuf {
         self.build_file_path(&self.originals_dir, id, extension)
@@ -237,6 +280,34 @@ impl StorageService {
         Ok(path)
     }
 
+    /// Store an optimized file by moving an existing file (e.g. an assembled
+    /// video upload, which is served untouched)
+    ///
+    /// Falls back to copying like [`Self::save_original_from_file`].
+    pub async fn save_optimized_from_file(
+        &self,
+        id: Uuid,
+        extension: &str,
+        source: &Path,
+    ) -> Result<PathBuf> {
+        let path = self.build_file_path(&self.optimized_dir, id, extension);
+
+        // Ensure subdirectory exists
+        self.ensure_subdir(&path).await?;
+
+        if fs::rename(source, &path).await.is_err() {
+            fs::copy(source, &path).await?;
+        }
+
+        debug!(
+            id = %id,
+            path = %path.display(),
+            "Saved optimized file from temp file"
+        );
+
+        Ok(path)
+    }
+
     /// Get path to an optimized file
     pub fn optimized_path(&self, id: Uuid, extension: &str) -> PathBuf {
         self.build_file_path(&self.optimized_dir, id, extension)
@@ -265,6 +336,67 @@ impl StorageService {
         Ok(())
     }
 
+    // =========================================================================
+    // Variant files
+    // =========================================================================
+
+    /// Get the directory holding all cached variants of a media file
+    fn variant_dir(&self, id: Uuid) -> PathBuf {
+        self.variants_dir
+            .join(self.subdir_path(id))
+            .join(id.to_string())
+    }
+
+    /// Get path to a cached variant file
+    ///
+    /// # Arguments
+    /// * `id` - Media UUID
+    /// * `key` - Normalized variant key (e.g., "256x256-cover-q70")
+    /// * `extension` - File extension of the variant format
+    pub fn variant_path(&self, id: Uuid, key: &str, extension: &str) -> PathBuf {
+        self.variant_dir(id).join(format!("{}.{}", key, extension))
+    }
+
+    /// Save a rendered variant
+    ///
+    /// The file is written to a temporary name first and then renamed, so
+    /// concurrent requests never observe a partially written variant.
+    pub async fn save_variant(
+        &self,
+        id: Uuid,
+        key: &str,
+        extension: &str,
+        data: &[u8],
+    ) -> Result<PathBuf> {
+        let path = self.variant_path(id, key, extension);
+        self.ensure_subdir(&path).await?;
+
+        let tmp_path = path.with_extension(format!("{}.tmp-{}", extension, Uuid::new_v4()));
+        fs::write(&tmp_path, data).await?;
+        fs::rename(&tmp_path, &path).await?;
+
+        debug!(
+            id = %id,
+            key = %key,
+            size = data.len(),
+            "Saved variant file"
+        );
+
+        Ok(path)
+    }
+
+    /// Delete all cached variants of a media file
+    pub async fn delete_variants(&self, id: Uuid) -> Result<()> {
+        let dir = self.variant_dir(id);
+
+        if dir.exists() {
+            fs::remove_dir_all(&dir).await?;
+            debug!(id = %id, path = %dir.display(), "Deleted variant files");
+        }
+
+        Ok(())
+    }
+
     // =========================================================================
     // Temporary files (chunked uploads)
     // =========================================================================
@@ -355,6 +487,11 @@ impl StorageService {
         Ok(metadata.len())
     }
 
+    /// Get the path to the assembled temp upload file
+    pub fn temp_file_path(&self, session_id: Uuid) -> PathBuf {
+        self.temp_session_path(session_id).join("upload")
+    }
+
     /// Read the assembled temp file
     pub async fn read_temp_file(&self, session_id: Uuid) -> Result<Vec<u8>> {
         let file_path = self.temp_session_path(session_id).join("upload");
@@ -423,10 +560,15 @@ impl StorageService {
     ///
     /// # Arguments
     /// * `max_age_secs` - Maximum age in seconds for temp directories
+    /// * `keep` - Sessions whose files are still needed (queued for processing)
     ///
     /// # Returns
     /// Number of sessions cleaned up
-    pub async fn cleanup_expired_sessions(&self, max_age_secs: u64) -> Result<usize> {
+    pub async fn cleanup_expired_sessions(
+        &self,
+        max_age_secs: u64,
+        keep: &HashSet<Uuid>,
+    ) -> Result<usize> {
         let mut cleaned = 0;
         let now = std::time::SystemTime::now();
         let max_age = std::time::Duration::from_secs(max_age_secs);
@@ -440,6 +582,14 @@ impl StorageService {
                 continue;
             }
 
+            let kept = path
+                .file_name()
+                .and_then(|name| Uuid::parse_str(&name.to_string_lossy()).ok())
+                .is_some_and(|id| keep.contains(&id));
+            if kept {
+                continue;
+            }
+
             // Check directory age based on modification time
             if let Ok(metadata) = fs::metadata(&path).await {
                 if let Ok(modified) = metadata.modified() {
@@ -497,6 +647,7 @@ impl StorageService {
     ) -> Result<()> {
         self.delete_original(id, original_ext).await?;
         self.delete_optimized(id, optimized_ext).await?;
+        self.delete_variants(id).await?;
         Ok(())
     }
 
@@ -505,6 +656,7 @@ impl StorageService {
         let originals_size = Self::dir_size(&self.originals_dir).await?;
         let optimized_size = Self::dir_size(&self.optimized_dir).await?;
         let temp_size = Self::dir_size(&self.temp_dir).await?;
+        let variants_size = Self::dir_size(&self.variants_dir).await?;
 
         let originals_count = Self::file_count(&self.originals_dir).await?;
         let optimized_count = Self::file_count(&self.optimized_dir).await?;
@@ -513,7 +665,8 @@ impl StorageService {
             originals_size,
             optimized_size,
             temp_size,
-            total_size: originals_size + optimized_size + temp_size,
+            variants_size,
+            total_size: originals_size + optimized_size + temp_size + variants_size,
             originals_count,
             optimized_count,
         })
@@ -571,6 +724,8 @@ pub struct StorageStats {
     pub optimized_size: u64,
     /// Size of temp directory in bytes
     pub temp_size: u64,
+    /// Size of variants cache directory in bytes
+    pub variants_size: u64,
     /// Total storage size in bytes
     pub total_size: u64,
     /// Number of original files
@@ -591,6 +746,7 @@ mod tests {
             originals_dir: "originals".to_string(),
             optimized_dir: "optimized".to_string(),
             temp_dir: "temp".to_string(),
+            variants_dir: "variants".to_string(),
             directory_levels: 2,
             database_file: "test.db".to_string(),
         };
@@ -623,5 +779,21 @@ mod tests {
         service.delete_original(id, "jpg").await.unwrap();
         assert!(!service.original_exists(id, "jpg").await);
     }
+
+    #[tokio::test]
+    async fn test_save_and_delete_variants() {
+        let (service, _temp) = create_test_service().await;
+        let id = Uuid::new_v4();
+
+        let path = service
+            .save_variant(id, "64x64-cover-q80", "jpg", b"variant data")
+            .await
+            .unwrap();
+        assert_eq!(path, service.variant_path(id, "64x64-cover-q80", "jpg"));
+        assert_eq!(fs::read(&path).await.unwrap(), b"variant data");
+
+        service.delete_media_files(id, "png", "webp").await.unwrap();
+        assert!(!path.exists());
+    }
 }
 
@@ -0,0 +1,211 @@
+//! SVG uploads: sanitizing and rasterizing.
+//!
+//! SVG is never served as uploaded. The document is parsed with `usvg`,
+//! which keeps only what affects rendering: scripts, event handlers,
+//! `foreignObject`, links, animations, stylesheets and external references
+//! are dropped, and embedded images are never loaded (see `rasterize`).
+//! The cleaned tree is written back out as the stored original and rendered
+//! with `resvg` (pure Rust) into the raster image that goes through the
+//! normal pipeline.
+//!
+//! Text is not rendered (no fonts are loaded); convert text to paths before
+//! uploading. Needs the `svg` feature; without it SVG uploads get 415.
+
+use image::DynamicImage;
+
+use crate::error::{AppError, Result};
+
+/// Rendered size of the longer side for documents smaller than this
+/// (vector art has no native resolution, so small icons are scaled up)
+pub const MIN_RENDER_SIZE: u32 = 512;
+
+/// Bytes looked at when sniffing for an SVG document
+const SNIFF_LEN: usize = 4096;
+
+/// A rasterized SVG upload
+#[derive(Debug)]
+pub struct RasterizedSvg {
+    /// Rendered image (RGBA, straight alpha)
+    pub image: DynamicImage,
+    /// Sanitized SVG document, stored as the original
+    pub sanitized: Vec<u8>,
+}
+
+/// Whether the data looks like an SVG document
+///
+/// Content sniffing for text formats: an `<svg` root element, optionally
+/// after an XML declaration, comments or a doctype, within the first
+/// [`SNIFF_LEN`] bytes. The rest of `data` is never looked at.
+pub fn is_svg(data: &[u8]) -> bool {
+    let header = &data[..data.len().min(SNIFF_LEN)];
+    let text = String::from_utf8_lossy(header);
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+
+    if text.starts_with("<svg") {
+        return true;
+    }
+
+    let prolog = ["<?xml", "<!--", "<!DOCTYPE"]
+        .iter()
+        .any(|p| text.starts_with(p));
+    prolog && text.contains("<svg")
+}
+
+/// Sanitize and render an SVG document
+///
+/// The longer side is rendered at the document's size, at least
+/// [`MIN_RENDER_SIZE`] and at most `max_dimension`. Fails with
+/// `AppError::ImageTooLarge` if the pixels would exceed `max_alloc` bytes.
+#[cfg(feature = "svg")]
+pub fn rasterize(data: &[u8], max_dimension: u32, max_alloc: u64) -> Result<RasterizedSvg> {
+    use image::RgbaImage;
+    use resvg::{tiny_skia, usvg};
+
+    // Embedded and linked images are never loaded
+    let options = usvg::Options {
+        image_href_resolver: usvg::ImageHrefResolver {
+            resolve_data: Box::new(|_, _, _| None),
+            resolve_string: Box::new(|_, _| None),
+        },
+        ..Default::default()
+    };
+
+    let tree = usvg::Tree::from_data(data, &options)
+        .map_err(|e| AppError::image_processing(format!("Invalid SVG: {}", e)))?;
+
+    let size = tree.size();
+    let longest = size.width().max(size.height());
+    let target = longest.max(MIN_RENDER_SIZE as f32).min(max_dimension as f32);
+    let scale = target / longest;
+    let width = ((size.width() * scale).round() as u32).max(1);
+    let height = ((size.height() * scale).round() as u32).max(1);
+
+    if u64::from(width) * u64::from(height) * 4 > max_alloc {
+        return Err(AppError::image_too_large(format!(
+            "SVG renders to {}x{}, which exceeds the decode limits",
+            width, height
+        )));
+    }
+
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)
+        .ok_or_else(|| AppError::image_processing("Invalid SVG size"))?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    // tiny-skia works with premultiplied alpha
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|p| {
+            let c = p.demultiply();
+            [c.red(), c.green(), c.blue(), c.alpha()]
+        })
+        .collect();
+    let image = RgbaImage::from_raw(width, height, pixels)
+        .ok_or_else(|| AppError::image_processing("Invalid SVG render buffer"))?;
+
+    Ok(RasterizedSvg {
+        image: DynamicImage::ImageRgba8(image),
+        sanitized: tree.to_string(&usvg::WriteOptions::default()).into_bytes(),
+    })
+}
+
+/// Sanitize and render an SVG document (not available in this build)
+#[cfg(not(feature = "svg"))]
+pub fn rasterize(_data: &[u8], _max_dimension: u32, _max_alloc: u64) -> Result<RasterizedSvg> {
+    Err(AppError::unsupported_media_type(
+        "SVG support is not enabled in this build",
+    ))
+}
+
+#[cfg(all(test, feature = "svg"))]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+
+    const LOGO: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink"
+     width="40" height="20" viewBox="0 0 40 20" onload="alert(1)">
+  <script>alert(document.cookie)</script>
+  <rect x="0" y="0" width="20" height="20" fill="#ff0000"/>
+  <image xlink:href="file:///etc/passwd" width="10" height="10"/>
+  <a xlink:href="javascript:alert(2)"><circle cx="30" cy="10" r="5" fill="#0000ff"/></a>
+</svg>"##;
+
+    #[test]
+    fn test_is_svg() {
+        assert!(is_svg(LOGO.as_bytes()));
+        assert!(is_svg(b"\xef\xbb\xbf  <svg xmlns=\"http://www.w3.org/2000/svg\"/>"));
+        assert!(is_svg(b"<!-- logo -->\n<svg></svg>"));
+        assert!(!is_svg(b"<?xml version=\"1.0\"?><html></html>"));
+        assert!(!is_svg(b"\x89PNG\r\n\x1a\n"));
+
+        // Only the start of the data is sniffed
+        let mut late = vec![b' '; SNIFF_LEN];
+        late.extend_from_slice(LOGO.as_bytes());
+        assert!(!is_svg(&late));
+    }
+
+    #[test]
+    fn test_rasterize_scales_small_documents_up() {
+        let svg = rasterize(LOGO.as_bytes(), 4096, 512 * 1024 * 1024).unwrap();
+
+        assert_eq!(svg.image.dimensions(), (512, 256));
+        // Red square on the left half, transparent background elsewhere
+        assert_eq!(svg.image.get_pixel(100, 128).0, [255, 0, 0, 255]);
+        assert_eq!(svg.image.get_pixel(300, 20).0[3], 0);
+    }
+
+    #[test]
+    fn test_rasterize_respects_limits() {
+        let svg = rasterize(LOGO.as_bytes(), 100, 512 * 1024 * 1024).unwrap();
+        assert_eq!(svg.image.dimensions(), (100, 50));
+
+        let err = rasterize(LOGO.as_bytes(), 4096, 1000).unwrap_err();
+        assert!(matches!(err, AppError::ImageTooLarge(_)));
+
+        assert!(rasterize(b"<svg", 4096, 1 << 20).is_err());
+    }
+
+    #[test]
+    fn test_sanitized_document_drops_active_content() {
+        let svg = rasterize(LOGO.as_bytes(), 4096, 512 * 1024 * 1024).unwrap();
+        let sanitized = String::from_utf8(svg.sanitized).unwrap();
+
+        assert!(sanitized.contains("<svg"));
+        for needle in ["script", "onload", "javascript:", "/etc/passwd", "alert"] {
+            assert!(!sanitized.contains(needle), "{} in {}", needle, sanitized);
+        }
+    }
+
+    #[test]
+    fn test_sanitized_document_drops_external_references() {
+        let doc = r##"<?xml version="1.0"?>
+<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink"
+     width="100" height="100">
+  <style>@import url(https://evil.example/x.css);</style>
+  <foreignObject width="50" height="50">
+    <body xmlns="http://www.w3.org/1999/xhtml"><iframe src="https://evil.example/"/></body>
+  </foreignObject>
+  <use xlink:href="https://evil.example/sprite.svg#icon"/>
+  <image href="https://evil.example/pixel.png" width="10" height="10"/>
+  <image href="data:image/svg+xml;base64,PHN2Zz48c2NyaXB0Lz48L3N2Zz4=" width="10" height="10"/>
+  <filter id="f"><feImage href="https://evil.example/f.png"/></filter>
+  <rect width="50" height="50" fill="#00ff00" filter="url(#f)"/>
+  <set attributeName="onmouseover" to="alert(1)"/>
+</svg>"##;
+
+        let svg = rasterize(doc.as_bytes(), 4096, 512 * 1024 * 1024).unwrap();
+        let sanitized = String::from_utf8(svg.sanitized).unwrap();
+
+        let needles = [
+            "evil.example", "foreignObject", "iframe", "data:", "<use", "<image", "<set", "@import",
+        ];
+        for needle in needles {
+            assert!(!sanitized.contains(needle), "{} in {}", needle, sanitized);
+        }
+    }
+}
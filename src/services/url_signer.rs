@@ -0,0 +1,176 @@
+//! Signed, expiring URLs for private media.
+//!
+//! Private media ([`Visibility::Private`]) is only served to URLs carrying
+//! an expiry (`exp`, Unix seconds) and a signature (`sig`):
+//!
+//! ```text
+//! /m/{id}?exp=1735689600&sig=<hex HMAC-SHA256 of "{id}:{exp}">
+//! ```
+//!
+//! A signature covers the media item, not a single URL: the same `exp` and
+//! `sig` work for the optimized file, variants, presets and the original.
+//!
+//! The first of `auth.url_signing_keys` signs, every key verifies. To rotate,
+//! put the new key first and drop the old one once the longest-lived URL
+//! signed with it has expired.
+//!
+//! [`Visibility::Private`]: crate::models::Visibility::Private
+
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::config::AuthConfig;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Signs and verifies URLs of private media
+#[derive(Clone)]
+pub struct UrlSigner {
+    /// Signing key first, then keys that are only verified
+    keys: Vec<Vec<u8>>,
+    /// Lifetime of URLs signed for upload responses (seconds)
+    default_ttl: u64,
+    /// Longest lifetime the admin API signs for (seconds)
+    max_ttl: u64,
+}
+
+/// Query parameters granting access to a media item
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedQuery {
+    /// `exp=...&sig=...`, to be appended to a media URL
+    pub query: String,
+    /// When the signature stops being accepted
+    pub expires_at: DateTime<Utc>,
+}
+
+impl UrlSigner {
+    /// Create a signer from the authentication configuration
+    pub fn new(config: &AuthConfig) -> Self {
+        Self {
+            keys: config
+                .url_signing_keys
+                .iter()
+                .map(|key| key.as_bytes().to_vec())
+                .collect(),
+            default_ttl: config.signed_url_ttl_seconds,
+            max_ttl: config.max_signed_url_ttl_seconds,
+        }
+    }
+
+    /// Whether a signing key is configured (private uploads need one)
+    pub fn is_enabled(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    /// Lifetime of URLs signed for upload responses (seconds)
+    pub fn default_ttl(&self) -> u64 {
+        self.default_ttl
+    }
+
+    /// Longest lifetime the admin API signs for (seconds)
+    pub fn max_ttl(&self) -> u64 {
+        self.max_ttl
+    }
+
+    /// Sign access to a media item until `exp` (Unix seconds)
+    ///
+    /// Returns `None` if no key is configured.
+    pub fn sign(&self, id: Uuid, exp: i64) -> Option<String> {
+        let key = self.keys.first()?;
+        Some(hex::encode(Self::mac(key, id, exp).finalize().into_bytes()))
+    }
+
+    /// Check a signature against every configured key
+    ///
+    /// Does not look at the expiry; callers compare `exp` with the clock.
+    pub fn verify(&self, id: Uuid, exp: i64, sig: &str) -> bool {
+        let Ok(sig) = hex::decode(sig) else {
+            return false;
+        };
+
+        self.keys
+            .iter()
+            .any(|key| Self::mac(key, id, exp).verify_slice(&sig).is_ok())
+    }
+
+    /// Query parameters granting access to a media item for `ttl` seconds
+    ///
+    /// Returns `None` if no key is configured.
+    pub fn signed_query(&self, id: Uuid, ttl: u64) -> Option<SignedQuery> {
+        let exp = Utc::now().timestamp().saturating_add(ttl as i64);
+        let sig = self.sign(id, exp)?;
+
+        Some(SignedQuery {
+            query: format!("exp={}&sig={}", exp, sig),
+            expires_at: DateTime::from_timestamp(exp, 0)?,
+        })
+    }
+
+    fn mac(key: &[u8], id: Uuid, exp: i64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+        mac.update(format!("{}:{}", id, exp).as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signer(keys: &[&str]) -> UrlSigner {
+        UrlSigner::new(&AuthConfig {
+            url_signing_keys: keys.iter().map(|k| k.to_string()).collect(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let signer = signer(&["first-key-0123456789abcdef0123456789"]);
+        let id = Uuid::new_v4();
+
+        let sig = signer.sign(id, 1_900_000_000).unwrap();
+        assert_eq!(sig.len(), 64);
+        assert!(signer.verify(id, 1_900_000_000, &sig));
+
+        // Bound to the media item and the expiry
+        assert!(!signer.verify(Uuid::new_v4(), 1_900_000_000, &sig));
+        assert!(!signer.verify(id, 1_900_000_001, &sig));
+        assert!(!signer.verify(id, 1_900_000_000, "not-hex"));
+        assert!(!signer.verify(id, 1_900_000_000, &sig[..32]));
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let old = signer(&["old-key-0123456789abcdef0123456789ab"]);
+        let rotated = signer(&[
+            "new-key-0123456789abcdef0123456789ab",
+            "old-key-0123456789abcdef0123456789ab",
+        ]);
+        let retired = signer(&["new-key-0123456789abcdef0123456789ab"]);
+        let id = Uuid::new_v4();
+
+        let old_sig = old.sign(id, 1_900_000_000).unwrap();
+        assert!(rotated.verify(id, 1_900_000_000, &old_sig));
+        assert!(!retired.verify(id, 1_900_000_000, &old_sig));
+        assert_ne!(rotated.sign(id, 1_900_000_000).unwrap(), old_sig);
+    }
+
+    #[test]
+    fn test_signed_query() {
+        let id = Uuid::new_v4();
+        assert!(signer(&[]).signed_query(id, 60).is_none());
+
+        let signer = signer(&["key-0123456789abcdef0123456789abcdef"]);
+        let signed = signer.signed_query(id, 60).unwrap();
+        let exp = signed.expires_at.timestamp();
+
+        assert!((exp - Utc::now().timestamp() - 60).abs() <= 1);
+        assert_eq!(
+            signed.query,
+            format!("exp={}&sig={}", exp, signer.sign(id, exp).unwrap())
+        );
+    }
+}
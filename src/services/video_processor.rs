@@ -0,0 +1,745 @@
+//! Video probing service.
+//!
+//! Videos are stored untouched; this module only reads container metadata
+//! (duration, dimensions, codec) so it can be recorded on the `Media` entry.
+//! Parsing is pure Rust and never reads the media payload itself:
+//!
+//! - **MP4 / QuickTime** (ISO-BMFF): walks the top-level boxes to `moov`,
+//!   then reads `mvhd` for the duration and the first video `trak`
+//!   (`tkhd`, `hdlr`, `stsd`) for dimensions and codec.
+//! - **WebM / Matroska** (EBML): reads `Segment/Info` for the duration and
+//!   the first video `TrackEntry` for dimensions and codec.
+//!
+//! Frames are never decoded in-process. When `processing.ffmpeg_path` is
+//! set, [`VideoProcessor::extract_poster`] runs ffmpeg on the stored file to
+//! grab a poster frame, which the upload pipeline encodes like an image.
+
+use crate::config::{ProcessingConfig, UploadConfig};
+use crate::error::{AppError, Result};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tracing::{debug, info};
+
+/// Number of leading bytes inspected for magic-byte detection
+const SNIFF_LEN: u64 = 8192;
+
+/// Largest `moov` box (MP4) read into memory
+const MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Largest `Info` / `Tracks` element (WebM) read into memory
+const MAX_EBML_HEADER_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Position of the poster frame, unless the video is shorter than twice this
+const POSTER_OFFSET_MS: u64 = 1000;
+
+/// Longest ffmpeg may take to extract a poster frame
+const POSTER_TIMEOUT: Duration = Duration::from_secs(30);
+
+// EBML element IDs (with their length marker bits, as written in the file)
+const EBML_HEADER: u32 = 0x1A45_DFA3;
+const EBML_DOC_TYPE: u32 = 0x4282;
+const MKV_SEGMENT: u32 = 0x1853_8067;
+const MKV_INFO: u32 = 0x1549_A966;
+const MKV_TIMECODE_SCALE: u32 = 0x2A_D7B1;
+const MKV_DURATION: u32 = 0x4489;
+const MKV_TRACKS: u32 = 0x1654_AE6B;
+const MKV_TRACK_ENTRY: u32 = 0xAE;
+const MKV_TRACK_TYPE: u32 = 0x83;
+const MKV_CODEC_ID: u32 = 0x86;
+const MKV_VIDEO: u32 = 0xE0;
+const MKV_PIXEL_WIDTH: u32 = 0xB0;
+const MKV_PIXEL_HEIGHT: u32 = 0xBA;
+const MKV_CLUSTER: u32 = 0x1F43_B675;
+
+/// Matroska `TrackType` of video tracks
+const MKV_TRACK_TYPE_VIDEO: u64 = 1;
+
+/// Result of probing a video container
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbedVideo {
+    /// Detected MIME type (e.g. "video/mp4")
+    pub mime: String,
+    /// File size in bytes
+    pub size: u64,
+    /// Width of the first video track in pixels (0 if unknown)
+    pub width: u32,
+    /// Height of the first video track in pixels (0 if unknown)
+    pub height: u32,
+    /// Duration in milliseconds, if the container declares one
+    pub duration_ms: Option<u64>,
+    /// Codec of the first video track (e.g. "h264", "vp9")
+    pub codec: Option<String>,
+}
+
+/// Container metadata collected by the format parsers
+#[derive(Debug, Default)]
+struct ContainerInfo {
+    width: u32,
+    height: u32,
+    duration_ms: Option<u64>,
+    codec: Option<String>,
+}
+
+/// Service for video probing operations
+#[derive(Debug, Clone, Default)]
+pub struct VideoProcessor {
+    /// ffmpeg binary for poster frames (`None` disables posters)
+    ffmpeg: Option<PathBuf>,
+}
+
+impl VideoProcessor {
+    /// Create a new video processor
+    pub fn new(config: &ProcessingConfig) -> Self {
+        Self {
+            ffmpeg: config.ffmpeg_path.as_ref().map(PathBuf::from),
+        }
+    }
+
+    /// Detect a video MIME type using magic bytes
+    ///
+    /// Returns `None` for anything that is not a video container.
+    pub fn detect_mime_type(data: &[u8]) -> Option<&'static str> {
+        infer::get(data)
+            .map(|kind| kind.mime_type())
+            .filter(|mime| mime.starts_with("video/"))
+    }
+
+    /// Probe an uploaded video
+    ///
+    /// Validates the container against `allowed_video_types` and reads its
+    /// metadata. Only container headers are read; seeking skips the media data.
+    ///
+    /// # Errors
+    /// Returns error if the type is not allowed or the container is malformed
+    pub fn probe<R: Read + Seek>(
+        &self,
+        mut reader: R,
+        upload_config: &UploadConfig,
+    ) -> Result<ProbedVideo> {
+        let size = reader.seek(SeekFrom::End(0))?;
+        reader.rewind()?;
+
+        let mut header = Vec::new();
+        reader.by_ref().take(SNIFF_LEN).read_to_end(&mut header)?;
+        reader.rewind()?;
+
+        let mime = Self::detect_mime_type(&header).ok_or_else(|| {
+            AppError::unsupported_media_type("Could not detect video type from content")
+        })?;
+        debug!(mime = %mime, size = size, "Detected video format");
+
+        if !upload_config.is_allowed_video_type(mime) {
+            return Err(AppError::unsupported_media_type(format!(
+                "Video type '{}' is not in allowed_video_types",
+                mime
+            )));
+        }
+
+        let container = match mime {
+            "video/mp4" | "video/quicktime" => probe_mp4(&mut reader, size)?,
+            "video/webm" | "video/x-matroska" => probe_webm(&mut reader, size)?,
+            _ => {
+                return Err(AppError::unsupported_media_type(format!(
+                    "Unsupported video container: {}",
+                    mime
+                )))
+            }
+        };
+
+        info!(
+            size = size,
+            width = container.width,
+            height = container.height,
+            duration_ms = ?container.duration_ms,
+            codec = ?container.codec,
+            "Probed video"
+        );
+
+        Ok(ProbedVideo {
+            mime: mime.to_string(),
+            size,
+            width: container.width,
+            height: container.height,
+            duration_ms: container.duration_ms,
+            codec: container.codec,
+        })
+    }
+
+    /// Extract a poster frame of a stored video as PNG
+    ///
+    /// The frame is taken [`POSTER_OFFSET_MS`] in (halfway through shorter
+    /// videos), past the black of a typical fade-in. Returns `None` when no
+    /// `ffmpeg_path` is configured.
+    ///
+    /// # Errors
+    /// Returns error if ffmpeg cannot be run, fails, times out or outputs nothing
+    pub async fn extract_poster(
+        &self,
+        path: &Path,
+        duration_ms: Option<u64>,
+    ) -> Result<Option<Vec<u8>>> {
+        let Some(ffmpeg) = &self.ffmpeg else {
+            return Ok(None);
+        };
+
+        let offset_ms = duration_ms.map_or(0, |duration| (duration / 2).min(POSTER_OFFSET_MS));
+        let mut command = tokio::process::Command::new(ffmpeg);
+        command
+            .args(["-hide_banner", "-loglevel", "error", "-nostdin"])
+            .arg("-ss")
+            .arg(format!("{}.{:03}", offset_ms / 1000, offset_ms % 1000))
+            .arg("-i")
+            .arg(path)
+            .args(["-frames:v", "1", "-f", "image2pipe", "-c:v", "png", "-"])
+            .stdin(Stdio::null())
+            .kill_on_drop(true);
+
+        let output = tokio::time::timeout(POSTER_TIMEOUT, command.output())
+            .await
+            .map_err(|_| AppError::image_processing("ffmpeg timed out extracting a poster frame"))?
+            .map_err(|e| AppError::internal(format!("Failed to run ffmpeg: {}", e)))?;
+
+        if !output.status.success() || output.stdout.is_empty() {
+            return Err(AppError::image_processing(format!(
+                "ffmpeg could not extract a poster frame: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        debug!(path = %path.display(), offset_ms = offset_ms, "Extracted poster frame");
+        Ok(Some(output.stdout))
+    }
+}
+
+fn malformed(msg: &str) -> AppError {
+    AppError::unsupported_media_type(format!("Malformed video container: {}", msg))
+}
+
+/// Read exactly `len` bytes at the current position
+fn read_vec<R: Read>(reader: &mut R, len: u64) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(len as usize);
+    reader.by_ref().take(len).read_to_end(&mut data)?;
+    if (data.len() as u64) < len {
+        return Err(malformed("unexpected end of file"));
+    }
+    Ok(data)
+}
+
+fn be_u16(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn be_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+// =============================================================================
+// MP4 / QuickTime (ISO-BMFF)
+// =============================================================================
+
+/// Find the `moov` box among the top-level boxes and parse it
+fn probe_mp4<R: Read + Seek>(reader: &mut R, file_size: u64) -> Result<ContainerInfo> {
+    let mut pos = 0u64;
+
+    while pos + 8 <= file_size {
+        reader.seek(SeekFrom::Start(pos))?;
+        let header = read_vec(reader, 8)?;
+        let mut box_size = u64::from(be_u32(&header, 0).unwrap_or(0));
+        let kind = &header[4..8];
+        let mut header_len = 8;
+
+        if box_size == 1 {
+            box_size = be_u64(&read_vec(reader, 8)?, 0).unwrap_or(0);
+            header_len = 16;
+        } else if box_size == 0 {
+            // Box extends to the end of the file
+            box_size = file_size - pos;
+        }
+
+        let box_end = pos.checked_add(box_size).filter(|end| *end <= file_size);
+        if box_size < header_len || box_end.is_none() {
+            return Err(malformed("invalid box size"));
+        }
+
+        if kind == b"moov" {
+            let body_len = box_size - header_len;
+            if body_len > MAX_MOOV_SIZE {
+                return Err(malformed("moov box too large"));
+            }
+            let body = read_vec(reader, body_len)?;
+            return Ok(parse_moov(&body));
+        }
+
+        pos += box_size;
+    }
+
+    Err(malformed("no moov box"))
+}
+
+/// Iterate over the boxes in an in-memory buffer as `(type, body)`
+fn mp4_boxes(mut data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    std::iter::from_fn(move || {
+        let size = be_u32(data, 0)? as u64;
+        let (header_len, size) = match size {
+            0 => (8, data.len() as u64),
+            1 => (16, be_u64(data, 8)?),
+            _ => (8, size),
+        };
+        if size < header_len || size > data.len() as u64 {
+            return None;
+        }
+
+        let (current, rest) = data.split_at(size as usize);
+        data = rest;
+        Some((&current[4..8], &current[header_len as usize..]))
+    })
+}
+
+/// Find the body of the first child box of the given type
+fn mp4_child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    mp4_boxes(data)
+        .find(|(k, _)| k == kind)
+        .map(|(_, body)| body)
+}
+
+fn parse_moov(moov: &[u8]) -> ContainerInfo {
+    let mut info = ContainerInfo {
+        duration_ms: mp4_child(moov, b"mvhd").and_then(parse_mvhd),
+        ..Default::default()
+    };
+
+    let video_track = mp4_boxes(moov)
+        .filter(|(kind, _)| *kind == b"trak")
+        .find(|(_, trak)| is_video_trak(trak));
+
+    if let Some((_, trak)) = video_track {
+        let sample_entry = mp4_child(trak, b"mdia")
+            .and_then(|mdia| mp4_child(mdia, b"minf"))
+            .and_then(|minf| mp4_child(minf, b"stbl"))
+            .and_then(|stbl| mp4_child(stbl, b"stsd"))
+            .and_then(|stsd| mp4_boxes(stsd.get(8..)?).next());
+
+        // Prefer the display size from tkhd, fall back to the coded size
+        let (mut width, mut height) = mp4_child(trak, b"tkhd")
+            .and_then(parse_tkhd)
+            .unwrap_or((0, 0));
+        if width == 0 || height == 0 {
+            if let Some((_, entry)) = sample_entry {
+                width = be_u16(entry, 24).map_or(0, u32::from);
+                height = be_u16(entry, 26).map_or(0, u32::from);
+            }
+        }
+
+        info.width = width;
+        info.height = height;
+        info.codec = sample_entry.map(|(fourcc, _)| mp4_codec_name(fourcc));
+    }
+
+    info
+}
+
+/// Duration in milliseconds from a `mvhd` body
+fn parse_mvhd(mvhd: &[u8]) -> Option<u64> {
+    let (timescale, duration) = match mvhd.first()? {
+        1 => (be_u32(mvhd, 20)?, be_u64(mvhd, 24)?),
+        _ => (be_u32(mvhd, 12)?, u64::from(be_u32(mvhd, 16)?)),
+    };
+
+    // All ones means "unknown"
+    if timescale == 0 || duration == u64::MAX || duration == u64::from(u32::MAX) {
+        return None;
+    }
+
+    Some((u128::from(duration) * 1000 / u128::from(timescale)) as u64)
+}
+
+/// Display width and height from a `tkhd` body (16.16 fixed point)
+fn parse_tkhd(tkhd: &[u8]) -> Option<(u32, u32)> {
+    let offset = match tkhd.first()? {
+        1 => 88,
+        _ => 76,
+    };
+    Some((be_u32(tkhd, offset)? >> 16, be_u32(tkhd, offset + 4)? >> 16))
+}
+
+/// Whether a `trak` has a video handler
+fn is_video_trak(trak: &[u8]) -> bool {
+    mp4_child(trak, b"mdia")
+        .and_then(|mdia| mp4_child(mdia, b"hdlr"))
+        .and_then(|hdlr| hdlr.get(8..12))
+        .is_some_and(|handler| handler == b"vide")
+}
+
+/// Normalize a sample entry fourcc to a codec name
+fn mp4_codec_name(fourcc: &[u8]) -> String {
+    match fourcc {
+        b"avc1" | b"avc3" => "h264".to_string(),
+        b"hvc1" | b"hev1" => "hevc".to_string(),
+        b"av01" => "av1".to_string(),
+        b"vp08" => "vp8".to_string(),
+        b"vp09" => "vp9".to_string(),
+        b"mp4v" => "mpeg4".to_string(),
+        b"apch" | b"apcn" | b"apcs" | b"apco" | b"ap4h" => "prores".to_string(),
+        other => String::from_utf8_lossy(other).trim().to_lowercase(),
+    }
+}
+
+// =============================================================================
+// WebM / Matroska (EBML)
+// =============================================================================
+
+/// Read an EBML variable-length integer: `(value, length)`
+///
+/// IDs keep their length marker bits; sizes have them stripped.
+fn read_vint(data: &[u8], keep_marker: bool) -> Option<(u64, usize)> {
+    let first = *data.first()?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 || data.len() < len {
+        return None;
+    }
+
+    let mut value = if keep_marker {
+        u64::from(first)
+    } else {
+        u64::from(first) & (0xFF >> len)
+    };
+    for byte in &data[1..len] {
+        value = (value << 8) | u64::from(*byte);
+    }
+    Some((value, len))
+}
+
+/// Parse an element header: `(id, size, header length)`
+///
+/// `size` is `None` for elements of unknown size (live streams).
+fn parse_ebml_header(data: &[u8]) -> Option<(u32, Option<u64>, usize)> {
+    let (id, id_len) = read_vint(data, true)?;
+    if id_len > 4 {
+        return None;
+    }
+    let (size, size_len) = read_vint(&data[id_len..], false)?;
+    let unknown = size == (1u64 << (7 * size_len)) - 1;
+
+    Some((id as u32, (!unknown).then_some(size), id_len + size_len))
+}
+
+/// Read an element header at the current reader position
+fn read_ebml_header<R: Read>(reader: &mut R) -> Result<(u32, Option<u64>, u64)> {
+    // Longest header: 4-byte ID + 8-byte size
+    let mut buf = [0u8; 12];
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = reader.read(&mut buf[filled..])?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+
+    let (id, size, len) =
+        parse_ebml_header(&buf[..filled]).ok_or_else(|| malformed("invalid EBML element"))?;
+    Ok((id, size, len as u64))
+}
+
+/// Iterate over the elements in an in-memory buffer as `(id, body)`
+fn ebml_elements(mut data: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+    std::iter::from_fn(move || {
+        let (id, size, header_len) = parse_ebml_header(data)?;
+        let end = header_len.checked_add(usize::try_from(size?).ok()?)?;
+        let body = data.get(header_len..end)?;
+        data = &data[end..];
+        Some((id, body))
+    })
+}
+
+fn ebml_uint(data: &[u8]) -> Option<u64> {
+    if data.is_empty() || data.len() > 8 {
+        return None;
+    }
+    Some(data.iter().fold(0, |acc, b| (acc << 8) | u64::from(*b)))
+}
+
+fn ebml_float(data: &[u8]) -> Option<f64> {
+    match data.len() {
+        4 => Some(f64::from(f32::from_be_bytes(data.try_into().ok()?))),
+        8 => Some(f64::from_be_bytes(data.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+fn ebml_string(data: &[u8]) -> String {
+    String::from_utf8_lossy(data)
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+/// Walk the EBML header and the top-level `Segment` children
+fn probe_webm<R: Read + Seek>(reader: &mut R, file_size: u64) -> Result<ContainerInfo> {
+    // EBML header with DocType
+    let (id, size, header_len) = read_ebml_header(reader)?;
+    let size = size.ok_or_else(|| malformed("EBML header of unknown size"))?;
+    if id != EBML_HEADER || size > MAX_EBML_HEADER_SIZE {
+        return Err(malformed("missing EBML header"));
+    }
+    reader.seek(SeekFrom::Start(header_len))?;
+    let doc_type = ebml_elements(&read_vec(reader, size)?)
+        .find(|(id, _)| *id == EBML_DOC_TYPE)
+        .map(|(_, body)| ebml_string(body));
+    if !matches!(doc_type.as_deref(), Some("webm") | Some("matroska")) {
+        return Err(malformed("unsupported EBML document type"));
+    }
+
+    // Segment
+    let segment_start = header_len + size;
+    reader.seek(SeekFrom::Start(segment_start))?;
+    let (id, size, header_len) = read_ebml_header(reader)?;
+    if id != MKV_SEGMENT {
+        return Err(malformed("missing Segment"));
+    }
+    let mut pos = segment_start + header_len;
+    let segment_end = size.map_or(file_size, |size| (pos + size).min(file_size));
+
+    let mut info = ContainerInfo::default();
+    let mut seen_info = false;
+    let mut seen_tracks = false;
+
+    while pos < segment_end && !(seen_info && seen_tracks) {
+        reader.seek(SeekFrom::Start(pos))?;
+        let (id, size, header_len) = read_ebml_header(reader)?;
+
+        // Clusters hold the media data; metadata precedes them in practice
+        let Some(size) = size.filter(|_| id != MKV_CLUSTER || !seen_tracks) else {
+            break;
+        };
+
+        match id {
+            MKV_INFO | MKV_TRACKS if size <= MAX_EBML_HEADER_SIZE => {
+                reader.seek(SeekFrom::Start(pos + header_len))?;
+                let body = read_vec(reader, size)?;
+                if id == MKV_INFO {
+                    info.duration_ms = parse_webm_info(&body);
+                    seen_info = true;
+                } else {
+                    parse_webm_tracks(&body, &mut info);
+                    seen_tracks = true;
+                }
+            }
+            _ => {}
+        }
+
+        pos += header_len + size;
+    }
+
+    if !seen_tracks {
+        return Err(malformed("no Tracks element"));
+    }
+
+    Ok(info)
+}
+
+/// Duration in milliseconds from a `Segment/Info` body
+fn parse_webm_info(data: &[u8]) -> Option<u64> {
+    let mut timecode_scale = 1_000_000u64;
+    let mut duration = None;
+
+    for (id, body) in ebml_elements(data) {
+        match id {
+            MKV_TIMECODE_SCALE => timecode_scale = ebml_uint(body).unwrap_or(timecode_scale),
+            MKV_DURATION => duration = ebml_float(body),
+            _ => {}
+        }
+    }
+
+    // Duration is in timecode units; the scale is nanoseconds per unit
+    duration
+        .filter(|d| d.is_finite() && *d >= 0.0)
+        .map(|d| (d * timecode_scale as f64 / 1_000_000.0).round() as u64)
+}
+
+/// Dimensions and codec of the first video `TrackEntry`
+fn parse_webm_tracks(data: &[u8], info: &mut ContainerInfo) {
+    for (_, entry) in ebml_elements(data).filter(|(id, _)| *id == MKV_TRACK_ENTRY) {
+        let mut is_video = false;
+        let mut codec = None;
+        let mut dimensions = (0, 0);
+
+        for (id, body) in ebml_elements(entry) {
+            match id {
+                MKV_TRACK_TYPE => is_video = ebml_uint(body) == Some(MKV_TRACK_TYPE_VIDEO),
+                MKV_CODEC_ID => codec = Some(webm_codec_name(&ebml_string(body))),
+                MKV_VIDEO => {
+                    for (id, body) in ebml_elements(body) {
+                        let value = ebml_uint(body).unwrap_or(0).min(u64::from(u32::MAX)) as u32;
+                        match id {
+                            MKV_PIXEL_WIDTH => dimensions.0 = value,
+                            MKV_PIXEL_HEIGHT => dimensions.1 = value,
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if is_video {
+            (info.width, info.height) = dimensions;
+            info.codec = codec;
+            return;
+        }
+    }
+}
+
+/// Normalize a Matroska `CodecID` to a codec name
+fn webm_codec_name(codec_id: &str) -> String {
+    match codec_id {
+        "V_VP8" => "vp8".to_string(),
+        "V_VP9" => "vp9".to_string(),
+        "V_AV1" => "av1".to_string(),
+        "V_MPEG4/ISO/AVC" => "h264".to_string(),
+        "V_MPEGH/ISO/HEVC" => "hevc".to_string(),
+        other => other.trim_start_matches("V_").to_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Build an ISO-BMFF box
+    fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(body);
+        data
+    }
+
+    /// Build an EBML element (IDs given with marker bits, 8-byte sizes)
+    fn ebml(id: u32, body: &[u8]) -> Vec<u8> {
+        let id_bytes = id.to_be_bytes();
+        let skip = id_bytes.iter().take_while(|b| **b == 0).count();
+        let mut data = id_bytes[skip..].to_vec();
+        data.push(0x01);
+        data.extend_from_slice(&(body.len() as u64).to_be_bytes()[1..]);
+        data.extend_from_slice(body);
+        data
+    }
+
+    /// Minimal MP4 with one H.264 video track, `mdat` before `moov`
+    fn sample_mp4(width: u16, height: u16, duration_ms: u32) -> Vec<u8> {
+        let mut mvhd = vec![0u8; 100];
+        mvhd[12..16].copy_from_slice(&1000u32.to_be_bytes());
+        mvhd[16..20].copy_from_slice(&duration_ms.to_be_bytes());
+
+        let mut tkhd = vec![0u8; 84];
+        tkhd[76..80].copy_from_slice(&(u32::from(width) << 16).to_be_bytes());
+        tkhd[80..84].copy_from_slice(&(u32::from(height) << 16).to_be_bytes());
+
+        let mut hdlr = vec![0u8; 24];
+        hdlr[8..12].copy_from_slice(b"vide");
+
+        let mut avc1 = vec![0u8; 78];
+        avc1[24..26].copy_from_slice(&width.to_be_bytes());
+        avc1[26..28].copy_from_slice(&height.to_be_bytes());
+        let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        stsd.extend(mp4_box(b"avc1", &avc1));
+
+        let stbl = mp4_box(b"stbl", &mp4_box(b"stsd", &stsd));
+        let minf = mp4_box(b"minf", &stbl);
+        let mut mdia = mp4_box(b"hdlr", &hdlr);
+        mdia.extend(minf);
+        let mut trak = mp4_box(b"tkhd", &tkhd);
+        trak.extend(mp4_box(b"mdia", &mdia));
+        let mut moov = mp4_box(b"mvhd", &mvhd);
+        moov.extend(mp4_box(b"trak", &trak));
+
+        let mut data = mp4_box(b"ftyp", b"isom\0\0\x02\0isomiso2avc1mp41");
+        data.extend(mp4_box(b"mdat", &[0u8; 64]));
+        data.extend(mp4_box(b"moov", &moov));
+        data
+    }
+
+    /// Minimal WebM with one VP9 video track
+    fn sample_webm(width: u16, height: u16, duration_ms: u32) -> Vec<u8> {
+        let header = ebml(EBML_DOC_TYPE, b"webm");
+
+        let mut info = ebml(MKV_TIMECODE_SCALE, &1_000_000u32.to_be_bytes());
+        info.extend(ebml(MKV_DURATION, &(duration_ms as f64).to_be_bytes()));
+
+        let mut video = ebml(MKV_PIXEL_WIDTH, &width.to_be_bytes());
+        video.extend(ebml(MKV_PIXEL_HEIGHT, &height.to_be_bytes()));
+        let mut entry = ebml(MKV_TRACK_TYPE, &[1]);
+        entry.extend(ebml(MKV_CODEC_ID, b"V_VP9"));
+        entry.extend(ebml(MKV_VIDEO, &video));
+
+        let mut segment = ebml(MKV_INFO, &info);
+        segment.extend(ebml(MKV_TRACKS, &ebml(MKV_TRACK_ENTRY, &entry)));
+        segment.extend(ebml(MKV_CLUSTER, &[0u8; 64]));
+
+        let mut data = ebml(EBML_HEADER, &header);
+        data.extend(ebml(MKV_SEGMENT, &segment));
+        data
+    }
+
+    fn upload_config() -> UploadConfig {
+        UploadConfig {
+            max_simple_upload_size: 1024 * 1024,
+            max_chunked_upload_size: 1024 * 1024,
+            chunk_size: 1024,
+            upload_session_timeout: 3600,
+            async_processing: false,
+            processing_workers: 2,
+            allowed_image_types: vec![],
+            allowed_video_types: vec!["video/mp4".to_string(), "video/webm".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_probe_mp4() {
+        let data = sample_mp4(1280, 720, 12_345);
+        let probed = VideoProcessor::default()
+            .probe(Cursor::new(&data), &upload_config())
+            .unwrap();
+
+        assert_eq!(probed.mime, "video/mp4");
+        assert_eq!(probed.size, data.len() as u64);
+        assert_eq!((probed.width, probed.height), (1280, 720));
+        assert_eq!(probed.duration_ms, Some(12_345));
+        assert_eq!(probed.codec.as_deref(), Some("h264"));
+    }
+
+    #[test]
+    fn test_probe_webm() {
+        let data = sample_webm(640, 360, 5_000);
+        let probed = VideoProcessor::default()
+            .probe(Cursor::new(&data), &upload_config())
+            .unwrap();
+
+        assert_eq!(probed.mime, "video/webm");
+        assert_eq!((probed.width, probed.height), (640, 360));
+        assert_eq!(probed.duration_ms, Some(5_000));
+        assert_eq!(probed.codec.as_deref(), Some("vp9"));
+    }
+
+    #[test]
+    fn test_probe_rejects_disallowed_and_malformed() {
+        let mut config = upload_config();
+        config.allowed_video_types = vec!["video/webm".to_string()];
+        let processor = VideoProcessor::default();
+
+        let mp4 = sample_mp4(16, 16, 1_000);
+        assert!(processor.probe(Cursor::new(&mp4), &config).is_err());
+
+        // Truncated inside the Tracks element
+        let webm = sample_webm(16, 16, 1_000);
+        assert!(processor
+            .probe(Cursor::new(&webm[..webm.len() - 120]), &config)
+            .is_err());
+    }
+}
@@ -14,8 +14,12 @@
 
 use crate::config::Config;
 use crate::error::Result;
-use crate::services::{DatabaseService, EvmService, ImageProcessor, StorageService};
-use std::sync::Arc;
+use crate::models::ReprocessStatus;
+use crate::services::{
+    AccessTracker, AssetRegistry, DatabaseService, EvmService, ImageProcessor, JobQueue, MediaCache,
+    ProcessingPool, StorageService, UrlSigner, VideoProcessor,
+};
+use std::sync::{Arc, Mutex};
 
 /// Shared application state
 ///
@@ -35,8 +39,32 @@ pub struct AppState {
     /// Image processor for format conversion
     pub image_processor: Arc<ImageProcessor>,
 
+    /// Blocking pool that image processing runs on
+    pub processing_pool: Arc<ProcessingPool>,
+
+    /// Chunked uploads waiting for background processing
+    pub processing_jobs: Arc<JobQueue>,
+
+    /// Video processor for container probing
+    pub video_processor: Arc<VideoProcessor>,
+
     /// EVM service for blockchain interactions (RexPump)
     pub evm: Arc<EvmService>,
+
+    /// Progress of the admin reprocess job
+    pub reprocess: Arc<Mutex<ReprocessStatus>>,
+
+    /// Built-in assets served at `/m/{name}`
+    pub assets: Arc<AssetRegistry>,
+
+    /// Signs and verifies URLs of private media
+    pub url_signer: Arc<UrlSigner>,
+
+    /// Popular media files kept in memory for `/m/{id}`
+    pub media_cache: Arc<MediaCache>,
+
+    /// Views counted in memory until the next flush
+    pub access_tracker: Arc<AccessTracker>,
 }
 
 impl AppState {
@@ -52,14 +80,27 @@ impl AppState {
         let db = DatabaseService::new(&config.storage)?;
         let storage = StorageService::new(&config.storage).await?;
         let image_processor = ImageProcessor::new(&config.processing);
+        let processing_pool = ProcessingPool::new(&config.processing);
+        let video_processor = VideoProcessor::new(&config.processing);
         let evm = EvmService::new(config.rexpump.networks.clone());
+        let assets = AssetRegistry::load(&config.assets)?;
+        let url_signer = UrlSigner::new(&config.auth);
+        let media_cache = MediaCache::new(&config.server);
 
         Ok(Self {
             config: Arc::new(config),
             db: Arc::new(db),
             storage: Arc::new(storage),
             image_processor: Arc::new(image_processor),
+            processing_pool: Arc::new(processing_pool),
+            processing_jobs: Arc::new(JobQueue::new()),
+            video_processor: Arc::new(video_processor),
             evm: Arc::new(evm),
+            reprocess: Arc::new(Mutex::new(ReprocessStatus::default())),
+            assets: Arc::new(assets),
+            url_signer: Arc::new(url_signer),
+            media_cache: Arc::new(media_cache),
+            access_tracker: Arc::new(AccessTracker::new()),
         })
     }
 
@@ -98,6 +139,11 @@ impl AppState {
         self.config.server.cleanup_interval_seconds
     }
 
+    /// Get the access flush interval in seconds
+    pub fn access_flush_interval(&self) -> u64 {
+        self.config.server.access_flush_interval_seconds
+    }
+
     /// Get output format extension (e.g., "webp")
     pub fn output_extension(&self) -> &'static str {
         self.config.processing.output_extension()
@@ -121,7 +167,15 @@ impl std::fmt::Debug for AppState {
             .field("db", &"<DatabaseService>")
             .field("storage", &"<StorageService>")
             .field("image_processor", &"<ImageProcessor>")
+            .field("processing_pool", &"<ProcessingPool>")
+            .field("processing_jobs", &"<JobQueue>")
+            .field("video_processor", &"<VideoProcessor>")
             .field("evm", &"<EvmService>")
+            .field("reprocess", &"<ReprocessStatus>")
+            .field("assets", &"<AssetRegistry>")
+            .field("url_signer", &"<UrlSigner>")
+            .field("media_cache", &"<MediaCache>")
+            .field("access_tracker", &"<AccessTracker>")
             .finish()
     }
 }
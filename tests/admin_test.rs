@@ -2,7 +2,9 @@
 
 mod common;
 
-use common::{create_test_png, TestServer};
+use common::{
+    count_dark_pixels, create_test_jpeg, create_test_png, create_test_png_with_subject, TestServer,
+};
 use reqwest::multipart;
 use serde_json::Value;
 
@@ -58,6 +60,125 @@ async fn test_admin_delete_media() {
     assert_eq!(serve_response.status(), 404);
 }
 
+#[tokio::test]
+async fn test_admin_delete_shared_media() {
+    let server = TestServer::start().await;
+    let client = server.client();
+    let image_data = create_test_png(80, 80);
+
+    // Upload identical content twice
+    let mut ids = Vec::new();
+    for _ in 0..2 {
+        let form = multipart::Form::new().part(
+            "file",
+            multipart::Part::bytes(image_data.clone())
+                .file_name("test.png")
+                .mime_str("image/png")
+                .unwrap(),
+        );
+        let response = client
+            .post(server.url("/api/upload"))
+            .multipart(form)
+            .send()
+            .await
+            .expect("Failed to upload");
+        let json: Value = response.json().await.unwrap();
+        ids.push(json["id"].as_str().unwrap().to_string());
+    }
+    assert_eq!(ids[0], ids[1]);
+    let id = &ids[0];
+
+    let info: Value = client
+        .get(server.admin(&format!("/admin/media/{}", id)))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(info["refs"], 2);
+
+    // First delete only drops a reference
+    let json: Value = client
+        .delete(server.admin(&format!("/admin/media/{}", id)))
+        .send()
+        .await
+        .expect("Failed to delete")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(json["deleted"], false);
+    assert_eq!(json["remaining_refs"], 1);
+
+    let serve_response = client
+        .get(server.url(&format!("/m/{}", id)))
+        .send()
+        .await
+        .expect("Failed to fetch");
+    assert_eq!(serve_response.status(), 200);
+
+    // Second delete removes the media
+    let json: Value = client
+        .delete(server.admin(&format!("/admin/media/{}", id)))
+        .send()
+        .await
+        .expect("Failed to delete")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(json["deleted"], true);
+
+    let serve_response = client
+        .get(server.url(&format!("/m/{}", id)))
+        .send()
+        .await
+        .expect("Failed to fetch");
+    assert_eq!(serve_response.status(), 404);
+}
+
+#[tokio::test]
+async fn test_admin_force_delete_shared_media() {
+    let server = TestServer::start().await;
+    let client = server.client();
+    let image_data = create_test_png(90, 90);
+
+    let mut id = String::new();
+    for _ in 0..2 {
+        let form = multipart::Form::new().part(
+            "file",
+            multipart::Part::bytes(image_data.clone())
+                .file_name("test.png")
+                .mime_str("image/png")
+                .unwrap(),
+        );
+        let response = client
+            .post(server.url("/api/upload"))
+            .multipart(form)
+            .send()
+            .await
+            .expect("Failed to upload");
+        let json: Value = response.json().await.unwrap();
+        id = json["id"].as_str().unwrap().to_string();
+    }
+
+    let json: Value = client
+        .delete(server.admin(&format!("/admin/media/{}?force=true", id)))
+        .send()
+        .await
+        .expect("Failed to delete")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(json["deleted"], true);
+
+    let serve_response = client
+        .get(server.url(&format!("/m/{}", id)))
+        .send()
+        .await
+        .expect("Failed to fetch");
+    assert_eq!(serve_response.status(), 404);
+}
+
 #[tokio::test]
 async fn test_admin_delete_nonexistent() {
     let server = TestServer::start().await;
@@ -110,6 +231,12 @@ async fn test_admin_stats() {
     assert_eq!(stats["media_count"], 3);
     assert!(stats["storage"]["originals_size"].is_number());
     assert!(stats["storage"]["optimized_size"].is_number());
+
+    // One processing job per upload, all finished
+    assert_eq!(stats["processing"]["completed"], 3);
+    assert_eq!(stats["processing"]["active"], 0);
+    assert_eq!(stats["processing"]["queued"], 0);
+    assert_eq!(stats["processing"]["workers"], 2);
 }
 
 #[tokio::test]
@@ -153,7 +280,467 @@ async fn test_admin_get_media_info() {
     assert_eq!(info["optimized_mime_type"], "image/webp");
     assert_eq!(info["width"], 100);
     assert_eq!(info["height"], 100);
+    assert_eq!(info["quality"], 80);
     assert!(info["content_hash"].is_string());
     assert!(info["created_at"].is_string());
 }
 
+#[tokio::test]
+async fn test_admin_media_views() {
+    let server = TestServer::start().await;
+    let client = server.client();
+
+    let form = multipart::Form::new().part(
+        "file",
+        multipart::Part::bytes(create_test_png(100, 100))
+            .file_name("test.png")
+            .mime_str("image/png")
+            .unwrap(),
+    );
+    let json: Value = client
+        .post(server.url("/api/upload"))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to upload")
+        .json()
+        .await
+        .unwrap();
+    let id = json["id"].as_str().unwrap();
+
+    let info = |client: reqwest::Client| {
+        let url = server.admin(&format!("/admin/media/{}", id));
+        async move {
+            let info: Value = client.get(url).send().await.unwrap().json().await.unwrap();
+            info
+        }
+    };
+
+    let before = info(client.clone()).await;
+    assert_eq!(before["views"], 0);
+    assert!(before["last_accessed_at"].is_null());
+
+    // Optimized (from disk, then from memory), a variant and the original
+    for path in ["", "", "?w=20", "/original"] {
+        let response = client
+            .get(server.url(&format!("/m/{}{}", id, path)))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+    }
+
+    // Counted in memory, visible before the periodic flush
+    let after = info(client.clone()).await;
+    assert_eq!(after["views"], 4);
+    assert!(after["last_accessed_at"].is_string());
+}
+
+#[tokio::test]
+async fn test_admin_media_info_lossless() {
+    let server = TestServer::start_with_config(|config| config.processing.webp_lossless = true).await;
+    let id = upload_png(&server, 40, 40).await;
+
+    let info: Value = server
+        .client()
+        .get(server.admin(&format!("/admin/media/{}", id)))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(info["optimized_mime_type"], "image/webp");
+    assert!(info.get("quality").is_none());
+
+    // Lossless WebP decodes to the uploaded pixels
+    let response = server
+        .client()
+        .get(server.url(&format!("/m/{}", id)))
+        .send()
+        .await
+        .unwrap();
+    let served = image::load_from_memory(&response.bytes().await.unwrap()).unwrap();
+    let uploaded = image::load_from_memory(&create_test_png(40, 40)).unwrap();
+    assert_eq!(served.to_rgb8(), uploaded.to_rgb8());
+}
+
+
+/// Upload a PNG and return its media ID
+async fn upload_png(server: &TestServer, width: u32, height: u32) -> String {
+    let form = multipart::Form::new().part(
+        "file",
+        multipart::Part::bytes(create_test_png(width, height))
+            .file_name("test.png")
+            .mime_str("image/png")
+            .unwrap(),
+    );
+
+    let response = server
+        .client()
+        .post(server.url("/api/upload"))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to upload");
+
+    assert!(response.status().is_success());
+    let json: Value = response.json().await.unwrap();
+    json["id"].as_str().unwrap().to_string()
+}
+
+/// Count files below the optimized directory
+fn count_optimized_files(server: &TestServer) -> usize {
+    fn walk(dir: &std::path::Path) -> usize {
+        std::fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .map(|entry| {
+                        let path = entry.path();
+                        if path.is_dir() {
+                            walk(&path)
+                        } else {
+                            1
+                        }
+                    })
+                    .sum()
+            })
+            .unwrap_or(0)
+    }
+
+    walk(&server.data_dir.path().join("optimized"))
+}
+
+#[tokio::test]
+async fn test_admin_delete_after_format_change() {
+    let server = TestServer::start().await;
+    let id = upload_png(&server, 60, 60).await;
+    assert_eq!(count_optimized_files(&server), 1);
+
+    let server = server
+        .restart_with_config(|config| config.processing.output_format = "jpeg".to_string())
+        .await;
+    let client = server.client();
+
+    // Stored media keeps its recorded format
+    let response = client
+        .get(server.url(&format!("/m/{}", id)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "image/webp");
+
+    // Deleting removes the file stored in the old format
+    let response = client
+        .delete(server.admin(&format!("/admin/media/{}", id)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(count_optimized_files(&server), 0);
+}
+
+#[tokio::test]
+async fn test_admin_reprocess_library() {
+    let server = TestServer::start().await;
+    let id = upload_png(&server, 60, 60).await;
+    let old_etag = server
+        .client()
+        .get(server.url(&format!("/m/{}", id)))
+        .send()
+        .await
+        .unwrap()
+        .headers()["etag"]
+        .clone();
+
+    let server = server
+        .restart_with_config(|config| config.processing.output_format = "jpeg".to_string())
+        .await;
+    let client = server.client();
+
+    let stats: Value = client
+        .get(server.admin("/admin/stats"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(stats["formats"]["image/webp"], 1);
+
+    // Start the job and wait for it to finish
+    let response = client
+        .post(server.admin("/admin/reprocess"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 202);
+    let json: Value = response.json().await.unwrap();
+    assert_eq!(json["target_mime_type"], "image/jpeg");
+
+    let mut status = Value::Null;
+    for _ in 0..100 {
+        status = client
+            .get(server.admin("/admin/reprocess"))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        if status["state"] != "running" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    assert_eq!(status["state"], "completed");
+    assert_eq!(status["total"], 1);
+    assert_eq!(status["processed"], 1);
+    assert_eq!(status["converted"], 1);
+
+    // Served in the new format, old file removed
+    let response = client
+        .get(server.url(&format!("/m/{}", id)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "image/jpeg");
+    // Clients holding the old file must not get a 304 for the new one
+    assert_ne!(response.headers()["etag"], old_etag);
+    let img = image::load_from_memory(&response.bytes().await.unwrap()).unwrap();
+    assert_eq!((img.width(), img.height()), (60, 60));
+    assert_eq!(count_optimized_files(&server), 1);
+
+    let stats: Value = client
+        .get(server.admin("/admin/stats"))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(stats["formats"]["image/jpeg"], 1);
+    assert!(stats["formats"].get("image/webp").is_none());
+}
+
+#[tokio::test]
+async fn test_admin_find_similar_media() {
+    let server = TestServer::start().await;
+    let client = server.client();
+
+    // The same picture as a PNG and as a smaller, low quality JPEG
+    let png_id = upload_png(&server, 200, 200).await;
+    let form = multipart::Form::new().part(
+        "file",
+        multipart::Part::bytes(create_test_jpeg(120, 120, 40))
+            .file_name("copy.jpg")
+            .mime_str("image/jpeg")
+            .unwrap(),
+    );
+    let json: Value = client
+        .post(server.url("/api/upload"))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let jpeg_id = json["id"].as_str().unwrap();
+    assert_ne!(jpeg_id, png_id);
+
+    let response = client
+        .get(server.admin(&format!("/admin/media/{}/similar", png_id)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let similar: Value = response.json().await.unwrap();
+    assert_eq!(similar["id"], png_id.as_str());
+    assert_eq!(similar["max_distance"], 10);
+    assert_eq!(similar["perceptual_hash"].as_str().unwrap().len(), 16);
+
+    // The media itself is not listed
+    let matches = similar["matches"].as_array().unwrap();
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0]["id"], jpeg_id);
+    assert!(matches[0]["distance"].as_u64().unwrap() <= 4);
+    assert!(matches[0]["url"].as_str().unwrap().ends_with(jpeg_id));
+
+    // Hashes are also part of the media info
+    let info: Value = client
+        .get(server.admin(&format!("/admin/media/{}", jpeg_id)))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(info["perceptual_hash"].is_string());
+
+    let response = client
+        .get(server.admin(&format!("/admin/media/{}/similar?max_distance=65", png_id)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+
+    let response = client
+        .get(server.admin(&format!("/admin/media/{}/similar", uuid::Uuid::new_v4())))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn test_admin_set_focal_point() {
+    let server = TestServer::start().await;
+    let client = server.client();
+
+    // Subject on the left; the detected focal point follows it
+    let form = multipart::Form::new().part(
+        "file",
+        multipart::Part::bytes(create_test_png_with_subject(400, 200, 20, 70, 60))
+            .file_name("logo.png")
+            .mime_str("image/png")
+            .unwrap(),
+    );
+    let json: Value = client
+        .post(server.url("/api/upload"))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id = json["id"].as_str().unwrap();
+    assert!(json["focal_point"]["x"].as_f64().unwrap() < 0.3);
+
+    let variant_url = server.url(&format!("/m/{}?w=50&h=50&fit=cover&focus=auto&fmt=png", id));
+    let before = client.get(&variant_url).send().await.unwrap();
+    let before_etag = before.headers()["etag"].clone();
+    assert!(count_dark_pixels(&before.bytes().await.unwrap()) > 200);
+
+    // Point at the empty right side instead
+    let response = client
+        .put(server.admin(&format!("/admin/media/{}/focal-point", id)))
+        .json(&serde_json::json!({"x": 1.0, "y": 0.5}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let info: Value = response.json().await.unwrap();
+    assert_eq!(info["focal_point"]["x"], 1.0);
+    assert_eq!(info["focal_point"]["y"], 0.5);
+    assert!(info["updated_at"].as_str().unwrap() > info["created_at"].as_str().unwrap());
+
+    // The cached variant was dropped and follows the new point, under a new ETag
+    let after = client.get(&variant_url).send().await.unwrap();
+    assert_ne!(after.headers()["etag"], before_etag);
+    assert_eq!(count_dark_pixels(&after.bytes().await.unwrap()), 0);
+
+    let response = client
+        .put(server.admin(&format!("/admin/media/{}/focal-point", id)))
+        .json(&serde_json::json!({"x": 1.5, "y": 0.5}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+
+    let response = client
+        .put(server.admin(&format!("/admin/media/{}/focal-point", uuid::Uuid::new_v4())))
+        .json(&serde_json::json!({"x": 0.5, "y": 0.5}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn test_admin_signed_url() {
+    let server = TestServer::start().await;
+    let client = server.client();
+
+    let form = multipart::Form::new()
+        .text("visibility", "private")
+        .part(
+            "file",
+            multipart::Part::bytes(create_test_png(30, 30))
+                .file_name("private.png")
+                .mime_str("image/png")
+                .unwrap(),
+        );
+    let json: Value = client
+        .post(server.url("/api/upload"))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id = json["id"].as_str().unwrap();
+
+    let info: Value = client
+        .get(server.admin(&format!("/admin/media/{}", id)))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(info["visibility"], "private");
+
+    let response = client
+        .post(server.admin(&format!("/admin/media/{}/signed-url", id)))
+        .json(&serde_json::json!({"ttl_seconds": 120}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let signed: Value = response.json().await.unwrap();
+    let expires_at = chrono::DateTime::parse_from_rfc3339(signed["expires_at"].as_str().unwrap())
+        .unwrap()
+        .timestamp();
+    assert!((expires_at - chrono::Utc::now().timestamp() - 120).abs() <= 2);
+
+    for url in [&signed["url"], &signed["original_url"]] {
+        let response = client.get(url.as_str().unwrap()).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["cache-control"], "private, max-age=120");
+    }
+
+    // TTL limited to 1..=max_signed_url_ttl_seconds
+    for ttl in [0, 7 * 24 * 3600 + 1] {
+        let response = client
+            .post(server.admin(&format!("/admin/media/{}/signed-url", id)))
+            .json(&serde_json::json!({"ttl_seconds": ttl}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    // Default TTL
+    let response = client
+        .post(server.admin(&format!("/admin/media/{}/signed-url", id)))
+        .json(&serde_json::json!({}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let response = client
+        .post(server.admin(&format!("/admin/media/{}/signed-url", uuid::Uuid::new_v4())))
+        .json(&serde_json::json!({}))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+}
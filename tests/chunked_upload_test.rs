@@ -2,8 +2,64 @@
 
 mod common;
 
-use common::{create_test_png, TestServer};
+use common::{create_test_mp4, create_test_png, TestServer};
+use media_upload_server::services::DatabaseService;
 use serde_json::Value;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Init a session and upload `data` as a single chunk, returning the session ID
+async fn upload_all_chunks(server: &TestServer, data: Vec<u8>, mime_type: &str) -> String {
+    let client = server.client();
+    let total_size = data.len();
+
+    let init_json: Value = client
+        .post(server.url("/api/upload/init"))
+        .header("Content-Type", "application/json")
+        .body(format!(
+            r#"{{"filename":"upload.bin","mime_type":"{}","total_size":{}}}"#,
+            mime_type, total_size
+        ))
+        .send()
+        .await
+        .expect("Failed to init")
+        .json()
+        .await
+        .unwrap();
+    let session_id = init_json["id"].as_str().unwrap().to_string();
+
+    let chunk_response = client
+        .patch(server.url(&format!("/api/upload/{}/chunk", session_id)))
+        .header("Content-Range", format!("bytes 0-{}/{}", total_size - 1, total_size))
+        .body(data)
+        .send()
+        .await
+        .expect("Failed to upload chunk");
+    assert!(chunk_response.status().is_success());
+
+    session_id
+}
+
+/// Poll the session status until it leaves `processing`
+async fn wait_for_processing(server: &TestServer, session_id: &str) -> Value {
+    let client = server.client();
+
+    for _ in 0..200 {
+        let status: Value = client
+            .get(server.url(&format!("/api/upload/{}/status", session_id)))
+            .send()
+            .await
+            .expect("Failed to get status")
+            .json()
+            .await
+            .unwrap();
+        if status["status"] != "processing" {
+            return status;
+        }
+        tokio::time::sleep(Duration::from_millis(25)).await;
+    }
+    panic!("session {} never finished processing", session_id);
+}
 
 #[tokio::test]
 async fn test_chunked_upload_init() {
@@ -131,6 +187,26 @@ async fn test_chunked_upload_multiple_chunks() {
         .expect("Failed to complete");
 
     assert!(complete_response.status().is_success());
+    let complete_json: Value = complete_response.json().await.unwrap();
+
+    // Hash computed across chunks matches a one-shot upload of the same bytes
+    let form = reqwest::multipart::Form::new().part(
+        "file",
+        reqwest::multipart::Part::bytes(image_data)
+            .file_name("test.png")
+            .mime_str("image/png")
+            .unwrap(),
+    );
+    let simple_json: Value = client
+        .post(server.url("/api/upload"))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to upload")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(simple_json["id"], complete_json["id"]);
 }
 
 #[tokio::test]
@@ -212,3 +288,221 @@ async fn test_chunked_upload_invalid_content_range() {
     assert_eq!(chunk_response.status(), 400);
 }
 
+
+#[tokio::test]
+async fn test_chunked_upload_video() {
+    let server = TestServer::start_with_config(|config| {
+        config.upload.allowed_video_types = vec!["video/mp4".to_string()];
+    })
+    .await;
+    let client = server.client();
+
+    let video_data = create_test_mp4(1920, 1080, 4_500);
+    let total_size = video_data.len();
+
+    let init_json: Value = client
+        .post(server.url("/api/upload/init"))
+        .header("Content-Type", "application/json")
+        .body(format!(
+            r#"{{"filename":"clip.mp4","mime_type":"video/mp4","total_size":{}}}"#,
+            total_size
+        ))
+        .send()
+        .await
+        .expect("Failed to init")
+        .json()
+        .await
+        .unwrap();
+    let session_id = init_json["id"].as_str().unwrap();
+
+    let chunk_response = client
+        .patch(server.url(&format!("/api/upload/{}/chunk", session_id)))
+        .header("Content-Range", format!("bytes 0-{}/{}", total_size - 1, total_size))
+        .body(video_data.clone())
+        .send()
+        .await
+        .expect("Failed to upload chunk");
+    assert!(chunk_response.status().is_success());
+
+    let complete_response = client
+        .post(server.url(&format!("/api/upload/{}/complete", session_id)))
+        .send()
+        .await
+        .expect("Failed to complete");
+    assert!(complete_response.status().is_success());
+
+    let json: Value = complete_response.json().await.unwrap();
+    assert_eq!(json["media_type"], "video");
+    assert_eq!(json["mime_type"], "video/mp4");
+    assert_eq!(json["width"], 1920);
+    assert_eq!(json["height"], 1080);
+    assert_eq!(json["duration_ms"], 4_500);
+    assert_eq!(json["video_codec"], "h264");
+    assert!(json.get("original_url").is_none());
+    assert!(json.get("variants").is_none());
+    assert!(json.get("blurhash").is_none());
+
+    // Served untouched with the container's content type
+    let response = client
+        .get(json["url"].as_str().unwrap())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "video/mp4");
+    assert_eq!(response.bytes().await.unwrap().as_ref(), video_data.as_slice());
+
+    // Variants are image-only
+    let response = client
+        .get(format!("{}?w=100", json["url"].as_str().unwrap()))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_chunked_upload_video_not_allowed() {
+    let server = TestServer::start().await;
+    let client = server.client();
+
+    let response = client
+        .post(server.url("/api/upload/init"))
+        .header("Content-Type", "application/json")
+        .body(r#"{"filename":"clip.mp4","mime_type":"video/mp4","total_size":1000}"#)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status(), 415);
+}
+
+#[tokio::test]
+async fn test_chunked_upload_async_processing() {
+    let server = TestServer::start_with_config(|config| config.upload.async_processing = true).await;
+    let client = server.client();
+
+    let session_id = upload_all_chunks(&server, create_test_png(100, 100), "image/png").await;
+
+    let complete_response = client
+        .post(server.url(&format!("/api/upload/{}/complete", session_id)))
+        .send()
+        .await
+        .expect("Failed to complete");
+    assert_eq!(complete_response.status(), 202);
+    let json: Value = complete_response.json().await.unwrap();
+    assert_eq!(json["status"], "processing");
+    assert!(json.get("media_id").is_none());
+
+    let status = wait_for_processing(&server, &session_id).await;
+    assert_eq!(status["status"], "completed");
+    let media_url = status["media_url"].as_str().unwrap();
+    assert!(media_url.ends_with(status["media_id"].as_str().unwrap()));
+
+    let response = client.get(media_url).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "image/webp");
+}
+
+#[tokio::test]
+async fn test_chunked_upload_async_failure() {
+    let server = TestServer::start_with_config(|config| config.upload.async_processing = true).await;
+    let client = server.client();
+
+    let session_id = upload_all_chunks(&server, vec![0u8; 2048], "image/png").await;
+
+    let complete_response = client
+        .post(server.url(&format!("/api/upload/{}/complete", session_id)))
+        .send()
+        .await
+        .expect("Failed to complete");
+    assert_eq!(complete_response.status(), 202);
+
+    let status = wait_for_processing(&server, &session_id).await;
+    assert_eq!(status["status"], "failed");
+    assert!(status["error"].is_string());
+    assert!(status.get("media_id").is_none());
+}
+
+#[tokio::test]
+async fn test_chunked_upload_async_resumes_after_restart() {
+    let server = TestServer::start_with_config(|config| config.upload.async_processing = true).await;
+    let session_id = upload_all_chunks(&server, create_test_png(64, 64), "image/png").await;
+    let id = Uuid::parse_str(&session_id).unwrap();
+
+    // Simulate a crash right after `complete` queued the upload
+    let server = server
+        .restart_with_config(|config| {
+            config.upload.async_processing = true;
+
+            let db = DatabaseService::new(&config.storage).unwrap();
+            let mut session = db.get_session(id).unwrap().unwrap();
+            session.mark_processing();
+            db.enqueue_processing_job(&session).unwrap();
+        })
+        .await;
+
+    let status = wait_for_processing(&server, &session_id).await;
+    assert_eq!(status["status"], "completed");
+    assert!(status["media_id"].is_string());
+}
+
+#[tokio::test]
+async fn test_chunked_upload_private() {
+    let server = TestServer::start().await;
+    let client = server.client();
+    let data = create_test_png(48, 48);
+    let total_size = data.len();
+
+    let init_json: Value = client
+        .post(server.url("/api/upload/init"))
+        .header("Content-Type", "application/json")
+        .body(format!(
+            r#"{{"filename":"private.png","mime_type":"image/png","total_size":{},"visibility":"private"}}"#,
+            total_size
+        ))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let session_id = init_json["id"].as_str().unwrap();
+
+    client
+        .patch(server.url(&format!("/api/upload/{}/chunk", session_id)))
+        .header("Content-Range", format!("bytes 0-{}/{}", total_size - 1, total_size))
+        .body(data)
+        .send()
+        .await
+        .unwrap();
+
+    let response = client
+        .post(server.url(&format!("/api/upload/{}/complete", session_id)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let media: Value = response.json().await.unwrap();
+    assert_eq!(media["visibility"], "private");
+
+    let response = client
+        .get(server.url(&format!("/m/{}", media["id"].as_str().unwrap())))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 403);
+
+    // The status endpoint hands out a signed URL as well
+    let status: Value = client
+        .get(server.url(&format!("/api/upload/{}/status", session_id)))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let media_url = status["media_url"].as_str().unwrap();
+    assert!(media_url.contains("&sig="), "{}", media_url);
+    assert_eq!(client.get(media_url).send().await.unwrap().status(), 200);
+}
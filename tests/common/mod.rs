@@ -1,17 +1,22 @@
 //! Common test utilities and helpers.
 
+#![allow(dead_code)]
+
 use media_upload_server::{
     config::{
         Config, LoggingConfig, ProcessingConfig, RateLimitConfig, RexPumpConfig, ServerConfig,
         StorageConfig, UploadConfig, AuthConfig,
     },
-    create_admin_router, create_public_router, AppState,
+    create_admin_router, create_public_router, start_processing_workers, AppState,
 };
 use std::net::TcpListener;
 use std::time::Duration;
 use tempfile::TempDir;
 use tokio::net::TcpListener as TokioTcpListener;
 
+/// Key signing private media URLs in the test config
+pub const TEST_URL_SIGNING_KEY: &str = "test-url-signing-key-0123456789abcdef";
+
 /// Test server instance
 pub struct TestServer {
     pub public_url: String,
@@ -28,28 +33,53 @@ impl TestServer {
 
     /// Start a test server with authentication enabled
     pub async fn start_with_auth(auth_enabled: bool, api_keys: Vec<String>) -> Self {
+        Self::start_with_config(move |config| {
+            config.auth.enabled = auth_enabled;
+            config.auth.api_keys = api_keys;
+        })
+        .await
+    }
+
+    /// Start a test server, adjusting the default test config first
+    pub async fn start_with_config(configure: impl FnOnce(&mut Config)) -> Self {
+        let data_dir = TempDir::new().expect("Failed to create temp dir");
+        Self::start_in(data_dir, configure).await
+    }
+
+    /// Stop this server and start a new one on the same data directory
+    ///
+    /// Used to simulate config changes between deployments.
+    pub async fn restart_with_config(mut self, configure: impl FnOnce(&mut Config)) -> Self {
+        if let Some(tx) = self.shutdown_tx.take() {
+            let _ = tx.send(());
+        }
+        // Let the old server drop its state (and database handle)
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let data_dir = std::mem::replace(
+            &mut self.data_dir,
+            TempDir::new().expect("Failed to create temp dir"),
+        );
+        Self::start_in(data_dir, configure).await
+    }
+
+    async fn start_in(data_dir: TempDir, configure: impl FnOnce(&mut Config)) -> Self {
         let public_port = get_available_port();
         let admin_port = get_available_port();
-        let data_dir = TempDir::new().expect("Failed to create temp dir");
 
         let public_url = format!("http://127.0.0.1:{}", public_port);
         let admin_url = format!("http://127.0.0.1:{}", admin_port);
 
-        let config = create_test_config(
-            &data_dir,
-            public_port,
-            admin_port,
-            &public_url,
-            auth_enabled,
-            api_keys,
-        );
+        let mut config = create_test_config(&data_dir, public_port, admin_port, &public_url);
+        configure(&mut config);
 
         let state = AppState::new(config)
             .await
             .expect("Failed to create app state");
 
         let public_app = create_public_router(state.clone());
-        let admin_app = create_admin_router(state);
+        let admin_app = create_admin_router(state.clone());
+        let workers = start_processing_workers(&state);
 
         let public_addr: std::net::SocketAddr = format!("127.0.0.1:{}", public_port)
             .parse()
@@ -74,6 +104,10 @@ impl TestServer {
                 _ = axum::serve(admin_listener, admin_app) => {}
                 _ = shutdown_rx => {}
             }
+            // Workers hold the state (and database handle) until stopped
+            for worker in workers {
+                worker.abort();
+            }
         });
 
         // Give servers time to start
@@ -120,8 +154,6 @@ fn create_test_config(
     public_port: u16,
     admin_port: u16,
     base_url: &str,
-    auth_enabled: bool,
-    api_keys: Vec<String>,
 ) -> Config {
     Config {
         server: ServerConfig {
@@ -134,12 +166,15 @@ fn create_test_config(
             max_connections: 100,
             cache_max_age: 3600,
             cleanup_interval_seconds: 60,
+            media_cache_mb: 16,
+            access_flush_interval_seconds: 30,
         },
         storage: StorageConfig {
             data_dir: data_dir.path().to_path_buf(),
             originals_dir: "originals".to_string(),
             optimized_dir: "optimized".to_string(),
             temp_dir: "temp".to_string(),
+            variants_dir: "variants".to_string(),
             directory_levels: 2,
             database_file: String::new(),
         },
@@ -152,16 +187,34 @@ fn create_test_config(
                 "image/png".to_string(),
                 "image/gif".to_string(),
                 "image/webp".to_string(),
+                "image/bmp".to_string(),
+                "image/tiff".to_string(),
+                "image/vnd.microsoft.icon".to_string(),
+                "image/svg+xml".to_string(),
+                "image/avif".to_string(),
             ],
             allowed_video_types: vec![],
             upload_session_timeout: 300,
+            async_processing: false,
+            processing_workers: 2,
         },
         processing: ProcessingConfig {
             output_format: "webp".to_string(),
+            output_formats: Vec::new(),
             output_quality: 80,
+            webp_lossless: false,
             max_image_dimension: 2048,
+            max_decode_width: 16384,
+            max_decode_height: 16384,
+            max_decode_alloc: 512 * 1024 * 1024,
             keep_originals: true,
             strip_exif: true,
+            metadata_policy: None,
+            preserve_animation: true,
+            variants: Default::default(),
+            max_concurrent_decodes: 2,
+            max_queued_decodes: 16,
+            ffmpeg_path: None,
         },
         rate_limit: RateLimitConfig {
             enabled: false,
@@ -175,12 +228,15 @@ fn create_test_config(
             file: String::new(),
         },
         auth: AuthConfig {
-            enabled: auth_enabled,
-            api_keys,
+            enabled: false,
+            api_keys: vec![],
             protected_paths: vec!["/api/upload".to_string()],
             public_paths: vec!["/health".to_string(), "/m/".to_string()],
+            url_signing_keys: vec![TEST_URL_SIGNING_KEY.to_string()],
+            ..Default::default()
         },
         rexpump: RexPumpConfig::default(),
+        assets: Default::default(),
     }
 }
 
@@ -215,6 +271,58 @@ pub fn create_test_png(width: u32, height: u32) -> Vec<u8> {
     buffer
 }
 
+/// Create a test image in any format the `image` crate can encode (BMP,
+/// TIFF, ICO, ...), with the same gradient as [`create_test_png`]
+pub fn create_test_image(width: u32, height: u32, format: image::ImageFormat) -> Vec<u8> {
+    use image::{ImageBuffer, Rgba};
+
+    let img: ImageBuffer<Rgba<u8>, Vec<u8>> = ImageBuffer::from_fn(width, height, |x, y| {
+        Rgba([
+            ((x * 255) / width) as u8,
+            ((y * 255) / height) as u8,
+            128,
+            255,
+        ])
+    });
+
+    let mut buffer = Vec::new();
+    img.write_to(&mut std::io::Cursor::new(&mut buffer), format)
+        .expect("Failed to encode image");
+
+    buffer
+}
+
+/// Create a test PNG: white canvas with a dark, patterned square subject
+pub fn create_test_png_with_subject(width: u32, height: u32, left: u32, top: u32, size: u32) -> Vec<u8> {
+    use image::{ImageBuffer, ImageFormat, Rgb};
+
+    let img: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_fn(width, height, |x, y| {
+        if x >= left && x < left + size && y >= top && y < top + size {
+            let v = if (x / 4 + y / 4) % 2 == 0 { 10 } else { 70 };
+            Rgb([v, v, 140])
+        } else {
+            Rgb([255, 255, 255])
+        }
+    });
+
+    let mut buffer = Vec::new();
+    img.write_to(&mut std::io::Cursor::new(&mut buffer), ImageFormat::Png)
+        .expect("Failed to encode PNG");
+
+    buffer
+}
+
+/// Count the dark pixels of an encoded image (the subject of
+/// [`create_test_png_with_subject`])
+pub fn count_dark_pixels(data: &[u8]) -> usize {
+    image::load_from_memory(data)
+        .expect("Failed to decode image")
+        .to_rgb8()
+        .pixels()
+        .filter(|p| p[0] < 128)
+        .count()
+}
+
 /// Create a test JPEG image
 pub fn create_test_jpeg(width: u32, height: u32, quality: u8) -> Vec<u8> {
     use image::codecs::jpeg::JpegEncoder;
@@ -236,3 +344,136 @@ pub fn create_test_jpeg(width: u32, height: u32, quality: u8) -> Vec<u8> {
 
     buffer
 }
+
+/// Create a test JPEG carrying an EXIF block
+///
+/// Pixels are `width` × `height` as stored; with an Orientation of 6 the
+/// upright image is `height` × `width`.
+pub fn create_test_jpeg_with_exif(width: u32, height: u32, fields: &[exif::Field]) -> Vec<u8> {
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{DynamicImage, ImageEncoder};
+
+    let mut writer = exif::experimental::Writer::new();
+    for field in fields {
+        writer.push_field(field);
+    }
+    let mut exif_data = std::io::Cursor::new(Vec::new());
+    writer.write(&mut exif_data, false).expect("Failed to encode EXIF");
+
+    let img = DynamicImage::new_rgb8(width, height);
+    let mut buffer = Vec::new();
+    let mut encoder = JpegEncoder::new_with_quality(&mut buffer, 90);
+    encoder
+        .set_exif_metadata(exif_data.into_inner())
+        .expect("EXIF not supported");
+    encoder
+        .write_image(img.as_bytes(), width, height, image::ExtendedColorType::Rgb8)
+        .expect("Failed to encode JPEG");
+
+    buffer
+}
+
+/// Create a looping animated GIF with one solid-colored frame per delay
+pub fn create_test_gif(width: u32, height: u32, delays_ms: &[u32]) -> Vec<u8> {
+    use image::codecs::gif::{GifEncoder, Repeat};
+    use image::{Delay, Frame, Rgba, RgbaImage};
+
+    let mut buffer = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut buffer);
+        encoder.set_repeat(Repeat::Infinite).unwrap();
+        for (i, &delay) in delays_ms.iter().enumerate() {
+            let shade = (i * 80 % 256) as u8;
+            let frame = RgbaImage::from_pixel(width, height, Rgba([shade, 255 - shade, 64, 255]));
+            encoder
+                .encode_frame(Frame::from_parts(
+                    frame,
+                    0,
+                    0,
+                    Delay::from_numer_denom_ms(delay, 1),
+                ))
+                .expect("Failed to encode GIF frame");
+        }
+    }
+
+    buffer
+}
+
+/// Create a PNG that declares `width` × `height` but holds almost no data
+///
+/// A decompression bomb: the header alone would make a decoder allocate
+/// `width × height × 3` bytes.
+pub fn create_png_bomb(width: u32, height: u32) -> Vec<u8> {
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in data {
+            crc ^= u32::from(byte);
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+            }
+        }
+        !crc
+    }
+
+    fn chunk(png: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend_from_slice(kind);
+        png.extend_from_slice(data);
+        let crc = crc32(&png[start..]);
+        png.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    let mut ihdr = Vec::new();
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    // 8-bit RGB, deflate, adaptive filtering, no interlace
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+    chunk(&mut png, b"IHDR", &ihdr);
+    // Empty zlib stream
+    chunk(&mut png, b"IDAT", &[0x78, 0x9C, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01]);
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Create a minimal MP4 container with one H.264 video track
+///
+/// Only the boxes needed for probing are present; the `mdat` payload is
+/// zero-filled, so this is not playable.
+pub fn create_test_mp4(width: u16, height: u16, duration_ms: u32) -> Vec<u8> {
+    fn mp4_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(body);
+        data
+    }
+
+    let mut mvhd = vec![0u8; 100];
+    mvhd[12..16].copy_from_slice(&1000u32.to_be_bytes());
+    mvhd[16..20].copy_from_slice(&duration_ms.to_be_bytes());
+
+    let mut tkhd = vec![0u8; 84];
+    tkhd[76..80].copy_from_slice(&(u32::from(width) << 16).to_be_bytes());
+    tkhd[80..84].copy_from_slice(&(u32::from(height) << 16).to_be_bytes());
+
+    let mut hdlr = vec![0u8; 24];
+    hdlr[8..12].copy_from_slice(b"vide");
+
+    let mut stsd = vec![0, 0, 0, 0, 0, 0, 0, 1];
+    stsd.extend(mp4_box(b"avc1", &[0u8; 78]));
+
+    let minf = mp4_box(b"minf", &mp4_box(b"stbl", &mp4_box(b"stsd", &stsd)));
+    let mut mdia = mp4_box(b"hdlr", &hdlr);
+    mdia.extend(minf);
+    let mut trak = mp4_box(b"tkhd", &tkhd);
+    trak.extend(mp4_box(b"mdia", &mdia));
+    let mut moov = mp4_box(b"mvhd", &mvhd);
+    moov.extend(mp4_box(b"trak", &trak));
+
+    let mut data = mp4_box(b"ftyp", b"isom\0\0\x02\0isomiso2avc1mp41");
+    data.extend(mp4_box(b"mdat", &[0u8; 4096]));
+    data.extend(mp4_box(b"moov", &moov));
+    data
+}
@@ -6,6 +6,7 @@
 
 mod common;
 
+use media_upload_server::config::{AssetConfig, EvmNetworkConfig};
 use reqwest::multipart::{Form, Part};
 use serde_json::json;
 
@@ -416,6 +417,9 @@ async fn test_admin_update_with_image() {
     let body: serde_json::Value = response.json().await.unwrap();
     assert!(body["image_light_url"].is_string());
     assert!(body["image_dark_url"].is_null());
+    assert!(body["image_light_blurhash"].is_string());
+    assert!(body["image_light_dominant_color"].is_string());
+    assert!(body["image_dark_blurhash"].is_null());
 }
 
 /// Test lock with defaults replaces content
@@ -482,3 +486,179 @@ async fn test_lock_with_defaults() {
     assert_eq!(body["metadata"]["description"], "");
     assert!(body["metadata"]["social_networks"].as_array().unwrap().is_empty());
 }
+
+/// Test that tokens locked with defaults point at the chain's built-in assets
+#[tokio::test]
+async fn test_locked_with_defaults_serves_builtin_assets() {
+    let assets_dir = tempfile::TempDir::new().unwrap();
+    let dark_path = assets_dir.path().join("dark.png");
+    std::fs::write(&dark_path, common::create_test_png(16, 16)).unwrap();
+
+    let server = common::TestServer::start_with_config(|config| {
+        config.rexpump.enabled = true;
+        config.rexpump.networks.insert(
+            "zilliqa_mainnet".to_string(),
+            EvmNetworkConfig {
+                name: "zilliqa_mainnet".to_string(),
+                chain_id: 32769,
+                rpc_url: "http://127.0.0.1:1".to_string(),
+                fallback_rpc_url: None,
+                default_image_light: None,
+                default_image_dark: Some("default-dark".to_string()),
+            },
+        );
+        config
+            .assets
+            .insert("default-dark".to_string(), AssetConfig { path: dark_path });
+    })
+    .await;
+    let token_address = "0x9999999999999999999999999999999999999999";
+
+    let lock_response = server
+        .client()
+        .post(format!(
+            "{}/admin/rexpump/lock/32769/{}",
+            server.admin_url, token_address
+        ))
+        .json(&json!({ "lock_type": "locked_with_defaults" }))
+        .send()
+        .await
+        .expect("Failed to send lock request");
+    assert_eq!(lock_response.status(), 200);
+
+    let body: serde_json::Value = server
+        .client()
+        .get(format!(
+            "{}/api/rexpump/metadata/32769/{}",
+            server.public_url, token_address
+        ))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let light_url = body["image_light_url"].as_str().unwrap();
+    let dark_url = body["image_dark_url"].as_str().unwrap();
+    assert_eq!(light_url, format!("{}/m/default", server.public_url));
+    assert_eq!(dark_url, format!("{}/m/default-dark", server.public_url));
+
+    // Both URLs resolve: the bundled WebP and the configured PNG
+    let light = server.client().get(light_url).send().await.unwrap();
+    assert_eq!(light.status(), 200);
+    assert_eq!(light.headers()["content-type"], "image/webp");
+
+    let dark = server.client().get(dark_url).send().await.unwrap();
+    assert_eq!(dark.status(), 200);
+    assert_eq!(dark.headers()["content-type"], "image/png");
+}
+
+/// Test that deleting one token's metadata keeps an image shared with another token
+#[tokio::test]
+async fn test_shared_image_survives_delete() {
+    let server = common::TestServer::start_with_auth(false, vec![]).await;
+    let png_data = common::create_test_png(60, 60);
+    let tokens = [
+        "0x7777777777777777777777777777777777777777",
+        "0x8888888888888888888888888888888888888888",
+    ];
+
+    let mut urls = Vec::new();
+    for token_address in tokens {
+        let response = server
+            .client()
+            .put(format!(
+                "{}/admin/rexpump/metadata/32769/{}",
+                server.admin_url, token_address
+            ))
+            .multipart(
+                Form::new()
+                    .text("metadata", r#"{"description":"Shared","social_networks":[]}"#)
+                    .part("image_light", Part::bytes(png_data.clone()).file_name("a.png").mime_str("image/png").unwrap())
+            )
+            .send()
+            .await
+            .expect("Failed to send request");
+
+        assert_eq!(response.status(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        urls.push(body["image_light_url"].as_str().unwrap().to_string());
+    }
+
+    // Identical content is deduplicated to the same media
+    assert_eq!(urls[0], urls[1]);
+
+    let delete_response = server
+        .client()
+        .delete(format!(
+            "{}/admin/rexpump/metadata/32769/{}",
+            server.admin_url, tokens[0]
+        ))
+        .send()
+        .await
+        .expect("Failed to send delete request");
+    assert_eq!(delete_response.status(), 200);
+
+    // Second token still references the image
+    let response = server.client().get(&urls[1]).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+
+    let delete_response = server
+        .client()
+        .delete(format!(
+            "{}/admin/rexpump/metadata/32769/{}",
+            server.admin_url, tokens[1]
+        ))
+        .send()
+        .await
+        .expect("Failed to send delete request");
+    assert_eq!(delete_response.status(), 200);
+
+    // Last reference gone, image removed
+    let response = server.client().get(&urls[1]).send().await.unwrap();
+    assert_eq!(response.status(), 404);
+}
+
+/// Test that a failed admin update keeps the stored images
+#[tokio::test]
+async fn test_admin_update_failure_keeps_images() {
+    let server = common::TestServer::start_with_auth(false, vec![]).await;
+    let token_address = "0x9999999999999999999999999999999999999999";
+    let url = format!("{}/admin/rexpump/metadata/32769/{}", server.admin_url, token_address);
+    let png = |size| Part::bytes(common::create_test_png(size, size)).file_name("a.png").mime_str("image/png").unwrap();
+
+    let response = server
+        .client()
+        .put(&url)
+        .multipart(Form::new().part("image_light", png(50)))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = response.json().await.unwrap();
+    let light_url = body["image_light_url"].as_str().unwrap().to_string();
+
+    // The new light image is fine, the dark one is not an image
+    let response = server
+        .client()
+        .put(&url)
+        .multipart(
+            Form::new()
+                .text("remove_image_light", "true")
+                .part("image_light", png(70))
+                .part("image_dark", Part::bytes(b"not an image".to_vec()).file_name("b.png")),
+        )
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert!(response.status().is_client_error());
+
+    // Nothing was released: the stored metadata still points at a servable image
+    let response = server.client().get(&url).send().await.unwrap();
+    let body: serde_json::Value = response.json().await.unwrap();
+    let light_id = body["metadata"]["image_light_id"].as_str().unwrap();
+    assert!(light_url.ends_with(light_id));
+    assert!(body["metadata"]["image_dark_id"].is_null());
+    let response = server.client().get(&light_url).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+}
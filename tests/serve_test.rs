@@ -2,7 +2,7 @@
 
 mod common;
 
-use common::{create_test_png, TestServer};
+use common::{count_dark_pixels, create_test_png, create_test_png_with_subject, TestServer};
 use reqwest::multipart;
 use serde_json::Value;
 
@@ -97,6 +97,96 @@ async fn test_serve_original_image() {
     );
 }
 
+#[tokio::test]
+async fn test_serve_svg_original_with_csp() {
+    let server = TestServer::start().await;
+    let client = server.client();
+
+    let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" width="40" height="40">
+  <circle cx="20" cy="20" r="15" fill="#336699"/>
+</svg>"##;
+    let form = multipart::Form::new().part(
+        "file",
+        multipart::Part::bytes(svg.as_bytes().to_vec())
+            .file_name("logo.svg")
+            .mime_str("image/svg+xml")
+            .unwrap(),
+    );
+    let json: Value = client
+        .post(server.url("/api/upload"))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id = json["id"].as_str().unwrap();
+
+    // The optimized rendition is a raster image without the policy
+    let response = client
+        .get(server.url(&format!("/m/{}", id)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["content-type"], "image/webp");
+    assert!(!response.headers().contains_key("content-security-policy"));
+
+    let response = client
+        .get(server.url(&format!("/m/{}/original", id)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "image/svg+xml");
+    assert_eq!(response.headers()["x-content-type-options"], "nosniff");
+
+    let csp = response.headers()["content-security-policy"].to_str().unwrap();
+    assert!(csp.contains("default-src 'none'"), "{}", csp);
+    assert!(csp.contains("sandbox"), "{}", csp);
+    assert!(!csp.contains("script-src"), "{}", csp);
+}
+
+#[tokio::test]
+async fn test_serve_builtin_asset() {
+    let server = TestServer::start().await;
+    let client = server.client();
+
+    let response = client.get(server.url("/m/default")).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "image/webp");
+    let cache_control = response.headers()["cache-control"].to_str().unwrap();
+    assert!(!cache_control.contains("immutable"), "{}", cache_control);
+    let etag = response.headers()["etag"].to_str().unwrap().to_string();
+    let body = response.bytes().await.unwrap();
+    assert_eq!(&body[..4], b"RIFF");
+
+    let response = client
+        .get(server.url("/m/default"))
+        .header("If-None-Match", &etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 304);
+
+    let response = client
+        .get(server.url("/m/default"))
+        .header("Range", "bytes=8-11")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 206);
+    assert_eq!(response.bytes().await.unwrap().as_ref(), b"WEBP");
+
+    let response = client.head(server.url("/m/default")).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-length"], body.len().to_string().as_str());
+
+    // No variants of built-in assets
+    let response = client.get(server.url("/m/default?w=10")).send().await.unwrap();
+    assert_eq!(response.status(), 400);
+}
+
 #[tokio::test]
 async fn test_serve_nonexistent_image() {
     let server = TestServer::start().await;
@@ -122,7 +212,8 @@ async fn test_serve_invalid_uuid() {
         .await
         .expect("Failed to fetch");
 
-    assert_eq!(response.status(), 400);
+    // Neither a media id nor a built-in asset
+    assert_eq!(response.status(), 404);
 }
 
 #[tokio::test]
@@ -178,3 +269,818 @@ async fn test_etag_caching() {
     assert_eq!(response2.status(), 304);
 }
 
+#[tokio::test]
+async fn test_serve_conditional_requests() {
+    let server = TestServer::start().await;
+    let client = server.client();
+    let id = upload_png(&server, 40, 40).await;
+
+    for path in [
+        format!("/m/{}", id),
+        format!("/m/{}/original", id),
+        format!("/m/{}?w=20", id),
+    ] {
+        let url = server.url(&path);
+        let response = client.get(&url).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        let etag = response.headers()["etag"].to_str().unwrap().to_string();
+        let last_modified = response.headers()["last-modified"].to_str().unwrap().to_string();
+        assert!(last_modified.ends_with(" GMT"), "{}", last_modified);
+
+        let status = |headers: Vec<(&'static str, String)>| {
+            let mut request = client.get(&url);
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            async move { request.send().await.unwrap() }
+        };
+
+        // Lists, weak tags and * all match for If-None-Match
+        for value in [
+            format!("\"other\", {}", etag),
+            format!("W/{}", etag),
+            "*".to_string(),
+        ] {
+            let response = status(vec![("If-None-Match", value)]).await;
+            assert_eq!(response.status(), 304, "{}", path);
+            assert_eq!(response.headers()["etag"], etag.as_str());
+            assert!(response.headers().contains_key("cache-control"));
+            assert_eq!(response.headers()["last-modified"], last_modified.as_str());
+        }
+        let response = status(vec![("If-None-Match", "\"other\"".to_string())]).await;
+        assert_eq!(response.status(), 200);
+
+        let response = status(vec![("If-Modified-Since", last_modified.clone())]).await;
+        assert_eq!(response.status(), 304, "{}", path);
+        let response = status(vec![(
+            "If-Modified-Since",
+            "Thu, 01 Jan 2015 00:00:00 GMT".to_string(),
+        )])
+        .await;
+        assert_eq!(response.status(), 200);
+
+        // If-None-Match wins over If-Modified-Since
+        let response = status(vec![
+            ("If-None-Match", "\"other\"".to_string()),
+            ("If-Modified-Since", last_modified.clone()),
+        ])
+        .await;
+        assert_eq!(response.status(), 200);
+
+        let response = status(vec![("If-Match", etag.clone())]).await;
+        assert_eq!(response.status(), 200);
+        let response = status(vec![("If-Match", "\"other\"".to_string())]).await;
+        assert_eq!(response.status(), 412, "{}", path);
+        let body: Value = response.json().await.unwrap();
+        assert_eq!(body["error"], "precondition_failed");
+
+        let response = status(vec![(
+            "If-Unmodified-Since",
+            "Thu, 01 Jan 2015 00:00:00 GMT".to_string(),
+        )])
+        .await;
+        assert_eq!(response.status(), 412);
+
+        // If-Range also accepts the Last-Modified date
+        let response = status(vec![
+            ("Range", "bytes=0-3".to_string()),
+            ("If-Range", last_modified.clone()),
+        ])
+        .await;
+        assert_eq!(response.status(), 206);
+    }
+
+    // HEAD is evaluated the same way
+    let response = client
+        .head(server.url(&format!("/m/{}", id)))
+        .header("If-None-Match", "*")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 304);
+
+    // Built-in assets have an ETag but no Last-Modified
+    let response = client.get(server.url("/m/default")).send().await.unwrap();
+    assert!(!response.headers().contains_key("last-modified"));
+    let etag = response.headers()["etag"].to_str().unwrap().to_string();
+    let response = client
+        .get(server.url("/m/default"))
+        .header("If-None-Match", format!("W/\"x\", W/{}", etag))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 304);
+}
+
+
+/// Upload a PNG and return its media ID
+async fn upload_png(server: &TestServer, width: u32, height: u32) -> String {
+    let form = multipart::Form::new().part(
+        "file",
+        multipart::Part::bytes(create_test_png(width, height))
+            .file_name("test.png")
+            .mime_str("image/png")
+            .unwrap(),
+    );
+
+    let response = server
+        .client()
+        .post(server.url("/api/upload"))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to upload");
+
+    assert!(response.status().is_success());
+    let json: Value = response.json().await.unwrap();
+    json["id"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn test_serve_variant_resize_and_convert() {
+    let server = TestServer::start().await;
+    let client = server.client();
+    let id = upload_png(&server, 200, 100).await;
+
+    // Width-only resize keeps aspect ratio and default output format
+    let response = client
+        .get(server.url(&format!("/m/{}?w=50", id)))
+        .send()
+        .await
+        .expect("Failed to fetch");
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers().get("content-type").unwrap(), "image/webp");
+    let body = response.bytes().await.unwrap();
+    let img = image::load_from_memory(&body).unwrap();
+    assert_eq!((img.width(), img.height()), (50, 25));
+
+    // Cover crop converted to JPEG
+    let response = client
+        .get(server.url(&format!("/m/{}?w=32&h=32&fit=cover&fmt=jpeg&q=70", id)))
+        .send()
+        .await
+        .expect("Failed to fetch");
+
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers().get("content-type").unwrap(), "image/jpeg");
+    let body = response.bytes().await.unwrap();
+    let img = image::load_from_memory(&body).unwrap();
+    assert_eq!((img.width(), img.height()), (32, 32));
+}
+
+#[tokio::test]
+async fn test_serve_variant_etag() {
+    let server = TestServer::start().await;
+    let client = server.client();
+    let id = upload_png(&server, 100, 100).await;
+
+    let base = client
+        .get(server.url(&format!("/m/{}", id)))
+        .send()
+        .await
+        .expect("Failed to fetch");
+    let base_etag = base.headers().get("etag").unwrap().clone();
+
+    let variant = client
+        .get(server.url(&format!("/m/{}?w=40&fmt=jpg", id)))
+        .send()
+        .await
+        .expect("Failed to fetch");
+    assert_eq!(variant.status(), 200);
+    let variant_etag = variant.headers().get("etag").unwrap().clone();
+    assert_ne!(base_etag, variant_etag);
+
+    // Equivalent parameters hit the same cached variant
+    let cached = client
+        .get(server.url(&format!("/m/{}?fmt=jpeg&w=40", id)))
+        .header("If-None-Match", variant_etag.to_str().unwrap())
+        .send()
+        .await
+        .expect("Failed to fetch");
+    assert_eq!(cached.status(), 304);
+}
+
+#[tokio::test]
+async fn test_serve_variant_invalid_params() {
+    let server = TestServer::start().await;
+    let client = server.client();
+    let id = upload_png(&server, 100, 100).await;
+
+    for query in ["w=0", "w=100000", "fit=stretch", "fmt=bmp", "q=0"] {
+        let response = client
+            .get(server.url(&format!("/m/{}?{}", id, query)))
+            .send()
+            .await
+            .expect("Failed to fetch");
+
+        assert_eq!(response.status(), 400, "query: {}", query);
+    }
+}
+
+#[tokio::test]
+async fn test_serve_variant_presets() {
+    use media_upload_server::config::VariantPreset;
+
+    let server = TestServer::start_with_config(|config| {
+        config.processing.variants.insert(
+            "thumb".to_string(),
+            VariantPreset {
+                width: Some(32),
+                height: Some(32),
+                fit: Some("cover".to_string()),
+                focus: None,
+                format: Some("jpeg".to_string()),
+                quality: Some(70),
+            },
+        );
+    })
+    .await;
+    let client = server.client();
+
+    let form = multipart::Form::new().part(
+        "file",
+        multipart::Part::bytes(create_test_png(120, 80))
+            .file_name("test.png")
+            .mime_str("image/png")
+            .unwrap(),
+    );
+    let response = client
+        .post(server.url("/api/upload"))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to upload");
+    assert_eq!(response.status(), 201);
+
+    let json: Value = response.json().await.unwrap();
+    let id = json["id"].as_str().unwrap();
+    let thumb_url = json["variants"]["thumb"].as_str().unwrap();
+    assert_eq!(thumb_url, server.url(&format!("/m/{}/v/thumb", id)));
+
+    // Preset was generated eagerly at upload time
+    let variant_dirs = std::fs::read_dir(server.data_dir.path().join("variants"))
+        .unwrap()
+        .count();
+    assert!(variant_dirs > 0);
+
+    let response = client.get(thumb_url).send().await.expect("Failed to fetch");
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers().get("content-type").unwrap(), "image/jpeg");
+    let preset_etag = response.headers().get("etag").unwrap().clone();
+
+    let bytes = response.bytes().await.unwrap();
+    let img = image::load_from_memory(&bytes).unwrap();
+    assert_eq!((img.width(), img.height()), (32, 32));
+
+    // The equivalent query string shares the preset's cached file
+    let response = client
+        .get(server.url(&format!("/m/{}?w=32&h=32&fit=cover&fmt=jpg&q=70", id)))
+        .send()
+        .await
+        .expect("Failed to fetch");
+    assert_eq!(response.headers().get("etag").unwrap(), &preset_etag);
+
+    // Unknown preset
+    let response = client
+        .get(server.url(&format!("/m/{}/v/missing", id)))
+        .send()
+        .await
+        .expect("Failed to fetch");
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn test_serve_range_requests() {
+    let server = TestServer::start().await;
+    let client = server.client();
+    let id = upload_png(&server, 64, 64).await;
+    let url = server.url(&format!("/m/{}", id));
+
+    // Full response advertises ranges and its length
+    let response = client.get(&url).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["accept-ranges"], "bytes");
+    let etag = response.headers()["etag"].to_str().unwrap().to_string();
+    let len: usize = response.headers()["content-length"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    let full = response.bytes().await.unwrap();
+    assert_eq!(full.len(), len);
+
+    // Single range
+    let response = client
+        .get(&url)
+        .header("Range", "bytes=0-9")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 206);
+    assert_eq!(
+        response.headers()["content-range"],
+        format!("bytes 0-9/{}", len).as_str()
+    );
+    assert_eq!(response.headers()["content-length"], "10");
+    assert_eq!(response.bytes().await.unwrap(), full.slice(0..10));
+
+    // Suffix range
+    let response = client
+        .get(&url)
+        .header("Range", "bytes=-5")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 206);
+    assert_eq!(response.bytes().await.unwrap(), full.slice(len - 5..));
+
+    // Multiple ranges
+    let response = client
+        .get(&url)
+        .header("Range", "bytes=0-1,4-5")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 206);
+    let content_type = response.headers()["content-type"].to_str().unwrap().to_string();
+    assert!(content_type.starts_with("multipart/byteranges; boundary="));
+    let declared: usize = response.headers()["content-length"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    let body = response.bytes().await.unwrap();
+    assert_eq!(body.len(), declared);
+    let text = String::from_utf8_lossy(&body);
+    assert!(text.contains(&format!("Content-Range: bytes 0-1/{}", len)));
+    assert!(text.contains(&format!("Content-Range: bytes 4-5/{}", len)));
+    assert!(text.trim_end().ends_with("--"));
+
+    // Unsatisfiable
+    let response = client
+        .get(&url)
+        .header("Range", format!("bytes={}-", len))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 416);
+    assert_eq!(
+        response.headers()["content-range"],
+        format!("bytes */{}", len).as_str()
+    );
+
+    // If-Range: matching ETag honors the range, anything else sends it all
+    let response = client
+        .get(&url)
+        .header("Range", "bytes=0-9")
+        .header("If-Range", &etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 206);
+
+    let response = client
+        .get(&url)
+        .header("Range", "bytes=0-9")
+        .header("If-Range", "\"stale\"")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.bytes().await.unwrap(), full);
+}
+
+#[tokio::test]
+async fn test_serve_head_and_original_range() {
+    let server = TestServer::start().await;
+    let client = server.client();
+    let id = upload_png(&server, 64, 64).await;
+
+    for path in [format!("/m/{}", id), format!("/m/{}/original", id)] {
+        let full = client
+            .get(server.url(&path))
+            .send()
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+
+        let response = client.head(server.url(&path)).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["accept-ranges"], "bytes");
+        assert_eq!(
+            response.headers()["content-length"],
+            full.len().to_string().as_str()
+        );
+        assert!(response.bytes().await.unwrap().is_empty());
+
+        let response = client
+            .get(server.url(&path))
+            .header("Range", "bytes=8-15")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 206);
+        assert_eq!(response.bytes().await.unwrap(), full.slice(8..16));
+    }
+}
+
+#[tokio::test]
+async fn test_serve_negotiates_rendition_by_accept() {
+    let server = TestServer::start_with_config(|config| {
+        config.processing.output_format = "jpeg".to_string();
+        config.processing.output_formats = vec!["avif".to_string(), "webp".to_string()];
+    })
+    .await;
+    let client = server.client();
+    let id = upload_png(&server, 64, 48).await;
+    let url = server.url(&format!("/m/{}", id));
+
+    let mut etags = Vec::new();
+    for (accept, expected) in [
+        ("*/*", "image/jpeg"),
+        ("image/avif,image/webp,*/*;q=0.8", "image/avif"),
+        ("image/webp,*/*", "image/webp"),
+        ("image/avif;q=0.5,image/webp", "image/webp"),
+        ("image/avif;q=0,image/jpeg", "image/jpeg"),
+        ("image/jpeg,image/avif;q=0.5", "image/jpeg"),
+        ("image/jpeg;q=0,*/*", "image/avif"),
+        ("image/jpeg;q=0,image/avif;q=0,*/*", "image/webp"),
+    ] {
+        let response = client
+            .get(&url)
+            .header("Accept", accept)
+            .send()
+            .await
+            .expect("Failed to fetch");
+
+        assert_eq!(response.status(), 200, "accept: {}", accept);
+        assert_eq!(response.headers().get("content-type").unwrap(), expected);
+        assert_eq!(response.headers().get("vary").unwrap(), "accept");
+        etags.push((expected, response.headers().get("etag").unwrap().clone()));
+
+        let body = response.bytes().await.unwrap();
+        if expected == "image/avif" {
+            assert_eq!(&body[4..12], b"ftypavif");
+        } else {
+            let img = image::load_from_memory(&body).unwrap();
+            assert_eq!((img.width(), img.height()), (64, 48));
+        }
+    }
+
+    // One ETag per rendition
+    let jpeg_etag = &etags[0].1;
+    assert_ne!(jpeg_etag, &etags[1].1);
+    assert_ne!(jpeg_etag, &etags[2].1);
+    assert_ne!(&etags[1].1, &etags[2].1);
+    assert_eq!(jpeg_etag, &etags[4].1);
+
+    // A cached JPEG does not validate the AVIF rendition
+    let response = client
+        .get(&url)
+        .header("Accept", "image/avif")
+        .header("If-None-Match", jpeg_etag.to_str().unwrap())
+        .send()
+        .await
+        .expect("Failed to fetch");
+    assert_eq!(response.status(), 200);
+
+    let response = client
+        .get(&url)
+        .header("Accept", "image/avif")
+        .header("If-None-Match", etags[1].1.to_str().unwrap())
+        .send()
+        .await
+        .expect("Failed to fetch");
+    assert_eq!(response.status(), 304);
+    assert_eq!(response.headers().get("vary").unwrap(), "accept");
+
+    let info: Value = client
+        .get(server.admin(&format!("/admin/media/{}", id)))
+        .send()
+        .await
+        .expect("Failed to fetch")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(info["renditions"], serde_json::json!(["image/avif", "image/webp"]));
+}
+
+#[tokio::test]
+async fn test_serve_variant_focus() {
+    let server = TestServer::start().await;
+    let client = server.client();
+
+    // Subject near the right edge, where a center crop cuts it off
+    let form = multipart::Form::new().part(
+        "file",
+        multipart::Part::bytes(create_test_png_with_subject(400, 200, 320, 60, 60))
+            .file_name("logo.png")
+            .mime_str("image/png")
+            .unwrap(),
+    );
+    let json: Value = client
+        .post(server.url("/api/upload"))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let id = json["id"].as_str().unwrap();
+    assert!(json["focal_point"]["x"].as_f64().unwrap() > 0.7);
+
+    let fetch = |query: &'static str| {
+        let url = server.url(&format!("/m/{}?w=50&h=50&fit=cover&fmt=png&{}", id, query));
+        let client = client.clone();
+        async move {
+            let response = client.get(url).send().await.unwrap();
+            assert_eq!(response.status(), 200, "query: {}", query);
+            count_dark_pixels(&response.bytes().await.unwrap())
+        }
+    };
+
+    // Center crop misses the subject, the detected focal point keeps it
+    assert_eq!(fetch("focus=center").await, 0);
+    assert!(fetch("focus=auto").await > 200);
+    assert!(fetch("focus=0.9,0.5").await > 200);
+    assert_eq!(fetch("focus=0,0.5").await, 0);
+
+    let response = client
+        .get(server.url(&format!("/m/{}?w=50&h=50&fit=cover&focus=2,2", id)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_serve_private_media_needs_signed_url() {
+    use media_upload_server::config::{AuthConfig, VariantPreset};
+    use media_upload_server::services::UrlSigner;
+
+    let server = TestServer::start_with_config(|config| {
+        config.processing.variants.insert(
+            "thumb".to_string(),
+            VariantPreset {
+                width: Some(16),
+                height: Some(16),
+                fit: None,
+                focus: None,
+                format: None,
+                quality: None,
+            },
+        );
+    })
+    .await;
+    let client = server.client();
+
+    let form = multipart::Form::new()
+        .text("visibility", "private")
+        .part(
+            "file",
+            multipart::Part::bytes(create_test_png(64, 64))
+                .file_name("private.png")
+                .mime_str("image/png")
+                .unwrap(),
+        );
+    let response = client
+        .post(server.url("/api/upload"))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 201);
+    let upload: Value = response.json().await.unwrap();
+    assert_eq!(upload["visibility"], "private");
+    assert!(upload["urls_expire_at"].is_string());
+    let id = upload["id"].as_str().unwrap();
+
+    // Every returned URL carries the signature
+    for url in [&upload["url"], &upload["original_url"], &upload["variants"]["thumb"]] {
+        let url = url.as_str().unwrap();
+        assert!(url.contains("?exp=") && url.contains("&sig="), "{}", url);
+
+        let response = client.get(url).send().await.unwrap();
+        assert_eq!(response.status(), 200, "{}", url);
+        let cache_control = response.headers()["cache-control"].to_str().unwrap();
+        assert!(cache_control.starts_with("private, max-age="), "{}", cache_control);
+    }
+
+    // Signature and variant parameters combine
+    let signed_url = upload["url"].as_str().unwrap();
+    let response = client
+        .get(format!("{}&w=8", signed_url))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    // Unsigned, tampered and expired URLs are refused
+    for path in [
+        format!("/m/{}", id),
+        format!("/m/{}/original", id),
+        format!("/m/{}/v/thumb", id),
+        format!("/m/{}?w=8", id),
+    ] {
+        let response = client.get(server.url(&path)).send().await.unwrap();
+        assert_eq!(response.status(), 403, "{}", path);
+    }
+
+    let (_, query) = signed_url.split_once('?').unwrap();
+    let tampered = query.replace("exp=", "exp=1");
+    let response = client
+        .get(server.url(&format!("/m/{}?{}", id, tampered)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 403);
+
+    let signer = UrlSigner::new(&AuthConfig {
+        url_signing_keys: vec![common::TEST_URL_SIGNING_KEY.to_string()],
+        ..Default::default()
+    });
+    let id = uuid::Uuid::parse_str(id).unwrap();
+    let exp = chrono::Utc::now().timestamp() - 10;
+    let expired = format!("/m/{}?exp={}&sig={}", id, exp, signer.sign(id, exp).unwrap());
+    let response = client.get(server.url(&expired)).send().await.unwrap();
+    assert_eq!(response.status(), 403);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["error"], "not_authorized");
+}
+
+#[tokio::test]
+async fn test_private_upload_validation() {
+    let server = TestServer::start().await;
+    let client = server.client();
+
+    let upload = |visibility: &'static str| {
+        multipart::Form::new()
+            .text("visibility", visibility)
+            .part(
+                "file",
+                multipart::Part::bytes(create_test_png(20, 20))
+                    .file_name("test.png")
+                    .mime_str("image/png")
+                    .unwrap(),
+            )
+    };
+
+    let response = client
+        .post(server.url("/api/upload"))
+        .multipart(upload("secret"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+
+    // Public and private uploads of the same content stay separate
+    let response = client
+        .post(server.url("/api/upload"))
+        .multipart(upload("private"))
+        .send()
+        .await
+        .unwrap();
+    let private: Value = response.json().await.unwrap();
+    let response = client
+        .post(server.url("/api/upload"))
+        .multipart(upload("public"))
+        .send()
+        .await
+        .unwrap();
+    let public: Value = response.json().await.unwrap();
+    assert_ne!(public["id"], private["id"]);
+    assert_eq!(public["visibility"], "public");
+    assert!(public.get("urls_expire_at").is_none());
+
+    let response = client
+        .get(server.url(&format!("/m/{}", public["id"].as_str().unwrap())))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let response = client
+        .get(server.url(&format!("/m/{}", private["id"].as_str().unwrap())))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 403);
+
+    // A private duplicate gets the private media back, never the public one
+    let response = client
+        .post(server.url("/api/upload"))
+        .multipart(upload("private"))
+        .send()
+        .await
+        .unwrap();
+    let duplicate: Value = response.json().await.unwrap();
+    assert_eq!(duplicate["id"], private["id"]);
+    assert_eq!(duplicate["visibility"], "private");
+
+    // Private uploads need a signing key
+    let server = TestServer::start_with_config(|config| config.auth.url_signing_keys.clear()).await;
+    let response = server
+        .client()
+        .post(server.url("/api/upload"))
+        .multipart(upload("private"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_serve_from_memory_cache() {
+    let server = TestServer::start().await;
+    let client = server.client();
+    let id = upload_png(&server, 40, 30).await;
+
+    // First request loads the file, the second is served from memory
+    let first = client
+        .get(server.url(&format!("/m/{}", id)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(first.status(), 200);
+    let etag = first.headers()["etag"].clone();
+    let first_body = first.bytes().await.unwrap();
+
+    let second = client
+        .get(server.url(&format!("/m/{}", id)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(second.status(), 200);
+    assert_eq!(second.headers()["etag"], etag);
+    assert!(second.headers().contains_key("last-modified"));
+    assert_eq!(second.bytes().await.unwrap(), first_body);
+
+    // Ranges and preconditions work on cached files too
+    let response = client
+        .get(server.url(&format!("/m/{}", id)))
+        .header("range", "bytes=0-9")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 206);
+    assert_eq!(response.bytes().await.unwrap(), first_body.slice(0..10));
+
+    let response = client
+        .get(server.url(&format!("/m/{}", id)))
+        .header("if-none-match", etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 304);
+
+    let cache = media_cache_stats(&server).await;
+    assert_eq!(cache["misses"], 1);
+    assert_eq!(cache["hits"], 3);
+    assert_eq!(cache["entries"], 1);
+    assert_eq!(cache["size_bytes"], first_body.len() as u64);
+
+    // Deleting the media drops it from memory as well
+    let response = client
+        .delete(server.admin(&format!("/admin/media/{}", id)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let response = client
+        .get(server.url(&format!("/m/{}", id)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+    assert_eq!(media_cache_stats(&server).await["entries"], 0);
+
+    // With the cache disabled every request goes to disk
+    let server = TestServer::start_with_config(|config| config.server.media_cache_mb = 0).await;
+    let id = upload_png(&server, 40, 30).await;
+    for _ in 0..2 {
+        let response = server
+            .client()
+            .get(server.url(&format!("/m/{}", id)))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+    }
+    let cache = media_cache_stats(&server).await;
+    assert_eq!((cache["hits"].as_u64(), cache["entries"].as_u64()), (Some(0), Some(0)));
+}
+
+/// The `media_cache` section of `/health/stats`
+async fn media_cache_stats(server: &TestServer) -> Value {
+    let json: Value = reqwest::get(server.url("/health/stats"))
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    json["media_cache"].clone()
+}
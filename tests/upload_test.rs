@@ -2,7 +2,10 @@
 
 mod common;
 
-use common::{create_test_jpeg, create_test_png, TestServer};
+use common::{
+    create_png_bomb, create_test_gif, create_test_image, create_test_jpeg,
+    create_test_jpeg_with_exif, create_test_mp4, create_test_png, TestServer,
+};
 use reqwest::multipart;
 use serde_json::Value;
 
@@ -42,6 +45,11 @@ async fn test_simple_upload_png() {
     assert_eq!(json["mime_type"], "image/webp"); // Converted to WebP
     assert_eq!(json["width"], 100);
     assert_eq!(json["height"], 100);
+
+    // Placeholders for rendering while the image loads
+    assert_eq!(json["blurhash"].as_str().unwrap().len(), 28);
+    let color = json["dominant_color"].as_str().unwrap();
+    assert!(color.len() == 7 && color.starts_with('#'), "unexpected color {}", color);
 }
 
 #[tokio::test]
@@ -126,6 +134,34 @@ async fn test_upload_empty_file() {
     assert!(response.status().is_client_error() || response.status().is_server_error());
 }
 
+#[tokio::test]
+async fn test_upload_decompression_bomb_rejected() {
+    let server = TestServer::start().await;
+
+    let bomb = create_png_bomb(60000, 60000);
+    assert!(bomb.len() < 100);
+
+    let form = multipart::Form::new().part(
+        "file",
+        multipart::Part::bytes(bomb)
+            .file_name("bomb.png")
+            .mime_str("image/png")
+            .unwrap(),
+    );
+    let response = server
+        .client()
+        .post(server.url("/api/upload"))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(response.status(), 422);
+    let json: Value = response.json().await.unwrap();
+    assert_eq!(json["error"], "image_too_large");
+    assert!(json["message"].as_str().unwrap().contains("60000x60000"));
+}
+
 #[tokio::test]
 async fn test_upload_deduplication() {
     let server = TestServer::start().await;
@@ -222,3 +258,483 @@ async fn test_upload_with_auth_required() {
     assert!(response.status().is_success());
 }
 
+
+/// EXIF fields of a phone photo: rotated 90° clockwise, with GPS and credits
+fn phone_exif() -> Vec<exif::Field> {
+    use exif::{Field, In, Tag, Value};
+
+    let ascii = |tag, text: &str| Field {
+        tag,
+        ifd_num: In::PRIMARY,
+        value: Value::Ascii(vec![text.as_bytes().to_vec()]),
+    };
+
+    vec![
+        ascii(Tag::Make, "Google"),
+        ascii(Tag::Model, "Pixel 8"),
+        ascii(Tag::DateTimeOriginal, "2024:05:17 14:03:21"),
+        ascii(Tag::Artist, "Jane Doe"),
+        ascii(Tag::Copyright, "(c) Jane Doe"),
+        ascii(Tag::GPSLatitudeRef, "N"),
+        Field {
+            tag: Tag::Orientation,
+            ifd_num: In::PRIMARY,
+            value: Value::Short(vec![6]),
+        },
+    ]
+}
+
+/// Upload a file and return the admin media info
+async fn upload_and_inspect(server: &TestServer, data: Vec<u8>) -> Value {
+    let form = multipart::Form::new().part(
+        "file",
+        multipart::Part::bytes(data)
+            .file_name("photo.jpg")
+            .mime_str("image/jpeg")
+            .unwrap(),
+    );
+    let response = server
+        .client()
+        .post(server.url("/api/upload"))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to upload");
+    assert_eq!(response.status(), 201);
+    let json: Value = response.json().await.unwrap();
+
+    server
+        .client()
+        .get(server.admin(&format!("/admin/media/{}", json["id"].as_str().unwrap())))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+/// Fetch the stored original and parse its EXIF block, if any
+async fn original_exif(server: &TestServer, id: &str) -> (image::DynamicImage, Option<exif::Exif>) {
+    let bytes = server
+        .client()
+        .get(server.url(&format!("/m/{}/original", id)))
+        .send()
+        .await
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap();
+
+    let img = image::load_from_memory(&bytes).unwrap();
+    let exif = exif::Reader::new()
+        .read_from_container(&mut std::io::Cursor::new(bytes.to_vec()))
+        .ok();
+    (img, exif)
+}
+
+#[tokio::test]
+async fn test_upload_applies_exif_orientation_and_strips_metadata() {
+    let server = TestServer::start().await;
+
+    let info = upload_and_inspect(&server, create_test_jpeg_with_exif(80, 40, &phone_exif())).await;
+    let id = info["id"].as_str().unwrap();
+
+    // Stored upright: 80x40 rotated by 90°
+    assert_eq!(info["width"], 40);
+    assert_eq!(info["height"], 80);
+
+    // Camera details are recorded for admins
+    assert_eq!(info["exif"]["camera_make"], "Google");
+    assert_eq!(info["exif"]["camera_model"], "Pixel 8");
+    assert_eq!(info["exif"]["taken_at"], "2024-05-17T14:03:21");
+    assert_eq!(info["exif"]["has_gps"], true);
+
+    // strip_exif (strip_all): the original is upright and carries no EXIF
+    let (img, exif) = original_exif(&server, id).await;
+    assert_eq!((img.width(), img.height()), (40, 80));
+    assert!(exif.is_none());
+}
+
+#[tokio::test]
+async fn test_upload_metadata_policy_keep_copyright() {
+    use media_upload_server::config::MetadataPolicy;
+
+    let server = TestServer::start_with_config(|config| {
+        config.processing.metadata_policy = Some(MetadataPolicy::KeepCopyright);
+    })
+    .await;
+
+    let info = upload_and_inspect(&server, create_test_jpeg_with_exif(80, 40, &phone_exif())).await;
+    let (img, exif) = original_exif(&server, info["id"].as_str().unwrap()).await;
+    assert_eq!((img.width(), img.height()), (40, 80));
+
+    let exif = exif.expect("credits kept");
+    assert!(exif.get_field(exif::Tag::Artist, exif::In::PRIMARY).is_some());
+    assert!(exif.get_field(exif::Tag::Copyright, exif::In::PRIMARY).is_some());
+    assert!(exif.get_field(exif::Tag::Make, exif::In::PRIMARY).is_none());
+    assert!(exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY).is_none());
+    assert!(exif.fields().all(|f| f.tag.context() != exif::Context::Gps));
+}
+
+#[tokio::test]
+async fn test_upload_metadata_policy_keep_all() {
+    let server = TestServer::start_with_config(|config| config.processing.strip_exif = false).await;
+
+    let upload = create_test_jpeg_with_exif(80, 40, &phone_exif());
+    let info = upload_and_inspect(&server, upload.clone()).await;
+    let id = info["id"].as_str().unwrap();
+
+    // Original stored byte for byte, optimized file and variants upright
+    let original = server
+        .client()
+        .get(server.url(&format!("/m/{}/original", id)))
+        .send()
+        .await
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap();
+    assert_eq!(original.to_vec(), upload);
+
+    let variant = server
+        .client()
+        .get(server.url(&format!("/m/{}?w=20", id)))
+        .send()
+        .await
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap();
+    let img = image::load_from_memory(&variant).unwrap();
+    assert_eq!((img.width(), img.height()), (20, 40));
+}
+
+#[tokio::test]
+async fn test_upload_animated_gif_stays_animated() {
+    use image::AnimationDecoder;
+
+    let server = TestServer::start().await;
+
+    let info = upload_and_inspect(&server, create_test_gif(48, 32, &[100, 200, 500])).await;
+    assert_eq!(info["original_mime_type"], "image/gif");
+    assert_eq!(info["optimized_mime_type"], "image/webp");
+    assert_eq!(info["frame_count"], 3);
+    assert_eq!(info["duration_ms"], 800);
+    assert_eq!((info["width"].as_u64(), info["height"].as_u64()), (Some(48), Some(32)));
+
+    let served = server
+        .client()
+        .get(server.url(&format!("/m/{}", info["id"].as_str().unwrap())))
+        .send()
+        .await
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap();
+    let decoder = image::codecs::webp::WebPDecoder::new(std::io::Cursor::new(served)).unwrap();
+    assert!(decoder.has_animation());
+    let delays: Vec<u32> = decoder
+        .into_frames()
+        .map(|frame| frame.unwrap().delay().numer_denom_ms().0)
+        .collect();
+    assert_eq!(delays, vec![100, 200, 500]);
+}
+
+#[tokio::test]
+async fn test_upload_animation_disallowed_stores_poster() {
+    let server =
+        TestServer::start_with_config(|config| config.processing.preserve_animation = false).await;
+
+    let info = upload_and_inspect(&server, create_test_gif(48, 32, &[100, 200])).await;
+    assert_eq!(info["optimized_mime_type"], "image/webp");
+    assert!(info.get("frame_count").is_none());
+    assert!(info.get("duration_ms").is_none());
+
+    let served = server
+        .client()
+        .get(server.url(&format!("/m/{}", info["id"].as_str().unwrap())))
+        .send()
+        .await
+        .unwrap()
+        .bytes()
+        .await
+        .unwrap();
+    let decoder = image::codecs::webp::WebPDecoder::new(std::io::Cursor::new(served)).unwrap();
+    assert!(!decoder.has_animation());
+}
+
+#[tokio::test]
+async fn test_upload_with_focal_point() {
+    let server = TestServer::start().await;
+    let client = server.client();
+
+    let upload = |data: Vec<u8>, focal_point: &'static str| {
+        let form = multipart::Form::new()
+            .text("focal_point", focal_point)
+            .part(
+                "file",
+                multipart::Part::bytes(data)
+                    .file_name("test.png")
+                    .mime_str("image/png")
+                    .unwrap(),
+            );
+        client.post(server.url("/api/upload")).multipart(form).send()
+    };
+
+    let response = upload(create_test_png(120, 80), "0.25,0.75").await.unwrap();
+    assert_eq!(response.status(), 201);
+    let json: Value = response.json().await.unwrap();
+    assert_eq!(json["focal_point"]["x"], 0.25);
+    assert_eq!(json["focal_point"]["y"], 0.75);
+
+    // A duplicate upload cannot move the focal point of shared media
+    let response = upload(create_test_png(120, 80), "0.9,0.1").await.unwrap();
+    let duplicate: Value = response.json().await.unwrap();
+    assert_eq!(duplicate["id"], json["id"]);
+    assert_eq!(duplicate["focal_point"]["x"], 0.25);
+
+    let response = upload(create_test_png(60, 60), "left").await.unwrap();
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_upload_reads_fields_after_file() {
+    let server = TestServer::start().await;
+    let part = |data: Vec<u8>| {
+        multipart::Part::bytes(data)
+            .file_name("test.png")
+            .mime_str("image/png")
+            .unwrap()
+    };
+
+    // Later files are skipped, other fields are read in any order
+    let form = multipart::Form::new()
+        .part("file", part(create_test_png(40, 30)))
+        .part("file", part(create_test_png(90, 70)))
+        .text("visibility", "private");
+    let response = server
+        .client()
+        .post(server.url("/api/upload"))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 201);
+
+    let json: Value = response.json().await.unwrap();
+    assert_eq!(json["width"], 40);
+    assert_eq!(json["height"], 30);
+    assert_eq!(json["visibility"], "private");
+    assert!(json["urls_expire_at"].is_string());
+}
+
+#[tokio::test]
+async fn test_upload_additional_raster_formats() {
+    let server = TestServer::start().await;
+    let client = server.client();
+
+    let formats = [
+        (image::ImageFormat::Bmp, "image/bmp", "test.bmp"),
+        (image::ImageFormat::Tiff, "image/tiff", "test.tiff"),
+        (image::ImageFormat::Ico, "image/x-icon", "favicon.ico"),
+    ];
+
+    for (format, mime, name) in formats {
+        let form = multipart::Form::new().part(
+            "file",
+            multipart::Part::bytes(create_test_image(64, 48, format))
+                .file_name(name)
+                .mime_str(mime)
+                .unwrap(),
+        );
+        let response = client
+            .post(server.url("/api/upload"))
+            .multipart(form)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 201, "{}", name);
+
+        let json: Value = response.json().await.unwrap();
+        assert_eq!(json["mime_type"], "image/webp", "{}", name);
+        assert_eq!(json["width"], 64, "{}", name);
+        assert_eq!(json["height"], 48, "{}", name);
+    }
+}
+
+#[tokio::test]
+async fn test_upload_svg_is_sanitized_and_rasterized() {
+    let server = TestServer::start().await;
+    let client = server.client();
+
+    let svg = r##"<?xml version="1.0"?>
+<svg xmlns="http://www.w3.org/2000/svg" width="64" height="32" onload="alert(1)">
+  <script>alert(document.cookie)</script>
+  <rect width="32" height="32" fill="#0000ff"/>
+</svg>"##;
+
+    let form = multipart::Form::new().part(
+        "file",
+        multipart::Part::bytes(svg.as_bytes().to_vec())
+            .file_name("logo.svg")
+            .mime_str("image/svg+xml")
+            .unwrap(),
+    );
+    let response = client
+        .post(server.url("/api/upload"))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 201);
+
+    // Small documents are rendered at 512px on the longer side
+    let json: Value = response.json().await.unwrap();
+    assert_eq!(json["mime_type"], "image/webp");
+    assert_eq!(json["width"], 512);
+    assert_eq!(json["height"], 256);
+
+    let id = json["id"].as_str().unwrap();
+    let response = client
+        .get(server.url(&format!("/m/{}/original", id)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "image/svg+xml");
+    assert!(response.headers().contains_key("content-security-policy"));
+
+    let original = response.text().await.unwrap();
+    assert!(original.contains("<svg"));
+    assert!(!original.contains("script"));
+    assert!(!original.contains("onload"));
+
+    // Variants render from the sanitized original
+    let response = client
+        .get(server.url(&format!("/m/{}?w=100&h=100&fit=cover", id)))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let variant = image::load_from_memory(&response.bytes().await.unwrap()).unwrap();
+    assert_eq!((variant.width(), variant.height()), (100, 100));
+}
+
+#[tokio::test]
+async fn test_upload_format_without_decoder_rejected() {
+    let server = TestServer::start().await;
+    let client = server.client();
+
+    // Minimal ISO-BMFF headers: AVIF (no decoder unless built with
+    // `avif-decode`) and HEIC (never supported)
+    let mut uploads = vec![(b"heic", "image/heic", "photo.heic")];
+    if !cfg!(feature = "avif-decode") {
+        uploads.push((b"avif", "image/avif", "photo.avif"));
+    }
+
+    for (brand, mime, name) in uploads {
+        let mut data = vec![0, 0, 0, 0x18];
+        data.extend_from_slice(b"ftyp");
+        data.extend_from_slice(brand);
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(b"mif1");
+        data.extend_from_slice(brand);
+        data.resize(256, 0);
+
+        let form = multipart::Form::new().part(
+            "file",
+            multipart::Part::bytes(data)
+                .file_name(name)
+                .mime_str(mime)
+                .unwrap(),
+        );
+        let response = client
+            .post(server.url("/api/upload"))
+            .multipart(form)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 415, "{}", name);
+    }
+}
+
+/// Upload a video through the simple upload endpoint
+async fn upload_video(server: &TestServer) -> Value {
+    let form = multipart::Form::new().part(
+        "file",
+        multipart::Part::bytes(create_test_mp4(640, 360, 3_000))
+            .file_name("clip.mp4")
+            .mime_str("video/mp4")
+            .unwrap(),
+    );
+
+    let response = server
+        .client()
+        .post(server.url("/api/upload"))
+        .multipart(form)
+        .send()
+        .await
+        .unwrap();
+    assert!(response.status().is_success(), "upload failed: {}", response.status());
+    response.json().await.unwrap()
+}
+
+/// Write an executable shell script standing in for ffmpeg
+fn fake_ffmpeg(dir: &std::path::Path, body: &str) -> String {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = dir.join("ffmpeg");
+    std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path.to_string_lossy().into_owned()
+}
+
+#[tokio::test]
+async fn test_upload_video_poster_frame() {
+    let tools = tempfile::TempDir::new().unwrap();
+    std::fs::write(tools.path().join("frame.png"), create_test_png(64, 36)).unwrap();
+    let ffmpeg = fake_ffmpeg(tools.path(), "exec cat \"$(dirname \"$0\")/frame.png\"");
+
+    let server = TestServer::start_with_config(|config| {
+        config.upload.allowed_video_types = vec!["video/mp4".to_string()];
+        config.processing.ffmpeg_path = Some(ffmpeg);
+    })
+    .await;
+
+    let json = upload_video(&server).await;
+    let poster_url = json["poster_url"].as_str().expect("poster_url missing");
+    assert!(poster_url.ends_with(&format!("/m/{}/poster", json["id"].as_str().unwrap())));
+
+    let response = server.client().get(poster_url).send().await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["content-type"], "image/webp");
+    let poster = image::load_from_memory(&response.bytes().await.unwrap()).unwrap();
+    assert_eq!((poster.width(), poster.height()), (64, 36));
+
+    // Images have no poster
+    let image = upload_and_inspect(&server, create_test_png(20, 20)).await;
+    let response = server
+        .client()
+        .get(server.url(&format!("/m/{}/poster", image["id"].as_str().unwrap())))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn test_upload_video_poster_failure_keeps_video() {
+    let tools = tempfile::TempDir::new().unwrap();
+    let ffmpeg = fake_ffmpeg(tools.path(), "echo 'no decoder' >&2; exit 1");
+
+    let server = TestServer::start_with_config(|config| {
+        config.upload.allowed_video_types = vec!["video/mp4".to_string()];
+        config.processing.ffmpeg_path = Some(ffmpeg);
+    })
+    .await;
+
+    let json = upload_video(&server).await;
+    assert_eq!(json["media_type"], "video");
+    assert!(json.get("poster_url").is_none());
+}